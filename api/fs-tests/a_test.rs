@@ -1,7 +1,11 @@
 use super::FSName;
+use cplfs_api::alloc::AllocStrategy;
 use cplfs_api::controller::Device;
 use cplfs_api::fs::{BlockSupport, FileSysSupport};
-use cplfs_api::types::SuperBlock;
+use cplfs_api::types::{
+    Block, BlockNo, DataIdx, DiscardPolicy, FragReport, FreeExtent, GroupDesc, MountOptions,
+    StatFs, SuperBlock, DINODE_SIZE, GROUPDESC_SIZE,
+};
 use std::path::{Path, PathBuf};
 
 #[path = "utils.rs"]
@@ -17,6 +21,14 @@ static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
     ndatablocks: 5,
     bmapstart: 4,
     datastart: 5,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 static SUPERBLOCK_BAD_INODES: SuperBlock = SuperBlock {
@@ -27,6 +39,14 @@ static SUPERBLOCK_BAD_INODES: SuperBlock = SuperBlock {
     ndatablocks: 5,
     bmapstart: 4,
     datastart: 5,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 static SUPERBLOCK_BAD_ORDER: SuperBlock = SuperBlock {
@@ -37,6 +57,14 @@ static SUPERBLOCK_BAD_ORDER: SuperBlock = SuperBlock {
     ndatablocks: 5,
     bmapstart: 5,
     datastart: 6,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 fn disk_prep_path(name: &str) -> PathBuf {
@@ -48,6 +76,11 @@ fn disk_setup(path: &Path) -> Device {
     utils::disk_setup(path, BLOCK_SIZE, NBLOCKS)
 }
 
+//Load an existing device
+fn disk_open(path: &Path) -> Device {
+    utils::disk_open(path, BLOCK_SIZE, NBLOCKS)
+}
+
 #[test]
 fn mkfs() {
     let path = disk_prep_path("mkfs");
@@ -57,7 +90,7 @@ fn mkfs() {
 
     //A working one
     let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
-    let sb = my_fs.b_get(0).unwrap();
+    let sb = my_fs.b_get(BlockNo(0)).unwrap();
     assert_eq!(
         sb.deserialize_from::<SuperBlock>(0).unwrap(),
         SUPERBLOCK_GOOD
@@ -94,7 +127,7 @@ fn mountfs_s() {
     dev.write_block(&sb).unwrap();
 
     let my_fs = FSName::mountfs(dev).unwrap();
-    let sb = my_fs.b_get(0).unwrap();
+    let sb = my_fs.b_get(BlockNo(0)).unwrap();
     assert_eq!(
         sb.deserialize_from::<SuperBlock>(0).unwrap(),
         SUPERBLOCK_GOOD
@@ -112,21 +145,21 @@ fn cod_block_ops() {
     let zb = |i| utils::zero_block(i, BLOCK_SIZE);
     for i in 1..NBLOCKS {
         //Will fail if you sneak in inodesupport
-        assert_eq!(my_fs.b_get(i).unwrap(), zb(i));
+        assert_eq!(my_fs.b_get(BlockNo(i)).unwrap(), zb(i));
     }
 
     let nb = utils::n_block(5, BLOCK_SIZE, 6);
     my_fs.b_put(&nb).unwrap();
-    let b = my_fs.b_get(5).unwrap();
+    let b = my_fs.b_get(BlockNo(5)).unwrap();
     assert_eq!(b, nb);
 
     let nb_bis = utils::n_block(6, BLOCK_SIZE, 6);
     my_fs.b_put(&nb_bis).unwrap();
-    my_fs.b_zero(1).unwrap(); //zero this block again
-    let b = my_fs.b_get(6).unwrap();
+    my_fs.b_zero(DataIdx(1)).unwrap(); //zero this block again
+    let b = my_fs.b_get(BlockNo(6)).unwrap();
     assert_eq!(b, zb(6));
 
-    assert!(my_fs.b_zero(5).is_err()); //out of bounds
+    assert!(my_fs.b_zero(DataIdx(5)).is_err()); //out of bounds
 
     let dev = my_fs.unmountfs();
     assert_eq!(dev.read_block(5).unwrap(), nb); //make sure you actually persisted stuff
@@ -143,24 +176,733 @@ fn free_alloc() {
 
     //Allocate
     for i in 0..SUPERBLOCK_GOOD.ndatablocks {
-        assert_eq!(my_fs.b_alloc().unwrap(), i); //Fill up all data blocks
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i)); //Fill up all data blocks
     }
     assert!(my_fs.b_alloc().is_err()); //No more blocks
 
     //Check the bitmap
-    let bb = my_fs.b_get(4).unwrap();
+    let bb = my_fs.b_get(BlockNo(4)).unwrap();
     bb.read_data(&mut byte, 0).unwrap();
     assert_eq!(byte[0], 0b0001_1111);
 
     //Deallocate
-    my_fs.b_free(3).unwrap();
-    assert!(my_fs.b_free(3).is_err());
+    my_fs.b_free(DataIdx(3)).unwrap();
+    assert!(my_fs.b_free(DataIdx(3)).is_err());
 
     //Check the bitmap
-    let bb = my_fs.b_get(4).unwrap();
+    let bb = my_fs.b_get(BlockNo(4)).unwrap();
     bb.read_data(&mut byte, 0).unwrap();
     assert_eq!(byte[0], 0b0001_0111);
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
 }
+
+#[test]
+fn grow() {
+    let path = disk_prep_path("grow");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+
+    for i in 0..2 {
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
+    }
+    let nb = utils::n_block(6, BLOCK_SIZE, 6);
+    my_fs.b_put(&nb).unwrap();
+
+    //Cannot grow to something smaller
+    assert!(my_fs.b_grow(NBLOCKS - 1, SUPERBLOCK_GOOD.ninodes).is_err());
+    assert!(my_fs.b_grow(NBLOCKS, SUPERBLOCK_GOOD.ninodes - 1).is_err());
+
+    //The bitmap has plenty of room, so only the data region grows
+    my_fs.b_grow(2 * NBLOCKS, SUPERBLOCK_GOOD.ninodes).unwrap();
+    let sb = my_fs.sup_get().unwrap();
+    assert_eq!(sb.nblocks, 2 * NBLOCKS);
+    assert_eq!(sb.ndatablocks, 2 * NBLOCKS - SUPERBLOCK_GOOD.datastart);
+    assert_eq!(sb.datastart, SUPERBLOCK_GOOD.datastart);
+    for i in 2..sb.ndatablocks {
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
+    }
+    assert!(my_fs.b_alloc().is_err());
+    for i in 2..sb.ndatablocks {
+        my_fs.b_free(DataIdx(i)).unwrap();
+    }
+
+    //Without a log, growing the inode region over the bitmap cannot be made crash-safe, so nothing changes
+    my_fs.b_free(DataIdx(0)).unwrap();
+    let ninodes = 4 * (BLOCK_SIZE / *DINODE_SIZE);
+    assert!(my_fs.b_grow(3 * NBLOCKS, ninodes).is_err());
+    assert_eq!(my_fs.sup_get().unwrap(), sb);
+    //No data block was in the way, but the bits of the data blocks in use would have to shift in place; the bitmap is left as it was
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(0));
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(2));
+    my_fs.b_free(DataIdx(2)).unwrap();
+    my_fs.b_free(DataIdx(0)).unwrap();
+    //The device gets its old size back, and the data region can still grow on its own
+    let dev = my_fs.unmountfs();
+    assert_eq!(dev.nblocks, 2 * NBLOCKS);
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    my_fs.b_grow(3 * NBLOCKS, SUPERBLOCK_GOOD.ninodes).unwrap();
+    let dev = my_fs.unmountfs();
+    assert_eq!(dev.nblocks, 3 * NBLOCKS);
+    utils::disk_destruct(dev);
+
+    //With a log, the inode region takes away the first data block, which is in use at first
+    let path = disk_prep_path("grow_log");
+    let sb_log = SuperBlock {
+        logstart: 1,
+        nlog: 3,
+        inodestart: 4,
+        bmapstart: 5,
+        datastart: 6,
+        ndatablocks: NBLOCKS - 6,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb_log).unwrap();
+    for i in 0..2 {
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
+    }
+    let nb = utils::n_block(7, BLOCK_SIZE, 7);
+    my_fs.b_put(&nb).unwrap();
+    let ninodes = 2 * (BLOCK_SIZE / *DINODE_SIZE);
+    assert!(my_fs.b_grow(2 * NBLOCKS, ninodes).is_err());
+    assert_eq!(my_fs.sup_get().unwrap(), sb_log);
+    my_fs.b_free(DataIdx(0)).unwrap();
+    my_fs.b_grow(2 * NBLOCKS, ninodes).unwrap();
+    let sb = my_fs.sup_get().unwrap();
+    assert_eq!(sb.ninodes, ninodes);
+    assert_eq!(sb.bmapstart, sb_log.bmapstart + 1);
+    assert_eq!(sb.datastart, sb_log.datastart + 1);
+    assert_eq!(sb.ndatablocks, 2 * NBLOCKS - sb.datastart);
+    //Block 7 is now the first data block, and is still in use
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(1));
+    assert!(my_fs.b_free(DataIdx(0)).is_ok());
+    assert_eq!(my_fs.b_get(BlockNo(7)).unwrap(), nb);
+
+    let dev = my_fs.unmountfs();
+    assert_eq!(dev.nblocks, 2 * NBLOCKS);
+    let sb_block = dev.read_block(0).unwrap();
+    assert_eq!(sb_block.deserialize_from::<SuperBlock>(0).unwrap(), sb);
+    assert_eq!(dev.read_block(1).unwrap(), utils::zero_block(1, BLOCK_SIZE));
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn shrink() {
+    let path = disk_prep_path("shrink");
+    //A file system with a bitmap region that is larger than it needs to be
+    let sb_loose = SuperBlock {
+        bmapstart: 2,
+        datastart: 4,
+        ndatablocks: 6,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb_loose).unwrap();
+    my_fs.b_alloc().unwrap();
+    let dev = my_fs.unmountfs();
+
+    //Without a log, shrinking the bitmap in place cannot be made crash-safe, so nothing changes
+    let image = std::fs::read(&path).unwrap();
+    assert!(FSName::b_shrink(dev, NBLOCKS - 2).is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), image);
+    utils::disk_destruct(disk_open(&path));
+
+    //Dropping free blocks at the end only changes the superblock, so that works without a log
+    let path = disk_prep_path("shrink_nolog");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    my_fs.b_alloc().unwrap();
+    let dev = FSName::b_shrink(my_fs.unmountfs(), NBLOCKS - 2).unwrap();
+    assert_eq!(dev.nblocks, NBLOCKS - 2);
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    assert_eq!(my_fs.sup_get().unwrap().ndatablocks, 3);
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(1));
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(2));
+    assert!(my_fs.b_alloc().is_err());
+    utils::disk_destruct(my_fs.unmountfs());
+
+    //The same file system, with a log in front of it
+    let path = disk_prep_path("shrink_log");
+    let nblocks = NBLOCKS + 3;
+    let sb_loose = SuperBlock {
+        nblocks,
+        logstart: 1,
+        nlog: 3,
+        inodestart: 4,
+        bmapstart: 5,
+        datastart: 7,
+        ndatablocks: 6,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb_loose).unwrap();
+    for i in 0..4 {
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
+    }
+    let nb = utils::n_block(8, BLOCK_SIZE, 8);
+    my_fs.b_put(&nb).unwrap();
+    my_fs.b_free(DataIdx(0)).unwrap();
+    let dev = my_fs.unmountfs();
+
+    //Cannot shrink to something larger, or over data that is in use
+    let image = std::fs::read(&path).unwrap();
+    assert!(FSName::b_shrink(dev, nblocks + 1).is_err());
+    let dev = utils::disk_open(&path, BLOCK_SIZE, nblocks);
+    assert!(FSName::b_shrink(dev, nblocks - 3).is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), image);
+
+    //Dropping the free blocks at the end also frees up a bitmap block
+    let dev = utils::disk_open(&path, BLOCK_SIZE, nblocks);
+    let dev = FSName::b_shrink(dev, nblocks - 2).unwrap();
+    assert_eq!(dev.nblocks, nblocks - 2);
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    let sb = my_fs.sup_get().unwrap();
+    assert_eq!(sb.nblocks, nblocks - 2);
+    assert_eq!(sb.bmapstart, 5);
+    assert_eq!(sb.datastart, 6);
+    assert_eq!(sb.ndatablocks, 5);
+
+    //Block 6 is the old bitmap block and is free now; blocks 8-10 remain in use
+    assert_eq!(my_fs.b_get(BlockNo(8)).unwrap(), nb);
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(0));
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(1));
+    assert!(my_fs.b_alloc().is_err());
+    assert!(my_fs.b_free(DataIdx(2)).is_ok());
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn block_groups() {
+    let path = disk_prep_path("block_groups");
+    //3 groups of 10 blocks, each with a bitmap block, an inode block and 8 data blocks
+    let sb_groups = SuperBlock {
+        nblocks: 35,
+        ninodes: 12,
+        inodestart: 3,
+        ndatablocks: 24,
+        bmapstart: 2,
+        datastart: 4,
+        ngroups: 3,
+        groupsize: 10,
+        ..SUPERBLOCK_GOOD
+    };
+    assert!(FSName::mkfs(
+        &path,
+        &SuperBlock {
+            ngroups: 4,
+            ndatablocks: 32,
+            ..sb_groups
+        }
+    )
+    .is_err()); //Does not fit
+    assert!(FSName::mkfs(
+        &path,
+        &SuperBlock {
+            ninodes: 13,
+            ..sb_groups
+        }
+    )
+    .is_err()); //Uneven split
+    assert!(FSName::mkfs(
+        &path,
+        &SuperBlock {
+            groupsize: 0,
+            ..sb_groups
+        }
+    )
+    .is_err());
+
+    let mut my_fs = FSName::mkfs(&path, &sb_groups).unwrap();
+    let gdt = my_fs.b_get(BlockNo(1)).unwrap();
+    let desc = |gdt: &Block, g: u64| {
+        gdt.deserialize_from::<GroupDesc>(g * *GROUPDESC_SIZE)
+            .unwrap()
+    };
+    assert_eq!(
+        desc(&gdt, 2),
+        GroupDesc {
+            bmap: 22,
+            inodestart: 23,
+            datastart: 24,
+            free_blocks: 8
+        }
+    );
+
+    //Allocation near a goal stays in the group of that goal
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(0));
+    assert_eq!(my_fs.b_alloc_near(DataIdx(8)).unwrap(), DataIdx(8));
+    assert_eq!(my_fs.b_alloc_near(DataIdx(17)).unwrap(), DataIdx(17));
+    for i in (16..24).filter(|&i| i != 17) {
+        assert_eq!(my_fs.b_alloc_near(DataIdx(16)).unwrap(), DataIdx(i));
+    }
+    //Group 2 is full; continue in the next group, wrapping around
+    assert_eq!(my_fs.b_alloc_near(DataIdx(20)).unwrap(), DataIdx(1));
+    assert!(my_fs.b_alloc_near(DataIdx(24)).is_err());
+    assert_eq!(
+        my_fs.b_get(BlockNo(22)).unwrap().contents_as_ref()[0],
+        0b1111_1111
+    );
+    assert_eq!(
+        my_fs.b_get(BlockNo(12)).unwrap().contents_as_ref()[0],
+        0b0000_0001
+    );
+
+    //Free counts in the descriptors are kept up to date
+    my_fs.b_free(DataIdx(20)).unwrap();
+    assert!(my_fs.b_free(DataIdx(20)).is_err());
+    assert!(my_fs.b_free(DataIdx(24)).is_err());
+    let gdt = my_fs.b_get(BlockNo(1)).unwrap();
+    assert_eq!(desc(&gdt, 0).free_blocks, 6);
+    assert_eq!(desc(&gdt, 1).free_blocks, 7);
+    assert_eq!(desc(&gdt, 2).free_blocks, 1);
+
+    //Data block indices map to the data region of their group
+    my_fs
+        .b_put(&Block::new(
+            14,
+            vec![1; BLOCK_SIZE as usize].into_boxed_slice(),
+        ))
+        .unwrap();
+    my_fs.b_zero(DataIdx(8)).unwrap();
+    assert_eq!(
+        my_fs.b_get(BlockNo(14)).unwrap(),
+        Block::new_zero(14, BLOCK_SIZE)
+    );
+
+    //Groups cannot be resized
+    assert!(my_fs.b_grow(45, 12).is_err());
+
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    assert_eq!(my_fs.b_alloc_near(DataIdx(16)).unwrap(), DataIdx(20));
+    assert!(my_fs.b_alloc_near(DataIdx(16)).is_ok()); //Group 0 still has room
+    my_fs.b_put(&Block::new_zero(1, BLOCK_SIZE)).unwrap(); //Corrupt the descriptors
+    let dev = my_fs.unmountfs();
+    assert!(FSName::mountfs(dev).is_err());
+    utils::disk_destruct(utils::disk_open(&path, BLOCK_SIZE, 35));
+}
+
+#[test]
+fn journal() {
+    let path = disk_prep_path("journal");
+    //A log of a header and 3 blocks between the super block and the inodes
+    let sb_log = SuperBlock {
+        nblocks: 12,
+        inodestart: 5,
+        bmapstart: 6,
+        datastart: 7,
+        logstart: 1,
+        nlog: 4,
+        ..SUPERBLOCK_GOOD
+    };
+    assert!(FSName::mkfs(
+        &path,
+        &SuperBlock {
+            inodestart: 1,
+            ..sb_log
+        }
+    )
+    .is_err());
+    assert!(FSName::mkfs(
+        &path,
+        &SuperBlock {
+            logstart: 2,
+            ..sb_log
+        }
+    )
+    .is_err());
+    assert!(FSName::mkfs(
+        &path,
+        &SuperBlock {
+            nlog: 1,
+            inodestart: 2,
+            ..sb_log
+        }
+    )
+    .is_err());
+    let mut my_fs = FSName::mkfs(&path, &sb_log).unwrap();
+    let head = |fs: &FSName| {
+        fs.b_get(BlockNo(1))
+            .unwrap()
+            .deserialize_from::<Vec<u64>>(0)
+            .unwrap()
+    };
+    assert!(my_fs.end_op().is_err()); //Nothing to end
+
+    //Writes only reach the disk when the outermost operation ends, but are visible before
+    my_fs.begin_op().unwrap();
+    my_fs.b_put(&utils::n_block(10, BLOCK_SIZE, 1)).unwrap();
+    my_fs.begin_op().unwrap();
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(0));
+    my_fs.end_op().unwrap();
+    my_fs.b_put(&utils::n_block(10, BLOCK_SIZE, 2)).unwrap(); //Absorbed
+    assert_eq!(
+        my_fs.b_get(BlockNo(10)).unwrap(),
+        utils::n_block(10, BLOCK_SIZE, 2)
+    );
+    my_fs.end_op().unwrap();
+    assert!(head(&my_fs).is_empty());
+    assert_eq!(
+        my_fs.b_get(BlockNo(10)).unwrap(),
+        utils::n_block(10, BLOCK_SIZE, 2)
+    );
+    assert_eq!(
+        my_fs.b_get(BlockNo(6)).unwrap().contents_as_ref()[0],
+        0b0000_0001
+    );
+
+    //An operation that does not fit in the log is discarded as a whole
+    my_fs.begin_op().unwrap();
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(1));
+    my_fs.b_put(&utils::n_block(9, BLOCK_SIZE, 3)).unwrap();
+    my_fs.b_put(&utils::n_block(10, BLOCK_SIZE, 3)).unwrap();
+    assert!(my_fs.b_put(&utils::n_block(11, BLOCK_SIZE, 3)).is_err());
+    assert!(my_fs.end_op().is_err());
+    assert_eq!(
+        my_fs.b_get(BlockNo(9)).unwrap(),
+        utils::zero_block(9, BLOCK_SIZE)
+    );
+    assert_eq!(
+        my_fs.b_get(BlockNo(10)).unwrap(),
+        utils::n_block(10, BLOCK_SIZE, 2)
+    );
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(1)); //The bitmap was not changed either
+
+    //A committed operation that was not installed yet is finished when mounting
+    my_fs.b_put(&utils::n_block(2, BLOCK_SIZE, 7)).unwrap();
+    let mut header = utils::zero_block(1, BLOCK_SIZE);
+    header.serialize_into(&vec![11u64], 0).unwrap();
+    my_fs.b_put(&header).unwrap();
+    let dev = my_fs.unmountfs();
+    let my_fs = FSName::mountfs(dev).unwrap();
+    assert_eq!(
+        my_fs.b_get(BlockNo(11)).unwrap(),
+        utils::n_block(11, BLOCK_SIZE, 7)
+    );
+    assert!(head(&my_fs).is_empty());
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn discard() {
+    let path = disk_prep_path("discard");
+    let my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let dev = my_fs.unmountfs();
+    let opts = MountOptions {
+        discard: DiscardPolicy::Zero,
+        ..Default::default()
+    };
+    let mut my_fs = FSName::mountfs_with(dev, &opts).unwrap();
+    for i in 0..3 {
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
+        my_fs.b_put(&utils::n_block(5 + i, BLOCK_SIZE, 1)).unwrap();
+    }
+
+    //Freed blocks are zeroed
+    my_fs.b_free(DataIdx(0)).unwrap();
+    assert_eq!(
+        my_fs.b_get(BlockNo(5)).unwrap(),
+        utils::zero_block(5, BLOCK_SIZE)
+    );
+
+    //Inside an operation, only once it ends, and not if the block is allocated again
+    my_fs.begin_op().unwrap();
+    my_fs.b_free(DataIdx(1)).unwrap();
+    assert_eq!(
+        my_fs.b_get(BlockNo(6)).unwrap(),
+        utils::n_block(6, BLOCK_SIZE, 1)
+    );
+    assert_eq!(my_fs.b_alloc_near(DataIdx(1)).unwrap(), DataIdx(1));
+    my_fs.b_put(&utils::n_block(6, BLOCK_SIZE, 2)).unwrap();
+    assert!(my_fs.b_trim(DiscardPolicy::Zero).is_err()); //Still running
+    my_fs.end_op().unwrap();
+    assert_eq!(
+        my_fs.b_get(BlockNo(6)).unwrap(),
+        utils::n_block(6, BLOCK_SIZE, 2)
+    );
+
+    //The policy is not stored on disk
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    my_fs.b_free(DataIdx(2)).unwrap();
+    assert_eq!(
+        my_fs.b_get(BlockNo(7)).unwrap(),
+        utils::n_block(7, BLOCK_SIZE, 1)
+    );
+
+    //Trimming discards all free blocks, and leaves the others alone
+    assert_eq!(my_fs.b_trim(DiscardPolicy::Keep).unwrap(), 0);
+    assert_eq!(
+        my_fs.b_get(BlockNo(7)).unwrap(),
+        utils::n_block(7, BLOCK_SIZE, 1)
+    );
+    assert_eq!(my_fs.b_trim(DiscardPolicy::PunchHole).unwrap(), 4);
+    assert_eq!(
+        my_fs.b_get(BlockNo(7)).unwrap(),
+        utils::zero_block(7, BLOCK_SIZE)
+    );
+    assert_eq!(
+        my_fs.b_get(BlockNo(6)).unwrap(),
+        utils::n_block(6, BLOCK_SIZE, 2)
+    );
+    assert_eq!(
+        my_fs.b_get(BlockNo(4)).unwrap().contents_as_ref()[0],
+        0b0000_0010
+    );
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn reserved() {
+    let path = disk_prep_path("reserved");
+    let sb_reserved = SuperBlock {
+        nreserved: 2,
+        ..SUPERBLOCK_GOOD
+    };
+    assert!(FSName::mkfs(
+        &path,
+        &SuperBlock {
+            nreserved: 6,
+            ..SUPERBLOCK_GOOD
+        }
+    )
+    .is_err());
+    let mut my_fs = FSName::mkfs(&path, &sb_reserved).unwrap();
+    let stats = |fs: &FSName| fs.statfs().unwrap();
+    assert_eq!(
+        stats(&my_fs),
+        StatFs {
+            block_size: BLOCK_SIZE,
+            blocks: 5,
+            free_blocks: 5,
+            available_blocks: 3,
+            reserved_blocks: 2,
+        }
+    );
+
+    //Only privileged writers can use the last 2 blocks
+    for i in 0..3 {
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
+    }
+    assert!(my_fs.b_alloc().is_err());
+    assert_eq!(stats(&my_fs).free_blocks, 2);
+    assert_eq!(stats(&my_fs).available_blocks, 0);
+    assert_eq!(my_fs.as_privileged(|fs| fs.b_alloc()).unwrap(), DataIdx(3));
+    assert!(!my_fs.b_set_privileged(false)); //The context was restored
+    assert!(my_fs.b_alloc().is_err());
+    assert!(!my_fs.b_set_privileged(true));
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(4));
+    assert!(my_fs.b_alloc().is_err());
+    assert!(my_fs.b_set_privileged(false));
+    assert_eq!(stats(&my_fs).free_blocks, 0);
+
+    //Freeing a block does not make it available yet, and the counts survive remounting
+    my_fs.b_free(DataIdx(1)).unwrap();
+    assert!(my_fs.b_alloc().is_err());
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    assert_eq!(stats(&my_fs).free_blocks, 1);
+    my_fs.b_free(DataIdx(2)).unwrap();
+    my_fs.b_free(DataIdx(3)).unwrap();
+    assert_eq!(stats(&my_fs).available_blocks, 1);
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(1));
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn bad_blocks() {
+    let path = disk_prep_path("bad_blocks");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    assert!(my_fs.b_bad_blocks().unwrap().is_empty());
+    assert!(my_fs.b_mark_bad(BlockNo(3)).is_err()); //Not a data block
+    assert!(my_fs.b_mark_bad(BlockNo(NBLOCKS)).is_err());
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(0));
+    assert!(my_fs.b_mark_bad(BlockNo(5)).is_err()); //In use, and the block layer cannot migrate it
+
+    //The list is stored in the first free block once a block is marked bad
+    my_fs.b_mark_bad(BlockNo(7)).unwrap();
+    assert_eq!(my_fs.b_bad_blocks().unwrap(), vec![BlockNo(7)]);
+    assert_eq!(my_fs.sup_get().unwrap().badlist, 6);
+    assert!(my_fs.b_mark_bad(BlockNo(7)).is_err()); //Already bad
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(3)); //Skips both
+    assert!(my_fs.b_free(DataIdx(2)).is_err());
+    assert!(my_fs.b_free(DataIdx(1)).is_err());
+
+    //Marking the block of the list itself moves the list
+    my_fs.b_mark_bad(BlockNo(6)).unwrap();
+    assert_eq!(my_fs.sup_get().unwrap().badlist, 9);
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    assert_eq!(my_fs.b_bad_blocks().unwrap(), vec![BlockNo(7), BlockNo(6)]);
+    assert_eq!(
+        my_fs
+            .b_get(BlockNo(9))
+            .unwrap()
+            .deserialize_from::<Vec<u64>>(0)
+            .unwrap(),
+        vec![7, 6]
+    );
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, 0);
+    assert!(my_fs.b_alloc().is_err());
+    assert!(my_fs.b_grow(NBLOCKS + 1, 6).is_err()); //Cannot resize with bad blocks
+
+    //A bad block, or the block of the list, that is marked free could be handed out, so such an image does not mount
+    let mut dev = my_fs.unmountfs();
+    let bitmap = dev.read_block(4).unwrap();
+    for cleared in [0b1_1011, 0b0_1111] {
+        let mut corrupt = Block::new_zero(4, BLOCK_SIZE);
+        corrupt.write_data(&[cleared], 0).unwrap();
+        dev.write_block(&corrupt).unwrap();
+        assert!(FSName::mountfs(dev).is_err());
+        dev = disk_open(&path);
+    }
+    dev.write_block(&bitmap).unwrap();
+    let my_fs = FSName::mountfs(dev).unwrap();
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn transaction() {
+    let path = disk_prep_path("transaction");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    assert!(my_fs.b_tx_commit().is_err()); //Nothing to commit
+    assert!(my_fs.b_tx_rollback().is_err());
+
+    //Dropping a transaction undoes its allocations and writes, even without a log
+    {
+        let mut tx = my_fs.transaction().unwrap();
+        assert_eq!(tx.b_alloc().unwrap(), DataIdx(0));
+        tx.b_put(&utils::n_block(5, BLOCK_SIZE, 1)).unwrap();
+        assert_eq!(
+            tx.b_get(BlockNo(5)).unwrap(),
+            utils::n_block(5, BLOCK_SIZE, 1)
+        );
+    }
+    assert_eq!(
+        my_fs.b_get(BlockNo(5)).unwrap(),
+        utils::zero_block(5, BLOCK_SIZE)
+    );
+    assert_eq!(my_fs.b_get(BlockNo(4)).unwrap().contents_as_ref()[0], 0);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, 5);
+
+    //Committing applies them
+    let mut tx = my_fs.transaction().unwrap();
+    assert_eq!(tx.b_alloc().unwrap(), DataIdx(0));
+    tx.b_put(&utils::n_block(5, BLOCK_SIZE, 1)).unwrap();
+    tx.commit().unwrap();
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    assert_eq!(
+        my_fs.b_get(BlockNo(5)).unwrap(),
+        utils::n_block(5, BLOCK_SIZE, 1)
+    );
+
+    //Rolling back a nested transaction only undoes its own changes, frees included
+    let mut outer = my_fs.transaction().unwrap();
+    assert_eq!(outer.b_alloc().unwrap(), DataIdx(1));
+    let mut inner = outer.transaction().unwrap();
+    inner.b_free(DataIdx(0)).unwrap();
+    assert_eq!(inner.b_alloc().unwrap(), DataIdx(0));
+    assert_eq!(inner.b_alloc().unwrap(), DataIdx(2));
+    inner.rollback().unwrap();
+    assert_eq!(outer.statfs().unwrap().free_blocks, 3);
+    outer.commit().unwrap();
+    assert_eq!(
+        my_fs.b_get(BlockNo(4)).unwrap().contents_as_ref()[0],
+        0b0000_0011
+    );
+    assert_eq!(
+        my_fs.b_get(BlockNo(5)).unwrap(),
+        utils::n_block(5, BLOCK_SIZE, 1)
+    );
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn alloc_policies() {
+    let sb = SuperBlock {
+        nblocks: 20,
+        ndatablocks: 15,
+        ..SUPERBLOCK_GOOD
+    };
+    //Blocks picked by b_alloc and by b_alloc_near(6), with free blocks 2, 3, 6 and 10 up to 14
+    let expected = [
+        (AllocStrategy::FirstFit, 2, 3),
+        (AllocStrategy::NextFit, 10, 11),
+        (AllocStrategy::BestFitExtent, 6, 2),
+        (AllocStrategy::Locality, 2, 6),
+    ];
+    for (strategy, first, near) in expected {
+        let path = disk_prep_path(&format!("alloc_policies_{:?}", strategy));
+        let dev = FSName::mkfs(&path, &sb).unwrap().unmountfs();
+        let opts = MountOptions {
+            alloc: strategy,
+            ..Default::default()
+        };
+        let mut my_fs = FSName::mountfs_with(dev, &opts).unwrap();
+        for i in 0..10 {
+            assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
+        }
+        for i in [2, 3, 6] {
+            my_fs.b_free(DataIdx(i)).unwrap();
+        }
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(first));
+        assert_eq!(my_fs.b_alloc_near(DataIdx(6)).unwrap(), DataIdx(near));
+        assert!(my_fs.b_alloc_near(DataIdx(15)).is_err());
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+}
+
+#[test]
+fn frag_report() {
+    let path = disk_prep_path("frag_report");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let report = my_fs.b_frag_report().unwrap();
+    assert_eq!(
+        report.free_extents,
+        vec![FreeExtent {
+            start: DataIdx(0),
+            len: 5
+        }]
+    );
+    assert_eq!(report.histogram, vec![0, 0, 1]);
+    assert_eq!((report.largest_free, report.score), (5, 0));
+
+    for _ in 0..5 {
+        my_fs.b_alloc().unwrap();
+    }
+    assert_eq!(my_fs.b_frag_report().unwrap(), FragReport::default());
+
+    for i in [0, 1, 3] {
+        my_fs.b_free(DataIdx(i)).unwrap();
+    }
+    let report = my_fs.b_frag_report().unwrap();
+    assert_eq!(
+        report.free_extents,
+        vec![
+            FreeExtent {
+                start: DataIdx(0),
+                len: 2
+            },
+            FreeExtent {
+                start: DataIdx(3),
+                len: 1
+            }
+        ]
+    );
+    assert_eq!(report.histogram, vec![1, 1]);
+    assert_eq!((report.largest_free, report.score), (2, 33));
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
@@ -1,6 +1,6 @@
 use super::FSName;
 use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeSupport};
-use cplfs_api::types::{FType, InodeLike, SuperBlock};
+use cplfs_api::types::{BlockNo, DataIdx, FType, InodeLike, SuperBlock};
 use std::path::PathBuf;
 
 #[path = "utils.rs"]
@@ -16,6 +16,14 @@ static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
     ndatablocks: 5,
     bmapstart: 4,
     datastart: 5,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 fn disk_prep_path(name: &str) -> PathBuf {
@@ -29,7 +37,7 @@ fn mkfs() {
 
     //A working one
     let my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
-    let sb = my_fs.b_get(0).unwrap();
+    let sb = my_fs.b_get(BlockNo(0)).unwrap();
     assert_eq!(
         sb.deserialize_from::<SuperBlock>(0).unwrap(),
         SUPERBLOCK_GOOD
@@ -66,7 +74,7 @@ fn get_put() {
         &[2, 3, 4],
     )
     .unwrap();
-    let b1 = my_fs.b_get(SUPERBLOCK_GOOD.inodestart).unwrap();
+    let b1 = my_fs.b_get(BlockNo(SUPERBLOCK_GOOD.inodestart)).unwrap();
 
     my_fs.i_put(&i1).unwrap();
     my_fs.i_put(&i2).unwrap();
@@ -112,12 +120,12 @@ fn free_alloc() {
     )
     .unwrap();
     my_fs.i_put(&i1).unwrap();
-    assert!(my_fs.b_free(2).is_err());
+    assert!(my_fs.b_free(DataIdx(2)).is_err());
     assert_eq!(my_fs.i_get(2).unwrap().get_ft(), FType::TFile);
 
     //Allocate blocks 5-6-7-8
     for i in 0..4 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let i2 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
         3,
@@ -130,8 +138,8 @@ fn free_alloc() {
     my_fs.i_put(&i2).unwrap();
     my_fs.i_free(3).unwrap();
     //Already freed
-    assert!(my_fs.b_free(2).is_err()); //watch out; absolute indices
-    assert!(my_fs.b_free(3).is_err());
+    assert!(my_fs.b_free(DataIdx(2)).is_err()); //watch out; absolute indices
+    assert!(my_fs.b_free(DataIdx(3)).is_err());
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
@@ -144,7 +152,7 @@ fn itrunc() {
 
     //Allocate blocks 5-6-7-8
     for i in 0..5 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let i2 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
         2,
@@ -163,9 +171,63 @@ fn itrunc() {
     assert_eq!(i3.get_nlink(), 0);
 
     //Already freed
-    assert!(my_fs.b_free(1).is_err());
-    assert!(my_fs.b_free(2).is_err());
-    assert!(my_fs.b_free(3).is_ok()); //sneaky; not deallocated
+    assert!(my_fs.b_free(DataIdx(1)).is_err());
+    assert!(my_fs.b_free(DataIdx(2)).is_err());
+    assert!(my_fs.b_free(DataIdx(3)).is_ok()); //sneaky; not deallocated
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn inode_bitmap() {
+    let path = disk_prep_path("inode_bitmap");
+    let sb = SuperBlock {
+        imapstart: 1,
+        inodestart: 2,
+        ..SUPERBLOCK_GOOD
+    };
+    assert!(!FSName::sb_valid(&SuperBlock { imapstart: 2, ..sb }));
+    assert!(!FSName::sb_valid(&SuperBlock {
+        imapstart: 1,
+        ..SUPERBLOCK_GOOD
+    }));
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    let imap = |fs: &FSName| fs.b_get(BlockNo(1)).unwrap().contents_as_ref()[0];
+    assert_eq!(imap(&my_fs), 0b1); //Only inode 0 is in use
+
+    for i in 1..6 {
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), i);
+    }
+    assert_eq!(imap(&my_fs), 0b111111);
+    assert!(my_fs.i_alloc(FType::TFile).is_err());
+    my_fs.i_free(3).unwrap();
+    assert_eq!(imap(&my_fs), 0b110111);
+    assert_eq!(my_fs.i_alloc(FType::TDir).unwrap(), 3);
+
+    //Allocations near a parent take the first free inode after it, wrapping around
+    my_fs.i_free(2).unwrap();
+    my_fs.i_free(4).unwrap();
+    assert_eq!(my_fs.i_alloc_near(FType::TFile, 3).unwrap(), 4);
+    assert_eq!(my_fs.i_alloc_near(FType::TFile, 5).unwrap(), 2);
+
+    //Writing an inode keeps its bit in sync
+    let mut i5 = my_fs.i_get(5).unwrap();
+    i5.disk_node.ft = FType::TFree;
+    my_fs.i_put(&i5).unwrap();
+    assert_eq!(imap(&my_fs), 0b011111);
+
+    //The bit map, rather than the inode region, decides which inodes are free
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    let mut b = my_fs.b_get(BlockNo(1)).unwrap();
+    b.write_data(&[0b110011], 0).unwrap();
+    my_fs.b_put(&b).unwrap();
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 2);
+    assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 3);
+    assert!(my_fs.i_alloc(FType::TFile).is_err());
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
@@ -1,6 +1,10 @@
 use super::FSName;
+use cplfs_api::clock::{Clock, ManualClock};
 use cplfs_api::fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport};
-use cplfs_api::types::{FType, InodeLike, SuperBlock, DIRENTRY_SIZE};
+use cplfs_api::types::{
+    DataIdx, FType, InodeLike, SuperBlock, Timestamp, DIRENTRY_SIZE, FEATURE_INLINE_DATA,
+    INLINE_DATA_MAX,
+};
 use std::path::PathBuf;
 
 #[path = "utils.rs"]
@@ -16,6 +20,14 @@ static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
     ndatablocks: 5,
     bmapstart: 4,
     datastart: 5,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 fn disk_prep_path(name: &str) -> PathBuf {
@@ -57,6 +69,43 @@ fn root() {
     utils::disk_destruct(dev);
 }
 
+#[test]
+fn inline_dirs() {
+    let path = disk_prep_path("inline_dirs");
+    let sb = SuperBlock {
+        features: FEATURE_INLINE_DATA,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    //The entries of the root directory fit in its inode
+    let mut root = my_fs.i_get(1).unwrap();
+    assert!(root.disk_node.is_inline());
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, sb.ndatablocks);
+
+    let fits = INLINE_DATA_MAX / *DIRENTRY_SIZE;
+    for i in 0..fits {
+        my_fs.dirlink(&mut root, &i.to_string(), 1).unwrap();
+    }
+    assert!(root.disk_node.is_inline());
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, sb.ndatablocks);
+
+    //One more entry moves them out to a data block
+    my_fs.dirlink(&mut root, "last", 1).unwrap();
+    assert!(!root.disk_node.is_inline());
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, sb.ndatablocks - 1);
+    assert_eq!(my_fs.i_get(1).unwrap(), root);
+    for i in 0..fits {
+        assert_eq!(my_fs.dirlookup(&root, &i.to_string()).unwrap().0.inum, 1);
+    }
+    assert_eq!(
+        my_fs.dirlookup(&root, "last").unwrap().1,
+        fits * *DIRENTRY_SIZE
+    );
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
 //does not test multi block allocations
 #[test]
 fn dirlookup_link() {
@@ -86,7 +135,7 @@ fn dirlookup_link() {
 
     //Allocate blocks 5-6-7
     for i in 0..3 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
 
     //Allocate inodes 2,3,4
@@ -141,3 +190,39 @@ fn dirlookup_link_root() {
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
 }
+
+#[test]
+fn dirlink_timestamps() {
+    let path = disk_prep_path("dirlink_timestamps");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let clock = ManualClock::new(Timestamp::from_secs(100));
+    my_fs.i_set_clock(Box::new(clock.clone()));
+    let created = clock.now();
+    let d = my_fs.i_alloc(FType::TDir).unwrap();
+    let f = my_fs.i_alloc(FType::TFile).unwrap();
+
+    //Both the directory and the inode that gets an extra link change
+    clock.advance(5);
+    let mut dir = my_fs.i_get(d).unwrap();
+    my_fs.dirlink(&mut dir, "file", f).unwrap();
+    assert_eq!(dir.get_mtime(), clock.now());
+    assert_eq!(dir.get_ctime(), clock.now());
+    assert_eq!(my_fs.i_get(d).unwrap(), dir);
+    let file = my_fs.i_get(f).unwrap();
+    assert_eq!(file.get_ctime(), clock.now());
+    assert_eq!(file.get_mtime(), created);
+    assert_eq!(file.get_crtime(), created);
+
+    //Lookups do not count as accesses
+    my_fs.dirlookup(&dir, "file").unwrap();
+    assert_eq!(my_fs.i_get(d).unwrap().get_atime(), created);
+
+    //A failed link changes nothing
+    clock.advance(5);
+    assert!(my_fs.dirlink(&mut dir, "file", f).is_err());
+    assert_eq!(my_fs.i_get(d).unwrap(), dir);
+    assert_eq!(dir.get_mtime(), Timestamp(created.0 + 5));
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
@@ -1,6 +1,9 @@
 use super::FSName;
 use cplfs_api::fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport, PathSupport};
-use cplfs_api::types::{FType, InodeLike, SuperBlock, DIRENTRY_SIZE};
+use cplfs_api::types::{
+    Access, Credentials, DataIdx, FType, InodeLike, SuperBlock, DEFAULT_DIR_MODE, DIRENTRY_SIZE,
+    FEATURE_XATTR, MODE_SETGID, MODE_STICKY, SYMLINK_INLINE_MAX,
+};
 use std::path::PathBuf;
 
 #[path = "utils.rs"]
@@ -16,6 +19,14 @@ static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
     ndatablocks: 7,
     bmapstart: 4,
     datastart: 5,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 fn disk_prep_path(name: &str) -> PathBuf {
@@ -66,7 +77,7 @@ fn root() {
     assert_eq!(my_fs.dirlookup(&mut iroot, "..").unwrap().1, *DIRENTRY_SIZE);
     assert_eq!(my_fs.dirlookup(&mut iroot, "..").unwrap().0.get_inum(), 1);
     //The root should have been allocated a block for its data
-    my_fs.b_free(0).unwrap();
+    my_fs.b_free(DataIdx(0)).unwrap();
 
     assert_eq!(my_fs.i_get(0).unwrap().get_ft(), FType::TFree);
     my_fs.i_free(1).unwrap(); //inode has been allocated, but should not be deallocated, as the root references itself
@@ -86,7 +97,7 @@ fn resolve() {
 
     //Allocate blocks 6-7-8
     for i in 0..3 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i + 1);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i + 1));
     }
     //Setup inode 3, using these blocks
     let i3 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
@@ -219,7 +230,7 @@ fn mkdir() {
 
     //Allocate blocks 6-7-8
     for i in 0..3 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i + 1);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i + 1));
     }
     //Setup inode 3, using these blocks
     let i3 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
@@ -314,7 +325,7 @@ fn unlink() {
 
     //Allocate blocks 6-7-8
     for i in 0..3 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i + 1);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i + 1));
     }
     //Setup inode 3, using these blocks
     let i3 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
@@ -414,4 +425,187 @@ fn unlink() {
     utils::disk_destruct(dev);
 }
 
+#[test]
+fn permissions() {
+    let path = disk_prep_path("permissions");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let alice = Credentials::new(1000, 100);
+    let bob = Credentials::new(2000, 200);
+    assert_eq!(my_fs.get_credentials(), Credentials::root());
+    assert_eq!(my_fs.i_get(1).unwrap().get_mode(), DEFAULT_DIR_MODE);
+
+    //Only root can write to the root directory
+    my_fs.set_credentials(alice.clone());
+    assert!(my_fs.mkdir("/a").is_err());
+    my_fs.set_credentials(Credentials::root());
+    let public = my_fs.mkdir("/pub").unwrap();
+    assert_eq!((public.get_uid(), public.get_gid()), (0, 0));
+    my_fs.chmod("/pub", 0o777 | MODE_STICKY).unwrap();
+
+    //New directories belong to their creator
+    my_fs.set_credentials(alice.clone());
+    let mine = my_fs.mkdir("/pub/mine").unwrap();
+    assert_eq!((mine.get_uid(), mine.get_gid()), (1000, 100));
+    assert_eq!(mine.get_mode(), DEFAULT_DIR_MODE);
+    assert!(my_fs.chown("/pub/mine", 2000, 200).is_err());
+
+    //Bob can write to /pub, but cannot remove what alice put there, nor write into it
+    my_fs.set_credentials(bob.clone());
+    my_fs.mkdir("/pub/bobs").unwrap();
+    assert!(my_fs.unlink("/pub/mine").is_err());
+    assert!(my_fs.mkdir("/pub/mine/x").is_err());
+    assert!(my_fs.chmod("/pub/mine", 0o777).is_err());
+    assert!(my_fs.open("/pub/mine", Access::Read).is_ok());
+    assert!(my_fs.open("/pub/mine", Access::Write).is_err());
+
+    //Without search permission, bob cannot look anything up in alice's directory
+    my_fs.set_credentials(alice.clone());
+    my_fs.mkdir("/pub/mine/x").unwrap();
+    my_fs.chmod("/pub/mine", 0o700).unwrap();
+    my_fs.set_credentials(bob.clone());
+    assert!(my_fs.resolve_path("/pub/mine/x").is_err());
+    assert!(my_fs.set_cwd("/pub/mine").is_some());
+    assert!(my_fs.resolve_path("./x").is_err());
+    my_fs.set_cwd("/");
+    assert!(my_fs.resolve_path("/pub/mine").is_ok());
+
+    //Setgid directories pass on their group
+    my_fs.set_credentials(Credentials::root());
+    my_fs.mkdir("/grp").unwrap();
+    my_fs.chown("/grp", 0, 300).unwrap();
+    my_fs.chmod("/grp", MODE_SETGID | 0o775).unwrap();
+    my_fs.set_credentials(Credentials {
+        groups: vec![300],
+        ..alice.clone()
+    });
+    let sub = my_fs.mkdir("/grp/sub").unwrap();
+    assert_eq!((sub.get_uid(), sub.get_gid()), (1000, 300));
+    assert_eq!(sub.get_mode(), MODE_SETGID | DEFAULT_DIR_MODE);
+    //Alice cannot hand out the setgid bit for a group she is not in
+    my_fs.set_credentials(Credentials::root());
+    my_fs.chown("/pub/mine", 1000, 500).unwrap();
+    my_fs.set_credentials(alice.clone());
+    my_fs.chmod("/pub/mine", MODE_SETGID | 0o755).unwrap();
+    assert_eq!(my_fs.resolve_path("/pub/mine").unwrap().get_mode(), 0o755);
+
+    //The owner of a directory in a sticky directory can remove it
+    my_fs.unlink("/pub/mine/x").unwrap();
+    my_fs.unlink("/pub/mine").unwrap();
+    assert!(my_fs.resolve_path("/pub/mine").is_err());
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn xattr_permissions() {
+    let path = disk_prep_path("xattr_permissions");
+    let sb = SuperBlock {
+        features: FEATURE_XATTR,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    let alice = Credentials::new(1000, 100);
+    let bob = Credentials::new(2000, 200);
+    my_fs.mkdir("/pub").unwrap();
+    my_fs.chmod("/pub", 0o777).unwrap();
+    my_fs.set_credentials(alice.clone());
+    my_fs.mkdir("/pub/mine").unwrap();
+
+    //The owner can manage user attributes, but not trusted ones
+    my_fs.setxattr("/pub/mine", "user.tag", b"a").unwrap();
+    assert!(my_fs.setxattr("/pub/mine", "trusted.tag", b"t").is_err());
+    assert!(my_fs.setxattr("/pub/mine", "other.tag", b"o").is_err());
+    assert!(my_fs.setxattr("/pub/none", "user.tag", b"a").is_err());
+
+    //Root can set trusted attributes, which others cannot see
+    my_fs.set_credentials(Credentials::root());
+    my_fs.setxattr("/pub/mine", "trusted.tag", b"t").unwrap();
+    assert_eq!(
+        my_fs.listxattr("/pub/mine").unwrap(),
+        vec!["trusted.tag".to_string(), "user.tag".to_string()]
+    );
+    my_fs.set_credentials(alice.clone());
+    assert_eq!(
+        my_fs.listxattr("/pub/mine").unwrap(),
+        vec!["user.tag".to_string()]
+    );
+    assert!(my_fs.getxattr("/pub/mine", "trusted.tag").is_err());
+    assert!(my_fs.removexattr("/pub/mine", "trusted.tag").is_err());
+
+    //Others need read access to get attributes and write access to change them
+    my_fs.set_credentials(bob.clone());
+    assert_eq!(
+        my_fs.getxattr("/pub/mine", "user.tag").unwrap(),
+        Some(b"a".to_vec())
+    );
+    assert!(my_fs.setxattr("/pub/mine", "user.tag", b"b").is_err());
+    assert!(my_fs.removexattr("/pub/mine", "user.tag").is_err());
+    my_fs.set_credentials(alice.clone());
+    my_fs.chmod("/pub/mine", 0o700).unwrap();
+    my_fs.set_credentials(bob.clone());
+    assert!(my_fs.getxattr("/pub/mine", "user.tag").is_err());
+    my_fs.set_credentials(alice.clone());
+    my_fs.removexattr("/pub/mine", "user.tag").unwrap();
+    assert_eq!(my_fs.getxattr("/pub/mine", "user.tag").unwrap(), None);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn symlinks() {
+    let path = disk_prep_path("symlinks");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let a = my_fs.mkdir("/a").unwrap().get_inum();
+    let b = my_fs.mkdir("/a/b").unwrap().get_inum();
+
+    //Absolute and relative targets, followed anywhere in a path
+    let l = my_fs.symlink("/a/b", "/l").unwrap();
+    assert_eq!(l.get_ft(), FType::TSymlink);
+    assert_eq!(l.get_size(), 4);
+    assert_eq!(my_fs.readlink("/l").unwrap(), "/a/b");
+    assert_eq!(my_fs.lresolve_path("/l").unwrap(), l);
+    assert_eq!(my_fs.resolve_path("/l").unwrap().get_inum(), b);
+    assert_eq!(my_fs.resolve_path("/l/..").unwrap().get_inum(), a);
+    my_fs.symlink("b", "/a/rel").unwrap();
+    my_fs.set_cwd("/a");
+    assert_eq!(my_fs.resolve_path("./rel/.").unwrap().get_inum(), b);
+    assert_eq!(my_fs.readlink("./rel").unwrap(), "b");
+    assert!(my_fs.readlink("./b").is_err());
+    my_fs.set_cwd("/");
+
+    //Long targets take up a data block, short ones do not
+    let target = "/a".to_string() + &"/b/..".repeat(20) + "/b";
+    assert!(target.len() as u64 > SYMLINK_INLINE_MAX);
+    let free = my_fs.statfs().unwrap().free_blocks;
+    my_fs.symlink(&target, "/long").unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 1);
+    assert_eq!(my_fs.readlink("/long").unwrap(), target);
+    assert_eq!(my_fs.resolve_path("/long").unwrap().get_inum(), b);
+    my_fs.unlink("/long").unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+
+    //Loops and dangling links
+    my_fs.symlink("./self", "/a/self").unwrap();
+    assert!(my_fs.resolve_path("/a/self").is_err());
+    assert!(my_fs.resolve_path("/a/self/b").is_err());
+    assert_eq!(
+        my_fs.lresolve_path("/a/self").unwrap().get_ft(),
+        FType::TSymlink
+    );
+    my_fs.symlink("/nothere", "/dangling").unwrap();
+    assert!(my_fs.resolve_path("/dangling").is_err());
+    assert_eq!(my_fs.readlink("/dangling").unwrap(), "/nothere");
+
+    //Invalid targets and names
+    assert!(my_fs.symlink("", "/x").is_err());
+    assert!(my_fs.symlink("a/", "/x").is_err());
+    assert!(my_fs.symlink("/a", "/l").is_err());
+    assert!(my_fs.symlink("/a", "/a/..").is_err());
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
 //Check that deleting a cyclic reference does not decrease the refcount
@@ -1,6 +1,14 @@
 use super::FSName;
-use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport};
-use cplfs_api::types::{Buffer, FType, InodeLike, SuperBlock};
+use cplfs_api::alloc::AllocStrategy;
+use cplfs_api::clock::{Clock, ManualClock};
+use cplfs_api::file::FileHandle;
+use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport, XattrSupport};
+use cplfs_api::types::{
+    AtimePolicy, Block, BlockNo, Buffer, DInode, DataIdx, DiscardPolicy, Extent, FType,
+    InodeExtents, InodeLike, MountOptions, SuperBlock, Timestamp, FEATURE_EXTENTS,
+    FEATURE_INLINE_DATA, FEATURE_XATTR, INLINE_DATA_MAX, INLINE_EXTENTS,
+};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 
 #[path = "utils.rs"]
@@ -16,6 +24,32 @@ static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
     ndatablocks: 6,
     bmapstart: 4,
     datastart: 5,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
+};
+//The same layout behind a log of 5 blocks, needed to resize in place
+static SUPERBLOCK_LOG: SuperBlock = SuperBlock {
+    block_size: BLOCK_SIZE,
+    nblocks: NBLOCKS + 5,
+    ninodes: 6,
+    inodestart: 6,
+    ndatablocks: 6,
+    bmapstart: 9,
+    datastart: 10,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 1,
+    nlog: 5,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 fn disk_prep_path(name: &str) -> PathBuf {
@@ -29,7 +63,7 @@ fn error_cases() {
 
     //Set up an inode with 3 blocks first
     for i in 0..5 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let b2 = utils::n_block(5, BLOCK_SIZE, 2);
     my_fs.b_put(&b2).unwrap();
@@ -52,11 +86,12 @@ fn error_cases() {
     let mut buf500 = Buffer::new_zero(500);
 
     //Try to perform some operations
-    assert!(my_fs.i_read(&i2, &mut buf500, 751, 0).is_err());
-    assert!(my_fs.i_write(&mut i2, &mut buf500, 751, 0).is_err());
+    assert!(my_fs.i_read(&mut i2, &mut buf500, 751, 0).is_err());
+    assert!(my_fs.i_write(&mut i2, &mut buf500, 751, 0).is_ok());
+    assert_eq!(i2.get_size(), 750);
     assert!(my_fs.i_write(&mut i2, &mut buf500, 750, 0).is_ok());
-    assert_eq!(my_fs.i_read(&i2, &mut buf500, 750, 1).unwrap(), 0);
-    assert!(my_fs.i_read(&i2, &mut buf50, 751, 51).is_err());
+    assert_eq!(my_fs.i_read(&mut i2, &mut buf500, 750, 1).unwrap(), 0);
+    assert!(my_fs.i_read(&mut i2, &mut buf50, 751, 51).is_err());
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
@@ -69,7 +104,7 @@ fn readi() {
 
     //Set up an inode with 3 blocks first
     for i in 0..5 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let b2 = utils::n_block(5, BLOCK_SIZE, 2);
     my_fs.b_put(&b2).unwrap();
@@ -77,7 +112,7 @@ fn readi() {
     my_fs.b_put(&b3).unwrap();
     let b4 = utils::n_block(7, BLOCK_SIZE, 4);
     my_fs.b_put(&b4).unwrap();
-    let i2 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
+    let mut i2 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
         2,
         &FType::TFile,
         0,
@@ -95,7 +130,7 @@ fn readi() {
     let mut read_result = vec![2; 33];
     read_result.append(&mut vec![3; 300]);
     read_result.append(&mut vec![4; 155]);
-    assert_eq!(my_fs.i_read(&i2, &mut buf500, 267, 488).unwrap(), 488);
+    assert_eq!(my_fs.i_read(&mut i2, &mut buf500, 267, 488).unwrap(), 488);
     assert_eq!(
         &buf500.contents_as_ref()[..488].len(),
         &read_result[..].len()
@@ -104,7 +139,7 @@ fn readi() {
 
     let mut read_result_2 = vec![2; 33];
     read_result_2.append(&mut vec![3; 17]);
-    assert_eq!(my_fs.i_read(&i2, &mut buf50, 267, 50).unwrap(), 50);
+    assert_eq!(my_fs.i_read(&mut i2, &mut buf50, 267, 50).unwrap(), 50);
     assert_eq!(&buf50.contents_as_ref()[..], &read_result_2[..]);
 
     let dev = my_fs.unmountfs();
@@ -118,7 +153,7 @@ fn writei() {
 
     //Set up an inode with 3 blocks first
     for i in 0..4 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let b2 = utils::n_block(5, BLOCK_SIZE, 2);
     my_fs.b_put(&b2).unwrap();
@@ -149,7 +184,10 @@ fn writei() {
     assert!(my_fs.i_write(&mut i2, &mut buf50, 703, 50).is_ok());
     assert_eq!(my_fs.i_get(2).unwrap(), i2);
     assert_eq!(753, i2.get_size());
-    assert_eq!(my_fs.b_get(7).unwrap().contents_as_ref(), &write_result[..]);
+    assert_eq!(
+        my_fs.b_get(BlockNo(7)).unwrap().contents_as_ref(),
+        &write_result[..]
+    );
 
     //And one that will require mapping in new blocks
     let mut write_result_2 = vec![6; 52];
@@ -157,13 +195,1281 @@ fn writei() {
     my_fs.i_write(&mut i2, &mut buf500, 753, 499).unwrap();
     assert_eq!(my_fs.i_get(2).unwrap(), i2);
     assert_eq!(1252, i2.get_size());
-    assert_eq!(9, i2.get_block(3));
-    assert_eq!(10, i2.get_block(4));
+    assert_eq!(BlockNo(9), i2.get_block(3));
+    assert_eq!(BlockNo(10), i2.get_block(4));
     assert_eq!(
-        my_fs.b_get(10).unwrap().contents_as_ref(),
+        my_fs.b_get(BlockNo(10)).unwrap().contents_as_ref(),
         &write_result_2[..]
     );
-    my_fs.b_free(4).unwrap();
+    my_fs.b_free(DataIdx(4)).unwrap();
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn grow() {
+    let path = disk_prep_path("grow");
+    //Without a log, moving the bitmap onto a data block in use cannot be done crash-safely
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+    let buf = Buffer::new(vec![1; 10].into_boxed_slice());
+    my_fs.i_write(&mut ino, &buf, 0, buf.len()).unwrap();
+    assert!(my_fs.b_grow(2 * NBLOCKS, 8).is_err());
+    assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+    let dev = my_fs.unmountfs();
+    assert_eq!(dev.nblocks, NBLOCKS);
+    utils::disk_destruct(dev);
+
+    let path = disk_prep_path("grow");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_LOG).unwrap();
+
+    //Write a file spanning 3 blocks
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+    let data: Vec<u8> = (0..(2.5 * (BLOCK_SIZE as f32)) as u64)
+        .map(|b| b as u8)
+        .collect();
+    let buf = Buffer::new(data.clone().into_boxed_slice());
+    my_fs.i_write(&mut ino, &buf, 0, buf.len()).unwrap();
+    assert_eq!(ino.get_block(0), BlockNo(SUPERBLOCK_LOG.datastart));
+
+    //Growing the inode region moves the bitmap onto the first data block, so its contents have to be relocated
+    my_fs.b_grow(2 * NBLOCKS, 8).unwrap();
+    let sb = my_fs.sup_get().unwrap();
+    assert_eq!(sb.ninodes, 8);
+    assert_eq!(sb.bmapstart, SUPERBLOCK_LOG.bmapstart + 1);
+    assert_eq!(sb.datastart, SUPERBLOCK_LOG.datastart + 1);
+    assert_eq!(sb.nblocks, 2 * NBLOCKS);
+    let mut ino = my_fs.i_get(i).unwrap();
+    assert!(ino.get_block(0) >= BlockNo(SUPERBLOCK_LOG.nblocks)); //moved to a block the old file system did not know about
+    assert_eq!(ino.get_block(1), BlockNo(SUPERBLOCK_LOG.datastart + 1));
+    let mut read = Buffer::new_zero(buf.len());
+    assert_eq!(
+        my_fs.i_read(&mut ino, &mut read, 0, buf.len()).unwrap(),
+        buf.len()
+    );
+    assert_eq!(read, buf);
+
+    //The new inodes are free
+    assert_eq!(my_fs.i_get(7).unwrap().get_ft(), FType::TFree);
+    for j in 2..8 {
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), j);
+    }
+    assert!(my_fs.i_alloc(FType::TFile).is_err());
+
+    //Everything is still there after remounting
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+    let mut read = Buffer::new_zero(buf.len());
+    assert_eq!(
+        my_fs.i_read(&mut ino, &mut read, 0, buf.len()).unwrap(),
+        buf.len()
+    );
+    assert_eq!(read.contents_as_ref(), &data[..]);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn shrink() {
+    let path = disk_prep_path("shrink");
+    //Without a log, relocating a block into a hole before the new end cannot be done crash-safely
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+    my_fs.b_alloc().unwrap();
+    let buf = Buffer::new(vec![1; 10].into_boxed_slice());
+    my_fs.i_write(&mut ino, &buf, 0, buf.len()).unwrap();
+    my_fs.b_free(DataIdx(0)).unwrap();
+    assert!(FSName::b_shrink(my_fs.unmountfs(), SUPERBLOCK_GOOD.datastart + 1).is_err());
+    //The copy went to a block that is still free, so only the superblock and the inode could tell, and neither changed
+    let mut my_fs = FSName::mountfs(utils::disk_open(&path, BLOCK_SIZE, NBLOCKS)).unwrap();
+    assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+    let mut ino = my_fs.i_get(i).unwrap();
+    assert_eq!(ino.get_block(0), BlockNo(SUPERBLOCK_GOOD.datastart + 1));
+    let mut read = Buffer::new_zero(buf.len());
+    my_fs.i_read(&mut ino, &mut read, 0, buf.len()).unwrap();
+    assert_eq!(read, buf);
+    utils::disk_destruct(my_fs.unmountfs());
+
+    let path = disk_prep_path("shrink");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_LOG).unwrap();
+
+    //One file of a single block, followed by a file spanning 3 blocks
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    let buf = Buffer::new(vec![1; 10].into_boxed_slice());
+    my_fs.i_write(&mut ino1, &buf, 0, buf.len()).unwrap();
+    let i2 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino2 = my_fs.i_get(i2).unwrap();
+    let data: Vec<u8> = (0..(2.5 * (BLOCK_SIZE as f32)) as u64)
+        .map(|b| b as u8)
+        .collect();
+    let buf = Buffer::new(data.clone().into_boxed_slice());
+    my_fs.i_write(&mut ino2, &buf, 0, buf.len()).unwrap();
+    assert_eq!(ino2.get_block(2), BlockNo(SUPERBLOCK_LOG.datastart + 3));
+    my_fs.i_trunc(&mut ino1).unwrap();
+
+    //The data does not fit in 2 blocks
+    let dev = my_fs.unmountfs();
+    let image = std::fs::read(&path).unwrap();
+    assert!(FSName::b_shrink(dev, SUPERBLOCK_LOG.datastart + 2).is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), image);
+
+    //The last block of the second file gets moved to the free first data block
+    let dev = utils::disk_open(&path, BLOCK_SIZE, SUPERBLOCK_LOG.nblocks);
+    let dev = FSName::b_shrink(dev, SUPERBLOCK_LOG.datastart + 3).unwrap();
+    assert_eq!(dev.nblocks, SUPERBLOCK_LOG.datastart + 3);
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    let sb = my_fs.sup_get().unwrap();
+    assert_eq!(sb.ndatablocks, 3);
+    assert_eq!(sb.datastart, SUPERBLOCK_LOG.datastart);
+    let mut ino2 = my_fs.i_get(i2).unwrap();
+    assert_eq!(ino2.get_block(2), BlockNo(SUPERBLOCK_LOG.datastart));
+    let mut read = Buffer::new_zero(buf.len());
+    assert_eq!(
+        my_fs.i_read(&mut ino2, &mut read, 0, buf.len()).unwrap(),
+        buf.len()
+    );
+    assert_eq!(read.contents_as_ref(), &data[..]);
+    assert!(my_fs.b_alloc().is_err());
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn block_groups() {
+    let path = disk_prep_path("block_groups");
+    //3 groups of 6 blocks, each with a bitmap block, an inode block holding 2 inodes and 4 data blocks
+    let sb_groups = SuperBlock {
+        nblocks: 20,
+        ninodes: 6,
+        inodestart: 3,
+        ndatablocks: 12,
+        bmapstart: 2,
+        datastart: 4,
+        ngroups: 3,
+        groupsize: 6,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb_groups).unwrap();
+
+    //Inodes are allocated in the group of their parent, if possible
+    assert_eq!(my_fs.i_alloc_near(FType::TFile, 3).unwrap(), 2);
+    assert_eq!(my_fs.i_alloc_near(FType::TDir, 5).unwrap(), 4);
+    assert_eq!(my_fs.i_alloc_near(FType::TFile, 2).unwrap(), 3);
+    assert_eq!(my_fs.i_alloc_near(FType::TFile, 3).unwrap(), 5); //Group 1 is full
+    assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 1);
+    assert!(my_fs.i_alloc_near(FType::TFile, 6).is_err());
+    assert!(my_fs.i_alloc_near(FType::TFile, 3).is_err());
+
+    //Data is allocated in the group of the inode, if possible
+    let data: Vec<u8> = (0..(2.5 * (BLOCK_SIZE as f32)) as u64)
+        .map(|b| b as u8)
+        .collect();
+    let buf = Buffer::new(data.clone().into_boxed_slice());
+    let mut ino = my_fs.i_get(2).unwrap();
+    my_fs.i_write(&mut ino, &buf, 0, buf.len()).unwrap();
+    assert_eq!(ino.get_block(0), BlockNo(10));
+    assert_eq!(ino.get_block(2), BlockNo(12));
+    let mut ino = my_fs.i_get(4).unwrap();
+    my_fs.i_write(&mut ino, &buf, 0, 2 * BLOCK_SIZE).unwrap();
+    assert_eq!(ino.get_block(0), BlockNo(16));
+    assert_eq!(ino.get_block(1), BlockNo(17));
+    let mut ino = my_fs.i_get(3).unwrap();
+    my_fs.i_write(&mut ino, &buf, 0, 2 * BLOCK_SIZE).unwrap();
+    assert_eq!(ino.get_block(0), BlockNo(13));
+    assert_eq!(ino.get_block(1), BlockNo(18)); //Group 1 is full
+
+    //Everything is still there after remounting, and the inode is stored in its group
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    let mut ino = my_fs.i_get(2).unwrap();
+    assert_eq!(
+        my_fs
+            .b_get(BlockNo(9))
+            .unwrap()
+            .deserialize_from::<DInode>(0)
+            .unwrap(),
+        ino.disk_node
+    );
+    let mut read = Buffer::new_zero(buf.len());
+    assert_eq!(
+        my_fs.i_read(&mut ino, &mut read, 0, buf.len()).unwrap(),
+        buf.len()
+    );
+    assert_eq!(read.contents_as_ref(), &data[..]);
+
+    //Freeing an inode frees its blocks in its own group
+    my_fs.i_free(2).unwrap();
+    assert_eq!(my_fs.b_alloc_near(DataIdx(5)).unwrap(), DataIdx(5));
+    assert_eq!(my_fs.b_alloc_near(DataIdx(5)).unwrap(), DataIdx(6));
+    assert_eq!(my_fs.b_alloc_near(DataIdx(5)).unwrap(), DataIdx(4));
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn journal() {
+    let path = disk_prep_path("journal");
+    //A log that holds only 3 blocks
+    let sb_log = SuperBlock {
+        nblocks: NBLOCKS + 4,
+        inodestart: 5,
+        bmapstart: 8,
+        datastart: 9,
+        logstart: 1,
+        nlog: 4,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb_log).unwrap();
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+
+    //Writing 4 blocks at once does not fit in the log, so the write is split up in operations that do
+    let contents: Vec<u8> = (0..4 * BLOCK_SIZE).map(|k| (k % 251) as u8).collect();
+    let buf = Buffer::new(contents.into_boxed_slice());
+    my_fs.i_write(&mut ino, &buf, 10, buf.len()).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+    assert_eq!(ino.get_size(), buf.len() + 10);
+    let mut read = Buffer::new_zero(buf.len());
+    my_fs.i_read(&mut ino, &mut read, 10, buf.len()).unwrap();
+    assert_eq!(read, buf);
+
+    //Running out of space halfway keeps the operations that were committed already
+    let big = Buffer::new(vec![7; 8 * BLOCK_SIZE as usize].into_boxed_slice());
+    assert!(my_fs.i_write(&mut ino, &big, 0, big.len()).is_err());
+    assert_eq!(ino.get_size(), 6 * BLOCK_SIZE);
+    assert_eq!(my_fs.i_get(i).unwrap().get_size(), 6 * BLOCK_SIZE);
+    let mut read = Buffer::new_zero(6 * BLOCK_SIZE);
+    my_fs
+        .i_read(&mut ino, &mut read, 0, 6 * BLOCK_SIZE)
+        .unwrap();
+    assert!(read.contents_as_ref().iter().all(|&b| b == 7));
+
+    //Every operation cleared the log header again
+    let dev = my_fs.unmountfs();
+    assert_eq!(dev.read_block(1).unwrap(), utils::zero_block(1, BLOCK_SIZE));
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn discard() {
+    let path = disk_prep_path("discard");
+    let my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let opts = MountOptions {
+        discard: DiscardPolicy::PunchHole,
+        ..Default::default()
+    };
+    let mut my_fs = FSName::mountfs_with(my_fs.unmountfs(), &opts).unwrap();
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+    let buf = Buffer::new(vec![3; 2 * BLOCK_SIZE as usize].into_boxed_slice());
+    my_fs.i_write(&mut ino, &buf, 0, buf.len()).unwrap();
+    let blocks = [ino.get_block(0), ino.get_block(1)];
+
+    //Truncating the file no longer leaves its contents readable in the image
+    my_fs.i_trunc(&mut ino).unwrap();
+    let dev = my_fs.unmountfs();
+    for b in blocks.iter() {
+        assert_eq!(
+            dev.read_block(b.0).unwrap(),
+            utils::zero_block(b.0, BLOCK_SIZE)
+        );
+    }
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn bad_blocks() {
+    let path = disk_prep_path("bad_blocks");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+    let mut contents = vec![1; BLOCK_SIZE as usize];
+    contents.append(&mut vec![2; BLOCK_SIZE as usize]);
+    let buf = Buffer::new(contents.into_boxed_slice());
+    my_fs.i_write(&mut ino, &buf, 0, buf.len()).unwrap();
+    assert_eq!(ino.get_block(0), BlockNo(5));
+
+    //The data of the file moves off the bad block, and the list goes in the next free block
+    my_fs.b_mark_bad(BlockNo(5)).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+    assert_eq!(ino.get_block(0), BlockNo(7));
+    assert_eq!(ino.get_block(1), BlockNo(6));
+    assert_eq!(my_fs.sup_get().unwrap().badlist, 8);
+    let mut read = Buffer::new_zero(buf.len());
+    my_fs.i_read(&mut ino, &mut read, 0, buf.len()).unwrap();
+    assert_eq!(read, buf);
+
+    //Freeing the file leaves the bad block alone
+    let mut ino = ino;
+    my_fs.i_trunc(&mut ino).unwrap();
+    assert_eq!(my_fs.b_bad_blocks().unwrap(), vec![BlockNo(5)]);
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(1));
+
+    //An image in which a file still refers to a bad block does not mount
+    let broken =
+        <<FSName as InodeSupport>::Inode as InodeLike>::new(i, &FType::TFile, 1, BLOCK_SIZE, &[5])
+            .unwrap();
+    my_fs.i_put(&broken).unwrap();
+    let dev = my_fs.unmountfs();
+    assert!(FSName::mountfs(dev).is_err());
+    utils::disk_destruct(utils::disk_open(&path, BLOCK_SIZE, NBLOCKS));
+}
+
+#[test]
+fn transaction() {
+    let path = disk_prep_path("transaction");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+
+    //A write whose end does not fit in 64 bits is rejected up front
+    let buf = Buffer::new(vec![1; 10].into_boxed_slice());
+    assert!(my_fs.i_write(&mut ino, &buf, u64::MAX, buf.len()).is_err());
+    assert_eq!(ino.get_size(), 0);
+    //and so is one that asks for more bytes than the buffer holds
+    assert!(my_fs.i_write(&mut ino, &buf, 0, 5 * buf.len()).is_err());
+    assert_eq!(ino.get_size(), 0);
+
+    //A write that runs out of blocks halfway does not happen at all
+    let buf = Buffer::new(vec![1; 7 * BLOCK_SIZE as usize].into_boxed_slice());
+    assert!(my_fs.i_write(&mut ino, &buf, 0, buf.len()).is_err());
+    assert_eq!(ino.get_size(), 0);
+    assert_eq!(my_fs.i_get(i).unwrap(), ino);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, 6);
+    assert_eq!(
+        my_fs.b_get(BlockNo(5)).unwrap(),
+        utils::zero_block(5, BLOCK_SIZE)
+    );
+
+    //Layers above the block layer can group their own operations as well
+    let mut tx = my_fs.transaction().unwrap();
+    tx.i_write(&mut ino, &buf, 0, BLOCK_SIZE).unwrap();
+    drop(tx);
+    assert_eq!(my_fs.i_get(i).unwrap().get_size(), 0);
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(0));
+
+    //A truncation that fails halfway does not happen either, and leaves the inode in memory alone
+    let mut broken = <<FSName as InodeSupport>::Inode as InodeLike>::new(
+        i,
+        &FType::TFile,
+        0,
+        2 * BLOCK_SIZE,
+        &[5, 1000],
+    )
+    .unwrap();
+    my_fs.i_put(&broken).unwrap();
+    assert!(my_fs.i_trunc(&mut broken).is_err());
+    assert_eq!(broken.get_size(), 2 * BLOCK_SIZE);
+    assert_eq!(broken.get_block(0), BlockNo(5));
+    assert_eq!(my_fs.i_get(i).unwrap(), broken);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, 5);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn alloc_policies() {
+    let path = disk_prep_path("alloc_policies");
+    let dev = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap().unmountfs();
+    let opts = MountOptions {
+        alloc: AllocStrategy::NextFit,
+        ..Default::default()
+    };
+    let mut my_fs = FSName::mountfs_with(dev, &opts).unwrap();
+
+    //Inodes get the policy as well, and inode 0 is still never allocated
+    assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 1);
+    assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 2);
+    my_fs.i_free(1).unwrap();
+    assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 3);
+    for i in [4, 5, 1] {
+        assert_eq!(my_fs.i_alloc_near(FType::TDir, 1).unwrap(), i);
+    }
+    assert!(my_fs.i_alloc(FType::TFile).is_err());
+
+    //Blocks and inodes keep track of their own position
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(0));
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn extent_summary() {
+    let path = disk_prep_path("extent_summary");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let i2 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i1).unwrap();
+    let buf = Buffer::new(vec![1; BLOCK_SIZE as usize].into_boxed_slice());
+
+    //Blocks 1, 3 and 4, since the others are taken in between
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(0));
+    my_fs.i_write(&mut ino, &buf, 0, BLOCK_SIZE).unwrap();
+    assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(2));
+    my_fs
+        .i_write(&mut ino, &buf, BLOCK_SIZE, BLOCK_SIZE)
+        .unwrap();
+    my_fs
+        .i_write(&mut ino, &buf, 2 * BLOCK_SIZE, BLOCK_SIZE)
+        .unwrap();
+
+    assert_eq!(
+        my_fs.i_extent_summary().unwrap(),
+        vec![
+            InodeExtents {
+                inum: i1,
+                blocks: 3,
+                extents: 2
+            },
+            InodeExtents {
+                inum: i2,
+                blocks: 0,
+                extents: 0
+            }
+        ]
+    );
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn timestamps() {
+    let path = disk_prep_path("timestamps");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let clock = ManualClock::new(Timestamp::from_secs(100));
+    my_fs.i_set_clock(Box::new(clock.clone()));
+    let times = |ino: &<FSName as InodeSupport>::Inode| {
+        (
+            ino.get_atime(),
+            ino.get_mtime(),
+            ino.get_ctime(),
+            ino.get_crtime(),
+        )
+    };
+
+    //A new inode gets the same time everywhere
+    let t0 = clock.now();
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i).unwrap();
+    assert_eq!(times(&ino), (t0, t0, t0, t0));
+
+    //Writing changes the contents, and therefore the inode as well
+    clock.advance(5);
+    let t1 = clock.now();
+    let buf = Buffer::new(vec![1; 10].into_boxed_slice());
+    my_fs.i_write(&mut ino, &buf, 0, 10).unwrap();
+    assert_eq!(times(&ino), (t0, t1, t1, t0));
+    assert_eq!(my_fs.i_get(i).unwrap(), ino);
+
+    //With relatime, only the first read after a change updates the access time...
+    let mut read = Buffer::new_zero(10);
+    clock.advance(5);
+    let t2 = clock.now();
+    my_fs.i_read(&mut ino, &mut read, 0, 10).unwrap();
+    clock.advance(5);
+    my_fs.i_read(&mut ino, &mut read, 0, 10).unwrap();
+    assert_eq!(times(&ino), (t2, t1, t1, t0));
+    assert_eq!(my_fs.i_get(i).unwrap(), ino);
+
+    //...unless the last access was long ago
+    clock.advance(AtimePolicy::RELATIME_INTERVAL);
+    my_fs.i_read(&mut ino, &mut read, 0, 10).unwrap();
+    assert_eq!(ino.get_atime(), clock.now());
+
+    //Reads never update the access time with noatime, even after a change, and always with strict
+    clock.advance(5);
+    my_fs.i_write(&mut ino, &buf, 0, 10).unwrap();
+    for (atime, updates) in [(AtimePolicy::Noatime, false), (AtimePolicy::Strict, true)] {
+        let dev = my_fs.unmountfs();
+        let opts = MountOptions {
+            atime,
+            ..Default::default()
+        };
+        my_fs = FSName::mountfs_with(dev, &opts).unwrap();
+        my_fs.i_set_clock(Box::new(clock.clone()));
+        let mut ino = my_fs.i_get(i).unwrap();
+        let before = ino.get_atime();
+        for _ in 0..2 {
+            clock.advance(5);
+            my_fs.i_read(&mut ino, &mut read, 0, 10).unwrap();
+            let expected = if updates { clock.now() } else { before };
+            assert_eq!(ino.get_atime(), expected);
+            assert_eq!(my_fs.i_get(i).unwrap(), ino);
+        }
+    }
+
+    //Truncating changes both the contents and the inode
+    let mut ino = my_fs.i_get(i).unwrap();
+    clock.advance(5);
+    my_fs.i_trunc(&mut ino).unwrap();
+    assert_eq!(ino.get_mtime(), clock.now());
+    assert_eq!(ino.get_ctime(), clock.now());
+    assert_eq!(ino.get_crtime(), t0);
+    assert_eq!(my_fs.i_get(i).unwrap(), ino);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn sparse() {
+    let path = disk_prep_path("sparse");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino = my_fs.i_get(i1).unwrap();
+    let free = my_fs.statfs().unwrap().free_blocks;
+
+    //Writing past the end leaves a hole of two blocks, which takes up no space
+    let buf = Buffer::new(vec![7; 10].into_boxed_slice());
+    my_fs
+        .i_write(&mut ino, &buf, 2 * BLOCK_SIZE + 5, 10)
+        .unwrap();
+    let size = 2 * BLOCK_SIZE + 15;
+    assert_eq!(ino.get_size(), size);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 1);
+    assert_eq!(
+        (ino.get_block(0), ino.get_block(1)),
+        (BlockNo(0), BlockNo(0))
+    );
+    assert_eq!(my_fs.i_get(ino.get_inum()).unwrap(), ino);
+
+    //Holes read as zeroes
+    let mut all = Buffer::new_zero(size);
+    assert_eq!(my_fs.i_read(&mut ino, &mut all, 0, size).unwrap(), size);
+    let mut expected = vec![0; size as usize];
+    expected[2 * BLOCK_SIZE as usize + 5..].copy_from_slice(&[7; 10]);
+    assert_eq!(all.contents_as_ref(), &expected[..]);
+
+    //Looking for data and holes
+    assert_eq!(my_fs.i_seek_data(&ino, 0).unwrap(), Some(2 * BLOCK_SIZE));
+    assert_eq!(my_fs.i_seek_data(&ino, size - 1).unwrap(), Some(size - 1));
+    assert_eq!(my_fs.i_seek_data(&ino, size).unwrap(), None);
+    assert_eq!(my_fs.i_seek_hole(&ino, 5).unwrap(), Some(5));
+    assert_eq!(my_fs.i_seek_hole(&ino, 2 * BLOCK_SIZE).unwrap(), Some(size));
+    assert_eq!(my_fs.i_seek_hole(&ino, size).unwrap(), None);
+
+    //Writing into a hole only fills the blocks written to
+    my_fs.i_write(&mut ino, &buf, BLOCK_SIZE + 1, 1).unwrap();
+    assert_eq!(ino.get_size(), size);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 2);
+    assert_eq!(my_fs.i_seek_data(&ino, 0).unwrap(), Some(BLOCK_SIZE));
+    assert_eq!(my_fs.i_seek_hole(&ino, BLOCK_SIZE).unwrap(), Some(size));
+    let mut two = Buffer::new_zero(2);
+    my_fs.i_read(&mut ino, &mut two, BLOCK_SIZE, 2).unwrap();
+    assert_eq!(two.contents_as_ref(), &[0, 7]);
+    assert_eq!(my_fs.i_extent_summary().unwrap()[0].blocks, 2);
+
+    //Truncating frees the blocks that are there
+    my_fs.i_trunc(&mut ino).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn extents() {
+    let path = disk_prep_path("extents");
+    let sb = SuperBlock {
+        nblocks: 60,
+        ndatablocks: 55,
+        features: FEATURE_EXTENTS,
+        ..SUPERBLOCK_GOOD
+    };
+    assert!(!FSName::sb_valid(&SuperBlock {
+        features: 1 << 63,
+        ..sb
+    }));
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    assert_eq!(my_fs.i_max_size(), u32::MAX as u64 * BLOCK_SIZE);
+    let free = my_fs.statfs().unwrap().free_blocks;
+
+    //A file larger than the direct pointers allow, in a single extent
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    let data: Vec<u8> = (0..20 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+    let big = Buffer::new(data.clone().into_boxed_slice());
+    my_fs.i_write(&mut ino1, &big, 0, 20 * BLOCK_SIZE).unwrap();
+    assert_eq!(ino1.disk_node.extent_header(), (1, 0));
+    assert_eq!(
+        ino1.disk_node.inline_extents(),
+        vec![Extent {
+            start: 0,
+            len: 20,
+            physical: sb.datastart
+        }]
+    );
+
+    //Two files growing in turns each get an extent per block, until their extents spill into an extent block
+    let i2 = my_fs.i_alloc(FType::TFile).unwrap();
+    let i3 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino2 = my_fs.i_get(i2).unwrap();
+    let mut ino3 = my_fs.i_get(i3).unwrap();
+    let rounds = INLINE_EXTENTS as u64 + 2;
+    for k in 0..rounds {
+        let buf = Buffer::new(vec![k as u8 + 1; BLOCK_SIZE as usize].into_boxed_slice());
+        my_fs
+            .i_write(&mut ino2, &buf, k * BLOCK_SIZE, BLOCK_SIZE)
+            .unwrap();
+        my_fs
+            .i_write(&mut ino3, &buf, k * BLOCK_SIZE, BLOCK_SIZE)
+            .unwrap();
+    }
+    let (n, block) = ino2.disk_node.extent_header();
+    assert_eq!(n, rounds);
+    assert_ne!(block, 0);
+    assert_eq!(
+        my_fs.statfs().unwrap().free_blocks,
+        free - 20 - 2 * rounds - 2
+    );
+    assert_eq!(my_fs.i_get(i2).unwrap(), ino2);
+
+    //Everything reads back after remounting
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    let mut ino2 = my_fs.i_get(i2).unwrap();
+    let mut read = Buffer::new_zero(20 * BLOCK_SIZE);
+    assert_eq!(
+        my_fs
+            .i_read(&mut ino1, &mut read, 0, 20 * BLOCK_SIZE)
+            .unwrap(),
+        20 * BLOCK_SIZE
+    );
+    assert_eq!(read.contents_as_ref(), &data[..]);
+    let mut one = Buffer::new_zero(1);
+    for k in 0..rounds {
+        my_fs
+            .i_read(&mut ino2, &mut one, k * BLOCK_SIZE + 7, 1)
+            .unwrap();
+        assert_eq!(one.contents_as_ref(), &[k as u8 + 1]);
+    }
+    let summary = my_fs.i_extent_summary().unwrap();
+    assert_eq!((summary[0].blocks, summary[0].extents), (20, 1));
+    assert_eq!((summary[1].blocks, summary[1].extents), (rounds, rounds));
+
+    //Holes far into the file take no room
+    let buf = Buffer::new(vec![9; 2].into_boxed_slice());
+    let far = 1000 * BLOCK_SIZE;
+    my_fs.i_write(&mut ino1, &buf, far, 2).unwrap();
+    assert_eq!(ino1.get_size(), far + 2);
+    assert_eq!(ino1.disk_node.extent_header(), (2, 0));
+    assert_eq!(
+        my_fs.i_seek_data(&ino1, 20 * BLOCK_SIZE).unwrap(),
+        Some(far)
+    );
+    assert_eq!(my_fs.i_seek_hole(&ino1, 0).unwrap(), Some(20 * BLOCK_SIZE));
+
+    //Moving a block of a file with an extent block updates its extents
+    let ino3 = my_fs.i_get(i3).unwrap();
+    let (_, block) = ino3.disk_node.extent_header();
+    let extents: Vec<Extent> = my_fs
+        .b_get(BlockNo(block))
+        .unwrap()
+        .deserialize_from(0)
+        .unwrap();
+    my_fs.b_mark_bad(BlockNo(extents[1].physical)).unwrap();
+    let mut ino3 = my_fs.i_get(i3).unwrap();
+    my_fs
+        .i_read(&mut ino3, &mut one, BLOCK_SIZE + 7, 1)
+        .unwrap();
+    assert_eq!(one.contents_as_ref(), &[2]);
+    assert_eq!(ino3.disk_node.extent_header(), (rounds, block));
+
+    //Truncating frees the data blocks and the extent block
+    my_fs.i_trunc(&mut ino1).unwrap();
+    my_fs.i_trunc(&mut ino2).unwrap();
+    assert_eq!(ino2.disk_node.extent_header(), (0, 0));
+    //the bad block and the bad-block list stay in use
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - rounds - 1 - 2);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn inline_data() {
+    let path = disk_prep_path("inline_data");
+    let sb = SuperBlock {
+        features: FEATURE_INLINE_DATA,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    let free = my_fs.statfs().unwrap().free_blocks;
+
+    //A small write stays in the inode
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    assert!(ino1.disk_node.is_inline());
+    let data: Vec<u8> = (0..BLOCK_SIZE + 10).map(|i| (i % 251) as u8).collect();
+    let small = Buffer::new(data[..20].to_vec().into_boxed_slice());
+    my_fs.i_write(&mut ino1, &small, 5, 20).unwrap();
+    assert_eq!(ino1.get_size(), 25);
+    assert!(ino1.disk_node.is_inline());
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+    assert_eq!(my_fs.i_get(i1).unwrap(), ino1);
+    let mut read = Buffer::new_zero(25);
+    assert_eq!(my_fs.i_read(&mut ino1, &mut read, 0, 25).unwrap(), 25);
+    assert_eq!(&read.contents_as_ref()[..5], &[0; 5]);
+    assert_eq!(&read.contents_as_ref()[5..], &data[..20]);
+    assert_eq!(my_fs.i_seek_data(&ino1, 0).unwrap(), Some(0));
+    assert_eq!(my_fs.i_seek_hole(&ino1, 0).unwrap(), Some(25));
+
+    //Filling the inline area exactly still fits
+    let buf = Buffer::new(data[..INLINE_DATA_MAX as usize].to_vec().into_boxed_slice());
+    my_fs.i_write(&mut ino1, &buf, 0, INLINE_DATA_MAX).unwrap();
+    assert!(ino1.disk_node.is_inline());
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+
+    //Growing past it moves the contents out to data blocks
+    let n = BLOCK_SIZE + 10;
+    let rest = Buffer::new(data[INLINE_DATA_MAX as usize..].to_vec().into_boxed_slice());
+    my_fs
+        .i_write(&mut ino1, &rest, INLINE_DATA_MAX, n - INLINE_DATA_MAX)
+        .unwrap();
+    assert!(!ino1.disk_node.is_inline());
+    assert_eq!(ino1.get_size(), n);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 2);
+
+    //The contents survive a remount
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    let mut read = Buffer::new_zero(n);
+    assert_eq!(my_fs.i_read(&mut ino1, &mut read, 0, n).unwrap(), n);
+    assert_eq!(read.contents_as_ref(), &data[..]);
+
+    //Truncating frees the blocks and makes the file inline again
+    my_fs.i_trunc(&mut ino1).unwrap();
+    assert!(ino1.disk_node.is_inline());
+    assert_eq!(ino1.get_size(), 0);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+    my_fs.i_write(&mut ino1, &small, 0, 20).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+
+    //Without the feature, files never start inline
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+    let path = disk_prep_path("inline_data_off");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    assert!(!ino1.disk_node.is_inline());
+    my_fs.i_write(&mut ino1, &small, 0, 20).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 1);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn truncate() {
+    let path = disk_prep_path("truncate");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let clock = ManualClock::new(Timestamp::from_secs(10));
+    my_fs.i_set_clock(Box::new(clock.clone()));
+    let free = my_fs.statfs().unwrap().free_blocks;
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    let n = 2 * BLOCK_SIZE + 150;
+    let data: Vec<u8> = (0..n).map(|i| (i % 251) as u8 + 1).collect();
+    let buf = Buffer::new(data.clone().into_boxed_slice());
+    my_fs.i_write(&mut ino1, &buf, 0, n).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 3);
+
+    //Shrinking frees the blocks past the new end only
+    clock.advance(5 * Timestamp::NANOS_PER_SEC);
+    my_fs.i_truncate(&mut ino1, BLOCK_SIZE + 50).unwrap();
+    assert_eq!(ino1.get_size(), BLOCK_SIZE + 50);
+    assert_eq!(ino1.disk_node.mtime, Timestamp::from_secs(15));
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 2);
+    assert_eq!(my_fs.i_get(i1).unwrap(), ino1);
+
+    //Extending leaves a hole, and the cut-off bytes do not come back
+    my_fs.i_truncate(&mut ino1, n).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 2);
+    let mut read = Buffer::new_zero(n);
+    assert_eq!(my_fs.i_read(&mut ino1, &mut read, 0, n).unwrap(), n);
+    let keep = (BLOCK_SIZE + 50) as usize;
+    assert_eq!(&read.contents_as_ref()[..keep], &data[..keep]);
+    assert!(read.contents_as_ref()[keep..].iter().all(|&b| b == 0));
+    assert_eq!(my_fs.i_seek_hole(&ino1, 0).unwrap(), Some(2 * BLOCK_SIZE));
+
+    //Truncating to the same size changes nothing, not even the timestamps
+    clock.advance(5 * Timestamp::NANOS_PER_SEC);
+    my_fs.i_truncate(&mut ino1, n).unwrap();
+    assert_eq!(ino1.disk_node.mtime, Timestamp::from_secs(15));
+
+    //Errors leave the inode as it was
+    assert!(my_fs.i_truncate(&mut ino1, my_fs.i_max_size() + 1).is_err());
+    assert_eq!(my_fs.i_get(i1).unwrap(), ino1);
+    let d = my_fs.i_alloc(FType::TDir).unwrap();
+    let mut dir = my_fs.i_get(d).unwrap();
+    assert!(my_fs.i_truncate(&mut dir, 10).is_err());
+    my_fs.i_truncate(&mut ino1, 0).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+
+    //Inline contents grow and shrink in the inode, and move out once they outgrow it
+    let path = disk_prep_path("truncate_inline");
+    let sb = SuperBlock {
+        features: FEATURE_INLINE_DATA,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    let i2 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino2 = my_fs.i_get(i2).unwrap();
+    let small = Buffer::new(data[..20].to_vec().into_boxed_slice());
+    my_fs.i_write(&mut ino2, &small, 0, 20).unwrap();
+    my_fs.i_truncate(&mut ino2, 10).unwrap();
+    my_fs.i_truncate(&mut ino2, 30).unwrap();
+    assert!(ino2.disk_node.is_inline());
+    let mut read = Buffer::new_zero(30);
+    my_fs.i_read(&mut ino2, &mut read, 0, 30).unwrap();
+    assert_eq!(&read.contents_as_ref()[..10], &data[..10]);
+    assert_eq!(&read.contents_as_ref()[10..], &[0; 20]);
+    my_fs.i_truncate(&mut ino2, 2 * BLOCK_SIZE).unwrap();
+    assert!(!ino2.disk_node.is_inline());
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 1);
+    let mut read = Buffer::new_zero(2 * BLOCK_SIZE);
+    my_fs
+        .i_read(&mut ino2, &mut read, 0, 2 * BLOCK_SIZE)
+        .unwrap();
+    assert_eq!(&read.contents_as_ref()[..10], &data[..10]);
+    assert!(read.contents_as_ref()[10..].iter().all(|&b| b == 0));
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+
+    //Extents are cut at the new end
+    let path = disk_prep_path("truncate_extents");
+    let sb = SuperBlock {
+        nblocks: 20,
+        ndatablocks: 15,
+        features: FEATURE_EXTENTS,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    let free = my_fs.statfs().unwrap().free_blocks;
+    let i3 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino3 = my_fs.i_get(i3).unwrap();
+    my_fs.i_write(&mut ino3, &buf, 0, n).unwrap();
+    my_fs.i_truncate(&mut ino3, BLOCK_SIZE + 1).unwrap();
+    assert_eq!(
+        ino3.disk_node.inline_extents(),
+        vec![Extent {
+            start: 0,
+            len: 2,
+            physical: sb.datastart
+        }]
+    );
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 2);
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+//Space can be reserved up front, and released or zeroed in the middle of a file
+#[test]
+fn fallocate() {
+    let path = disk_prep_path("fallocate");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let clock = ManualClock::new(Timestamp::from_secs(10));
+    my_fs.i_set_clock(Box::new(clock.clone()));
+    let free = my_fs.statfs().unwrap().free_blocks;
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    let n = 2 * BLOCK_SIZE + 150;
+    let data: Vec<u8> = (0..n).map(|i| (i % 251) as u8 + 1).collect();
+    let buf = Buffer::new(data.clone().into_boxed_slice());
+    my_fs.i_write(&mut ino1, &buf, 0, 50).unwrap();
+
+    //Preallocating while keeping the size takes blocks past the end, which truncation releases
+    clock.advance(5 * Timestamp::NANOS_PER_SEC);
+    my_fs
+        .i_fallocate(&mut ino1, 0, 3 * BLOCK_SIZE, true)
+        .unwrap();
+    assert_eq!(ino1.get_size(), 50);
+    assert_eq!(ino1.disk_node.mtime, Timestamp::from_secs(15));
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 3);
+    assert_eq!(my_fs.i_get(i1).unwrap(), ino1);
+    let mut read = Buffer::new_zero(50);
+    my_fs.i_read(&mut ino1, &mut read, 0, 50).unwrap();
+    assert_eq!(read.contents_as_ref(), &data[..50]);
+    my_fs.i_trunc(&mut ino1).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+
+    //Punching a hole frees the blocks entirely inside it and zeroes its edges, keeping the size
+    my_fs.i_write(&mut ino1, &buf, 0, n).unwrap();
+    my_fs
+        .i_punch_hole(&mut ino1, BLOCK_SIZE / 2, 2 * BLOCK_SIZE)
+        .unwrap();
+    assert_eq!(ino1.get_size(), n);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 2);
+    assert_eq!(my_fs.i_seek_hole(&ino1, 0).unwrap(), Some(BLOCK_SIZE));
+    let (lo, hi) = ((BLOCK_SIZE / 2) as usize, (5 * BLOCK_SIZE / 2) as usize);
+    let mut read = Buffer::new_zero(n);
+    my_fs.i_read(&mut ino1, &mut read, 0, n).unwrap();
+    assert_eq!(&read.contents_as_ref()[..lo], &data[..lo]);
+    assert!(read.contents_as_ref()[lo..hi].iter().all(|&b| b == 0));
+    assert_eq!(&read.contents_as_ref()[hi..], &data[hi..]);
+
+    //Zeroing the range fills the hole again
+    my_fs
+        .i_zero_range(&mut ino1, BLOCK_SIZE, BLOCK_SIZE, false)
+        .unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 3);
+    assert_eq!(my_fs.i_seek_hole(&ino1, 0).unwrap(), Some(n));
+    let mut read2 = Buffer::new_zero(n);
+    my_fs.i_read(&mut ino1, &mut read2, 0, n).unwrap();
+    assert_eq!(read2.contents_as_ref(), read.contents_as_ref());
+
+    //Preallocating past the end grows the file with zeroes
+    my_fs.i_fallocate(&mut ino1, n, 100, false).unwrap();
+    assert_eq!(ino1.get_size(), n + 100);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 3);
+    let mut read = Buffer::new_zero(100);
+    my_fs.i_read(&mut ino1, &mut read, n, 100).unwrap();
+    assert_eq!(read.contents_as_ref(), &[0; 100]);
+
+    //Errors leave the inode as it was
+    assert!(my_fs
+        .i_fallocate(&mut ino1, 0, my_fs.i_max_size() + 1, false)
+        .is_err());
+    assert!(my_fs
+        .i_zero_range(&mut ino1, my_fs.i_max_size(), 1, true)
+        .is_err());
+    assert_eq!(my_fs.i_get(i1).unwrap(), ino1);
+    let d = my_fs.i_alloc(FType::TDir).unwrap();
+    let mut dir = my_fs.i_get(d).unwrap();
+    assert!(my_fs.i_fallocate(&mut dir, 0, 10, false).is_err());
+    assert!(my_fs.i_punch_hole(&mut dir, 0, 10).is_err());
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+
+    //Inline contents are zeroed in the inode, and move out once the range outgrows it
+    let path = disk_prep_path("fallocate_inline");
+    let sb = SuperBlock {
+        features: FEATURE_INLINE_DATA,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    let i2 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino2 = my_fs.i_get(i2).unwrap();
+    my_fs.i_write(&mut ino2, &buf, 0, 20).unwrap();
+    my_fs.i_zero_range(&mut ino2, 5, 10, false).unwrap();
+    my_fs.i_punch_hole(&mut ino2, 18, 100).unwrap();
+    assert!(ino2.disk_node.is_inline());
+    assert_eq!(ino2.get_size(), 20);
+    my_fs
+        .i_fallocate(&mut ino2, 0, 2 * BLOCK_SIZE, false)
+        .unwrap();
+    assert!(!ino2.disk_node.is_inline());
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 2);
+    let mut read = Buffer::new_zero(2 * BLOCK_SIZE);
+    my_fs
+        .i_read(&mut ino2, &mut read, 0, 2 * BLOCK_SIZE)
+        .unwrap();
+    let mut expected = data[..20].to_vec();
+    expected[5..15].fill(0);
+    expected[18..].fill(0);
+    expected.resize(2 * BLOCK_SIZE as usize, 0);
+    assert_eq!(read.contents_as_ref(), &expected[..]);
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+
+    //Punching a hole splits an extent, and preallocating the hole merges it again
+    let path = disk_prep_path("fallocate_extents");
+    let sb = SuperBlock {
+        nblocks: 20,
+        ndatablocks: 15,
+        features: FEATURE_EXTENTS,
+        ..SUPERBLOCK_GOOD
+    };
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    let free = my_fs.statfs().unwrap().free_blocks;
+    let i3 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino3 = my_fs.i_get(i3).unwrap();
+    my_fs.i_write(&mut ino3, &buf, 0, n).unwrap();
+    my_fs
+        .i_punch_hole(&mut ino3, BLOCK_SIZE, BLOCK_SIZE)
+        .unwrap();
+    assert_eq!(
+        ino3.disk_node.inline_extents(),
+        vec![
+            Extent {
+                start: 0,
+                len: 1,
+                physical: sb.datastart
+            },
+            Extent {
+                start: 2,
+                len: 1,
+                physical: sb.datastart + 2
+            }
+        ]
+    );
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 2);
+    my_fs
+        .i_fallocate(&mut ino3, BLOCK_SIZE, BLOCK_SIZE, true)
+        .unwrap();
+    assert_eq!(
+        ino3.disk_node.inline_extents(),
+        vec![Extent {
+            start: 0,
+            len: 3,
+            physical: sb.datastart
+        }]
+    );
+    //blocks preallocated past the end are extents like any other
+    my_fs.i_fallocate(&mut ino3, n, BLOCK_SIZE, true).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 4);
+    my_fs.i_truncate(&mut ino3, BLOCK_SIZE).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 1);
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn xattrs() {
+    let path = disk_prep_path("xattrs");
+    //Without the feature, extended attributes are unavailable
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    assert!(my_fs.i_getxattr(&ino1, "user.a").is_err());
+    assert!(my_fs.i_setxattr(&mut ino1, "user.a", b"x").is_err());
+    assert!(my_fs.i_listxattr(&ino1).is_err());
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+
+    //With it, every inode slot grows, so only one inode fits in a block here
+    let sb = SuperBlock {
+        ninodes: 3,
+        features: FEATURE_XATTR,
+        ..SUPERBLOCK_GOOD
+    };
+    assert!(!FSName::sb_valid(&SuperBlock { ninodes: 6, ..sb }));
+    let path = disk_prep_path("xattrs_feature");
+    let mut my_fs = FSName::mkfs(&path, &sb).unwrap();
+    let clock = ManualClock::new(Timestamp::from_secs(10));
+    my_fs.i_set_clock(Box::new(clock.clone()));
+    let free = my_fs.statfs().unwrap().free_blocks;
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    assert_eq!(my_fs.i_listxattr(&ino1).unwrap(), Vec::<String>::new());
+    assert_eq!(my_fs.i_getxattr(&ino1, "user.a").unwrap(), None);
+
+    //Setting attributes takes a single block and updates the change time
+    clock.advance(5 * Timestamp::NANOS_PER_SEC);
+    my_fs.i_setxattr(&mut ino1, "user.b", b"two").unwrap();
+    my_fs.i_setxattr(&mut ino1, "trusted.a", b"one").unwrap();
+    my_fs.i_setxattr(&mut ino1, "user.b", b"2").unwrap();
+    assert_eq!(ino1.disk_node.ctime, Timestamp::from_secs(15));
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 1);
+    assert_eq!(my_fs.i_get(i1).unwrap(), ino1);
+    assert_eq!(
+        my_fs.i_listxattr(&ino1).unwrap(),
+        vec!["trusted.a".to_string(), "user.b".to_string()]
+    );
+    assert_eq!(
+        my_fs.i_getxattr(&ino1, "user.b").unwrap(),
+        Some(b"2".to_vec())
+    );
+
+    //Invalid names and values that do not fit are rejected, leaving the attributes untouched
+    assert!(my_fs.i_setxattr(&mut ino1, "system.a", b"x").is_err());
+    assert!(my_fs.i_setxattr(&mut ino1, "user.", b"x").is_err());
+    let name = format!("user.{}", "n".repeat(251));
+    assert!(my_fs.i_setxattr(&mut ino1, &name, b"x").is_err());
+    let big = vec![1; BLOCK_SIZE as usize];
+    assert!(my_fs.i_setxattr(&mut ino1, "user.c", &big).is_err());
+    assert!(my_fs.i_removexattr(&mut ino1, "user.c").is_err());
+    assert_eq!(ino1.disk_node.ctime, Timestamp::from_secs(15));
+    assert_eq!(my_fs.i_listxattr(&ino1).unwrap().len(), 2);
+
+    //Attributes survive remounting
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    assert_eq!(
+        my_fs.i_getxattr(&ino1, "trusted.a").unwrap(),
+        Some(b"one".to_vec())
+    );
+
+    //Removing the last attribute releases the block
+    my_fs.i_removexattr(&mut ino1, "user.b").unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 1);
+    my_fs.i_removexattr(&mut ino1, "trusted.a").unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+
+    //Freeing an inode releases its attributes
+    my_fs.i_setxattr(&mut ino1, "user.a", b"x").unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 1);
+    my_fs.i_free(i1).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+    let i2 = my_fs.i_alloc(FType::TFile).unwrap();
+    assert_eq!(i2, i1);
+    let ino2 = my_fs.i_get(i2).unwrap();
+    assert_eq!(my_fs.i_listxattr(&ino2).unwrap(), Vec::<String>::new());
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn file_handle() {
+    let path = disk_prep_path("file_handle");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let data: Vec<u8> = (0..2 * BLOCK_SIZE + 50).map(|i| (i % 251) as u8).collect();
+
+    //Copying into a borrowed handle writes across block boundaries
+    let mut file = FileHandle::open(&mut my_fs, i1).unwrap();
+    assert_eq!(
+        io::copy(&mut &data[..], &mut file).unwrap(),
+        data.len() as u64
+    );
+    assert_eq!(file.position(), data.len() as u64);
+    assert_eq!(file.inode().get_size(), data.len() as u64);
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+    assert_eq!(file.read(&mut [0; 10]).unwrap(), 0);
+
+    //Seeking relative to the end or the cursor, but never before the start
+    assert_eq!(
+        file.seek(SeekFrom::End(-10)).unwrap(),
+        data.len() as u64 - 10
+    );
+    assert_eq!(
+        file.seek(SeekFrom::Current(5)).unwrap(),
+        data.len() as u64 - 5
+    );
+    let mut tail = [0; 10];
+    assert_eq!(file.read(&mut tail).unwrap(), 5);
+    assert_eq!(&tail[..5], &data[data.len() - 5..]);
+    assert!(file
+        .seek(SeekFrom::Current(-(data.len() as i64) - 1))
+        .is_err());
+    assert_eq!(file.position(), data.len() as u64);
+    assert_eq!(
+        file.seek(SeekFrom::Current(i64::MIN + 1))
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidInput
+    );
+    file.seek(SeekFrom::Start(u64::MAX)).unwrap();
+    assert!(file.seek(SeekFrom::Current(1)).is_err());
+    //Writing that far out errors instead of overflowing, and leaves the file alone
+    assert!(file.write(b"x").is_err());
+    file.seek(SeekFrom::Start(u64::MAX - 1)).unwrap();
+    assert!(file.write(b"x").is_err());
+    assert_eq!(file.position(), u64::MAX - 1);
+    assert_eq!(file.inode().get_size(), data.len() as u64);
+    file.seek(SeekFrom::Start(u64::MAX)).unwrap();
+    assert_eq!(
+        file.seek(SeekFrom::Current(i64::MIN)).unwrap(),
+        (1 << 63) - 1
+    );
+    file.seek(SeekFrom::Start(data.len() as u64)).unwrap();
+
+    //Overwriting in place keeps the size, append mode always writes at the end
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.write_all(b"line one\nline two\n").unwrap();
+    assert_eq!(file.inode().get_size(), data.len() as u64);
+    file.set_append(true);
+    assert!(file.is_append());
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.write_all(b"end").unwrap();
+    assert_eq!(file.position(), data.len() as u64 + 3);
+    file.flush().unwrap();
+    drop(file);
+    let ino1 = my_fs.i_get(i1).unwrap();
+    assert_eq!(ino1.get_size(), data.len() as u64 + 3);
+
+    //A buffered reader over an owned handle, which gives the file system back afterwards
+    let file = FileHandle::open(Box::new(my_fs), i1).unwrap();
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "line one\n");
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "line two\n");
+    let mut file = reader.into_inner();
+    file.seek(SeekFrom::End(-3)).unwrap();
+    let mut end = String::new();
+    file.read_to_string(&mut end).unwrap();
+    assert_eq!(end, "end");
+    let mut my_fs = *file.into_inner().unwrap();
+
+    //Opening an inode that cannot be read fails
+    assert!(FileHandle::open(&mut my_fs, 100).is_err());
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+#[test]
+fn fsync() {
+    let path = disk_prep_path("fsync");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let clock = ManualClock::new(Timestamp::from_secs(10));
+    my_fs.i_set_clock(Box::new(clock.clone()));
+
+    //A new inode is dirty until it is synced
+    let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino1 = my_fs.i_get(i1).unwrap();
+    assert!(my_fs.i_is_dirty(&ino1).unwrap());
+    my_fs.i_fsync(&ino1).unwrap();
+    assert!(!my_fs.i_is_dirty(&ino1).unwrap());
+
+    //Growing the file changes its size, so syncing its data persists the inode too
+    let data: Vec<u8> = (0..2 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+    let buf = Buffer::new(data.into_boxed_slice());
+    my_fs.i_write(&mut ino1, &buf, 0, 2 * BLOCK_SIZE).unwrap();
+    assert!(my_fs.i_is_dirty(&ino1).unwrap());
+    my_fs.i_fdatasync(&ino1).unwrap();
+    assert!(!my_fs.i_is_dirty(&ino1).unwrap());
+
+    //Overwriting only changes the timestamps of the inode, which only a full sync persists
+    clock.advance(Timestamp::NANOS_PER_SEC);
+    my_fs.i_write(&mut ino1, &buf, 0, 10).unwrap();
+    my_fs.i_fdatasync(&ino1).unwrap();
+    assert!(my_fs.i_is_dirty(&ino1).unwrap());
+    my_fs.i_fsync(&ino1).unwrap();
+    assert!(!my_fs.i_is_dirty(&ino1).unwrap());
+
+    //A block written directly makes the inode it belongs to dirty
+    let b = ino1.get_block(1).0;
+    my_fs.b_put(&Block::new_zero(b, BLOCK_SIZE)).unwrap();
+    assert!(my_fs.i_is_dirty(&ino1).unwrap());
+    my_fs.i_fdatasync(&ino1).unwrap();
+    assert!(!my_fs.i_is_dirty(&ino1).unwrap());
+
+    //Syncing one inode leaves an inode in another block dirty
+    let i2 = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut ino2 = my_fs.i_get(i2).unwrap();
+    my_fs.i_write(&mut ino2, &buf, 0, 10).unwrap();
+    my_fs.i_write(&mut ino1, &buf, 0, 10).unwrap();
+    my_fs.i_fsync(&ino1).unwrap();
+    assert!(!my_fs.i_is_dirty(&ino1).unwrap());
+    assert!(my_fs.i_is_dirty(&ino2).unwrap());
+    my_fs.i_fsync(&ino2).unwrap();
+    assert!(!my_fs.i_is_dirty(&ino2).unwrap());
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
@@ -1,6 +1,6 @@
 use super::FSName;
 use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport};
-use cplfs_api::types::{Buffer, FType, InodeLike, SuperBlock, DIRECT_POINTERS};
+use cplfs_api::types::{BlockNo, Buffer, DataIdx, FType, InodeLike, SuperBlock, DIRECT_POINTERS};
 use std::path::PathBuf;
 
 #[path = "utils.rs"]
@@ -14,8 +14,17 @@ static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
     ninodes: 6,
     inodestart: 1,
     ndatablocks: 30,
-    bmapstart: 4,
-    datastart: 5,
+    //inodes with indirect blocks no longer fit two to a block
+    bmapstart: 7,
+    datastart: 8,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 fn disk_prep_path(name: &str) -> PathBuf {
@@ -33,8 +42,8 @@ fn basics() {
         &(0..(DIRECT_POINTERS + 1)).collect::<Vec<_>>()[..],
     )
     .unwrap();
-    assert_eq!(i1.get_block(DIRECT_POINTERS), DIRECT_POINTERS);
-    assert_eq!(i1.get_block(DIRECT_POINTERS + 1), 0);
+    assert_eq!(i1.get_block(DIRECT_POINTERS), BlockNo(DIRECT_POINTERS));
+    assert_eq!(i1.get_block(DIRECT_POINTERS + 1), BlockNo(0));
 }
 
 #[test]
@@ -55,7 +64,7 @@ fn read_write() {
     my_fs.i_write(&mut i1, &buf5000, 0, 5000).unwrap();
     let mut i1 = my_fs.i_get(1).unwrap();
     assert_eq!(i1.get_size(), 5000);
-    assert_ne!(i1.get_block(DIRECT_POINTERS), 0); //should have allocated an indirect block, and not zero
+    assert_ne!(i1.get_block(DIRECT_POINTERS), BlockNo(0)); //should have allocated an indirect block, and not zero
 
     let mut buf5000r = Buffer::new(vec![0; 5000].into_boxed_slice());
     assert_eq!(my_fs.i_read(&mut i1, &mut buf5000r, 0, 5000).unwrap(), 5000);
@@ -64,7 +73,7 @@ fn read_write() {
 
     //shouldve allocated ceil(5000/300) +  1 -> 18 blocks
     for i in 0..18 {
-        my_fs.b_free(i).unwrap();
+        my_fs.b_free(DataIdx(i)).unwrap();
     }
     assert!(my_fs.i_free(18).is_err()); //not allocated
 
@@ -90,7 +99,7 @@ fn free() {
     my_fs.i_write(&mut i1, &buf5000, 0, 5000).unwrap();
     let i1 = my_fs.i_get(1).unwrap();
     assert_eq!(i1.get_size(), 5000);
-    assert_ne!(i1.get_block(DIRECT_POINTERS), 0); //should have allocated an indirect block, and not zero
+    assert_ne!(i1.get_block(DIRECT_POINTERS), BlockNo(0)); //should have allocated an indirect block, and not zero
 
     my_fs.i_free(1).unwrap();
     //shouldve deallocated all
@@ -101,3 +110,230 @@ fn free() {
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
 }
+
+//Blocks past the reach of the single indirect block go through the double- and triple-indirect ones
+#[test]
+fn multi_level() {
+    let path = disk_prep_path("multi_level");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let p = BLOCK_SIZE / 8;
+    let max_blocks = DIRECT_POINTERS + p + p * p + p * p * p;
+    assert_eq!(my_fs.i_max_size(), max_blocks * BLOCK_SIZE);
+
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut i1 = my_fs.i_get(i).unwrap();
+    let free = my_fs.statfs().unwrap().free_blocks;
+    let double = (DIRECT_POINTERS + p) * BLOCK_SIZE;
+    let triple = (DIRECT_POINTERS + p + p * p) * BLOCK_SIZE + 5;
+    let buf = Buffer::new(vec![7; 2].into_boxed_slice());
+    my_fs.i_write(&mut i1, &buf, double, 2).unwrap();
+    assert_ne!(i1.get_block(DIRECT_POINTERS + 1), BlockNo(0));
+    assert_eq!(i1.get_block(DIRECT_POINTERS + 2), BlockNo(0));
+    //double-indirect, single-indirect and data block
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 3);
+    my_fs.i_write(&mut i1, &buf, triple, 2).unwrap();
+    assert_ne!(i1.get_block(DIRECT_POINTERS + 2), BlockNo(0));
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 7);
+    assert_eq!(i1.get_size(), triple + 2);
+
+    //the contents survive remounting, and everything in between reads as zeroes
+    let dev = my_fs.unmountfs();
+    let mut my_fs = FSName::mountfs(dev).unwrap();
+    let mut i1 = my_fs.i_get(i).unwrap();
+    let mut buf4 = Buffer::new_zero(4);
+    assert_eq!(my_fs.i_read(&mut i1, &mut buf4, double - 1, 4).unwrap(), 4);
+    assert_eq!(buf4.contents_as_ref(), &[0, 7, 7, 0]);
+    assert_eq!(my_fs.i_read(&mut i1, &mut buf4, triple - 1, 4).unwrap(), 3);
+    assert_eq!(buf4.contents_as_ref(), &[0, 7, 7, 0]);
+    assert_eq!(my_fs.i_seek_data(&i1, 0).unwrap(), Some(double));
+    assert_eq!(
+        my_fs.i_seek_hole(&i1, double).unwrap(),
+        Some(double + BLOCK_SIZE)
+    );
+    assert_eq!(
+        my_fs.i_seek_data(&i1, double + BLOCK_SIZE).unwrap(),
+        Some(triple - 5)
+    );
+    let summary = my_fs.i_extent_summary().unwrap();
+    assert_eq!(summary[0].blocks, 2);
+
+    //writes past the maximum size fail without changing anything
+    assert!(my_fs
+        .i_write(&mut i1, &buf, max_blocks * BLOCK_SIZE - 1, 2)
+        .is_err());
+    assert_eq!(i1.get_size(), triple + 2);
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - 7);
+
+    //truncating releases the indirect blocks too
+    my_fs.i_trunc(&mut i1).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+    for k in 0..(DIRECT_POINTERS + 3) {
+        assert_eq!(i1.get_block(k), BlockNo(0));
+    }
+    assert_eq!(my_fs.i_get(i).unwrap(), i1);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+//Superblocks whose inode region is too small for the larger inodes are rejected
+#[test]
+fn inode_region() {
+    let path = disk_prep_path("inode_region");
+    let sb = SuperBlock {
+        bmapstart: 4,
+        datastart: 5,
+        ..SUPERBLOCK_GOOD
+    };
+    assert!(!FSName::sb_valid(&sb));
+    assert!(FSName::mkfs(&path, &sb).is_err());
+    assert!(FSName::sb_valid(&SUPERBLOCK_GOOD));
+}
+
+//Truncating to a length frees only the blocks past it, indirect blocks included
+#[test]
+fn truncate() {
+    let path = disk_prep_path("truncate");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let free = my_fs.statfs().unwrap().free_blocks;
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut i1 = my_fs.i_get(i).unwrap();
+    let n = (DIRECT_POINTERS + 3) * BLOCK_SIZE;
+    let data: Vec<u8> = (0..n).map(|k| (k % 251) as u8 + 1).collect();
+    let buf = Buffer::new(data.clone().into_boxed_slice());
+    my_fs.i_write(&mut i1, &buf, 0, n).unwrap();
+    //the data blocks and a single indirect block
+    assert_eq!(
+        my_fs.statfs().unwrap().free_blocks,
+        free - DIRECT_POINTERS - 4
+    );
+
+    //Back into the direct blocks: the indirect block goes as well
+    let short = (DIRECT_POINTERS - 1) * BLOCK_SIZE + 10;
+    my_fs.i_truncate(&mut i1, short).unwrap();
+    assert_eq!(i1.get_size(), short);
+    assert_eq!(i1.get_block(DIRECT_POINTERS), BlockNo(0));
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - DIRECT_POINTERS);
+    assert_eq!(my_fs.i_get(i).unwrap(), i1);
+
+    //Extending again leaves a hole, and the cut-off bytes do not come back
+    my_fs.i_truncate(&mut i1, n).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - DIRECT_POINTERS);
+    let mut read = Buffer::new_zero(n);
+    assert_eq!(my_fs.i_read(&mut i1, &mut read, 0, n).unwrap(), n);
+    assert_eq!(
+        &read.contents_as_ref()[..short as usize],
+        &data[..short as usize]
+    );
+    assert!(read.contents_as_ref()[short as usize..]
+        .iter()
+        .all(|&b| b == 0));
+    assert_eq!(
+        my_fs.i_seek_hole(&i1, 0).unwrap(),
+        Some(DIRECT_POINTERS * BLOCK_SIZE)
+    );
+
+    //Past the maximum size nothing changes
+    assert!(my_fs.i_truncate(&mut i1, my_fs.i_max_size() + 1).is_err());
+    assert_eq!(i1.get_size(), n);
+    my_fs.i_truncate(&mut i1, 0).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+//Preallocating, punching holes and zeroing ranges also work through indirect blocks
+#[test]
+fn fallocate() {
+    let path = disk_prep_path("fallocate");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let free = my_fs.statfs().unwrap().free_blocks;
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut i1 = my_fs.i_get(i).unwrap();
+
+    //Blocks past the end are reserved without growing the file, and released by truncation
+    let n = (DIRECT_POINTERS + 2) * BLOCK_SIZE;
+    my_fs.i_fallocate(&mut i1, 0, n, true).unwrap();
+    assert_eq!(i1.get_size(), 0);
+    assert_eq!(
+        my_fs.statfs().unwrap().free_blocks,
+        free - DIRECT_POINTERS - 3
+    );
+    my_fs.i_trunc(&mut i1).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+
+    //Punching out all blocks behind the indirect block frees it as well
+    let data: Vec<u8> = (0..n).map(|k| (k % 251) as u8 + 1).collect();
+    let buf = Buffer::new(data.clone().into_boxed_slice());
+    my_fs.i_write(&mut i1, &buf, 0, n).unwrap();
+    let off = DIRECT_POINTERS * BLOCK_SIZE - 10;
+    my_fs.i_punch_hole(&mut i1, off, n).unwrap();
+    assert_eq!(i1.get_size(), n);
+    assert_eq!(i1.get_block(DIRECT_POINTERS), BlockNo(0));
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free - DIRECT_POINTERS);
+    let mut read = Buffer::new_zero(n);
+    my_fs.i_read(&mut i1, &mut read, 0, n).unwrap();
+    assert_eq!(
+        &read.contents_as_ref()[..off as usize],
+        &data[..off as usize]
+    );
+    assert!(read.contents_as_ref()[off as usize..]
+        .iter()
+        .all(|&b| b == 0));
+
+    //Zeroing a range allocates what it covers, and grows the file unless asked not to
+    my_fs.i_zero_range(&mut i1, n, BLOCK_SIZE, false).unwrap();
+    assert_eq!(i1.get_size(), n + BLOCK_SIZE);
+    assert_eq!(
+        my_fs.statfs().unwrap().free_blocks,
+        free - DIRECT_POINTERS - 2
+    );
+    assert_eq!(my_fs.i_seek_data(&i1, off).unwrap(), Some(off));
+    assert_eq!(my_fs.i_seek_data(&i1, off + 10).unwrap(), Some(n));
+
+    assert!(my_fs
+        .i_fallocate(&mut i1, my_fs.i_max_size(), 1, false)
+        .is_err());
+    assert_eq!(my_fs.i_get(i).unwrap(), i1);
+    my_fs.i_trunc(&mut i1).unwrap();
+    assert_eq!(my_fs.statfs().unwrap().free_blocks, free);
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
+
+//Syncing an inode also persists its indirect blocks
+#[test]
+fn fsync() {
+    let path = disk_prep_path("fsync");
+    let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+    let i = my_fs.i_alloc(FType::TFile).unwrap();
+    let mut i1 = my_fs.i_get(i).unwrap();
+    assert!(my_fs.i_is_dirty(&i1).unwrap());
+
+    let n = (DIRECT_POINTERS + 1) * BLOCK_SIZE;
+    let buf = Buffer::new(vec![1; n as usize].into_boxed_slice());
+    my_fs.i_write(&mut i1, &buf, 0, n).unwrap();
+    my_fs.i_fdatasync(&i1).unwrap();
+    assert!(!my_fs.i_is_dirty(&i1).unwrap());
+
+    //Rewriting the indirect block makes the inode dirty again
+    let ind = i1.get_block(DIRECT_POINTERS);
+    let block = my_fs.b_get(ind).unwrap();
+    my_fs.b_put(&block).unwrap();
+    assert!(my_fs.i_is_dirty(&i1).unwrap());
+    my_fs.i_fsync(&i1).unwrap();
+    assert!(!my_fs.i_is_dirty(&i1).unwrap());
+
+    //Changing only the permissions of the inode is not persisted by a data sync
+    i1.disk_node.mode = 0o600;
+    my_fs.i_put(&i1).unwrap();
+    my_fs.i_fdatasync(&i1).unwrap();
+    assert!(my_fs.i_is_dirty(&i1).unwrap());
+    my_fs.i_fsync(&i1).unwrap();
+    assert!(!my_fs.i_is_dirty(&i1).unwrap());
+
+    let dev = my_fs.unmountfs();
+    utils::disk_destruct(dev);
+}
@@ -2,7 +2,7 @@ use super::FSName;
 use cplfs_api::fs::{
     BlockSupport, FileSysSupport, InodeCacheSupport, InodeRWSupport, InodeSupport,
 };
-use cplfs_api::types::{Buffer, FType, InodeLike, SuperBlock};
+use cplfs_api::types::{BlockNo, Buffer, DataIdx, FType, InodeLike, SuperBlock};
 use std::path::PathBuf;
 
 #[path = "utils.rs"]
@@ -18,6 +18,14 @@ static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
     ndatablocks: 6,
     bmapstart: 4,
     datastart: 5,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 static BLOCK_SIZE_C: u64 = 1000; //make blocks somewhat smaller on this one, should still be sufficient for a reasonable inode
@@ -29,6 +37,14 @@ static SUPERBLOCK_GOOD_C: SuperBlock = SuperBlock {
     ndatablocks: 6,
     bmapstart: 4,
     datastart: 5,
+    ngroups: 0,
+    groupsize: 0,
+    logstart: 0,
+    nlog: 0,
+    nreserved: 0,
+    badlist: 0,
+    features: 0,
+    imapstart: 0,
 };
 
 fn disk_prep_path(name: &str) -> PathBuf {
@@ -113,7 +129,7 @@ fn get_put() {
         &[2, 3, 4],
     )
     .unwrap();
-    let b1 = my_fs.b_get(SUPERBLOCK_GOOD.inodestart).unwrap();
+    let b1 = my_fs.b_get(BlockNo(SUPERBLOCK_GOOD.inodestart)).unwrap();
 
     my_fs.i_put(&i1).unwrap();
     my_fs.i_put(&i2).unwrap();
@@ -154,7 +170,7 @@ fn free_alloc() {
 
     //Allocate blocks 5-6-7-8
     for i in 0..4 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let i2 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
         3,
@@ -167,8 +183,8 @@ fn free_alloc() {
     my_fs.i_put(&i2).unwrap();
     my_fs.i_free(3).unwrap();
     //Already freed
-    assert!(my_fs.b_free(2).is_err()); //watch out; absolute indices
-    assert!(my_fs.b_free(3).is_err());
+    assert!(my_fs.b_free(DataIdx(2)).is_err()); //watch out; absolute indices
+    assert!(my_fs.b_free(DataIdx(3)).is_err());
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
@@ -181,7 +197,7 @@ fn itrunc() {
 
     //Allocate blocks 5-6-7-8
     for i in 0..5 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let i2 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
         2,
@@ -200,9 +216,9 @@ fn itrunc() {
     assert_eq!(i3.get_nlink(), 0);
 
     //Already freed
-    assert!(my_fs.b_free(1).is_err());
-    assert!(my_fs.b_free(2).is_err());
-    assert!(my_fs.b_free(3).is_ok()); //sneaky; not deallocated
+    assert!(my_fs.b_free(DataIdx(1)).is_err());
+    assert!(my_fs.b_free(DataIdx(2)).is_err());
+    assert!(my_fs.b_free(DataIdx(3)).is_ok()); //sneaky; not deallocated
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
@@ -215,7 +231,7 @@ fn error_cases() {
 
     //Set up an inode with 3 blocks first
     for i in 0..5 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let b2 = utils::n_block(5, BLOCK_SIZE, 2);
     my_fs.b_put(&b2).unwrap();
@@ -238,11 +254,11 @@ fn error_cases() {
     let mut buf500 = Buffer::new_zero(500);
 
     //Try to perform some operations
-    assert!(my_fs.i_read(&i2, &mut buf500, 751, 0).is_err());
+    assert!(my_fs.i_read(&mut i2, &mut buf500, 751, 0).is_err());
     assert!(my_fs.i_write(&mut i2, &mut buf500, 751, 0).is_err());
     assert!(my_fs.i_write(&mut i2, &mut buf500, 750, 0).is_ok());
-    assert_eq!(my_fs.i_read(&i2, &mut buf500, 750, 1).unwrap(), 0);
-    assert!(my_fs.i_read(&i2, &mut buf50, 751, 51).is_err());
+    assert_eq!(my_fs.i_read(&mut i2, &mut buf500, 750, 1).unwrap(), 0);
+    assert!(my_fs.i_read(&mut i2, &mut buf50, 751, 51).is_err());
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
@@ -255,7 +271,7 @@ fn readi() {
 
     //Set up an inode with 3 blocks first
     for i in 0..5 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let b2 = utils::n_block(5, BLOCK_SIZE, 2);
     my_fs.b_put(&b2).unwrap();
@@ -263,7 +279,7 @@ fn readi() {
     my_fs.b_put(&b3).unwrap();
     let b4 = utils::n_block(7, BLOCK_SIZE, 4);
     my_fs.b_put(&b4).unwrap();
-    let i2 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
+    let mut i2 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
         2,
         &FType::TFile,
         0,
@@ -281,7 +297,7 @@ fn readi() {
     let mut read_result = vec![2; 33];
     read_result.append(&mut vec![3; 300]);
     read_result.append(&mut vec![4; 155]);
-    assert_eq!(my_fs.i_read(&i2, &mut buf500, 267, 488).unwrap(), 488);
+    assert_eq!(my_fs.i_read(&mut i2, &mut buf500, 267, 488).unwrap(), 488);
     assert_eq!(
         &buf500.contents_as_ref()[..488].len(),
         &read_result[..].len()
@@ -290,7 +306,7 @@ fn readi() {
 
     let mut read_result_2 = vec![2; 33];
     read_result_2.append(&mut vec![3; 17]);
-    assert_eq!(my_fs.i_read(&i2, &mut buf50, 267, 50).unwrap(), 50);
+    assert_eq!(my_fs.i_read(&mut i2, &mut buf50, 267, 50).unwrap(), 50);
     assert_eq!(&buf50.contents_as_ref()[..], &read_result_2[..]);
 
     let dev = my_fs.unmountfs();
@@ -304,7 +320,7 @@ fn writei() {
 
     //Set up an inode with 3 blocks first
     for i in 0..4 {
-        assert_eq!(my_fs.b_alloc().unwrap(), i);
+        assert_eq!(my_fs.b_alloc().unwrap(), DataIdx(i));
     }
     let b2 = utils::n_block(5, BLOCK_SIZE, 2);
     my_fs.b_put(&b2).unwrap();
@@ -336,7 +352,10 @@ fn writei() {
     assert!(my_fs.i_write(&mut i2, &mut buf50, 703, 50).is_ok());
     assert_eq!(my_fs.i_get_mut(2).unwrap(), i2);
     assert_eq!(753, i2.get_size());
-    assert_eq!(my_fs.b_get(7).unwrap().contents_as_ref(), &write_result[..]);
+    assert_eq!(
+        my_fs.b_get(BlockNo(7)).unwrap().contents_as_ref(),
+        &write_result[..]
+    );
 
     //And one that will require mapping in new blocks
     let mut write_result_2 = vec![6; 52];
@@ -344,13 +363,13 @@ fn writei() {
     my_fs.i_write(&mut i2, &mut buf500, 753, 499).unwrap();
     assert_eq!(my_fs.i_get_mut(2).unwrap(), i2);
     assert_eq!(1252, i2.get_size());
-    assert_eq!(9, i2.get_block(3));
-    assert_eq!(10, i2.get_block(4));
+    assert_eq!(BlockNo(9), i2.get_block(3));
+    assert_eq!(BlockNo(10), i2.get_block(4));
     assert_eq!(
-        my_fs.b_get(10).unwrap().contents_as_ref(),
+        my_fs.b_get(BlockNo(10)).unwrap().contents_as_ref(),
         &write_result_2[..]
     );
-    my_fs.b_free(4).unwrap();
+    my_fs.b_free(DataIdx(4)).unwrap();
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
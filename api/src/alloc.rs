@@ -0,0 +1,262 @@
+//! Policies deciding which free data block or inode a file system allocates next.
+//!
+//! Allocation only ever sees the bit map through [`UsedSlots`]: slot `i` is in use if `used.is_used(i)` holds.
+//! File systems hand the policies a map that reads the disk lazily, so a policy that stops at the first free slot it finds only loads the bit map blocks up to that slot.
+//! The slots are split into groups of `group` consecutive slots, which correspond to the block groups of a file system that has them (see [`GroupDesc`](../types/struct.GroupDesc.html)).
+//! A file system without block groups passes the total number of slots as the group size, so that everything lives in a single group.
+//!
+//! The policy a file system uses is picked at mount time through [`MountOptions::alloc`](../types/struct.MountOptions.html#structfield.alloc); it is not stored on the disk.
+//! The policies only differ in where new blocks end up, so they can be compared by running the same workload under each of them and looking at how fragmented the resulting files are.
+//!
+//! [`UsedSlots`]: trait.UsedSlots.html
+
+use std::fmt::Debug;
+
+/// A bit map of slots the allocation policies search for a free one
+pub trait UsedSlots {
+    /// Returns the number of slots in the bit map
+    fn nslots(&self) -> u64;
+    /// Returns whether slot `i`, which is below `nslots`, is in use
+    fn is_used(&mut self, i: u64) -> bool;
+}
+
+impl UsedSlots for Vec<bool> {
+    fn nslots(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn is_used(&mut self, i: u64) -> bool {
+        self[i as usize]
+    }
+}
+
+/// A policy choosing a free slot out of a bit map
+pub trait AllocPolicy: Debug {
+    /// Returns the index of a free slot in `used`, or `None` if all slots are in use.
+    /// Policies only look at as many slots as they need to, so looking up a slot may be costly.
+    /// The slots are split into groups of `group` slots each, the last group possibly being smaller.
+    /// `goal` is the slot the caller would like to be close to, if any; policies are free to ignore it.
+    /// The policy does not mark the slot as used itself; it may however remember its choice for later calls.
+    fn choose(&mut self, used: &mut dyn UsedSlots, group: u64, goal: Option<u64>) -> Option<u64>;
+}
+
+/// The allocation policies that can be picked when mounting a file system
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AllocStrategy {
+    /// See [`FirstFit`](struct.FirstFit.html)
+    FirstFit,
+    /// See [`NextFit`](struct.NextFit.html)
+    NextFit,
+    /// See [`BestFitExtent`](struct.BestFitExtent.html)
+    BestFitExtent,
+    /// See [`Locality`](struct.Locality.html)
+    #[default]
+    Locality,
+}
+
+impl AllocStrategy {
+    /// Creates a fresh policy of this kind, i.e. one that has not remembered anything yet
+    pub fn policy(self) -> Box<dyn AllocPolicy> {
+        match self {
+            AllocStrategy::FirstFit => Box::new(FirstFit),
+            AllocStrategy::NextFit => Box::new(NextFit::default()),
+            AllocStrategy::BestFitExtent => Box::new(BestFitExtent),
+            AllocStrategy::Locality => Box::new(Locality),
+        }
+    }
+}
+
+/// Always picks the free slot with the lowest index, ignoring the goal
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FirstFit;
+
+impl AllocPolicy for FirstFit {
+    fn choose(&mut self, used: &mut dyn UsedSlots, _group: u64, _goal: Option<u64>) -> Option<u64> {
+        (0..used.nslots()).find(|&i| !used.is_used(i))
+    }
+}
+
+/// Picks the first free slot after the slot it picked last time, wrapping around at the end, and ignoring the goal.
+/// Spreads allocations over the entire bit map instead of filling the holes at the start over and over again.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NextFit {
+    /// Slot the next search starts at
+    next: u64,
+}
+
+impl AllocPolicy for NextFit {
+    fn choose(&mut self, used: &mut dyn UsedSlots, _group: u64, _goal: Option<u64>) -> Option<u64> {
+        let n = used.nslots();
+        let from = if self.next < n { self.next } else { 0 };
+        let i = (from..n).chain(0..from).find(|&i| !used.is_used(i))?;
+        self.next = i + 1;
+        Some(i)
+    }
+}
+
+/// Picks the first slot of the smallest run of free slots, so that large runs stay available for files that need many blocks.
+/// Runs do not cross group boundaries, since the blocks of different groups are not adjacent on the disk.
+/// Ties are broken by picking the run closest to the goal, or the lowest one without a goal.
+/// Unlike the other policies, this one has to look at every slot.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BestFitExtent;
+
+impl AllocPolicy for BestFitExtent {
+    fn choose(&mut self, used: &mut dyn UsedSlots, group: u64, goal: Option<u64>) -> Option<u64> {
+        let n = used.nslots();
+        let group = group.max(1);
+        let distance = |start: u64| goal.map_or(start, |goal| start.abs_diff(goal));
+        //(length, distance to the goal, start) of the best run so far
+        let mut best: Option<(u64, u64, u64)> = None;
+        let mut start: Option<u64> = None;
+        for i in 0..=n {
+            let free = i < n && !used.is_used(i);
+            let ends = !free || i % group == 0;
+            if let (true, Some(s)) = (ends, start) {
+                let run = (i - s, distance(s), s);
+                best = Some(best.map_or(run, |b| b.min(run)));
+                start = None;
+            }
+            if free && start.is_none() {
+                start = Some(i);
+            }
+        }
+        best.map(|(_, _, s)| s)
+    }
+}
+
+/// Picks the first free slot at or after the goal in the group of the goal, wrapping around within that group.
+/// If that group is full, the next groups are searched from their start, in order and wrapping around at the last group.
+/// Without a goal, slot 0 is the goal, which makes this the same as first fit.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Locality;
+
+impl AllocPolicy for Locality {
+    fn choose(&mut self, used: &mut dyn UsedSlots, group: u64, goal: Option<u64>) -> Option<u64> {
+        let n = used.nslots();
+        let group = group.max(1);
+        let goal = goal.filter(|&g| g < n).unwrap_or(0);
+        let ngroups = n.div_ceil(group);
+        let first = goal / group;
+        (0..ngroups)
+            .map(|k| (first + k) % ngroups)
+            .flat_map(|g| {
+                let (start, end) = (g * group, ((g + 1) * group).min(n));
+                let from = if g == first { goal } else { start };
+                (from..end).chain(start..from)
+            })
+            .find(|&i| !used.is_used(i))
+    }
+}
+
+#[cfg(test)]
+mod alloc_tests {
+
+    use super::{
+        AllocPolicy, AllocStrategy, BestFitExtent, FirstFit, Locality, NextFit, UsedSlots,
+    };
+
+    //Turns a string of `x` (used) and `.` (free) characters into a bit map
+    fn map(s: &str) -> Vec<bool> {
+        s.chars().map(|c| c == 'x').collect()
+    }
+
+    #[test]
+    fn first_fit() {
+        let mut p = FirstFit;
+        assert_eq!(p.choose(&mut map("xx.x.."), 6, None), Some(2));
+        assert_eq!(p.choose(&mut map("xx.x.."), 6, Some(4)), Some(2));
+        assert_eq!(p.choose(&mut map("xxxx"), 4, None), None);
+        assert_eq!(p.choose(&mut vec![], 1, None), None);
+    }
+
+    #[test]
+    fn next_fit() {
+        let mut p = NextFit::default();
+        let mut used = map("x..x..");
+        for expected in [1, 2, 4, 5] {
+            let i = p.choose(&mut used, 6, Some(0)).unwrap();
+            assert_eq!(i, expected);
+            used[i as usize] = true;
+        }
+        assert_eq!(p.choose(&mut used, 6, None), None);
+        //freed slots before the last pick are only reused after wrapping around
+        used[1] = false;
+        used[5] = false;
+        assert_eq!(p.choose(&mut used, 6, None), Some(1));
+        assert_eq!(p.choose(&mut used, 6, None), Some(5));
+        //a smaller bit map than before starts over
+        assert_eq!(p.choose(&mut map(".."), 2, None), Some(0));
+    }
+
+    #[test]
+    fn best_fit_extent() {
+        let mut p = BestFitExtent;
+        assert_eq!(p.choose(&mut map("...x.x..x"), 9, None), Some(4));
+        assert_eq!(p.choose(&mut map("..x..x..."), 9, None), Some(0));
+        assert_eq!(p.choose(&mut map("..x..x..."), 9, Some(4)), Some(3));
+        assert_eq!(p.choose(&mut map("xxx"), 3, None), None);
+        //runs stop at group boundaries
+        assert_eq!(p.choose(&mut map("x.....xx"), 4, None), Some(4));
+    }
+
+    #[test]
+    fn locality() {
+        let mut p = Locality;
+        let mut used = map("..x.xx.xxxxx.xx.");
+        assert_eq!(p.choose(&mut used, 4, None), Some(0));
+        assert_eq!(p.choose(&mut used, 4, Some(2)), Some(3));
+        assert_eq!(p.choose(&mut used, 4, Some(5)), Some(6));
+        //wraps around within the group of the goal first
+        assert_eq!(p.choose(&mut used, 4, Some(7)), Some(6));
+        assert_eq!(p.choose(&mut map("..xx"), 4, Some(3)), Some(0));
+        //full group, so the next ones are searched from their start
+        assert_eq!(p.choose(&mut used, 4, Some(9)), Some(12));
+        //and then around to the first group
+        assert_eq!(p.choose(&mut map(".xxx"), 2, Some(2)), Some(0));
+        assert_eq!(p.choose(&mut map("xxxx"), 2, Some(1)), None);
+    }
+
+    //A bit map that counts how many slots were looked at
+    struct Counting(Vec<bool>, u64);
+
+    impl UsedSlots for Counting {
+        fn nslots(&self) -> u64 {
+            self.0.len() as u64
+        }
+
+        fn is_used(&mut self, i: u64) -> bool {
+            self.1 += 1;
+            self.0[i as usize]
+        }
+    }
+
+    #[test]
+    fn stops_early() {
+        let mut used = Counting(map("xx.x........"), 0);
+        assert_eq!(FirstFit.choose(&mut used, 12, None), Some(2));
+        assert_eq!(used.1, 3);
+        used.1 = 0;
+        assert_eq!(Locality.choose(&mut used, 4, Some(5)), Some(5));
+        assert_eq!(used.1, 1);
+        used.1 = 0;
+        assert_eq!(BestFitExtent.choose(&mut used, 12, None), Some(2));
+        assert_eq!(used.1, 12);
+    }
+
+    #[test]
+    fn strategies() {
+        let mut used = map("x.xx..");
+        let picks: Vec<Option<u64>> = [
+            AllocStrategy::FirstFit,
+            AllocStrategy::NextFit,
+            AllocStrategy::BestFitExtent,
+            AllocStrategy::Locality,
+        ]
+        .iter()
+        .map(|s| s.policy().choose(&mut used, 6, Some(5)))
+        .collect();
+        assert_eq!(picks, vec![Some(1), Some(1), Some(1), Some(5)]);
+        assert_eq!(AllocStrategy::default(), AllocStrategy::Locality);
+    }
+}
@@ -0,0 +1,73 @@
+//! Clocks the file system reads the current time from when it updates the timestamps of inodes.
+//!
+//! A file system uses the [`SystemClock`] unless it is given a different one through [`InodeSupport::i_set_clock`](../fs/trait.InodeSupport.html#method.i_set_clock).
+//! Tests typically install a [`ManualClock`] instead, so that the timestamps they check do not depend on when they run:
+//!
+//! ```ignore
+//! let clock = ManualClock::new(Timestamp::from_secs(1000));
+//! fs.i_set_clock(Box::new(clock.clone()));
+//! let i = fs.i_alloc(FType::TFile)?;
+//! clock.advance(5);
+//! //... a write to `i` now sets its mtime to 1000 seconds and 5 nanoseconds
+//! ```
+//!
+//! [`SystemClock`]: struct.SystemClock.html
+//! [`ManualClock`]: struct.ManualClock.html
+
+use super::types::Timestamp;
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A source of the current time
+pub trait Clock: Debug {
+    /// Returns the current time
+    fn now(&self) -> Timestamp;
+}
+
+/// The real time of the system, as reported by `SystemTime::now`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        //a system clock that is set before the epoch is treated as being at the epoch
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Timestamp(since_epoch.as_nanos() as u64)
+    }
+}
+
+/// A clock that only moves when it is told to.
+/// Clones share the same time, so a test can keep a clone to control the clock it has handed to a file system.
+#[derive(Debug, Default, Clone)]
+pub struct ManualClock {
+    now: Rc<Cell<Timestamp>>,
+}
+
+impl ManualClock {
+    /// Creates a clock that is stopped at time `now`
+    pub fn new(now: Timestamp) -> ManualClock {
+        ManualClock {
+            now: Rc::new(Cell::new(now)),
+        }
+    }
+
+    /// Sets the time of this clock and all its clones to `now`
+    pub fn set(&self, now: Timestamp) {
+        self.now.set(now);
+    }
+
+    /// Moves this clock and all its clones `nanos` nanoseconds forward
+    pub fn advance(&self, nanos: u64) {
+        self.now.set(Timestamp(self.now.get().0 + nanos));
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        self.now.get()
+    }
+}
@@ -12,7 +12,9 @@ use super::error_given::APIError;
 use super::types::Block;
 use memmap::MmapMut;
 use std::{
+    collections::BTreeSet,
     fs::{remove_file, OpenOptions},
+    io,
     path::{Path, PathBuf},
 };
 
@@ -35,6 +37,8 @@ pub struct Device {
     path: PathBuf,
     /// Memory-mapped contents of the above file. This is what is manipulated in the read and write functions.
     contents: MmapMut,
+    /// Indices of the blocks that were written since they were last persisted to the file backing this device
+    dirty: BTreeSet<u64>,
 }
 
 /// Small enum, used to specify whether we expect to open a new file system
@@ -83,6 +87,7 @@ impl Device {
             nblocks: nblocks,
             path: path_buf,
             contents: mmapf,
+            dirty: BTreeSet::new(),
         })
     }
 
@@ -130,6 +135,89 @@ impl Device {
         &self.path
     }
 
+    /// Resize this device to `nblocks` blocks, by growing or truncating the file backing it.
+    /// All writes are persisted before the file is resized.
+    /// Blocks added at the end of the device will have contents 0 at each address, and blocks past the new end are discarded.
+    pub fn resize(&mut self, nblocks: u64) -> error_given::Result<()> {
+        self.sync()?;
+        let f = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        f.set_len(self.block_size * nblocks)?;
+        self.contents = unsafe { memmap::MmapOptions::new().map_mut(&f)? };
+        self.nblocks = nblocks;
+        Ok(())
+    }
+
+    /// Discard the `n` blocks starting at index `index`, after which they have contents 0 at each address.
+    /// On Linux, this punches a hole in the file backing this device, so the space these blocks took up is given back to the host file system.
+    /// Elsewhere, or if the host file system cannot punch holes, the blocks are simply overwritten with zeroes.
+    /// Fails if any of the blocks lies past the end of the device.
+    pub fn discard(&mut self, index: u64, n: u64) -> error_given::Result<()> {
+        let addr = self.index_to_addr(index);
+        let len = self.index_to_addr(n);
+        if addr + len > self.device_size() {
+            return Err(APIError::ControllerInput(
+                "Discard past the end of the device",
+            ));
+        }
+        if len == 0 || self.punch_hole(addr, len)? {
+            return Ok(());
+        }
+        self.write(addr, &vec![0; len as usize])
+    }
+
+    /// Punch a hole of `len` bytes at address `addr` in the file backing this device.
+    /// Returns whether the host file system supports this.
+    #[cfg(target_os = "linux")]
+    fn punch_hole(&mut self, addr: u64, len: u64) -> error_given::Result<bool> {
+        use std::os::unix::io::AsRawFd;
+        let f = OpenOptions::new().write(true).open(&self.path)?;
+        let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+        // the mapping is shared, so the pages of the hole read as 0 afterwards
+        let res = unsafe {
+            libc::fallocate(f.as_raw_fd(), mode, addr as libc::off_t, len as libc::off_t)
+        };
+        if res == 0 {
+            return Ok(true);
+        }
+        match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(false),
+            e => Err(e.into()),
+        }
+    }
+
+    /// Punching holes is only supported on Linux
+    #[cfg(not(target_os = "linux"))]
+    fn punch_hole(&mut self, _addr: u64, _len: u64) -> error_given::Result<bool> {
+        Ok(false)
+    }
+
+    /// Returns whether block `index` was written since it was last persisted to the file backing this device
+    pub fn is_dirty(&self, index: u64) -> bool {
+        self.dirty.contains(&index)
+    }
+
+    /// Indices of all blocks that were written since they were last persisted, in increasing order
+    pub fn dirty_blocks(&self) -> Vec<u64> {
+        self.dirty.iter().copied().collect()
+    }
+
+    /// Persist the writes to block `index` to the file backing this device, if there are any
+    pub fn sync_block(&mut self, index: u64) -> error_given::Result<()> {
+        if self.dirty.remove(&index) {
+            let addr = self.index_to_addr(index);
+            self.contents
+                .flush_range(addr as usize, self.block_size as usize)?;
+        }
+        Ok(())
+    }
+
+    /// Persist all writes to the file backing this device
+    pub fn sync(&mut self) -> error_given::Result<()> {
+        self.contents.flush()?;
+        self.dirty.clear();
+        Ok(())
+    }
+
     fn index_to_addr(&self, index: u64) -> u64 {
         self.block_size * index
     }
@@ -169,6 +257,10 @@ impl Device {
         let start = addr as usize;
         let end = (addr as usize) + b.len();
         self.contents[start..end].copy_from_slice(b);
+        if !b.is_empty() {
+            let last = (end as u64 - 1) / self.block_size;
+            self.dirty.extend(addr / self.block_size..=last);
+        }
         Ok(())
     }
 
@@ -387,4 +479,99 @@ mod tests {
         //Make sure the file has actually been destroyed
         assert!(!path.exists());
     }
+
+    // Here we test growing and shrinking a disk image, and reloading it at its new size.
+    #[test]
+    fn resize_disk_test() {
+        let path = disk_prep_path("resize");
+        let mut dev = disk_setup(&path);
+
+        let bw = Block::new(8, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+
+        //Grow the device; old contents remain, new blocks are zero
+        dev.resize(NBBLOCKS + 5).unwrap();
+        assert_eq!(dev.nblocks, NBBLOCKS + 5);
+        assert_eq!(dev.device_size(), BLOCK_SIZE * (NBBLOCKS + 5));
+        assert_eq!(dev.read_block(8).unwrap(), bw);
+        assert_eq!(
+            dev.read_block(NBBLOCKS + 4).unwrap(),
+            Block::new_zero(NBBLOCKS + 4, BLOCK_SIZE)
+        );
+        let bw2 = Block::new(NBBLOCKS + 2, (10..20).collect());
+        dev.write_block(&bw2).unwrap();
+        drop(dev);
+
+        //The image now only loads at its new size
+        assert!(Device::load(&path, BLOCK_SIZE, NBBLOCKS).is_err());
+        let mut dev = Device::load(&path, BLOCK_SIZE, NBBLOCKS + 5).unwrap();
+        assert_eq!(dev.read_block(NBBLOCKS + 2).unwrap(), bw2);
+
+        //Shrink the device again; blocks past the end are gone
+        dev.resize(NBBLOCKS - 1).unwrap();
+        assert_eq!(dev.read_block(8).unwrap(), bw);
+        assert!(dev.read_block(NBBLOCKS - 1).is_err());
+        assert!(dev.write_block(&bw2).is_err());
+        drop(dev);
+        let dev = Device::load(&path, BLOCK_SIZE, NBBLOCKS - 1).unwrap();
+
+        disk_destruct(dev);
+        assert!(!path.exists());
+    }
+
+    // Here we test discarding blocks, which zeroes them without touching the blocks around them.
+    #[test]
+    fn discard_disk_test() {
+        let path = disk_prep_path("discard");
+        let mut dev = disk_setup(&path);
+        for i in 0..NBBLOCKS {
+            dev.write_block(&Block::new(i, vec![i as u8 + 1; 10].into_boxed_slice()))
+                .unwrap();
+        }
+
+        dev.discard(2, 3).unwrap();
+        dev.discard(9, 0).unwrap(); //Nothing to discard
+        assert!(dev.discard(8, 3).is_err()); //Past the end
+        for i in 0..NBBLOCKS {
+            let expected = match i {
+                2..=4 => Block::new_zero(i, BLOCK_SIZE),
+                _ => Block::new(i, vec![i as u8 + 1; 10].into_boxed_slice()),
+            };
+            assert_eq!(dev.read_block(i).unwrap(), expected);
+        }
+
+        //The discarded blocks are still zero after reloading the image
+        drop(dev);
+        let dev = disk_open(&path);
+        assert_eq!(dev.read_block(3).unwrap(), Block::new_zero(3, BLOCK_SIZE));
+        assert_eq!(dev.read_block(5).unwrap().contents_as_ref()[0], 6);
+
+        disk_destruct(dev);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn dirty_disk_test() {
+        let path = disk_prep_path("dirty");
+        let mut dev = disk_setup(&path);
+        assert!(dev.dirty_blocks().is_empty());
+
+        //Writes mark every block they touch as dirty
+        dev.write_block(&Block::new(3, vec![1; 10].into_boxed_slice()))
+            .unwrap();
+        dev.write(78, &[1, 2, 3]).unwrap();
+        assert_eq!(dev.dirty_blocks(), vec![3, 7, 8]);
+        assert!(dev.is_dirty(7));
+
+        //Syncing a block only cleans that block
+        dev.sync_block(7).unwrap();
+        dev.sync_block(5).unwrap(); //Not dirty, nothing to do
+        assert!(!dev.is_dirty(7));
+        assert_eq!(dev.dirty_blocks(), vec![3, 8]);
+        dev.sync().unwrap();
+        assert!(dev.dirty_blocks().is_empty());
+
+        disk_destruct(dev);
+        assert!(!path.exists());
+    }
 }
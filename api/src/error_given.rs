@@ -90,6 +90,9 @@ pub enum APIError {
     /// Invalid input to a block
     #[error("Invalid block input: {0}")]
     BlockInput(&'static str),
+    /// Invalid parameters to compute a file system layout with
+    #[error("Invalid layout input: {0}")]
+    LayoutInput(&'static str),
 
     ///*EXTRA:* *Avoid* using this catch-all error in your own submission, as it is not practical to handle
     ///The [`anyhow`](https://docs.rs/anyhow/1.0.33/anyhow/) package allows defining universal error types, that any error can be cast into
@@ -0,0 +1,200 @@
+//! Standard I/O on top of the inode layer
+//!
+//! A [`FileHandle`] pairs a file system with one of its inodes and a cursor into that inode, and implements [`Read`], [`Write`] and [`Seek`] on top of `i_read` and `i_write`.
+//! This way, everything in the Rust ecosystem that works with these traits, like `io::copy` or a `BufReader`, works on the files in an image as well.
+//! The handle holds on to the file system through anything that dereferences to it, so it can either borrow the file system or own it, e.g. in a `Box`.
+//!
+//! ```ignore
+//! let i = fs.i_alloc(FType::TFile)?;
+//! let mut file = FileHandle::open(&mut fs, i)?;
+//! file.write_all(b"hello")?;
+//! file.seek(SeekFrom::Start(0))?;
+//! let mut s = String::new();
+//! file.read_to_string(&mut s)?; //"hello"
+//! ```
+//!
+//! [`FileHandle`]: struct.FileHandle.html
+//! [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+//! [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+//! [`Seek`]: https://doc.rust-lang.org/std/io/trait.Seek.html
+
+use super::fs::{FileSysSupport, InodeRWSupport, InodeSupport};
+use super::types::{Buffer, InodeLike};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::DerefMut;
+
+/// The inode type of the file system a handle of type `H` refers to
+type InodeOf<H> = <<H as std::ops::Deref>::Target as InodeSupport>::Inode;
+/// The error type of the file system a handle of type `H` refers to
+type ErrorOf<H> = <<H as std::ops::Deref>::Target as FileSysSupport>::Error;
+
+/// An open file, see the [module documentation](index.html).
+/// `H` is the way the handle holds on to the file system, typically `&mut F` or `Box<F>`.
+pub struct FileHandle<H>
+where
+    H: DerefMut,
+    H::Target: InodeRWSupport,
+{
+    //Only `None` once `into_inner` took the file system back
+    fs: Option<H>,
+    inode: InodeOf<H>,
+    pos: u64,
+    append: bool,
+}
+
+impl<H> FileHandle<H>
+where
+    H: DerefMut,
+    H::Target: InodeRWSupport,
+{
+    /// Create a handle to `inode` on the file system `fs`, with its cursor at the start of the file
+    pub fn new(fs: H, inode: InodeOf<H>) -> FileHandle<H> {
+        FileHandle {
+            fs: Some(fs),
+            inode,
+            pos: 0,
+            append: false,
+        }
+    }
+
+    /// Create a handle to the inode with number `i` on the file system `fs`, with its cursor at the start of the file.
+    /// Returns an error if the inode cannot be read.
+    pub fn open(fs: H, i: u64) -> Result<FileHandle<H>, ErrorOf<H>> {
+        let inode = fs.i_get(i)?;
+        Ok(FileHandle::new(fs, inode))
+    }
+
+    /// Put the handle in append mode or take it out of it.
+    /// In append mode, every write first moves the cursor to the end of the file, regardless of where it was; reads and seeks are not affected.
+    pub fn set_append(&mut self, append: bool) {
+        self.append = append;
+    }
+
+    /// Returns whether the handle is in append mode
+    pub fn is_append(&self) -> bool {
+        self.append
+    }
+
+    /// Returns the inode this handle reads from and writes to, as last seen by the handle
+    pub fn inode(&self) -> &InodeOf<H> {
+        &self.inode
+    }
+
+    /// Returns the current position of the cursor, as a byte offset in the file
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Write the inode back to the disk and return the file system the handle was holding on to
+    pub fn into_inner(mut self) -> Result<H, ErrorOf<H>> {
+        let mut fs = self.fs.take().unwrap();
+        fs.i_put(&self.inode)?;
+        Ok(fs)
+    }
+}
+
+/// Wrap an error of the file system in an `io::Error`
+fn io_error<E: std::error::Error>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+impl<H> Read for FileHandle<H>
+where
+    H: DerefMut,
+    H::Target: InodeRWSupport,
+{
+    /// Read from the cursor onwards, and advance the cursor past the bytes read.
+    /// Reading at or past the end of the file reads nothing.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inode.get_size();
+        if self.pos >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let n = (buf.len() as u64).min(size - self.pos);
+        let mut data = Buffer::new_zero(n);
+        let fs = self.fs.as_mut().unwrap();
+        let read = fs
+            .i_read(&mut self.inode, &mut data, self.pos, n)
+            .map_err(io_error)?;
+        buf[..read as usize].copy_from_slice(&data.contents_as_ref()[..read as usize]);
+        self.pos += read;
+        Ok(read as usize)
+    }
+}
+
+impl<H> Write for FileHandle<H>
+where
+    H: DerefMut,
+    H::Target: InodeRWSupport,
+{
+    /// Write at the cursor, or at the end of the file in append mode, and advance the cursor past the bytes written.
+    /// Writing past the end of the file leaves a hole, like `i_write` does.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.append {
+            self.pos = self.inode.get_size();
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let end = self.pos.checked_add(buf.len() as u64).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot write past the largest offset",
+            )
+        })?;
+        let data = Buffer::new(buf.to_vec().into_boxed_slice());
+        let fs = self.fs.as_mut().unwrap();
+        fs.i_write(&mut self.inode, &data, self.pos, buf.len() as u64)
+            .map_err(io_error)?;
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    /// Write the inode back to the disk
+    fn flush(&mut self) -> io::Result<()> {
+        let inode = &self.inode;
+        self.fs.as_mut().unwrap().i_put(inode).map_err(io_error)
+    }
+}
+
+impl<H> Seek for FileHandle<H>
+where
+    H: DerefMut,
+    H::Target: InodeRWSupport,
+{
+    /// Move the cursor; it may end up past the end of the file, but not before its start
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(off) => {
+                self.pos = off;
+                return Ok(off);
+            }
+            SeekFrom::End(delta) => (self.inode.get_size(), delta),
+            SeekFrom::Current(delta) => (self.pos, delta),
+        };
+        match base.checked_add_signed(delta) {
+            Some(new) => {
+                self.pos = new;
+                Ok(new)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot seek to a negative or overflowing offset",
+            )),
+        }
+    }
+}
+
+impl<H> Drop for FileHandle<H>
+where
+    H: DerefMut,
+    H::Target: InodeRWSupport,
+{
+    /// Write the inode back to the disk.
+    /// Errors cannot be reported from here; call `flush` or `into_inner` to see them.
+    fn drop(&mut self) {
+        if let Some(fs) = self.fs.as_mut() {
+            let _ = fs.i_put(&self.inode);
+        }
+    }
+}
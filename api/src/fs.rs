@@ -4,9 +4,14 @@
 //! Make sure your implementation provides auxiliary functions for repetitive tasks like these.
 //! You might need to wrap (some of) the types I provided in the API into your own types, to be able to define additional behavior on them.
 
+use super::clock::Clock;
+use super::transaction::Transaction;
 use super::{
     controller::Device,
-    types::{Block, Buffer, DirEntry, FType, InodeLike, SuperBlock},
+    types::{
+        Access, Block, BlockNo, Buffer, Credentials, DataIdx, DirEntry, DiscardPolicy, FType,
+        FragReport, InodeExtents, InodeLike, MountOptions, StatFs, SuperBlock,
+    },
 };
 use std::{error, path::Path};
 
@@ -82,7 +87,13 @@ pub trait FileSysSupport: Sized {
     ///
     /// You do **not** need to deserialize each individual object in each region to check that it is indeed a valid object; to keep matters simple, we will assume that the contents of each region has been properly initialized.
     /// Additionally, we could add `dev` to the return type to reclaim ownership in case of an error, but we do not bother recovering invalid devices, for simplicity reasons.
-    fn mountfs(dev: Device) -> Result<Self, Self::Error>;
+    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
+        Self::mountfs_with(dev, &MountOptions::default())
+    }
+
+    /// Like `mountfs`, but mount the file system with the given options rather than the default ones.
+    /// The options only affect the mounted file system, and are not written to `dev`.
+    fn mountfs_with(dev: Device, opts: &MountOptions) -> Result<Self, Self::Error>;
 
     /// Unmount the give file system, thereby consuming it
     /// Returns the image of the file system, i.e. the `Device` backing it.
@@ -95,7 +106,7 @@ pub trait FileSysSupport: Sized {
 pub trait BlockSupport: FileSysSupport {
     /// Read the *n*th block *of the entire disk* and return it\
     /// The implementation of this method should be trivial
-    fn b_get(&self, i: u64) -> Result<Block, Self::Error>;
+    fn b_get(&self, i: BlockNo) -> Result<Block, Self::Error>;
 
     /// Write the *n*th block *of the entire disk* and return it\
     /// The implementation of this method should be trivial
@@ -111,11 +122,16 @@ pub trait BlockSupport: FileSysSupport {
     ///
     /// If the *i*th block is already a free block, the state of memory should not change, and this method should return an error
     /// Also errors if the index *i* is out of bounds, i.e. if it is higher than the number of data blocks
-    fn b_free(&mut self, i: u64) -> Result<(), Self::Error>;
+    ///
+    /// The contents of the freed block are then handled according to the `discard` mount option (see [`DiscardPolicy`](../types/enum.DiscardPolicy.html)).
+    /// Inside an operation, the block is only discarded once the outermost operation has been committed, and not at all if it is allocated again before that.
+    ///
+    /// Bad blocks and the block holding the bad-block list (see `b_mark_bad`) can never be freed; trying to do so errors as well.
+    fn b_free(&mut self, i: DataIdx) -> Result<(), Self::Error>;
 
     /// Zeroes the *i*th block *in the block data region* in memory (without freeing it)\
     /// Errors if the index *i* is out of bounds, i.e. if it is higher than the number of data blocks
-    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error>;
+    fn b_zero(&mut self, i: DataIdx) -> Result<(), Self::Error>;
 
     /// Allocate the first free block, starting from the beginning, in the data block region, thereby setting its bit in the bitmap region to one and the entire contents of the block to zero.
     /// Again, you will need bit-wise operators to implement this function.
@@ -123,7 +139,41 @@ pub trait BlockSupport: FileSysSupport {
     /// Returns the index (*within the data region*) of the newly allocated block.
     /// Make sure to load each *bitmap* block only once in your implementation.
     /// Errors appropriately if no blocks are available.
-    fn b_alloc(&mut self) -> Result<u64, Self::Error>;
+    ///
+    /// Outside a privileged context (see `b_set_privileged`), the last `nreserved` free data blocks (see the `SuperBlock`) count as unavailable.
+    ///
+    /// The above describes the default allocation policy; a file system mounted with a different [`AllocStrategy`](../alloc/enum.AllocStrategy.html) allocates whichever free block that policy picks.
+    fn b_alloc(&mut self) -> Result<DataIdx, Self::Error>;
+
+    /// Like `b_alloc`, but allocate a block close to the data block with index `goal`, to keep related data together.
+    /// In a file system with block groups, the first free block of the group containing `goal`, at or after `goal`, is allocated, and the next groups are tried in order if this group is full.
+    /// Without block groups, the first free block at or after `goal` is allocated, wrapping around to the start of the data region if necessary.
+    /// Errors appropriately if `goal` is out of bounds, or if no blocks are available.
+    /// As for `b_alloc`, this is the behaviour of the default allocation policy.
+    fn b_alloc_near(&mut self, goal: DataIdx) -> Result<DataIdx, Self::Error>;
+
+    /// Enter (`true`) or leave (`false`) a privileged context, in which allocations may also use the reserved data blocks.
+    /// Returns whether the file system was in a privileged context before.
+    /// File systems are mounted outside a privileged context.
+    fn b_set_privileged(&mut self, privileged: bool) -> bool;
+
+    /// Run `op` on this file system in a privileged context, and restore the previous context afterwards, also when `op` fails.
+    fn as_privileged<T, G>(&mut self, op: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&mut Self) -> Result<T, Self::Error>,
+    {
+        let old = self.b_set_privileged(true);
+        let result = op(self);
+        self.b_set_privileged(old);
+        result
+    }
+
+    /// Report the number of data blocks of this file system, and how many of them are free, reserved and available outside a privileged context
+    fn statfs(&self) -> Result<StatFs, Self::Error>;
+
+    /// Walk the free bit map and report how fragmented the free data blocks are, see [`FragReport`](../types/struct.FragReport.html).
+    /// Bad blocks are never free, so they split the runs they are part of.
+    fn b_frag_report(&self) -> Result<FragReport, Self::Error>;
 
     /// Get the superblock describing the current file system
     fn sup_get(&self) -> Result<SuperBlock, Self::Error>;
@@ -133,6 +183,92 @@ pub trait BlockSupport: FileSysSupport {
     /// In this case, that is not strictly necessary, as the superblock is the only useful thing that is stored on the first disk block.
     /// However, in case other data were to be stored past the superblock struct in the future, do implement this function in this conservative way.
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error>;
+
+    /// Start a file system operation, i.e. a group of block writes that should either all reach the disk or not at all.
+    /// Operations can be nested; the writes of nested operations become part of the outermost one.
+    /// Every call to `begin_op` has to be matched by a call to `end_op`, also when the operation fails.
+    ///
+    /// If the file system has a log (see the `nlog` field of the `SuperBlock`), all writes until the matching `end_op` are kept in memory, and subsequent reads of these blocks see the new contents.
+    /// Without a log, this method does nothing.
+    fn begin_op(&mut self) -> Result<(), Self::Error>;
+
+    /// End the current file system operation.
+    /// If this ends the outermost operation, all blocks written since the matching `begin_op` are committed, in the style of xv6:
+    /// they are first written to the log, then the log header is written to mark the operation as committed, after which the blocks are written to their actual location and the log header is cleared.
+    /// When a crash happens after the operation has been committed, `mountfs` finishes writing the blocks listed in the log.
+    ///
+    /// If the operation wrote more distinct blocks than the log can hold, none of its writes are committed, and this method errors.
+    /// Errors as well if there is no operation to end.
+    fn end_op(&mut self) -> Result<(), Self::Error>;
+
+    /// Start a transaction, i.e. an operation (see `begin_op`) that can be rolled back as well.
+    /// All writes until the matching `b_tx_commit` or `b_tx_rollback` are kept in memory, also if the file system has no log, and subsequent reads see the new contents.
+    /// Prefer [`transaction`](#method.transaction), which cannot be left open by accident.
+    fn b_tx_begin(&mut self) -> Result<(), Self::Error>;
+
+    /// Commit the current transaction, and end the operation it started (see `end_op`).
+    /// Without a log, the writes of the transaction are written to the disk directly once no other transaction is running anymore.
+    /// Errors if there is no transaction to commit.
+    fn b_tx_commit(&mut self) -> Result<(), Self::Error>;
+
+    /// Throw away all writes made since the matching `b_tx_begin`, which undoes all allocations and frees made since then as well, and end the operation it started.
+    /// Errors if there is no transaction to roll back.
+    fn b_tx_rollback(&mut self) -> Result<(), Self::Error>;
+
+    /// Start a transaction that is committed with [`Transaction::commit`](../transaction/struct.Transaction.html#method.commit), and rolled back when it is dropped before that
+    fn transaction(&mut self) -> Result<Transaction<'_, Self>, Self::Error> {
+        Transaction::begin(self)
+    }
+
+    /// Grow the file system while it is mounted, so that it spans `nblocks` blocks and has room for `ninodes` inodes.
+    /// The underlying device is grown to `nblocks` blocks first, if it is smaller than that, and gets its old size back if growing fails.
+    /// All new blocks are added to the data region, and the bitmap region is extended to keep track of them.
+    /// Pass the current number of inodes as `ninodes` to leave the inode region as it is.
+    ///
+    /// If the inode or bitmap region needs more blocks than it has, these blocks are taken from the start of the data region.
+    /// Data blocks that are in use there have to be relocated first; this is only possible at the levels of abstraction that know where the pointers to these blocks are stored, i.e. the block layer errors instead.
+    /// Newly added inodes are marked as free.
+    ///
+    /// Blocks the old geometry does not use are written first; all other writes form a single operation, and the superblock is written last.
+    /// With a log, this operation is committed atomically.
+    /// Without a log, the superblock write is the only commit point, so growing errors if it would have to change a block the old geometry uses.
+    /// Growing the inode or bitmap region shifts the data region, and so its bits in the bitmap, so a file system without a log can in practice only grow its data region into the room its bitmap has left, unless none of its data blocks are in use.
+    /// Errors and leaves the file system unchanged if `nblocks` or `ninodes` is smaller than the current value, or if the blocks that are in the way cannot be relocated.
+    /// File systems with block groups cannot be resized, so this method errors for them, and so does `b_shrink`.
+    fn b_grow(&mut self, nblocks: u64, ninodes: u64) -> Result<(), Self::Error>;
+
+    /// Shrink the file system stored on the unmounted device `dev`, so that it spans `nblocks` blocks, and return the shrunk device.
+    /// Data blocks in use past the new end are relocated to free data blocks before it, and the pointers to them are updated.
+    /// Like for `b_grow`, the block layer cannot update these pointers, and errors instead.
+    /// The bitmap region shrinks to the number of blocks it still needs, and the blocks it gives up are added to the data region.
+    /// The inode region is left as it is.
+    /// Finally, the superblock is updated and the device is truncated to `nblocks` blocks.
+    /// Like for `b_grow`, all writes to blocks the old geometry uses form a single operation, so without a log, shrinking errors if it needs any of them.
+    /// Relocating a block or giving up a bitmap block both change such blocks, so a file system without a log can in practice only drop free blocks at the end of its data region.
+    /// A shrink that errors for this reason may already have copied blocks to data blocks that are free, but leaves the file system itself unchanged.
+    ///
+    /// Errors and leaves the image unchanged if `nblocks` is larger than the current size, or if the data in use does not fit in the shrunk file system.
+    fn b_shrink(dev: Device, nblocks: u64) -> Result<Device, Self::Error>;
+
+    /// Discard all data blocks that are currently free according to `policy`, regardless of the `discard` mount option, and return how many blocks were discarded.
+    /// Runs of adjacent free blocks are discarded together.
+    /// Discarding with `DiscardPolicy::Keep` does nothing and returns 0.
+    /// Errors if an operation is still running, since the blocks it freed have not been committed yet.
+    fn b_trim(&mut self, policy: DiscardPolicy) -> Result<u64, Self::Error>;
+
+    /// Mark the data block `b` as bad, so that it is never allocated again, and add it to the persistent bad-block list (see the `badlist` field of the `SuperBlock`).
+    /// If `b` is in use, its contents are first copied to a newly allocated block, and the pointers to it are updated.
+    /// Like for `b_grow`, the block layer cannot update these pointers, and errors instead.
+    ///
+    /// The list itself is stored in a data block that is allocated when the first block is marked bad, and that moves to a new block when its own block is marked bad.
+    /// Blocks needed by this method may be taken from the reserved blocks.
+    /// Errors if `b` is not a data block, if it is already marked bad, or if the list is full.
+    /// File systems with bad blocks cannot be resized.
+    /// Mounting checks the list: every bad block, and the block of the list itself, has to be marked in use, and at the levels of abstraction with inodes, no inode in use may refer to a bad block.
+    fn b_mark_bad(&mut self, b: BlockNo) -> Result<(), Self::Error>;
+
+    /// Returns the blocks that are marked bad, in the order in which they were marked
+    fn b_bad_blocks(&self) -> Result<Vec<BlockNo>, Self::Error>;
 }
 
 /// This trait adds the abstraction of inodes to your file system.
@@ -166,14 +302,53 @@ pub trait InodeSupport: BlockSupport {
     /// The inode with index 0 should *never* be allocated.
     /// Errors appropriately if no inodes are available
     /// Only read each inode block once in your implementation
+    ///
+    /// With an allocation policy other than the default one, the free inode that policy picks is allocated instead, see `b_alloc`.
+    ///
+    /// If the superblock lists an inode bit map region, the free inodes are looked up there rather than in the inode region, and every inode write keeps the bit map in sync with the `ft` of the inode.
+    /// A search for a free inode starts at the lowest inode that may be free, and a file system without free inodes errors without reading anything.
+    ///
+    /// All timestamps of the new inode are set to the current time of the file system's clock.
+    /// The new inode is owned by root (user and group 0), and gets [`DEFAULT_DIR_MODE`](../types/constant.DEFAULT_DIR_MODE.html) if it is a directory and [`DEFAULT_FILE_MODE`](../types/constant.DEFAULT_FILE_MODE.html) otherwise.
     fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error>;
 
+    /// Like `i_alloc`, but prefer an inode close to the inode `parent`, typically the directory the new inode will be linked into.
+    /// In a file system with block groups, the first free inode in the group of `parent` is allocated, and the next groups are tried in order if this group is full.
+    /// In a file system with an inode bit map, the first free inode after `parent` is allocated, wrapping around at the last inode.
+    /// Otherwise, this is the same as `i_alloc`.
+    /// This is what the default allocation policy does; other policies are free to ignore `parent`, see `b_alloc`.
+    /// Errors appropriately if `parent` is out of bounds, or if no inodes are available.
+    fn i_alloc_near(&mut self, ft: FType, parent: u64) -> Result<u64, Self::Error>;
+
+    /// For every inode in use, report how many data blocks it points to and into how many runs of consecutive blocks these are split, in increasing order of inode number.
+    /// A file with about as many extents as blocks is badly fragmented, and a good candidate for defragmentation.
+    fn i_extent_summary(&self) -> Result<Vec<InodeExtents>, Self::Error>;
+
+    /// Replace the clock the timestamps of inodes are taken from, which is the [`SystemClock`](../clock/struct.SystemClock.html) after `mkfs` and `mountfs`.
+    /// The clock is not stored on the disk.
+    fn i_set_clock(&mut self, clock: Box<dyn Clock>);
+
     /// Truncate the given `inode`, i.e. release its contents (without freeing it).
     /// Sets all of the given inode's `direct_blocks` to point to address 0.
     /// Releases all blocks `direct_blocks` belonging to this inode, and sets its `size` to 0.
     /// Changes both the given `inode` and the corresponding inode on the disk.
     /// Note that only the first `size` blocks should be released as only these are allocated. In other words, do not blindly release all values listed in the `direct_blocks` field
+    /// The exception are blocks preallocated past the end of the file (see `i_fallocate`), which are released as well.
+    /// Sets the `mtime` and `ctime` of the inode to the current time.
     fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error>;
+
+    /// Shrink or extend the given `inode` to `new_size` bytes, like `truncate` does.
+    /// Shrinking releases the blocks that lie entirely past the new end, and zeroes the rest of the block the new end falls in, so that extending the file later does not bring the old bytes back.
+    /// Extending leaves a hole between the old and the new end, which takes up no blocks and reads back as zeroes (see `i_write`).
+    /// The released blocks and the inode are written in a single transaction, so that the free bit map and the inode never disagree, not even after a crash.
+    /// Changes both the given `inode` and the corresponding inode on the disk, and sets its `mtime` and `ctime` to the current time if its size changes.
+    /// Errors, and changes nothing, if `inode` is not a regular file or if `new_size` exceeds the maximum size of an inode.
+    fn i_truncate(&mut self, inode: &mut Self::Inode, new_size: u64) -> Result<(), Self::Error>;
+
+    /// Returns the largest size, in bytes, an inode of this file system can grow to.
+    /// This follows from the block size in the superblock and from how many blocks the inode format can point to, directly or through indirect blocks.
+    /// Writes past this size error.
+    fn i_max_size(&self) -> u64;
 }
 
 ///This trait additionally provides support to read and write from inodes using buffers; the data structure that we used before to hold the contents of a `Block`.
@@ -186,9 +361,13 @@ pub trait InodeRWSupport: InodeSupport {
     /// If a read starts at `inode.get_size()`, returns with 0 bytes read.
     /// By contrast, returns an error and does not read anything in case the provided index falls further outside of the file's bounds.
     ///If `buf` cannot hold `n` bytes of data, reads until `buf` is full instead.
+    ///
+    /// Sets the `atime` of the inode to the current time, both in `inode` and on the disk, if the [`AtimePolicy`](../types/enum.AtimePolicy.html) the file system was mounted with says so.
+    ///
+    /// Holes in the file (see `i_write`) read as zeroes, without reading any block from the device.
     fn i_read(
-        &self,
-        inode: &Self::Inode,
+        &mut self,
+        inode: &mut Self::Inode,
         buf: &mut Buffer,
         off: u64,
         n: u64,
@@ -197,11 +376,14 @@ pub trait InodeRWSupport: InodeSupport {
     /// Write `n` bytes of data from the given buffer `buff` into the inode `inode`, starting from byte offset `off`
     /// If the end of the file is reached while writing, **continue writing**.
     /// If necessary, start allocating extra blocks to expand the file and continue writing into the new blocks.
-    /// Allows writes to start at index `inode.get_size()`.
-    /// By contrast, returns an error and does not write anything in case the provided starting index falls further outside of the file's bounds.
+    /// Allows writes to start at index `inode.get_size()`, or further: a write past the end of the file leaves a *hole* between the old end and `off`.
+    /// No blocks are allocated for a hole; its block pointers stay 0, and it reads back as zeroes. Writing into a hole later allocates the blocks it writes to.
+    /// A write of 0 bytes changes nothing, wherever it starts.
     /// If the inode changes while writing, do not forget to write it back to the disk too.
     /// Returns an error if `buf` cannot hold at least `n` bytes of data.
     /// If the write would make the inode exceed its maximum possible size, do nothing and return an error.
+    /// A write of at least one byte sets the `mtime` and `ctime` of the inode to the current time.
+    /// On a file system with inline data, small contents are kept in the inode itself and move out to a data block once they grow past `INLINE_DATA_MAX` bytes.
     fn i_write(
         &mut self,
         inode: &mut Self::Inode,
@@ -209,6 +391,104 @@ pub trait InodeRWSupport: InodeSupport {
         off: u64,
         n: u64,
     ) -> Result<(), Self::Error>;
+
+    /// Returns the first offset at or after `off` that lies in data rather than in a hole, like `lseek` with `SEEK_DATA`.
+    /// Holes are tracked per block, so the returned offset is either `off` itself or the start of a block.
+    /// Returns `None` if `off` is at or past the end of the file, or if only holes follow it.
+    fn i_seek_data(&self, inode: &Self::Inode, off: u64) -> Result<Option<u64>, Self::Error>;
+
+    /// Returns the first offset at or after `off` that lies in a hole, like `lseek` with `SEEK_HOLE`.
+    /// The end of the file counts as a hole, so the size of the file is returned if no hole follows `off`.
+    /// Returns `None` if `off` is at or past the end of the file.
+    fn i_seek_hole(&self, inode: &Self::Inode, off: u64) -> Result<Option<u64>, Self::Error>;
+
+    /// Reserve space for the `len` bytes starting at offset `off` of `inode`, like `fallocate` does.
+    /// Allocates a zeroed block for every hole in the range, so that writing to it later cannot run out of space; blocks that are already there are left as they are.
+    /// Grows the size of the file to `off + len` if it ends before that, unless `keep_size` is set; the blocks past the end then stay allocated until the file is truncated.
+    /// A range of 0 bytes changes nothing.
+    /// The blocks and the inode are written in a single transaction, and a non-empty range sets the `mtime` and `ctime` of the inode to the current time.
+    /// Errors, and changes nothing, if `inode` is not a regular file, if the range exceeds the maximum size of an inode, or if the blocks run out.
+    fn i_fallocate(
+        &mut self,
+        inode: &mut Self::Inode,
+        off: u64,
+        len: u64,
+        keep_size: bool,
+    ) -> Result<(), Self::Error>;
+
+    /// Release the `len` bytes starting at offset `off` of `inode`, like `fallocate` with `FALLOC_FL_PUNCH_HOLE`.
+    /// Frees the blocks that lie entirely in the range, turning them into holes, and zeroes the bytes of the range in the blocks at its edges.
+    /// The size of the file never changes.
+    /// A range of 0 bytes changes nothing.
+    /// The blocks and the inode are written in a single transaction, and a non-empty range sets the `mtime` and `ctime` of the inode to the current time.
+    /// Errors, and changes nothing, if `inode` is not a regular file.
+    fn i_punch_hole(
+        &mut self,
+        inode: &mut Self::Inode,
+        off: u64,
+        len: u64,
+    ) -> Result<(), Self::Error>;
+
+    /// Zero the `len` bytes starting at offset `off` of `inode`, like `fallocate` with `FALLOC_FL_ZERO_RANGE`.
+    /// Allocates the range like `i_fallocate` does, with the same meaning of `keep_size`, and then zeroes every byte in it, so the range reads back as zeroes without leaving holes.
+    /// A range of 0 bytes changes nothing.
+    /// The blocks and the inode are written in a single transaction, and a non-empty range sets the `mtime` and `ctime` of the inode to the current time.
+    /// Errors, and changes nothing, if `inode` is not a regular file, if the range exceeds the maximum size of an inode, or if the blocks run out.
+    fn i_zero_range(
+        &mut self,
+        inode: &mut Self::Inode,
+        off: u64,
+        len: u64,
+        keep_size: bool,
+    ) -> Result<(), Self::Error>;
+
+    /// Returns whether `inode` has changes that have not been persisted to the disk yet.
+    /// This is the case if the inode was written since it was last synced, or if one of the blocks it refers to was written since it was last persisted.
+    fn i_is_dirty(&self, inode: &Self::Inode) -> Result<bool, Self::Error>;
+
+    /// Persist all changes to `inode`, like `fsync`.
+    /// Persists the dirty blocks it refers to, i.e. its contents, the blocks that map them and the block holding its extended attributes, as well as the block holding the inode itself and the dirty blocks that keep track of the allocation state of the file system.
+    /// Changes to other files are not persisted, unless they share one of these blocks.
+    /// Errors if an operation is running, as its writes have not reached the disk yet.
+    fn i_fsync(&mut self, inode: &Self::Inode) -> Result<(), Self::Error>;
+
+    /// Persist the changes to the contents of `inode`, like `fdatasync`.
+    /// Behaves like `i_fsync`, except that the inode itself is only persisted if it changed in a way that is needed to read back its contents, e.g. if its size or block pointers changed.
+    /// Changes to only its timestamps, permissions, ownership or link count, and to its extended attributes, are left unpersisted, so the inode stays dirty in that case.
+    /// Errors if an operation is running, as its writes have not reached the disk yet.
+    fn i_fdatasync(&mut self, inode: &Self::Inode) -> Result<(), Self::Error>;
+}
+
+///This trait adds extended attributes to inodes: named values, such as content hashes, provenance or labels, that are stored alongside the contents of a file.
+///Only file systems with [`FEATURE_XATTR`](../types/constant.FEATURE_XATTR.html) can store them; on other file systems, every method errors.
+///
+///The name of an attribute starts with one of the [`XATTR_NAMESPACES`](../types/constant.XATTR_NAMESPACES.html) followed by at least one more byte, and is at most [`XATTR_NAME_MAX`](../types/constant.XATTR_NAME_MAX.html) bytes long.
+///The inode layer does not check who accesses which namespace; the path-level counterparts of these methods in [`PathSupport`] do.
+///All attributes of an inode are stored together in a single block, which is allocated for the first attribute, freed together with the last one, and freed by `i_free` as well.
+///
+/// [`PathSupport`]: ../fs/trait.PathSupport.html
+pub trait XattrSupport: InodeSupport {
+    /// Returns the value of the attribute `name` of `inode`, or `None` if it has no such attribute.
+    /// Errors if `name` is not a valid attribute name.
+    fn i_getxattr(&self, inode: &Self::Inode, name: &str) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// Sets the attribute `name` of `inode` to `value`, adding it if `inode` does not have it yet, and sets the `ctime` of the inode to the current time.
+    /// Changes both the given `inode` and the corresponding inode on the disk.
+    /// Errors, and changes nothing, if `name` is not a valid attribute name, or if the attributes of `inode` would no longer fit in a block.
+    fn i_setxattr(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), Self::Error>;
+
+    /// Returns the names of all attributes of `inode`, in sorted order
+    fn i_listxattr(&self, inode: &Self::Inode) -> Result<Vec<String>, Self::Error>;
+
+    /// Removes the attribute `name` of `inode`, and sets the `ctime` of the inode to the current time.
+    /// Changes both the given `inode` and the corresponding inode on the disk.
+    /// Errors, and changes nothing, if `name` is not a valid attribute name, or if `inode` has no such attribute.
+    fn i_removexattr(&mut self, inode: &mut Self::Inode, name: &str) -> Result<(), Self::Error>;
 }
 
 ///This trait adds the abstraction of directories and their entries to the file system
@@ -261,6 +541,8 @@ pub trait DirectorySupport: InodeSupport {
     ///
     /// Returns the byte offset at which the entry was written into the given `inode`
     ///
+    /// The `mtime` and `ctime` of `inode` are set to the current time, and so is the `ctime` of the inode whose `nlink` increases.
+    ///
     /// *EXTRA*:In our model, we do not have to worry about hardlink-loops in our file system tree, as we currently provide no way of duplicating inodes.
     fn dirlink(
         &mut self,
@@ -287,7 +569,7 @@ pub trait DirectorySupport: InodeSupport {
 /// **However**, you can assume that the parent of the cwd **is** the previous directory in the path when appending a relative path in the `set_cwd` method. This is also how terminals usually operate when e.g. following symbolic links and then running `cd ..`, in order not to confuse users.
 /// That being said, any paths provided to other methods than `set_cwd` **should go through the file system**, i.e. read ".." at each point to figure out what the actual parent inode is.
 /// If this explanation is unclear to you, consult the tests provided with the assignment
-pub trait PathSupport: DirectorySupport {
+pub trait PathSupport: DirectorySupport + XattrSupport {
     /// Returns true iff the given string represents a valid path
     /// We support two different path formats:
     /// - *absolute paths* start with the character "/" and specify a path starting from the root node
@@ -313,6 +595,13 @@ pub trait PathSupport: DirectorySupport {
     /// As stated above, you should then **not** go through the file system to determine the new cwd, but rather have ".." cancel out the previous directory name, i.e. if the cwd is `test/child` then providing `../child_new` to this method, will unconditionally result in a new cwd of `test/child_new`, whereas it will not necessarily lead there when we read through the file system in the other methods.
     fn set_cwd(&mut self, path: &str) -> Option<()>;
 
+    ///Return the credentials path-level operations are currently performed with
+    fn get_credentials(&self) -> Credentials;
+
+    ///Perform all following path-level operations on behalf of `cred`, until the credentials are set again.
+    ///Like the cwd, the credentials are not stored on the disk; a freshly created or mounted file system uses the credentials of root, i.e. [`Credentials::root`](../types/struct.Credentials.html#method.root).
+    fn set_credentials(&mut self, cred: Credentials);
+
     ///Given a path name (possibly relative to the cwd), look up the inode corresponding to this path (the final inode could be either a file or a directory), and return it.
     ///Works as follows:
     ///1. Figures out what inode the cwd corresponds to (skip this step if `path` is absolute)
@@ -322,8 +611,74 @@ pub trait PathSupport: DirectorySupport {
     /// - the path is invalid
     /// - any directory, referenced in the path (or the cwd), does not exist
     /// - any of the intermediate names refers to a file that is not of directory type.
+    /// - the credentials do not allow searching (see [`Access::Exec`](../types/enum.Access.html)) any of the directories a name is looked up in, including the root and the directories on the way to the cwd.
+    /// - more than [`MAX_SYMLINK_HOPS`](../types/constant.MAX_SYMLINK_HOPS.html) symbolic links have to be followed, which is how loops of links are detected.
+    ///
+    ///Symbolic links are followed wherever they occur in the path, including the final name, so the returned inode is never a link.
+    ///A link is followed by continuing the lookup with its target instead of its name: from the root if the target is absolute, and from the directory containing the link otherwise.
     fn resolve_path(&self, path: &str) -> Result<Self::Inode, Self::Error>;
 
+    ///Like `resolve_path`, but if the final name of `path` is a symbolic link, the link itself is returned rather than the file it points to.
+    ///Links in the intermediate names are still followed.
+    fn lresolve_path(&self, path: &str) -> Result<Self::Inode, Self::Error>;
+
+    ///Create a symbolic link at `path`, pointing to `target`, and return its inode.
+    ///The target is stored as is, and does not have to exist; it is only resolved when the link is followed.
+    ///Targets up to [`SYMLINK_INLINE_MAX`](../types/constant.SYMLINK_INLINE_MAX.html) bytes long are stored inline in the inode of the link; longer ones in data blocks.
+    ///Like a new directory, the link is owned by the caller, and gets its group the same way as in `mkdir`.
+    ///
+    ///Errors
+    /// - if `path` is not valid, or its last name is "." or ".."
+    /// - if `target` is empty, ends in "/", or contains a name that is not a valid directory entry name; unlike in paths, a target can be relative without starting with "." or ".."
+    /// - if the directory the link is created in does not exist, or already contains an entry with the same name
+    /// - if the credentials do not allow writing to and searching that directory
+    fn symlink(&mut self, target: &str, path: &str) -> Result<Self::Inode, Self::Error>;
+
+    ///Return the target of the symbolic link at `path`, without following it.
+    ///
+    ///Errors if resolving the path with `lresolve_path` fails, or if it does not lead to a symbolic link.
+    fn readlink(&self, path: &str) -> Result<String, Self::Error>;
+
+    ///Resolve `path` like `resolve_path`, and check that the credentials allow `access` to the inode it leads to.
+    ///This is the gate for reading and writing files by path: the returned inode is meant to be passed to the read and write methods of the inode layer.
+    ///
+    ///Errors if resolving the path fails, or if the credentials do not permit `access`.
+    fn open(&self, path: &str, access: Access) -> Result<Self::Inode, Self::Error>;
+
+    ///Set the mode of the file at `path` to `mode`, keeping only the bits in [`MODE_MASK`](../types/constant.MODE_MASK.html).
+    ///If the caller is not root and not a member of the group of the file, the setgid bit is cleared.
+    ///
+    ///Errors if resolving the path fails, or if the caller is neither the owner of the file nor root.
+    fn chmod(&mut self, path: &str, mode: u16) -> Result<(), Self::Error>;
+
+    ///Change the owner of the file at `path` to `uid` and its group to `gid`, and clear its setuid and setgid bits.
+    ///
+    ///Errors if resolving the path fails, or if the caller is not root.
+    fn chown(&mut self, path: &str, uid: u32, gid: u32) -> Result<(), Self::Error>;
+
+    ///Return the value of the extended attribute `name` of the file at `path`, or `None` if it has no such attribute; see [`XattrSupport`](../fs/trait.XattrSupport.html).
+    ///
+    ///Errors if resolving the path fails, if `name` is not a valid attribute name, or if the credentials do not permit reading the attribute:
+    ///attributes in the `user.` namespace can be read by anyone who may read the file, those in the `trusted.` namespace by root only.
+    fn getxattr(&self, path: &str, name: &str) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    ///Set the extended attribute `name` of the file at `path` to `value`.
+    ///
+    ///Errors if resolving the path fails, if setting the attribute on the inode fails, or if the credentials do not permit writing the attribute:
+    ///attributes in the `user.` namespace can be written by anyone who may write the file, those in the `trusted.` namespace by root only.
+    fn setxattr(&mut self, path: &str, name: &str, value: &[u8]) -> Result<(), Self::Error>;
+
+    ///Return the names of the extended attributes of the file at `path`, in sorted order.
+    ///Attributes in the `trusted.` namespace are only listed for root.
+    ///
+    ///Errors if resolving the path fails.
+    fn listxattr(&self, path: &str) -> Result<Vec<String>, Self::Error>;
+
+    ///Remove the extended attribute `name` of the file at `path`.
+    ///
+    ///Errors if resolving the path fails, if removing the attribute from the inode fails, or if the credentials do not permit writing the attribute, like for `setxattr`.
+    fn removexattr(&mut self, path: &str, name: &str) -> Result<(), Self::Error>;
+
     ///Create a new directory at the given path, where the last name of the path is the name for the new directory.
     ///For example, the path `/test/dir` will create a directory named `dir` in the parent directory `test`.
     ///Returns the newly created inode in case of success. Note that the newly created directory is already referenced once, i.e. its `nlink` field is not 0.
@@ -331,10 +686,14 @@ pub trait PathSupport: DirectorySupport {
     ///New directories are created with 2 default entries in them, i.e. "." and ".." (in this order), that point to the current directory's inode and its parent's inode, respectively. This allows us to easily go back to the parent directory of the current directory, and to succinctly reference sibling files and folders in the same directory.
     ///Note that the addition of ".." causes the `nlink` field of the parent directory to increase by 1.
     ///
+    ///The new directory is owned by the caller and gets [`DEFAULT_DIR_MODE`](../types/constant.DEFAULT_DIR_MODE.html).
+    ///If the parent directory has the setgid bit set, the new directory gets the group of the parent, and the setgid bit as well; otherwise, it gets the primary group of the caller.
+    ///
     ///Error
     /// - if the path is not valid
     /// - if the path's prefix (i.e. the part of the path without the name of the directory that we are about to create) does not exist in the file system yet.
     /// - if the last part of the path is not a valid directory name (i.e. it cannot be "." or "..")
+    /// - if the credentials do not allow writing to and searching the parent directory
     fn mkdir(&mut self, path: &str) -> Result<Self::Inode, Self::Error>;
 
     ///Remove the directory entry located at path `path`, i.e. set the `inum` of this entry to 0 and the name of the entry to all zeroes, i.e. to `"0".repeat(*DIRNAME_SIZE)`
@@ -349,6 +708,8 @@ pub trait PathSupport: DirectorySupport {
     ///- the path does not exist yet in the file system, which includes the next bullet;
     ///- the entry is not present in the directory
     ///- the entry we are about to delete is itself a directory and non-empty (apart from the 2 default entries) - note: you cannot judge emptiness just from the size of the file, as it might contain directory entries that were previously unlinked as well
+    ///- the credentials do not allow writing to and searching the parent directory
+    ///- the parent directory has the sticky bit set, and the caller is neither root nor the owner of the entry or of the parent directory
     fn unlink(&mut self, path: &str) -> Result<(), Self::Error>;
 }
 
@@ -0,0 +1,721 @@
+//! Module to compute a file system layout from a handful of high-level parameters.
+//!
+//! Filling in a [`SuperBlock`](../types/struct.SuperBlock.html) by hand requires computing the start of every region and making sure the regions are large enough to hold the inodes and bitmap bits they are supposed to hold.
+//! The [`LayoutBuilder`] below does this computation for you: given a block size, the size of the device and either an inode count or a number of bytes per inode, it derives a valid superblock where all regions are packed as tightly as possible, i.e.
+//!     \[super block | inode blocks | free bit map | data blocks\]
+//! without any unused blocks in between.
+//!
+//! Optionally, an inode bit map region is placed in front of the inode region, see [`LayoutBuilder::inode_bitmap`](struct.LayoutBuilder.html#method.inode_bitmap).
+//!
+//! Optionally, the builder computes an ext2-style layout with block groups instead, see [`LayoutBuilder::block_groups`](struct.LayoutBuilder.html#method.block_groups) and the documentation of [`GroupDesc`](../types/struct.GroupDesc.html).
+//!
+//! [`LayoutBuilder`]: struct.LayoutBuilder.html
+
+use super::error_given;
+use super::error_given::APIError;
+use super::types::{
+    GroupDesc, SuperBlock, DINODE_SIZE, FEATURE_XATTR, GROUPDESC_SIZE, SUPERBLOCK_SIZE,
+    XATTR_PTR_SIZE,
+};
+use std::ops::Range;
+
+/// Number of bits in a byte, i.e. the number of data blocks a single byte of the bitmap keeps track of
+const BITS_PER_BYTE: u64 = 8;
+
+/// Number of bytes a block number takes up in the log header
+const BYTES_PER_BLOCK_NO: u64 = 8;
+
+/// The way the size of the file system was specified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Size {
+    /// Size in blocks
+    Blocks(u64),
+    /// Size in bytes, rounded down to a whole number of blocks
+    Bytes(u64),
+}
+
+/// The way the number of inodes was specified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Inodes {
+    /// Explicit number of inodes, including the unused inode 0
+    Count(u64),
+    /// One inode for every so many bytes of the file system
+    BytesPerInode(u64),
+}
+
+/// The way the number of reserved data blocks was specified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reserved {
+    /// Explicit number of reserved data blocks
+    Blocks(u64),
+    /// Percentage of the data blocks, rounded down
+    Percent(u64),
+}
+
+/// Builder that derives a tightly packed `SuperBlock` from high-level parameters.
+///
+/// Both the size of the file system and the number of inodes have to be specified before calling [`build`](#method.build).
+/// The size of a single inode defaults to `DINODE_SIZE`, but can be overridden for file systems that use their own inode type.
+///
+/// ```
+/// use cplfs_api::layout::LayoutBuilder;
+///
+/// let layout = LayoutBuilder::new(1000)
+///     .total_blocks(10)
+///     .ninodes(6)
+///     .build()
+///     .unwrap();
+/// assert_eq!(layout.sb.inodestart, 1);
+/// assert_eq!(layout.data_blocks.end, 10);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutBuilder {
+    block_size: u64,
+    size: Option<Size>,
+    inodes: Option<Inodes>,
+    inode_size: u64,
+    groupsize: Option<u64>,
+    nlog: u64,
+    reserved: Reserved,
+    features: u64,
+    inode_bitmap: bool,
+}
+
+impl LayoutBuilder {
+    /// Start building a layout for a file system with blocks of `block_size` bytes
+    pub fn new(block_size: u64) -> LayoutBuilder {
+        LayoutBuilder {
+            block_size,
+            size: None,
+            inodes: None,
+            inode_size: *DINODE_SIZE,
+            groupsize: None,
+            nlog: 0,
+            reserved: Reserved::Blocks(0),
+            features: 0,
+            inode_bitmap: false,
+        }
+    }
+
+    /// Make the file system `nblocks` blocks large, including the super block
+    pub fn total_blocks(mut self, nblocks: u64) -> LayoutBuilder {
+        self.size = Some(Size::Blocks(nblocks));
+        self
+    }
+
+    /// Make the file system `nbytes` bytes large.
+    /// Any trailing bytes that do not make up a full block are not used.
+    pub fn total_bytes(mut self, nbytes: u64) -> LayoutBuilder {
+        self.size = Some(Size::Bytes(nbytes));
+        self
+    }
+
+    /// Reserve room for exactly `ninodes` inodes.
+    /// Like the `ninodes` field of the `SuperBlock`, this number includes inode 0, which is never used.
+    pub fn ninodes(mut self, ninodes: u64) -> LayoutBuilder {
+        self.inodes = Some(Inodes::Count(ninodes));
+        self
+    }
+
+    /// Reserve room for one inode per `bytes` bytes of the file system, rounding down
+    pub fn bytes_per_inode(mut self, bytes: u64) -> LayoutBuilder {
+        self.inodes = Some(Inodes::BytesPerInode(bytes));
+        self
+    }
+
+    /// Use inodes of `inode_size` bytes instead of `DINODE_SIZE` to compute the size of the inode region
+    pub fn inode_size(mut self, inode_size: u64) -> LayoutBuilder {
+        self.inode_size = inode_size;
+        self
+    }
+
+    /// Enable the optional on-disk `features` of the file system, a combination of the `FEATURE_*` flags of the [`SuperBlock`](../types/struct.SuperBlock.html).
+    /// By default, no features are enabled.
+    pub fn features(mut self, features: u64) -> LayoutBuilder {
+        self.features = features;
+        self
+    }
+
+    /// Keep track of the free inodes in an inode bit map region, right before the inode region, see the `imapstart` field of the `SuperBlock`.
+    /// By default, the file system has no inode bit map. Layouts with block groups do not support one.
+    pub fn inode_bitmap(mut self) -> LayoutBuilder {
+        self.inode_bitmap = true;
+        self
+    }
+
+    /// Reserve a log region of `nlog` blocks, including the log header, right after the super block.
+    /// The log has to be large enough to hold all blocks written by a single file system operation.
+    /// By default, the file system has no log.
+    pub fn log_blocks(mut self, nlog: u64) -> LayoutBuilder {
+        self.nlog = nlog;
+        self
+    }
+
+    /// Split the file system in block groups of `groupsize` blocks each, rather than using a single inode, bitmap and data region.
+    /// As many groups as fit on the device are created, and the inodes are spread evenly over them, rounding the number of inodes up to a multiple of the number of groups.
+    /// Since every group has a single bitmap block, a group can hold at most 8 times the block size of data blocks.
+    pub fn block_groups(mut self, groupsize: u64) -> LayoutBuilder {
+        self.groupsize = Some(groupsize);
+        self
+    }
+
+    /// Reserve `n` data blocks for privileged writers, see the `nreserved` field of the `SuperBlock`.
+    /// By default, no data blocks are reserved.
+    pub fn reserved_blocks(mut self, n: u64) -> LayoutBuilder {
+        self.reserved = Reserved::Blocks(n);
+        self
+    }
+
+    /// Reserve `percent` percent of the data blocks for privileged writers, rounded down, like ext2 does.
+    pub fn reserved_percent(mut self, percent: u64) -> LayoutBuilder {
+        self.reserved = Reserved::Percent(percent);
+        self
+    }
+
+    /// Compute the layout.
+    /// The inode region starts right after the super block, the log and the inode bit map, if any, the bitmap right after the inode region and the data region right after the bitmap.
+    /// All blocks that remain after the inode region are split between the bitmap and the data region, so that the bitmap is just large enough to keep track of all data blocks.
+    ///
+    /// Errors if any of the parameters is missing or zero, if an inode or a super block does not fit in a single block, if the log header cannot list all log blocks, if the device is too small to hold at least one inode block, one bitmap block and one data block, or if more data blocks are reserved than there are.
+    pub fn build(&self) -> error_given::Result<Layout> {
+        let mut layout = self.build_regions()?;
+        let ndatablocks = layout.sb.ndatablocks;
+        layout.sb.nreserved = match self.reserved {
+            Reserved::Blocks(n) if n <= ndatablocks => n,
+            Reserved::Percent(p) if p <= 100 => ndatablocks * p / 100,
+            _ => {
+                return Err(APIError::LayoutInput(
+                    "Cannot reserve more data blocks than there are",
+                ))
+            }
+        };
+        Ok(layout)
+    }
+
+    /// Returns the number of bytes an inode takes up in the inode region, which includes the number of its attribute block with `FEATURE_XATTR`
+    fn slot_size(&self) -> u64 {
+        if self.features & FEATURE_XATTR != 0 {
+            self.inode_size + XATTR_PTR_SIZE
+        } else {
+            self.inode_size
+        }
+    }
+
+    /// Compute the regions of the layout, without any reserved blocks
+    fn build_regions(&self) -> error_given::Result<Layout> {
+        let bs = self.block_size;
+        if bs == 0 || self.inode_size == 0 {
+            return Err(APIError::LayoutInput(
+                "Block size and inode size must be positive",
+            ));
+        }
+        if self.slot_size() > bs {
+            return Err(APIError::LayoutInput("An inode does not fit in a block"));
+        }
+        if *SUPERBLOCK_SIZE > bs {
+            return Err(APIError::LayoutInput(
+                "The super block does not fit in a block",
+            ));
+        }
+
+        let nblocks = match self.size {
+            Some(Size::Blocks(n)) => n,
+            Some(Size::Bytes(n)) => n / bs,
+            None => return Err(APIError::LayoutInput("No file system size given")),
+        };
+        let ninodes = match self.inodes {
+            Some(Inodes::Count(n)) => n,
+            Some(Inodes::BytesPerInode(0)) => {
+                return Err(APIError::LayoutInput("Bytes per inode must be positive"))
+            }
+            Some(Inodes::BytesPerInode(b)) => {
+                nblocks
+                    .checked_mul(bs)
+                    .map(|n| n / b)
+                    .ok_or(APIError::LayoutInput(
+                        "File system size in bytes does not fit in 64 bits",
+                    ))?
+            }
+            None => return Err(APIError::LayoutInput("No inode count given")),
+        };
+        if ninodes == 0 {
+            return Err(APIError::LayoutInput(
+                "File system needs at least one inode",
+            ));
+        }
+
+        let logstart = match self.nlog {
+            0 => 0,
+            1 => {
+                return Err(APIError::LayoutInput(
+                    "A log needs at least one block besides its header",
+                ))
+            }
+            n if log_capacity(bs) < n - 1 => {
+                return Err(APIError::LayoutInput(
+                    "Log header cannot list all log blocks",
+                ))
+            }
+            _ => 1,
+        };
+        let inodes_per_block = bs / self.slot_size();
+        if let Some(groupsize) = self.groupsize {
+            return self.build_groups(bs, nblocks, ninodes, inodes_per_block, groupsize);
+        }
+        let (imapstart, imap_blocks) = match self.inode_bitmap {
+            false => (0, 0),
+            true => (1 + self.nlog, ninodes.div_ceil(bs * BITS_PER_BYTE)),
+        };
+        let inodestart = 1 + self.nlog + imap_blocks;
+        let bmapstart = inodestart + ninodes.div_ceil(inodes_per_block);
+        if bmapstart + 2 > nblocks {
+            return Err(APIError::LayoutInput(
+                "Device too small to hold the requested inodes and any data",
+            ));
+        }
+
+        // Every bitmap block keeps track of `bits_per_block` data blocks, so a chunk of `bits_per_block + 1` blocks is one bitmap block and the data blocks it tracks.
+        let bits_per_block = bs * BITS_PER_BYTE;
+        let remaining = nblocks - bmapstart;
+        let bmap_blocks = remaining.div_ceil(bits_per_block + 1);
+        let datastart = bmapstart + bmap_blocks;
+        let ndatablocks = nblocks - datastart;
+
+        let sb = SuperBlock {
+            block_size: bs,
+            nblocks,
+            ninodes,
+            inodestart,
+            ndatablocks,
+            bmapstart,
+            datastart,
+            ngroups: 0,
+            groupsize: 0,
+            logstart,
+            nlog: self.nlog,
+            nreserved: 0,
+            badlist: 0,
+            features: self.features,
+            imapstart,
+        };
+        Ok(Layout::new(sb))
+    }
+
+    /// Compute a layout with block groups of `groupsize` blocks
+    fn build_groups(
+        &self,
+        bs: u64,
+        nblocks: u64,
+        ninodes: u64,
+        inodes_per_block: u64,
+        groupsize: u64,
+    ) -> error_given::Result<Layout> {
+        if groupsize == 0 {
+            return Err(APIError::LayoutInput("Block groups must not be empty"));
+        }
+        if self.inode_bitmap {
+            return Err(APIError::LayoutInput(
+                "Block groups do not support an inode bit map",
+            ));
+        }
+        let descs_per_block = bs / *GROUPDESC_SIZE;
+        let gdtstart = 1 + self.nlog;
+        let mut ngroups = nblocks.saturating_sub(gdtstart) / groupsize;
+        while ngroups > 0
+            && gdtstart + ngroups.div_ceil(descs_per_block) + ngroups * groupsize > nblocks
+        {
+            ngroups -= 1;
+        }
+        if ngroups == 0 {
+            return Err(APIError::LayoutInput(
+                "Device too small to hold a single block group",
+            ));
+        }
+
+        let group_inodes = ninodes.div_ceil(ngroups);
+        let group_inode_blocks = group_inodes.div_ceil(inodes_per_block);
+        if group_inode_blocks + 2 > groupsize {
+            return Err(APIError::LayoutInput(
+                "Block group too small to hold its inodes and any data",
+            ));
+        }
+        let group_data_blocks = groupsize - 1 - group_inode_blocks;
+        if group_data_blocks > bs * BITS_PER_BYTE {
+            return Err(APIError::LayoutInput(
+                "Block group has more data blocks than a single bitmap block can keep track of",
+            ));
+        }
+
+        let bmapstart = gdtstart + ngroups.div_ceil(descs_per_block);
+        let sb = SuperBlock {
+            block_size: bs,
+            nblocks,
+            ninodes: group_inodes * ngroups,
+            inodestart: bmapstart + 1,
+            ndatablocks: group_data_blocks * ngroups,
+            bmapstart,
+            datastart: bmapstart + 1 + group_inode_blocks,
+            ngroups,
+            groupsize,
+            logstart: if self.nlog == 0 { 0 } else { 1 },
+            nlog: self.nlog,
+            nreserved: 0,
+            badlist: 0,
+            features: self.features,
+            imapstart: 0,
+        };
+        Ok(Layout::new(sb))
+    }
+}
+
+/// Maximum number of blocks a log header in a block of `block_size` bytes can list, i.e. the maximum number of blocks in a log besides its header.
+/// The header holds the number of blocks in the log, followed by their block numbers.
+pub fn log_capacity(block_size: u64) -> u64 {
+    (block_size / BYTES_PER_BLOCK_NO).saturating_sub(1)
+}
+
+/// Number of blocks taken up by the group descriptor region of the file system described by `sb`, i.e. 0 for a file system without block groups
+pub fn group_desc_blocks(sb: &SuperBlock) -> u64 {
+    match sb.block_size / *GROUPDESC_SIZE {
+        0 => sb.ngroups,
+        per_block => sb.ngroups.div_ceil(per_block),
+    }
+}
+
+/// Compute the descriptors of all block groups of the file system described by `sb`, as they are right after creating the file system, i.e. with all data blocks free.
+/// The location of every group follows from the regions of group 0 listed in `sb`.
+/// Returns an empty vector for a file system without block groups.
+pub fn group_descs(sb: &SuperBlock) -> Vec<GroupDesc> {
+    if sb.ngroups == 0 {
+        return vec![];
+    }
+    let free_blocks = sb.ndatablocks / sb.ngroups;
+    (0..sb.ngroups)
+        .map(|g| {
+            let start = g * sb.groupsize;
+            GroupDesc {
+                bmap: sb.bmapstart + start,
+                inodestart: sb.inodestart + start,
+                datastart: sb.datastart + start,
+                free_blocks,
+            }
+        })
+        .collect()
+}
+
+/// A file system layout, i.e. a superblock together with the block regions it describes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// The superblock describing this layout
+    pub sb: SuperBlock,
+    /// Block indices of the log region, including the log header
+    pub log_blocks: Range<u64>,
+    /// Block indices of the inode bit map region, or an empty range if there is none
+    pub imap_blocks: Range<u64>,
+    /// Block indices of the inode region
+    pub inode_blocks: Range<u64>,
+    /// Block indices of the free bit map region
+    pub bmap_blocks: Range<u64>,
+    /// Block indices of the data region
+    pub data_blocks: Range<u64>,
+    /// Descriptors of all block groups, or an empty vector for a layout without block groups
+    pub groups: Vec<GroupDesc>,
+}
+
+impl Layout {
+    /// Derive the regions described by the given superblock.
+    /// Every region runs until the start of the next one, and the data region runs until `nblocks`.
+    /// For a layout with block groups, the regions are those of group 0, and the data region runs until the end of this group.
+    pub fn new(sb: SuperBlock) -> Layout {
+        if sb.ngroups > 0 {
+            return Layout {
+                sb,
+                log_blocks: sb.logstart..sb.logstart + sb.nlog,
+                imap_blocks: 0..0,
+                inode_blocks: sb.inodestart..sb.datastart,
+                bmap_blocks: sb.bmapstart..sb.inodestart,
+                data_blocks: sb.datastart..sb.bmapstart + sb.groupsize,
+                groups: group_descs(&sb),
+            };
+        }
+        Layout {
+            sb,
+            log_blocks: sb.logstart..sb.logstart + sb.nlog,
+            imap_blocks: match sb.imapstart {
+                0 => 0..0,
+                start => start..sb.inodestart,
+            },
+            inode_blocks: sb.inodestart..sb.bmapstart,
+            bmap_blocks: sb.bmapstart..sb.datastart,
+            data_blocks: sb.datastart..sb.nblocks,
+            groups: vec![],
+        }
+    }
+}
+
+///Tests for the layout builder
+#[cfg(test)]
+mod layout_tests {
+
+    use super::{Layout, LayoutBuilder};
+    use crate::types::{BlockNo, DataIdx, SuperBlock, DINODE_SIZE, FEATURE_XATTR};
+
+    static BLOCK_SIZE: u64 = 1000;
+
+    //Check the conditions any valid superblock has to satisfy
+    fn assert_valid(l: &Layout, inode_size: u64) {
+        let sb = &l.sb;
+        let inode_blocks = l.inode_blocks.end - l.inode_blocks.start;
+        let bmap_blocks = l.bmap_blocks.end - l.bmap_blocks.start;
+        assert_eq!(
+            sb.inodestart,
+            1 + sb.nlog + (l.imap_blocks.end - l.imap_blocks.start)
+        );
+        assert!(inode_blocks * (sb.block_size / inode_size) >= sb.ninodes);
+        assert!(bmap_blocks * sb.block_size * 8 >= sb.ndatablocks);
+        assert_eq!(sb.datastart + sb.ndatablocks, sb.nblocks);
+        //Tightly packed: one bitmap block less would not suffice
+        assert!((bmap_blocks - 1) * sb.block_size * 8 < sb.ndatablocks);
+    }
+
+    #[test]
+    fn small_layout() {
+        let l = LayoutBuilder::new(BLOCK_SIZE)
+            .total_blocks(10)
+            .ninodes(6)
+            .build()
+            .unwrap();
+        assert_valid(&l, *DINODE_SIZE);
+        assert_eq!(
+            l.sb,
+            SuperBlock {
+                block_size: BLOCK_SIZE,
+                nblocks: 10,
+                ninodes: 6,
+                inodestart: 1,
+                ndatablocks: 7,
+                bmapstart: 2,
+                datastart: 3,
+                ngroups: 0,
+                groupsize: 0,
+                logstart: 0,
+                nlog: 0,
+                nreserved: 0,
+                badlist: 0,
+                features: 0,
+                imapstart: 0,
+            }
+        );
+        assert_eq!(l.inode_blocks, 1..2);
+        assert_eq!(l.bmap_blocks, 2..3);
+        assert_eq!(l.data_blocks, 3..10);
+        assert_eq!(Layout::new(l.sb), l);
+    }
+
+    #[test]
+    fn bytes_and_inode_ratio() {
+        //Small blocks, so the bitmap spans multiple blocks
+        let l = LayoutBuilder::new(128)
+            .total_bytes(128 * 2000 + 10)
+            .bytes_per_inode(1024)
+            .inode_size(32)
+            .build()
+            .unwrap();
+        assert_eq!(l.sb.nblocks, 2000);
+        assert_eq!(l.sb.ninodes, 250);
+        assert_eq!(l.inode_blocks, 1..64);
+        assert!(l.bmap_blocks.end - l.bmap_blocks.start > 1);
+        assert_valid(&l, 32);
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let b = LayoutBuilder::new(BLOCK_SIZE);
+        assert!(b.build().is_err()); //No size, no inodes
+        assert!(b.total_blocks(10).build().is_err()); //No inodes
+        assert!(b.ninodes(6).build().is_err()); //No size
+        assert!(b.total_blocks(10).ninodes(0).build().is_err());
+        assert!(b.total_blocks(10).bytes_per_inode(0).build().is_err());
+        assert!(b
+            .total_blocks(u64::MAX / 2)
+            .bytes_per_inode(1)
+            .build()
+            .is_err()); //Size in bytes overflows
+        assert!(b.total_blocks(3).ninodes(6).build().is_err()); //No room for data
+        assert!(b.total_blocks(10).ninodes(1000).build().is_err()); //Inodes take up the device
+        assert!(b
+            .total_blocks(10)
+            .ninodes(6)
+            .inode_size(1001)
+            .build()
+            .is_err());
+        assert!(LayoutBuilder::new(0)
+            .total_blocks(10)
+            .ninodes(6)
+            .build()
+            .is_err());
+        assert!(LayoutBuilder::new(16)
+            .total_blocks(10)
+            .ninodes(6)
+            .inode_size(1)
+            .build()
+            .is_err()); //Super block does not fit
+        assert!(b.total_blocks(4).ninodes(6).build().is_ok()); //Smallest possible
+        assert!(b.total_blocks(10).ninodes(6).log_blocks(1).build().is_err()); //Header only
+        assert!(b
+            .total_blocks(500)
+            .ninodes(6)
+            .log_blocks(200)
+            .build()
+            .is_err()); //Header too small
+    }
+
+    #[test]
+    fn log() {
+        let l = LayoutBuilder::new(BLOCK_SIZE)
+            .total_blocks(20)
+            .ninodes(6)
+            .log_blocks(5)
+            .build()
+            .unwrap();
+        assert_valid(&l, *DINODE_SIZE);
+        assert_eq!(l.sb.logstart, 1);
+        assert_eq!(l.log_blocks, 1..6);
+        assert_eq!(l.inode_blocks, 6..7);
+        assert_eq!(super::log_capacity(BLOCK_SIZE), 124);
+
+        let l = LayoutBuilder::new(BLOCK_SIZE)
+            .total_blocks(37)
+            .ninodes(10)
+            .log_blocks(5)
+            .block_groups(10)
+            .build()
+            .unwrap();
+        assert_eq!(l.sb.ngroups, 3);
+        assert_eq!(l.log_blocks, 1..6);
+        assert_eq!(l.sb.bmapstart, 7); //Behind the log and a single descriptor block
+        assert_eq!(l.groups[2].bmap, 27);
+    }
+
+    #[test]
+    fn block_groups() {
+        let l = LayoutBuilder::new(BLOCK_SIZE)
+            .total_blocks(35)
+            .ninodes(10)
+            .block_groups(10)
+            .build()
+            .unwrap();
+        //3 groups of 10 blocks, behind the super block and a single descriptor block
+        assert_eq!(l.sb.ngroups, 3);
+        assert_eq!(l.sb.ninodes, 12);
+        assert_eq!(l.sb.bmapstart, 2);
+        assert_eq!(l.sb.inodestart, 3);
+        assert_eq!(l.sb.datastart, 4);
+        assert_eq!(l.sb.ndatablocks, 3 * 8);
+        assert_eq!(l.data_blocks, 4..12);
+        assert_eq!(l.groups.len(), 3);
+        assert_eq!(l.groups[2].bmap, 22);
+        assert_eq!(l.groups[2].inodestart, 23);
+        assert_eq!(l.groups[2].datastart, 24);
+        assert_eq!(l.groups[2].free_blocks, 8);
+        assert_eq!(super::group_desc_blocks(&l.sb), 1);
+
+        let b = LayoutBuilder::new(BLOCK_SIZE).ninodes(10);
+        assert!(b.total_blocks(10).block_groups(10).build().is_err()); //No room for descriptors
+        assert!(b.total_blocks(35).block_groups(0).build().is_err());
+        assert!(b.total_blocks(35).block_groups(2).build().is_err()); //No room for data
+        let l = b.total_blocks(35).log_blocks(5).block_groups(10).build();
+        assert_eq!(l.unwrap().sb.ngroups, 2); //Log takes up the room of the last group
+        assert!(LayoutBuilder::new(16)
+            .total_blocks(1000)
+            .ninodes(6)
+            .inode_size(8)
+            .block_groups(500)
+            .build()
+            .is_err()); //Bitmap block too small
+    }
+
+    #[test]
+    fn block_addresses() {
+        let flat = LayoutBuilder::new(BLOCK_SIZE)
+            .total_blocks(10)
+            .ninodes(8)
+            .build()
+            .unwrap()
+            .sb;
+        let first = flat.datastart;
+        assert_eq!(flat.block_no(DataIdx(0)), Some(BlockNo(first)));
+        assert_eq!(flat.data_idx(BlockNo(first + 2)), Some(DataIdx(2)));
+        assert_eq!(flat.block_no(DataIdx(flat.ndatablocks)), None);
+        assert_eq!(flat.data_idx(BlockNo(first - 1)), None);
+        assert_eq!(flat.data_idx(BlockNo(flat.nblocks)), None);
+
+        let grouped = LayoutBuilder::new(BLOCK_SIZE)
+            .total_blocks(35)
+            .ninodes(10)
+            .block_groups(10)
+            .build()
+            .unwrap()
+            .sb;
+        assert_eq!(grouped.block_no(DataIdx(7)), Some(BlockNo(11)));
+        assert_eq!(grouped.block_no(DataIdx(8)), Some(BlockNo(14)));
+        assert_eq!(grouped.block_no(DataIdx(23)), Some(BlockNo(31)));
+        assert_eq!(grouped.block_no(DataIdx(24)), None);
+        assert_eq!(grouped.data_idx(BlockNo(14)), Some(DataIdx(8)));
+        assert_eq!(grouped.data_idx(BlockNo(12)), None); //Bitmap of group 1
+        assert_eq!(grouped.data_idx(BlockNo(32)), None); //Behind the last group
+        for i in 0..grouped.ndatablocks {
+            let b = grouped.block_no(DataIdx(i)).unwrap();
+            assert_eq!(grouped.data_idx(b), Some(DataIdx(i)));
+        }
+    }
+
+    #[test]
+    fn inode_bitmap() {
+        let l = LayoutBuilder::new(128)
+            .total_blocks(200)
+            .ninodes(1500)
+            .inode_size(8)
+            .log_blocks(3)
+            .inode_bitmap()
+            .build()
+            .unwrap();
+        assert_valid(&l, 8);
+        //1500 bits take up 2 blocks of 1024 bits, right behind the log
+        assert_eq!(l.sb.imapstart, 4);
+        assert_eq!(l.imap_blocks, 4..6);
+        assert_eq!(l.inode_blocks.start, 6);
+        assert_eq!(Layout::new(l.sb), l);
+
+        let l = LayoutBuilder::new(BLOCK_SIZE).total_blocks(10).ninodes(6);
+        assert_eq!(l.build().unwrap().imap_blocks, 0..0);
+        assert!(l.block_groups(5).inode_bitmap().build().is_err());
+    }
+
+    #[test]
+    fn xattr_slots() {
+        //every inode is followed by the number of its attribute block
+        let b = LayoutBuilder::new(128).total_blocks(20).ninodes(32);
+        let l = b.inode_size(24).features(FEATURE_XATTR).build().unwrap();
+        assert_valid(&l, 32);
+        assert_eq!(l.inode_blocks, 1..9);
+        assert_eq!(l.sb.inode_slot_size(24), 32);
+        assert_eq!(b.inode_size(24).build().unwrap().inode_blocks, 1..8);
+        assert!(b.inode_size(124).features(FEATURE_XATTR).build().is_err());
+    }
+
+    #[test]
+    fn reserved() {
+        let b = LayoutBuilder::new(BLOCK_SIZE).total_blocks(10).ninodes(6);
+        assert_eq!(b.build().unwrap().sb.nreserved, 0);
+        assert_eq!(b.reserved_blocks(3).build().unwrap().sb.nreserved, 3);
+        assert_eq!(b.reserved_percent(50).build().unwrap().sb.nreserved, 3); //Rounded down
+        assert_eq!(b.reserved_percent(100).build().unwrap().sb.nreserved, 7);
+        assert!(b.reserved_blocks(8).build().is_err());
+        assert!(b.reserved_percent(101).build().is_err());
+        //The last call wins
+        let l = b.reserved_percent(101).reserved_blocks(1).build().unwrap();
+        assert_eq!(l.sb.nreserved, 1);
+        assert_eq!(l.sb.ndatablocks, 7);
+    }
+}
@@ -15,6 +15,15 @@ pub mod error_given;
 
 //Basic modules for types
 pub mod types;
+//Computing file system layouts
+pub mod layout;
+//Policies for allocating blocks and inodes
+pub mod alloc;
+//Sources of inode timestamps
+pub mod clock;
 
 //Traits you should implement
 pub mod fs;
+pub mod transaction;
+//Standard I/O on files
+pub mod file;
@@ -0,0 +1,72 @@
+//! Transactions on top of the block layer
+//!
+//! A [`Transaction`] groups block writes, allocations and frees, and any other operation on the file system that boils down to these, so that they either all happen or not at all.
+//! Until the transaction is committed, its effects are only visible through the file system it was started on, and they are thrown away when the transaction is rolled back or dropped.
+//! Transactions nest, also inside operations started with `begin_op`; the changes of a committed nested transaction only become permanent once the outermost transaction or operation ends.
+//!
+//! ```ignore
+//! let mut tx = fs.transaction()?;
+//! let i = tx.b_alloc()?;
+//! tx.b_put(&block)?;
+//! tx.commit()?; //or just drop `tx` to undo the allocation and the write
+//! ```
+//!
+//! [`Transaction`]: struct.Transaction.html
+
+use super::fs::BlockSupport;
+use std::ops::{Deref, DerefMut};
+
+/// A running transaction on the file system `F`, see the [module documentation](index.html).
+/// Dereferences to the file system, so all its methods can be called on the transaction directly.
+#[derive(Debug)]
+pub struct Transaction<'a, F: BlockSupport> {
+    fs: &'a mut F,
+    finished: bool,
+}
+
+impl<'a, F: BlockSupport> Transaction<'a, F> {
+    /// Start a new transaction on `fs`
+    pub fn begin(fs: &'a mut F) -> Result<Transaction<'a, F>, F::Error> {
+        fs.b_tx_begin()?;
+        Ok(Transaction {
+            fs,
+            finished: false,
+        })
+    }
+
+    /// Apply all changes made during this transaction
+    pub fn commit(mut self) -> Result<(), F::Error> {
+        self.finished = true;
+        self.fs.b_tx_commit()
+    }
+
+    /// Throw away all changes made during this transaction
+    pub fn rollback(mut self) -> Result<(), F::Error> {
+        self.finished = true;
+        self.fs.b_tx_rollback()
+    }
+}
+
+impl<'a, F: BlockSupport> Deref for Transaction<'a, F> {
+    type Target = F;
+
+    fn deref(&self) -> &F {
+        self.fs
+    }
+}
+
+impl<'a, F: BlockSupport> DerefMut for Transaction<'a, F> {
+    fn deref_mut(&mut self) -> &mut F {
+        self.fs
+    }
+}
+
+impl<'a, F: BlockSupport> Drop for Transaction<'a, F> {
+    /// Roll back the transaction if it was neither committed nor rolled back explicitly.
+    /// Errors cannot be reported from here; call `rollback` to see them.
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.fs.b_tx_rollback();
+        }
+    }
+}
@@ -1,10 +1,11 @@
 //! Module containing the types used in this project.
 //! You can define your own wrappers around these types if you need more than the provided functionality.
 
+use super::alloc::AllocStrategy;
 use super::error_given;
 use super::error_given::APIError;
 use lazy_static::lazy_static;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
 use std::io::prelude::*;
 use std::io::{Cursor, SeekFrom};
 
@@ -188,7 +189,10 @@ impl Block {
 /// 3. *free bit map*: a sequence of blocks keeping track of the allocation state (allocated or free) of all disk blocks in the next data block region. The *n*th bit in this sequence specifies whether or not the *n*th data block is currently in use.
 /// 4. *data blocks*: contain the actual file and directory data, as a long sequence of disk blocks.
 ///
-/// *EXTRA*: Since we do not support logging, there is no need for an additional memory region to store any logs in
+/// *EXTRA*: Optionally, a log region of `nlog` blocks sits between the super block and the inode blocks, i.e.
+///     \[super block | log | inode blocks | free bit map | data blocks\]
+/// Like in xv6, the first block of the log is its header, listing the block numbers of the blocks stored in the rest of the log.
+/// Writes that belong to a single file system operation are first written to the log and only then to their actual location, so a crash in the middle of an operation never leaves it half done.
 /// Also note that in contrast to more realistic device layouts, we ignore the fact that the first block of the device is often reserved for bootstrapping code, and makes use of e.g. a Master Boot Record (MBR) or Volume Boot Record (VBR).
 /// *EXTRA*: Note that just like blocks, inodes are not being cached either. The consequence is that the users of our APIs are responsible for ensuring that they aren't handling different aliases to the same inode without realizing it. This will not scale well to a parallellized setting. In our case, this is no major problem, as we have no parallellism, and we have simple system call interactions, that will not handle a lot of inodes at the same time, and will hence not need to perform many of those inode equality checks.
 #[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -203,7 +207,7 @@ pub struct SuperBlock {
     ///This number does not necessarily have to fill up the entire region, i.e. it is possible to make the inode region unnecessarily big
     pub ninodes: u64,
     ///The block index of the first block of inodes\
-    ///Since the super block is only a single block long and located at index 0, this will usually be the block with index\
+    ///Since the super block is only a single block long and located at index 0, this will usually be the block with index 1, unless a log or an inode bit map comes first\
     ///The inode region runs until `bmapstart`\
     ///The inode region is assumed to be sufficiently long to contain `niondes` inodes
     pub inodestart: u64,
@@ -218,6 +222,292 @@ pub struct SuperBlock {
     ///The data block region runs until `nblocks`, i.e. the end of the file system\
     ///The data block region is assumed to be at least `ndatablocks` blocks large
     pub datastart: u64,
+    ///Number of block groups, or 0 for the single-region layout described above\
+    ///See [`GroupDesc`](struct.GroupDesc.html) for the layout of a file system with block groups
+    pub ngroups: u64,
+    ///Number of blocks in every block group, or 0 for the single-region layout described above
+    pub groupsize: u64,
+    ///The block index of the first block of the log region, i.e. 1 if there is a log, and 0 otherwise\
+    ///In a file system with block groups, the log region sits between the super block and the group descriptors
+    pub logstart: u64,
+    ///Number of blocks in the log region, including its header block, or 0 if there is no log
+    pub nlog: u64,
+    ///Number of data blocks reserved for privileged writers\
+    ///Allocations outside a privileged context fail as soon as no more than this many data blocks are free, so that e.g. the operations needed to free up space or to repair the file system still succeed on a full file system
+    pub nreserved: u64,
+    ///The block index of the data block holding the list of bad blocks, or 0 if no block has been marked bad yet\
+    ///The list is a serialized `Vec<u64>` of the block indices of all bad data blocks. Both the list block and the bad blocks are marked as in use in the free bit map, so they are never allocated
+    pub badlist: u64,
+    ///The optional features of the on-disk format this file system uses, as a combination of the `FEATURE_*` flags, e.g. [`FEATURE_EXTENTS`](constant.FEATURE_EXTENTS.html)\
+    ///A superblock with flags that are not in [`FEATURES_SUPPORTED`](constant.FEATURES_SUPPORTED.html) is not valid
+    pub features: u64,
+    ///The block index of the first block of the inode bit map region, or 0 if the file system keeps no inode bit map\
+    ///The inode bit map region sits right after the log, before the inode region, and runs until `inodestart`. It holds a bit for every inode, which is set if the inode is in use; the bit of inode 0 is always set\
+    ///Without an inode bit map, free inodes are found by scanning the inode region. File systems with block groups do not support an inode bit map
+    pub imapstart: u64,
+}
+
+/// Feature flag of the `SuperBlock`: inodes map their contents with extents rather than with a pointer per block, see [`Extent`](struct.Extent.html)
+pub const FEATURE_EXTENTS: u64 = 1;
+
+/// Feature flag of the `SuperBlock`: new regular files and directories store their contents in their inode until they grow past [`INLINE_DATA_MAX`](constant.INLINE_DATA_MAX.html) bytes
+pub const FEATURE_INLINE_DATA: u64 = 2;
+
+/// Feature flag of the `SuperBlock`: inodes can carry extended attributes, see [`XattrSupport`](../fs/trait.XattrSupport.html).
+/// Every inode is followed by the number of the block holding its attributes, or 0 if it has none, so it takes up [`XATTR_PTR_SIZE`](constant.XATTR_PTR_SIZE.html) more bytes in the inode region; see [`SuperBlock::inode_slot_size`](struct.SuperBlock.html#method.inode_slot_size).
+pub const FEATURE_XATTR: u64 = 4;
+
+/// All feature flags of the `SuperBlock` that are known
+pub const FEATURES_SUPPORTED: u64 = FEATURE_EXTENTS | FEATURE_INLINE_DATA | FEATURE_XATTR;
+
+/// Size, in bytes, of the number of the attribute block stored after every inode of a file system with [`FEATURE_XATTR`](constant.FEATURE_XATTR.html)
+pub const XATTR_PTR_SIZE: u64 = 8;
+/// Prefixes the name of an extended attribute has to start with: `user.` for attributes that anyone allowed to read or write the file may use, and `trusted.` for attributes that only root may use
+pub const XATTR_NAMESPACES: [&str; 2] = ["user.", "trusted."];
+/// Longest name, in bytes, of an extended attribute, including its namespace prefix.
+/// The values have no limit of their own, but all attributes of an inode together have to fit in a single block.
+pub const XATTR_NAME_MAX: u64 = 255;
+
+/// Absolute number of a block on the device, i.e. the index used by `b_get` and `Device::read_block`.
+/// Kept distinct from [`DataIdx`](struct.DataIdx.html), so that mixing up both kinds of addresses fails to compile.
+/// Convert between both with [`SuperBlock::block_no`](struct.SuperBlock.html#method.block_no) and [`SuperBlock::data_idx`](struct.SuperBlock.html#method.data_idx).
+#[derive(
+    Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct BlockNo(pub u64);
+
+/// Index of a block *within the data region*, i.e. the index used by `b_free`, `b_zero` and `b_alloc`, and the index of its bit in the free bit map.
+#[derive(
+    Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct DataIdx(pub u64);
+
+impl SuperBlock {
+    /// Returns the number of bytes an inode of `inode_size` bytes takes up in the inode region of this file system.
+    /// With [`FEATURE_XATTR`](constant.FEATURE_XATTR.html), this includes the number of its attribute block, which follows the inode.
+    pub fn inode_slot_size(&self, inode_size: u64) -> u64 {
+        if self.features & FEATURE_XATTR != 0 {
+            inode_size + XATTR_PTR_SIZE
+        } else {
+            inode_size
+        }
+    }
+
+    /// Number of data blocks in every block group, or in the entire data region if there are no block groups
+    pub fn group_data_blocks(&self) -> u64 {
+        match self.ngroups {
+            0 => self.ndatablocks,
+            n => self.ndatablocks / n,
+        }
+    }
+
+    /// Convert the index of a data block to its absolute block number.
+    /// Returns `None` if `i` is out of bounds, i.e. if it is not smaller than `ndatablocks`.
+    pub fn block_no(&self, i: DataIdx) -> Option<BlockNo> {
+        if i.0 >= self.ndatablocks {
+            return None;
+        }
+        if self.ngroups == 0 {
+            return Some(BlockNo(self.datastart + i.0));
+        }
+        let n = self.group_data_blocks();
+        Some(BlockNo(
+            self.datastart + (i.0 / n) * self.groupsize + i.0 % n,
+        ))
+    }
+
+    /// Convert an absolute block number to the index of this block within the data region.
+    /// Returns `None` if `b` is not a data block.
+    pub fn data_idx(&self, b: BlockNo) -> Option<DataIdx> {
+        let off = b.0.checked_sub(self.datastart)?;
+        if self.ngroups == 0 {
+            return Some(DataIdx(off)).filter(|i| i.0 < self.ndatablocks);
+        }
+        let n = self.group_data_blocks();
+        let (g, within) = (off / self.groupsize, off % self.groupsize);
+        match g < self.ngroups && within < n {
+            true => Some(DataIdx(g * n + within)),
+            false => None,
+        }
+    }
+}
+
+/// Descriptor of a single block group, in a file system with block groups.
+/// Like in ext2, such a file system is laid out as follows:
+///     \[super block | log | group descriptors | group 0 | group 1 | ... \]
+/// , where every group of `groupsize` blocks has the layout
+///     \[free bit map | inode blocks | data blocks\]
+/// , and the group descriptor region holds one `GroupDesc` per group, packed in as few blocks as possible.
+///
+/// Every group holds an equal share of the inodes and data blocks of the file system, i.e. `ninodes / ngroups` inodes and `ndatablocks / ngroups` data blocks.
+/// Inode numbers and data block indices still run over the entire file system; inode *i* is stored in group *i / (ninodes / ngroups)*, and likewise for data blocks.
+/// The free bit map of a group is a single block, which keeps track of the data blocks of this group only.
+/// The `inodestart`, `bmapstart` and `datastart` fields of the `SuperBlock` describe the regions of group 0.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GroupDesc {
+    ///The block index of the free bit map block of this group
+    pub bmap: u64,
+    ///The block index of the first inode block of this group
+    pub inodestart: u64,
+    ///The block index of the first data block of this group
+    pub datastart: u64,
+    ///Number of free data blocks in this group
+    pub free_blocks: u64,
+}
+
+/// A point in time, as the number of nanoseconds since the Unix epoch.
+/// Inodes store their timestamps in this format, see [`DInode`](struct.DInode.html); the file system gets them from a [`Clock`](../clock/trait.Clock.html).
+#[derive(
+    Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    /// Number of nanoseconds in a second
+    pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+    /// Returns the timestamp `secs` seconds after the Unix epoch
+    pub const fn from_secs(secs: u64) -> Timestamp {
+        Timestamp(secs * Timestamp::NANOS_PER_SEC)
+    }
+}
+
+/// When reading a file updates its access time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AtimePolicy {
+    /// Every read updates the access time
+    Strict,
+    /// A read only updates the access time if the file has changed since it was last accessed, or if the last access was at least a day ago.
+    /// This still lets tools find out whether a file has been read since it last changed, without writing an inode for almost every read.
+    #[default]
+    Relatime,
+    /// Reads never update the access time
+    Noatime,
+}
+
+impl AtimePolicy {
+    /// Minimal age of the access time before a read updates it under `Relatime`, in nanoseconds
+    pub const RELATIME_INTERVAL: u64 = 24 * 60 * 60 * Timestamp::NANOS_PER_SEC;
+
+    /// Returns whether a read at time `now` updates the access time of a file with the given timestamps
+    pub fn updates(
+        self,
+        atime: Timestamp,
+        mtime: Timestamp,
+        ctime: Timestamp,
+        now: Timestamp,
+    ) -> bool {
+        match self {
+            AtimePolicy::Strict => true,
+            AtimePolicy::Relatime => {
+                atime <= mtime
+                    || atime <= ctime
+                    || now.0.saturating_sub(atime.0) >= AtimePolicy::RELATIME_INTERVAL
+            }
+            AtimePolicy::Noatime => false,
+        }
+    }
+}
+
+/// What a file system does with the contents of the data blocks it frees
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DiscardPolicy {
+    /// Leave the contents of freed blocks as they are
+    #[default]
+    Keep,
+    /// Overwrite freed blocks with zeroes, so their old contents can no longer be read from the image
+    Zero,
+    /// Punch a hole in the file backing the device for freed blocks, so sparse images shrink; see [`Device::discard`](../controller/struct.Device.html#method.discard)
+    PunchHole,
+}
+
+/// Options a file system is mounted with, see [`FileSysSupport::mountfs_with`](../fs/trait.FileSysSupport.html#method.mountfs_with).
+/// These options are not stored on the disk, so every mount of the same file system can pick different ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MountOptions {
+    /// What to do with data blocks once they are freed
+    pub discard: DiscardPolicy,
+    /// How free data blocks and inodes are picked when allocating, see [`AllocStrategy`](../alloc/enum.AllocStrategy.html)
+    pub alloc: AllocStrategy,
+    /// When reads update the access time of files
+    pub atime: AtimePolicy,
+}
+
+/// Statistics of a mounted file system, see [`BlockSupport::statfs`](../fs/trait.BlockSupport.html#method.statfs)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatFs {
+    /// Size of the blocks of the file system, in bytes
+    pub block_size: u64,
+    /// Total number of data blocks
+    pub blocks: u64,
+    /// Number of free data blocks, including the reserved ones
+    pub free_blocks: u64,
+    /// Number of free data blocks that can still be allocated outside a privileged context
+    pub available_blocks: u64,
+    /// Number of data blocks reserved for privileged writers
+    pub reserved_blocks: u64,
+}
+
+/// A run of consecutive free data blocks, see [`FragReport`](struct.FragReport.html)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FreeExtent {
+    /// Index of the first free block of the run
+    pub start: DataIdx,
+    /// Number of free blocks in the run
+    pub len: u64,
+}
+
+/// How fragmented the free space of a file system is, see [`BlockSupport::b_frag_report`](../fs/trait.BlockSupport.html#method.b_frag_report)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FragReport {
+    /// All maximal runs of free data blocks, in increasing order.
+    /// Runs do not cross the boundary between two block groups, as the data blocks of different groups are not adjacent on the disk.
+    pub free_extents: Vec<FreeExtent>,
+    /// Histogram of the lengths of the free runs: `histogram[k]` is the number of runs with a length of at least `2^k` and less than `2^(k+1)` blocks.
+    /// Empty if there are no free blocks, and never ends in a zero otherwise.
+    pub histogram: Vec<u64>,
+    /// Length of the largest free run
+    pub largest_free: u64,
+    /// Percentage of the free blocks that lie outside the largest free run.
+    /// 0 means that all free space is in a single run (or that there is none), and the score approaches 100 as the free space is split into ever more and smaller runs.
+    pub score: u64,
+}
+
+impl FragReport {
+    /// Computes the report for the given free runs, which have to be in increasing order
+    pub fn from_extents(free_extents: Vec<FreeExtent>) -> FragReport {
+        let mut histogram = vec![];
+        for e in free_extents.iter().filter(|e| e.len > 0) {
+            let k = e.len.ilog2() as usize;
+            if histogram.len() <= k {
+                histogram.resize(k + 1, 0);
+            }
+            histogram[k] += 1;
+        }
+        let free: u64 = free_extents.iter().map(|e| e.len).sum();
+        let largest_free = free_extents.iter().map(|e| e.len).max().unwrap_or(0);
+        let score = match free {
+            0 => 0,
+            free => 100 * (free - largest_free) / free,
+        };
+        FragReport {
+            free_extents,
+            histogram,
+            largest_free,
+            score,
+        }
+    }
+}
+
+/// How fragmented a single file is, see [`InodeSupport::i_extent_summary`](../fs/trait.InodeSupport.html#method.i_extent_summary)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InodeExtents {
+    /// Number of the inode
+    pub inum: u64,
+    /// Number of data blocks the inode points to
+    pub blocks: u64,
+    /// Number of runs of consecutive blocks the contents of the inode are split into; a file that is not fragmented at all has a single extent, or none if it is empty
+    pub extents: u64,
 }
 
 lazy_static! {
@@ -228,13 +518,19 @@ lazy_static! {
     pub static ref SUPERBLOCK_SIZE : u64 = bincode::serialize(&SuperBlock::default()).unwrap().len() as u64;
 }
 
+lazy_static! {
+    /// Size a group descriptor takes up on disk, in bytes.
+    pub static ref GROUPDESC_SIZE : u64 = bincode::serialize(&GroupDesc::default()).unwrap().len() as u64;
+}
+
 /// Hard-coded number of data blocks each inode can point to
 pub const DIRECT_POINTERS: u64 = 12;
 
 /// Enum describing file types
-/// Currently, either a file `T_FILE`, a directory `T_DIR` or a free inode `T_Free`
+/// Currently, either a file `T_FILE`, a directory `T_DIR`, a symbolic link `T_SYMLINK` or a free inode `T_Free`
 /// The file type `T_FREE` is used to signify a free inode, that can be used to allocate a new file or directory.
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
+/// On the disk, the file type takes up a single byte.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum FType {
     /// Directory file type
     TDir,
@@ -242,6 +538,8 @@ pub enum FType {
     TFile,
     /// Free file type
     TFree,
+    /// Symbolic link file type: the contents of the file are the path the link points to, see [`SYMLINK_INLINE_MAX`](constant.SYMLINK_INLINE_MAX.html)
+    TSymlink,
 }
 impl Default for FType {
     fn default() -> FType {
@@ -249,22 +547,209 @@ impl Default for FType {
     }
 }
 
+impl Serialize for FType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+impl<'de> Deserialize<'de> for FType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FType, D::Error> {
+        match u8::deserialize(deserializer)? {
+            0 => Ok(FType::TDir),
+            1 => Ok(FType::TFile),
+            2 => Ok(FType::TFree),
+            3 => Ok(FType::TSymlink),
+            ft => Err(de::Error::custom(format!("invalid file type {}", ft))),
+        }
+    }
+}
+
+/// Mode bit: executing the file runs it with the user id of its owner.
+/// Only stored in this file system, since it does not execute files.
+pub const MODE_SETUID: u16 = 0o4000;
+/// Mode bit: for a directory, entries created in it get the group of the directory instead of the group of their creator, and new subdirectories inherit this bit
+pub const MODE_SETGID: u16 = 0o2000;
+/// Mode bit: for a directory, an entry in it can only be removed by the owner of the entry, the owner of the directory or root, even if others can write to the directory
+pub const MODE_STICKY: u16 = 0o1000;
+/// Mask of the read, write and execute bits for the owner, the group and others, in this order from the most significant bit on
+pub const MODE_PERMS: u16 = 0o777;
+/// Mask of all mode bits that are stored for an inode
+pub const MODE_MASK: u16 = MODE_SETUID | MODE_SETGID | MODE_STICKY | MODE_PERMS;
+/// Mode of newly allocated regular files: read and write for the owner, read for everyone else
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+/// Mode of newly allocated directories: everything for the owner, read and search for everyone else
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+/// Mode of newly allocated symbolic links. The permission bits of a link are never checked; those of the file it points to are.
+pub const DEFAULT_SYMLINK_MODE: u16 = 0o777;
+
+/// Longest target, in bytes, of a symbolic link that is stored inline, i.e. in the space of the block pointers of its inode instead of in a data block.
+/// Reading such a link takes no disk accesses beyond the one for its inode, and it takes up no data blocks.
+/// Longer targets are stored in data blocks, like the contents of a regular file.
+pub const SYMLINK_INLINE_MAX: u64 = DIRECT_POINTERS * 8;
+/// Largest contents, in bytes, of a regular file or directory that can be stored inline in a file system with [`FEATURE_INLINE_DATA`](constant.FEATURE_INLINE_DATA.html), in the same space as the target of a symbolic link.
+/// Such inodes are marked with [`INODE_INLINE_DATA`](constant.INODE_INLINE_DATA.html).
+pub const INLINE_DATA_MAX: u64 = SYMLINK_INLINE_MAX;
+/// Flag of a [`DInode`](struct.DInode.html) whose contents are stored in the space of its block pointers, rather than in data blocks
+pub const INODE_INLINE_DATA: u8 = 1;
+/// Flag of a [`DInode`](struct.DInode.html) that may have blocks past its end, preallocated while keeping its size.
+/// Without it, only the block pointers within the size of the inode are meaningful.
+pub const INODE_EOF_BLOCKS: u8 = 2;
+/// Maximal number of symbolic links followed while resolving a single path, after which a loop is assumed
+pub const MAX_SYMLINK_HOPS: u64 = 40;
+
 /// Struct describing data held by an inode on the disk.
 /// Derives the Serialize and Deserialize traits, to allow for easy (de-)serialization when writing to disk blocks
 ///
 /// *EXTRA*: In real-life file systems, files also contain a field pointing to a data block containing more data blocks, called an indirect pointer.
 /// For simplicity reasons, we do not support this in the current file system.
 /// In other words, files are made up of a total of at most `DIRECT_POINTERS` data blocks.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
 pub struct DInode {
     /// Registers the file type
     pub ft: FType,
+    /// Flags of this inode, such as [`INODE_INLINE_DATA`](constant.INODE_INLINE_DATA.html)
+    pub flags: u8,
     /// Counts the number of links to this inode in the file system. The point of doing this is that if the inode is written back to disk when it has no links to it, it should be freed instead, thereby setting its file type to `T_FREE`.
     pub nlink: u16,
+    /// Permission bits of this file, together with the setuid, setgid and sticky bits; see [`MODE_MASK`](constant.MODE_MASK.html)
+    pub mode: u16,
+    /// User id of the owner of this file
+    pub uid: u32,
+    /// Group id of this file
+    pub gid: u32,
     /// Size of the file in bytes. Used to see when a read or write would go out of file bounds.
     pub size: u64,
     /// A list of up to `DIRECT_POINTERS` valid data block addresses, to specify where the contents of this file are stored.
     pub direct_blocks: [u64; DIRECT_POINTERS as usize],
+    /// Time of the last access to the contents of this file, subject to the [`AtimePolicy`](enum.AtimePolicy.html) the file system is mounted with
+    pub atime: Timestamp,
+    /// Time of the last change to the contents of this file
+    pub mtime: Timestamp,
+    /// Time of the last change to this inode, i.e. to the contents of the file or to one of the other fields, such as `nlink`
+    pub ctime: Timestamp,
+    /// Time at which this inode was allocated
+    pub crtime: Timestamp,
+}
+
+impl DInode {
+    /// Returns whether this is a symbolic link with its target stored inline, i.e. one of at most [`SYMLINK_INLINE_MAX`](constant.SYMLINK_INLINE_MAX.html) bytes.
+    /// The block pointers of such an inode do not point to any blocks.
+    pub fn is_inline_symlink(&self) -> bool {
+        self.ft == FType::TSymlink && self.size <= SYMLINK_INLINE_MAX
+    }
+
+    /// Returns whether the contents of this inode are stored in the space of its block pointers, i.e. whether it is an inline symbolic link or has the [`INODE_INLINE_DATA`](constant.INODE_INLINE_DATA.html) flag
+    pub fn is_inline(&self) -> bool {
+        self.is_inline_symlink() || self.flags & INODE_INLINE_DATA != 0
+    }
+
+    /// Returns the first `size` bytes stored in the space of the block pointers
+    pub fn inline_data(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = self
+            .direct_blocks
+            .iter()
+            .flat_map(|ptr| ptr.to_le_bytes())
+            .collect();
+        data.truncate(self.size.min(SYMLINK_INLINE_MAX) as usize);
+        data
+    }
+
+    /// Stores `data` in the space of the block pointers, and sets `size` to its length.
+    /// Returns `None` and leaves the inode unchanged if `data` is longer than [`SYMLINK_INLINE_MAX`](constant.SYMLINK_INLINE_MAX.html).
+    pub fn set_inline_data(&mut self, data: &[u8]) -> Option<()> {
+        if data.len() as u64 > SYMLINK_INLINE_MAX {
+            return None;
+        }
+        let mut bytes = [0; SYMLINK_INLINE_MAX as usize];
+        bytes[..data.len()].copy_from_slice(data);
+        for (ptr, chunk) in self.direct_blocks.iter_mut().zip(bytes.chunks(8)) {
+            let mut le = [0; 8];
+            le.copy_from_slice(chunk);
+            *ptr = u64::from_le_bytes(le);
+        }
+        self.size = data.len() as u64;
+        Some(())
+    }
+}
+
+/// Number of extents an inode of a file system with [`FEATURE_EXTENTS`](constant.FEATURE_EXTENTS.html) holds itself, see [`Extent`](struct.Extent.html)
+pub const INLINE_EXTENTS: usize = (DIRECT_POINTERS as usize - 2) / 2;
+
+/// A run of consecutive blocks of a file that are stored in consecutive data blocks, in a file system with [`FEATURE_EXTENTS`](constant.FEATURE_EXTENTS.html).
+///
+/// The inodes of such a file system use the space of their block pointers differently: the first pointer holds the number of extents of the file, and the second one the extent block holding them, or 0 if they are stored in the inode itself.
+/// The remaining pointers hold up to [`INLINE_EXTENTS`](constant.INLINE_EXTENTS.html) extents, two pointers each.
+/// Once a file has more extents than that, they all move to its extent block, as a serialized `Vec<Extent>`.
+/// Extents are kept sorted on `start`, and never overlap; blocks of the file that no extent covers are holes.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// Index of the first block of the file in this run
+    pub start: u32,
+    /// Number of blocks in this run
+    pub len: u32,
+    /// Absolute number of the data block holding block `start` of the file
+    pub physical: u64,
+}
+
+impl Extent {
+    /// Returns the index of the first block of the file after this run
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.len as u64
+    }
+
+    /// Returns the absolute number of the data block holding block `k` of the file, if this run contains it
+    pub fn map(&self, k: u64) -> Option<u64> {
+        if (self.start as u64..self.end()).contains(&k) {
+            Some(self.physical + (k - self.start as u64))
+        } else {
+            None
+        }
+    }
+}
+
+impl DInode {
+    /// Returns the number of extents of this inode, and the number of the extent block holding them, or 0 if they are stored in the inode itself.
+    /// Only meaningful in a file system with [`FEATURE_EXTENTS`](constant.FEATURE_EXTENTS.html).
+    pub fn extent_header(&self) -> (u64, u64) {
+        (self.direct_blocks[0], self.direct_blocks[1])
+    }
+
+    /// Returns the extents stored in this inode itself, which are all of them if it has no extent block
+    pub fn inline_extents(&self) -> Vec<Extent> {
+        let (n, _) = self.extent_header();
+        self.direct_blocks[2..]
+            .chunks(2)
+            .take(n.min(INLINE_EXTENTS as u64) as usize)
+            .map(|p| Extent {
+                start: (p[0] >> 32) as u32,
+                len: p[0] as u32,
+                physical: p[1],
+            })
+            .collect()
+    }
+
+    /// Stores `extents` in this inode itself, and forgets about its extent block.
+    /// Returns `None` and leaves the inode unchanged if there are more than [`INLINE_EXTENTS`](constant.INLINE_EXTENTS.html) extents.
+    pub fn set_inline_extents(&mut self, extents: &[Extent]) -> Option<()> {
+        if extents.len() > INLINE_EXTENTS {
+            return None;
+        }
+        self.direct_blocks = [0; DIRECT_POINTERS as usize];
+        self.direct_blocks[0] = extents.len() as u64;
+        for (p, e) in self.direct_blocks[2..].chunks_mut(2).zip(extents) {
+            p[0] = (e.start as u64) << 32 | e.len as u64;
+            p[1] = e.physical;
+        }
+        Some(())
+    }
+
+    /// Records that this inode has `n` extents, stored in extent block `block`
+    pub fn set_extent_block(&mut self, n: u64, block: u64) {
+        self.direct_blocks = [0; DIRECT_POINTERS as usize];
+        self.direct_blocks[0] = n;
+        self.direct_blocks[1] = block;
+    }
 }
 
 lazy_static! {
@@ -311,9 +796,23 @@ pub trait InodeLike: Sized {
     fn get_size(&self) -> u64;
     ///Get the address of the *i*th block pointed to by this inode, if there is any block under index *i*
     ///Note that this function's behavior is undefined for unallocated indexes `i` (as these are commonly set to 0). It is up to the caller to figure out whether the index *i* was sensible, based on `get_size()` and the external file system block size.
-    fn get_block(&self, i: u64) -> u64;
+    fn get_block(&self, i: u64) -> BlockNo;
     ///Get the number of this inode on the disk
     fn get_inum(&self) -> u64;
+    ///Get the time of the last access to the contents of this inode
+    fn get_atime(&self) -> Timestamp;
+    ///Get the time of the last change to the contents of this inode
+    fn get_mtime(&self) -> Timestamp;
+    ///Get the time of the last change to this inode itself
+    fn get_ctime(&self) -> Timestamp;
+    ///Get the time at which this inode was allocated
+    fn get_crtime(&self) -> Timestamp;
+    ///Get the mode of this inode, i.e. its permission bits together with the setuid, setgid and sticky bits
+    fn get_mode(&self) -> u16;
+    ///Get the user id of the owner of this inode
+    fn get_uid(&self) -> u32;
+    ///Get the group id of this inode
+    fn get_gid(&self) -> u32;
 }
 
 ///You get the implementation of `InodeLike` for free for the `Inode` I defined above
@@ -337,6 +836,7 @@ impl InodeLike for Inode {
             nlink: nlink as u16,
             size,
             direct_blocks: db,
+            ..Default::default()
         };
         Some(Inode::new(inum, di))
     }
@@ -350,16 +850,116 @@ impl InodeLike for Inode {
     fn get_size(&self) -> u64 {
         self.disk_node.size
     }
-    fn get_block(&self, i: u64) -> u64 {
+    fn get_block(&self, i: u64) -> BlockNo {
         if DIRECT_POINTERS <= i {
-            return 0;
+            return BlockNo(0);
         }
-        self.disk_node.direct_blocks[i as usize]
+        BlockNo(self.disk_node.direct_blocks[i as usize])
     }
 
     fn get_inum(&self) -> u64 {
         self.inum
     }
+
+    fn get_atime(&self) -> Timestamp {
+        self.disk_node.atime
+    }
+    fn get_mtime(&self) -> Timestamp {
+        self.disk_node.mtime
+    }
+    fn get_ctime(&self) -> Timestamp {
+        self.disk_node.ctime
+    }
+    fn get_crtime(&self) -> Timestamp {
+        self.disk_node.crtime
+    }
+    fn get_mode(&self) -> u16 {
+        self.disk_node.mode
+    }
+    fn get_uid(&self) -> u32 {
+        self.disk_node.uid
+    }
+    fn get_gid(&self) -> u32 {
+        self.disk_node.gid
+    }
+}
+
+/// Kinds of access to a file that are subject to its permission bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Reading the contents of a file, or listing the entries of a directory
+    Read,
+    /// Changing the contents of a file, or adding and removing entries of a directory
+    Write,
+    /// Executing a file, or searching a directory, i.e. looking up a name in it
+    Exec,
+}
+
+impl Access {
+    /// The bit of this access for others; the bits for the group and the owner are 3 and 6 places higher
+    fn other_bit(self) -> u16 {
+        match self {
+            Access::Read => 0o4,
+            Access::Write => 0o2,
+            Access::Exec => 0o1,
+        }
+    }
+}
+
+/// The identity on behalf of which path-level operations are performed, see [`PathSupport::set_credentials`](../fs/trait.PathSupport.html#method.set_credentials)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// User id of the caller; 0 is root
+    pub uid: u32,
+    /// Primary group id of the caller, given to the files it creates
+    pub gid: u32,
+    /// Supplementary groups of the caller, which count for access checks just like the primary group
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// Credentials of root, which the default credentials are equal to
+    pub fn root() -> Credentials {
+        Credentials::default()
+    }
+
+    /// Credentials of user `uid` with primary group `gid` and no supplementary groups
+    pub fn new(uid: u32, gid: u32) -> Credentials {
+        Credentials {
+            uid,
+            gid,
+            groups: vec![],
+        }
+    }
+
+    /// Returns whether these are the credentials of root
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// Returns whether the caller is a member of group `gid`
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+
+    /// Returns whether the caller may perform `access` on `inode`.
+    /// Only one class of permission bits applies: those of the owner if the caller owns the inode, else those of the group if the caller is in its group, and those of others otherwise.
+    /// Root may always read and write, and may search any directory, but may only execute a file if any of its execute bits are set.
+    pub fn permits<I: InodeLike>(&self, inode: &I, access: Access) -> bool {
+        let mode = inode.get_mode();
+        if self.is_root() {
+            return access != Access::Exec || inode.get_ft() == FType::TDir || mode & 0o111 != 0;
+        }
+        let bit = access.other_bit();
+        let bit = if self.uid == inode.get_uid() {
+            bit << 6
+        } else if self.in_group(inode.get_gid()) {
+            bit << 3
+        } else {
+            bit
+        };
+        mode & bit != 0
+    }
 }
 
 /// Hard-coded number of characters each directory entry can contain for its name
@@ -426,7 +1026,10 @@ mod block_tests {
     }
 
     //Importing some example deserializable struct
-    use crate::types::{DInode, FType, DINODE_SIZE, DIRECT_POINTERS};
+    use crate::types::{
+        Access, Credentials, DInode, Extent, FType, Inode, InodeLike, Timestamp, DINODE_SIZE,
+        DIRECT_POINTERS, INLINE_EXTENTS, INODE_INLINE_DATA, MODE_SETGID, SYMLINK_INLINE_MAX,
+    };
     //Another testing struct to perform (de)serialization on
     #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
     struct Point(u64, u64);
@@ -439,9 +1042,17 @@ mod block_tests {
         let in1 = DInode::default();
         let in2 = DInode {
             ft: FType::TFree,
+            flags: INODE_INLINE_DATA,
             nlink: 13,
+            mode: MODE_SETGID | 0o750,
+            uid: 1000,
+            gid: u32::MAX,
             size: 142,
             direct_blocks: [1000; DIRECT_POINTERS as usize],
+            atime: Timestamp(1),
+            mtime: Timestamp(2),
+            ctime: Timestamp(3),
+            crtime: Timestamp(u64::MAX),
         };
 
         //Testing some length consistency, and the global variable DINODE_SIZE
@@ -484,5 +1095,100 @@ mod block_tests {
         assert!(b1.serialize_into(&p2, BLOCK_SIZE + 1 - point_size).is_err());
         //Ensure contents don't change after faulty reads or writes
         assert_eq!(b1.contents_as_ref(), vec![0; BLOCK_SIZE as usize]);
+
+        //File types take a single byte, and unknown ones are rejected
+        assert_eq!(bincode::serialize(&FType::TFile).unwrap(), vec![1]);
+        assert_eq!(
+            bincode::deserialize::<FType>(&[3]).unwrap(),
+            FType::TSymlink
+        );
+        assert!(bincode::deserialize::<FType>(&[4]).is_err());
+
+        //Short symlink targets fit in the block pointers
+        let mut in3 = DInode {
+            ft: FType::TSymlink,
+            ..Default::default()
+        };
+        assert!(in3.is_inline_symlink());
+        assert!(in3.is_inline() && in2.is_inline() && !in1.is_inline());
+        let target = "/some/where".repeat(9);
+        assert!(in3
+            .set_inline_data(&target.as_bytes()[..SYMLINK_INLINE_MAX as usize + 1])
+            .is_none());
+        in3.set_inline_data(&target.as_bytes()[..SYMLINK_INLINE_MAX as usize])
+            .unwrap();
+        assert!(in3.is_inline_symlink());
+        assert_eq!(
+            in3.inline_data(),
+            &target.as_bytes()[..SYMLINK_INLINE_MAX as usize]
+        );
+        in3.set_inline_data(b"../up").unwrap();
+        assert_eq!((in3.size, in3.inline_data()), (5, b"../up".to_vec()));
+        assert_eq!(in3.direct_blocks[1], 0);
+    }
+
+    //Testing how extents are packed in the space of the block pointers
+    #[test]
+    fn extents_test() {
+        let mut di = DInode::default();
+        assert_eq!(di.extent_header(), (0, 0));
+        assert!(di.inline_extents().is_empty());
+        let extents: Vec<Extent> = (0..INLINE_EXTENTS as u32)
+            .map(|i| Extent {
+                start: u32::MAX - 10 * i,
+                len: i + 1,
+                physical: u64::MAX - i as u64,
+            })
+            .collect();
+        assert_eq!(di.set_inline_extents(&extents), Some(()));
+        assert_eq!(di.extent_header(), (INLINE_EXTENTS as u64, 0));
+        assert_eq!(di.inline_extents(), extents);
+        assert_eq!(extents[1].map(u32::MAX as u64 - 10), Some(u64::MAX - 1));
+        assert_eq!(extents[1].map(u32::MAX as u64 - 8), None);
+
+        let mut more = extents.clone();
+        more.push(Extent::default());
+        assert_eq!(di.set_inline_extents(&more), None);
+        assert_eq!(di.inline_extents(), extents);
+        di.set_extent_block(more.len() as u64, 42);
+        assert_eq!(di.extent_header(), (more.len() as u64, 42));
+        assert!(di.inline_extents().iter().all(|e| *e == Extent::default()));
+    }
+
+    //Testing which class of permission bits applies to whom
+    #[test]
+    fn permissions_test() {
+        let inode = |ft: FType, mode: u16| {
+            Inode::new(
+                2,
+                DInode {
+                    ft,
+                    mode,
+                    uid: 10,
+                    gid: 20,
+                    ..Default::default()
+                },
+            )
+        };
+        let file = inode(FType::TFile, 0o640);
+        let owner = Credentials::new(10, 30);
+        let member = Credentials {
+            groups: vec![20],
+            ..Credentials::new(11, 30)
+        };
+        let other = Credentials::new(12, 30);
+        assert!(owner.permits(&file, Access::Write));
+        assert!(member.permits(&file, Access::Read));
+        assert!(!member.permits(&file, Access::Write));
+        assert!(!other.permits(&file, Access::Read));
+        //only the owner class applies to the owner, even if the group class would allow more
+        let file = inode(FType::TFile, 0o064);
+        assert!(!owner.permits(&file, Access::Read));
+        //root reads and writes anything, but only executes what is executable by someone
+        assert!(Credentials::root().permits(&inode(FType::TFile, 0), Access::Write));
+        assert!(!Credentials::root().permits(&inode(FType::TFile, 0o644), Access::Exec));
+        assert!(Credentials::root().permits(&inode(FType::TFile, 0o010), Access::Exec));
+        assert!(Credentials::root().permits(&inode(FType::TDir, MODE_SETGID), Access::Exec));
+        assert_eq!(file.get_mode(), 0o064);
     }
 }
@@ -23,16 +23,23 @@
 
 // We import std::error and std::format so we can say error::Error instead of
 // std::error::Error, etc.
+use std::cell::RefCell;
 use std::path::Path;
 
 // If you want to import things from the API crate, do so as follows:
 use bit_field::BitField;
+use cplfs_api::alloc::{AllocPolicy, AllocStrategy, UsedSlots};
 use cplfs_api::controller::Device;
 use cplfs_api::fs::BlockSupport;
 use cplfs_api::fs::FileSysSupport;
-use cplfs_api::types::{Block, SuperBlock, DINODE_SIZE};
+use cplfs_api::layout;
+use cplfs_api::types::{
+    Block, BlockNo, DataIdx, DiscardPolicy, FragReport, FreeExtent, GroupDesc, MountOptions,
+    StatFs, SuperBlock, DINODE_SIZE, FEATURES_SUPPORTED, GROUPDESC_SIZE,
+};
 
 use super::error_fs::BlockLayerError;
+use super::journal::{self, Journal};
 
 /// You are free to choose the name for your file system. As we will use
 /// automated tests when grading your assignment, indicate here the name of
@@ -48,6 +55,131 @@ pub struct BlockLayerFS {
 
     /// the encapsulated device
     device: Device,
+
+    /// cached group descriptors, empty if the file system has no block groups
+    groups: Vec<GroupDesc>,
+
+    /// the log of the current operation
+    journal: Journal,
+
+    /// what to do with the contents of freed data blocks
+    discard: DiscardPolicy,
+
+    /// blocks freed by the current operation, to be discarded once it has been committed
+    discarded: Vec<u64>,
+
+    /// number of free data blocks
+    free_count: u64,
+
+    /// whether allocations may use the reserved data blocks
+    privileged: bool,
+
+    /// cached bad-block list
+    bad: Vec<u64>,
+
+    /// blocks to discard at the start of every running transaction, innermost last
+    tx_discarded: Vec<Vec<u64>>,
+
+    /// picks the data blocks to allocate; borrowed mutably while the free bit map is borrowed by the policy
+    policy: RefCell<Box<dyn AllocPolicy>>,
+
+    /// number of free inodes, if the file system has an inode bit map
+    free_inodes: u64,
+
+    /// the lowest inode that may be free, if the file system has an inode bit map
+    inode_hint: u64,
+
+    /// state of the resize that is being carried out, if any
+    resizing: Option<Resizing>,
+}
+
+/// State of a resize that is being carried out, see [`resize_in_tx`](fn.resize_in_tx.html)
+#[derive(Debug)]
+struct Resizing {
+    /// superblock before the resize
+    sb: SuperBlock,
+    /// free bit map before the resize
+    bitmap: Vec<u8>,
+    /// size of the device before the resize
+    dev_nblocks: u64,
+    /// blocks written directly to the device, as the old geometry does not use them
+    staged: Vec<u64>,
+}
+
+/// Number of bits in a byte of the free bit map
+const BYTE_SIZE: u64 = 8;
+
+/// Returns whether bit `i` of the given bitmap is set
+fn bit_get(bitmap: &[u8], i: u64) -> bool {
+    bitmap[(i / BYTE_SIZE) as usize].get_bit((i % BYTE_SIZE) as usize)
+}
+
+/// Sets bit `i` of the given bitmap to `value`
+fn bit_set(bitmap: &mut [u8], i: u64, value: bool) {
+    bitmap[(i / BYTE_SIZE) as usize].set_bit((i % BYTE_SIZE) as usize, value);
+}
+
+/// A grow or shrink operation that has been planned, but not carried out yet.
+/// Created by [`grow_prepare`](struct.BlockLayerFS.html#method.grow_prepare) or [`shrink_prepare`](struct.BlockLayerFS.html#method.shrink_prepare) and carried out by [`resize_in_tx`](fn.resize_in_tx.html).
+#[derive(Debug)]
+pub struct ResizePlan {
+    /// The superblock the file system will have after committing
+    pub sb: SuperBlock,
+    /// Data blocks that have to be relocated, as pairs of (old, new) absolute block numbers.
+    /// Every new block is unused in the current geometry; the contents of the old block are copied to it before the caller updates the pointers to it.
+    pub moved: Vec<(BlockNo, BlockNo)>,
+    /// Contents of the new free bit map region
+    bitmap: Vec<u8>,
+}
+
+/// Looks up slot `i` of a [`LazySlots`](struct.LazySlots.html), returning the block that keeps track of it and the position of the slot within that block
+type Locate<'a> = Box<dyn Fn(&BlockLayerFS, u64) -> (BlockNo, u64) + 'a>;
+/// Tells whether slot `i` of a [`LazySlots`](struct.LazySlots.html) is in use, given the block keeping track of it and the position within that block
+type Test<'a> = Box<dyn FnMut(u64, &Block, u64) -> Result<bool, BlockLayerError> + 'a>;
+
+/// Data blocks or inodes, seen as slots for the allocation policies, that are read from the disk lazily.
+/// Only the block holding the slot looked up last is kept, so a policy that stops at the first free slot does not load the blocks past that slot.
+/// A slot that cannot be read is reported as used; the error is kept and returned by [`finish`](#method.finish).
+pub struct LazySlots<'a> {
+    fs: &'a BlockLayerFS,
+    nslots: u64,
+    locate: Locate<'a>,
+    test: Test<'a>,
+    block: Option<Block>,
+    error: Option<BlockLayerError>,
+}
+
+impl LazySlots<'_> {
+    /// Returns the first error that occurred while looking up slots, if any
+    pub fn finish(self) -> Result<(), BlockLayerError> {
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+impl UsedSlots for LazySlots<'_> {
+    fn nslots(&self) -> u64 {
+        self.nslots
+    }
+
+    fn is_used(&mut self, i: u64) -> bool {
+        let (block_no, pos) = (self.locate)(self.fs, i);
+        if self.block.as_ref().map(|b| b.block_no) != Some(block_no.0) {
+            match self.fs.b_get(block_no) {
+                Ok(b) => self.block = Some(b),
+                Err(e) => {
+                    self.error.get_or_insert(e);
+                    return true;
+                }
+            }
+        }
+        match (self.test)(i, self.block.as_ref().unwrap(), pos) {
+            Ok(used) => used,
+            Err(e) => {
+                self.error.get_or_insert(e);
+                true
+            }
+        }
+    }
 }
 
 /// Functions specific to BlockLayerFS
@@ -56,79 +188,355 @@ impl BlockLayerFS {
     pub fn sup_as_ref(&self) -> &SuperBlock {
         &self.super_block
     }
-}
 
-impl FileSysSupport for BlockLayerFS {
-    type Error = BlockLayerError;
+    /// Number of inodes in every block group, or in the entire inode region if there are no block groups
+    pub fn group_inodes(&self) -> u64 {
+        match self.super_block.ngroups {
+            0 => self.super_block.ninodes,
+            n => self.super_block.ninodes / n,
+        }
+    }
 
-    fn sb_valid(sb: &SuperBlock) -> bool {
-        let inode_blocks =
-            (sb.ninodes as f64 / (sb.block_size / *DINODE_SIZE) as f64).ceil() as u64;
-        // ((*DINODE_SIZE * sb.ninodes) as f64 / sb.block_size as f64).ceil() as u64;
-        let bmap_blocks = (sb.ndatablocks as f64 / (sb.block_size * 8) as f64).ceil() as u64;
-        sb.inodestart == 1
-            && sb.inodestart + inode_blocks - 1 < sb.bmapstart
-            && sb.bmapstart + bmap_blocks - 1 < sb.datastart
-            && sb.datastart + sb.ndatablocks - 1 < sb.nblocks
+    /// Returns the block group data block `i` belongs to, i.e. 0 if there are no block groups
+    pub fn data_group(&self, i: DataIdx) -> u64 {
+        i.0 / self.super_block.group_data_blocks().max(1)
     }
 
-    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
-        match Self::sb_valid(sb) {
-            false => Err(BlockLayerError::BlockLayerInput("SuperBlock not valid")),
-            true => {
-                let mut device = Device::new(path, sb.block_size, sb.nblocks)?;
-                let mut super_block = Block::new_zero(0, sb.block_size);
-                super_block.serialize_into(sb, 0)?;
-                device.write_block(&super_block)?;
-                Ok(BlockLayerFS {
-                    super_block: SuperBlock::from(*sb),
-                    device,
-                })
-            }
+    /// Returns the block group inode `i` belongs to, i.e. 0 if there are no block groups
+    pub fn inode_group(&self, i: u64) -> u64 {
+        i / self.group_inodes().max(1)
+    }
+
+    /// Returns the index of the first data block of block group `g`
+    pub fn group_first_data(&self, g: u64) -> DataIdx {
+        DataIdx(g * self.super_block.group_data_blocks())
+    }
+
+    /// Returns the number of the first inode of block group `g`
+    pub fn group_first_inode(&self, g: u64) -> u64 {
+        g * self.group_inodes()
+    }
+
+    /// Returns the block of the free bit map that keeps track of data block `i`, together with the index of the bit within that block
+    fn bitmap_pos(&self, i: DataIdx) -> (BlockNo, u64) {
+        if self.groups.is_empty() {
+            let bits = self.super_block.block_size * BYTE_SIZE;
+            return (BlockNo(self.super_block.bmapstart + i.0 / bits), i.0 % bits);
         }
+        let n = self.super_block.group_data_blocks();
+        (BlockNo(self.groups[(i.0 / n) as usize].bmap), i.0 % n)
     }
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
-        let sblock = dev.read_block(0)?;
-        let super_block = sblock.deserialize_from::<SuperBlock>(0)?;
-        match Self::sb_valid(&super_block) {
-            false => Err(BlockLayerError::BlockLayerInput("SuperBlock not valid")),
-            true => Ok(BlockLayerFS {
-                super_block,
-                device: dev,
+    /// Returns the block of the inode bit map that keeps track of inode `i`, together with the index of the bit within that block
+    fn imap_pos(&self, i: u64) -> (BlockNo, u64) {
+        let bits = self.super_block.block_size * BYTE_SIZE;
+        (BlockNo(self.super_block.imapstart + i / bits), i % bits)
+    }
+
+    /// Returns how many data blocks a single operation can write to fit in the log, if it writes `fixed` other blocks as well as `per_block` blocks of metadata for every data block.
+    /// Returns `None` if the file system has no log, in which case operations can be of any size, and at least 1 otherwise, even if such an operation does not fit.
+    pub fn op_data_blocks(&self, fixed: u64, per_block: u64) -> Option<u64> {
+        let extra = per_block + (self.super_block.ngroups > 0) as u64;
+        (self.super_block.nlog > 0)
+            .then(|| (self.journal.capacity().saturating_sub(fixed) / (1 + extra)).max(1))
+    }
+
+    /// Returns whether this file system keeps track of its free inodes in an inode bit map
+    pub fn has_inode_bitmap(&self) -> bool {
+        self.super_block.imapstart != 0
+    }
+
+    /// Returns the data blocks as slots for the allocation policies, which read the free bit map lazily
+    pub fn data_slots(&self) -> LazySlots<'_> {
+        LazySlots {
+            fs: self,
+            nslots: self.super_block.ndatablocks,
+            locate: Box::new(|fs, i| fs.bitmap_pos(DataIdx(i))),
+            test: Box::new(|_, block, bit| Ok(bit_get(block.contents_as_ref(), bit))),
+            block: None,
+            error: None,
+        }
+    }
+
+    /// Returns the inodes as slots for the allocation policies, which read the inode bit map lazily, or `None` if the file system has no inode bit map
+    pub fn inode_slots(&self) -> Option<LazySlots<'_>> {
+        self.has_inode_bitmap().then(|| LazySlots {
+            fs: self,
+            nslots: self.super_block.ninodes,
+            locate: Box::new(|fs, i| fs.imap_pos(i)),
+            test: Box::new(|_, block, bit| Ok(bit_get(block.contents_as_ref(), bit))),
+            block: None,
+            error: None,
+        })
+    }
+
+    /// Returns the inodes as slots for the allocation policies, which load the inode blocks lazily, for inodes of `inode_size` bytes.
+    /// `test` tells whether an inode is in use, given its number, the block holding it and its offset within that block.
+    /// Meant for file systems without an inode bit map.
+    pub fn inode_table_slots<'a, T>(&'a self, inode_size: u64, test: T) -> LazySlots<'a>
+    where
+        T: FnMut(u64, &Block, u64) -> Result<bool, BlockLayerError> + 'a,
+    {
+        LazySlots {
+            fs: self,
+            nslots: self.super_block.ninodes,
+            locate: Box::new(move |fs, i| {
+                let (b, offset) = fs.inode_pos(i, inode_size);
+                (BlockNo(b), offset)
             }),
+            test: Box::new(test),
+            block: None,
+            error: None,
         }
     }
 
-    fn unmountfs(self) -> Device {
-        self.device
+    /// Returns for every inode whether its bit is set in the inode bit map, or `None` if the file system has no inode bit map.
+    /// Each bitmap block is only loaded once.
+    pub fn used_inodes(&self) -> Result<Option<Vec<bool>>, BlockLayerError> {
+        if !self.has_inode_bitmap() {
+            return Ok(None);
+        }
+        let mut used = vec![];
+        let mut block: Option<Block> = None;
+        for i in 0..self.super_block.ninodes {
+            let (imap, bit) = self.imap_pos(i);
+            if block.as_ref().map(|b| b.block_no) != Some(imap.0) {
+                block = Some(self.b_get(imap)?);
+            }
+            used.push(bit_get(block.as_ref().unwrap().contents_as_ref(), bit));
+        }
+        Ok(Some(used))
     }
-}
 
-impl BlockSupport for BlockLayerFS {
-    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
-        Ok(self.device.read_block(i)?)
+    /// Sets the bit of inode `i` in the inode bit map to `used`, and updates the count of free inodes and the search hint.
+    /// Does nothing if the bit already has this value, if `i` is 0, whose bit is always set, or if the file system has no inode bit map.
+    pub fn set_inode_used(&mut self, i: u64, used: bool) -> Result<(), BlockLayerError> {
+        if !self.has_inode_bitmap() || i == 0 {
+            return Ok(());
+        }
+        if i >= self.super_block.ninodes {
+            return Err(BlockLayerError::BlockLayerInput(
+                "Inode number is outside the inode bit map bounds",
+            ));
+        }
+        let (imap, bit) = self.imap_pos(i);
+        let mut block = self.b_get(imap)?;
+        if bit_get(block.contents_as_ref(), bit) == used {
+            return Ok(());
+        }
+        let byte = bit / BYTE_SIZE;
+        let mut byte_slice: [u8; 1] = Default::default();
+        block.read_data(&mut byte_slice, byte)?;
+        byte_slice[0].set_bit((bit % BYTE_SIZE) as usize, used);
+        block.write_data(&byte_slice, byte)?;
+        self.b_put(&block)?;
+        if used {
+            self.free_inodes -= 1;
+            if i == self.inode_hint {
+                self.inode_hint += 1;
+            }
+        } else {
+            self.free_inodes += 1;
+            self.inode_hint = self.inode_hint.min(i);
+        }
+        Ok(())
     }
 
-    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
-        Ok(self.device.write_block(b)?)
+    /// Returns the number of free inodes, or `None` if the file system has no inode bit map to count them with
+    pub fn free_inodes(&self) -> Option<u64> {
+        self.has_inode_bitmap().then_some(self.free_inodes)
+    }
+
+    /// Returns the lowest inode that may be free, i.e. where a search for a free inode can start, or `None` if the file system has no inode bit map.
+    /// All inodes below the hint are in use.
+    pub fn inode_hint(&self) -> Option<u64> {
+        self.has_inode_bitmap().then_some(self.inode_hint)
+    }
+
+    /// Returns the block that stores inode `i`, for inodes of `inode_size` bytes, together with the offset in bytes of the inode within that block.
+    /// Inodes are spaced according to [`SuperBlock::inode_slot_size`](../../cplfs_api/types/struct.SuperBlock.html#method.inode_slot_size).
+    /// Does not check whether `i` is in bounds.
+    pub fn inode_pos(&self, i: u64, inode_size: u64) -> (u64, u64) {
+        let slot_size = self.super_block.inode_slot_size(inode_size);
+        let inodes_per_block = self.super_block.block_size / slot_size;
+        if self.groups.is_empty() {
+            return (
+                self.super_block.inodestart + i / inodes_per_block,
+                (i % inodes_per_block) * slot_size,
+            );
+        }
+        let n = self.group_inodes();
+        let within = i % n;
+        (
+            self.groups[(i / n) as usize].inodestart + within / inodes_per_block,
+            (within % inodes_per_block) * slot_size,
+        )
+    }
+
+    /// Returns whether block `b` was written since it was last persisted to the disk
+    pub fn is_dirty(&self, b: u64) -> bool {
+        self.device.is_dirty(b)
+    }
+
+    /// Persists the writes to each of the given blocks that is dirty.
+    /// Errors if an operation is running, as its writes have not reached the device yet.
+    pub fn sync_blocks(&mut self, blocks: &[u64]) -> Result<(), BlockLayerError> {
+        if self.journal.active() {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Cannot persist blocks while an operation is running",
+            ));
+        }
+        for &b in blocks {
+            self.device.sync_block(b)?;
+        }
+        Ok(())
+    }
+
+    /// Persists the writes to the blocks that keep track of the layout and the allocation state of the file system, like the superblock and the bit maps.
+    /// These are the dirty blocks outside of the data region that do not hold any inodes of `inode_size` bytes.
+    pub fn sync_metadata(&mut self, inode_size: u64) -> Result<(), BlockLayerError> {
+        let meta: Vec<u64> = self
+            .device
+            .dirty_blocks()
+            .into_iter()
+            .filter(|&b| {
+                self.super_block.data_idx(BlockNo(b)).is_none()
+                    && !self.is_inode_block(b, inode_size)
+            })
+            .collect();
+        self.sync_blocks(&meta)
+    }
+
+    /// Returns whether block `b` belongs to the inode region, or to the inode region of a block group, for inodes of `inode_size` bytes
+    fn is_inode_block(&self, b: u64, inode_size: u64) -> bool {
+        let inodes_per_block =
+            self.super_block.block_size / self.super_block.inode_slot_size(inode_size);
+        if self.groups.is_empty() {
+            let start = self.super_block.inodestart;
+            return (start..start + self.super_block.ninodes.div_ceil(inodes_per_block))
+                .contains(&b);
+        }
+        let n = self.group_inodes().div_ceil(inodes_per_block);
+        self.groups
+            .iter()
+            .any(|g| (g.inodestart..g.inodestart + n).contains(&b))
+    }
+
+    /// Checks the part of a superblock that is specific to file systems with block groups
+    fn sb_groups_valid(sb: &SuperBlock) -> bool {
+        let inodes_per_block = sb.block_size / sb.inode_slot_size(*DINODE_SIZE);
+        if sb.ngroups == 0
+            || sb.groupsize == 0
+            || inodes_per_block == 0
+            || *GROUPDESC_SIZE > sb.block_size
+            || !sb.ninodes.is_multiple_of(sb.ngroups)
+            || !sb.ndatablocks.is_multiple_of(sb.ngroups)
+        {
+            return false;
+        }
+        let group_inode_blocks = (sb.ninodes / sb.ngroups).div_ceil(inodes_per_block);
+        let group_data_blocks = sb.ndatablocks / sb.ngroups;
+        let end = sb
+            .ngroups
+            .checked_mul(sb.groupsize)
+            .and_then(|size| size.checked_add(sb.bmapstart));
+        sb.bmapstart == 1 + sb.nlog + layout::group_desc_blocks(sb)
+            && sb.inodestart == sb.bmapstart + 1
+            && sb.inodestart + group_inode_blocks <= sb.datastart
+            && group_data_blocks <= sb.block_size * BYTE_SIZE
+            && sb.datastart - sb.bmapstart + group_data_blocks <= sb.groupsize
+            && end.is_some_and(|end| end <= sb.nblocks)
+    }
+
+    /// Returns the block of the group descriptor region that holds the descriptor of group `g`, and the offset of the descriptor in this block
+    fn group_desc_pos(&self, g: u64) -> (u64, u64) {
+        let per_block = self.super_block.block_size / *GROUPDESC_SIZE;
+        (
+            1 + self.super_block.nlog + g / per_block,
+            (g % per_block) * (*GROUPDESC_SIZE),
+        )
     }
 
-    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+    /// Reads the superblock, the group descriptors and the bad-block list from the disk into the cache, checking the group descriptors and the list on the way
+    fn load_cache(&mut self) -> Result<(), BlockLayerError> {
+        self.super_block = self.b_get(BlockNo(0))?.deserialize_from::<SuperBlock>(0)?;
+        self.groups = vec![];
+        for expected in layout::group_descs(&self.super_block) {
+            let (b, offset) = self.group_desc_pos(self.groups.len() as u64);
+            let desc = self
+                .b_get(BlockNo(b))?
+                .deserialize_from::<GroupDesc>(offset)?;
+            if (desc.bmap, desc.inodestart, desc.datastart)
+                != (expected.bmap, expected.inodestart, expected.datastart)
+                || desc.free_blocks > expected.free_blocks
+            {
+                return Err(BlockLayerError::BlockLayerInput(
+                    "Group descriptor not valid",
+                ));
+            }
+            self.groups.push(desc);
+        }
+        self.bad = match self.super_block.badlist {
+            0 => vec![],
+            b => self.b_get(BlockNo(b))?.deserialize_from::<Vec<u64>>(0)?,
+        };
+        let sb = self.super_block;
+        if self.bad.len() as u64 > self.bad_capacity()
+            || self.bad.iter().any(|&b| sb.data_idx(BlockNo(b)).is_none())
+        {
+            return Err(BlockLayerError::BlockLayerInput("Bad-block list not valid"));
+        }
+        //bad blocks and the block of the list are never free, so that they are never handed out
+        let listed = Some(sb.badlist).filter(|&b| b != 0);
+        for b in self.bad.iter().copied().chain(listed) {
+            let (bmap, bit) = self.bitmap_pos(sb.data_idx(BlockNo(b)).unwrap());
+            if !bit_get(self.b_get(bmap)?.contents_as_ref(), bit) {
+                return Err(BlockLayerError::BlockLayerInput(
+                    "Bad block is marked free in the bit map",
+                ));
+            }
+        }
+        self.free_count = match self.groups.is_empty() {
+            true => self.free_data_blocks()?.len() as u64,
+            false => self.groups.iter().map(|g| g.free_blocks).sum(),
+        };
+        if let Some(used) = self.used_inodes()? {
+            if used.first() != Some(&true) {
+                return Err(BlockLayerError::BlockLayerInput("Inode bit map not valid"));
+            }
+            self.free_inodes = used.iter().filter(|&&u| !u).count() as u64;
+            self.inode_hint = used.iter().position(|&u| !u).unwrap_or(used.len()) as u64;
+        }
+        Ok(())
+    }
+
+    /// Writes the cached descriptor of group `g` to the disk
+    fn put_group(&mut self, g: u64) -> Result<(), BlockLayerError> {
+        let (b, offset) = self.group_desc_pos(g);
+        let mut block = self.b_get(BlockNo(b))?;
+        block.serialize_into(&self.groups[g as usize], offset)?;
+        self.b_put(&block)
+    }
+
+    /// Clears the bit of data block `i` in the free bit map, see `b_free`
+    fn free_block(&mut self, i: DataIdx) -> Result<(), BlockLayerError> {
         let byte_size = 8;
-        let t_block_addr =
-            self.super_block.bmapstart + i / (self.super_block.block_size * byte_size);
-        if t_block_addr >= self.super_block.datastart {
+        if i.0 >= self.super_block.ndatablocks {
             return Err(BlockLayerError::BlockLayerInput(
                 "Block address is outside bitmap bounds",
             ));
         }
-        //how many bits inside the target block we have to look
-        let block_offset_bit = i % (self.super_block.block_size * byte_size);
+        let block_no = self.super_block.block_no(i).unwrap().0;
+        if self.bad.contains(&block_no) || block_no == self.super_block.badlist {
+            return Err(BlockLayerError::BlockLayerWrite(
+                "Trying to free a bad block or the bad-block list",
+            ));
+        }
+        //bitmap block and bit inside that block we have to look at
+        let (t_block_addr, block_offset_bit) = self.bitmap_pos(i);
         //offset of the byte inside the target_block
         let target_byte = block_offset_bit / byte_size;
         let target_bit = block_offset_bit % byte_size;
-        //get bitmap starting address, divide i/blocksize and then
         let mut target_block = self.b_get(t_block_addr)?;
         //byte that will contain the bit we want to change
         let mut byte_slice: [u8; 1] = Default::default();
@@ -146,53 +554,703 @@ impl BlockSupport for BlockLayerFS {
         //write back
         target_block.write_data(&byte_slice, target_byte)?;
         self.b_put(&target_block)?;
+        if !self.groups.is_empty() {
+            let g = self.data_group(i);
+            self.groups[g as usize].free_blocks += 1;
+            self.put_group(g)?;
+        }
+        self.free_count += 1;
+        if self.discard != DiscardPolicy::Keep {
+            self.discarded.push(self.super_block.block_no(i).unwrap().0);
+        }
+        Ok(())
+    }
+
+    /// Sets the bit of the free data block `i` in the free bit map, and updates the counts of free blocks
+    fn claim_block(&mut self, i: DataIdx) -> Result<(), BlockLayerError> {
+        let (bmap, bit) = self.bitmap_pos(i);
+        let mut block = self.b_get(bmap)?;
+        if bit_get(block.contents_as_ref(), bit) {
+            return Err(BlockLayerError::BlockLayerWrite(
+                "Trying to claim a block that is in use",
+            ));
+        }
+        let byte = bit / BYTE_SIZE;
+        let mut byte_slice: [u8; 1] = Default::default();
+        block.read_data(&mut byte_slice, byte)?;
+        byte_slice[0].set_bit((bit % BYTE_SIZE) as usize, true);
+        block.write_data(&byte_slice, byte)?;
+        self.b_put(&block)?;
+        if !self.groups.is_empty() {
+            let g = self.data_group(i);
+            self.groups[g as usize].free_blocks -= 1;
+            self.put_group(g)?;
+        }
+        self.free_count -= 1;
+        //a block that is in use again must not be discarded anymore
+        let block_no = self.super_block.block_no(i).unwrap().0;
+        self.discarded.retain(|&b| b != block_no);
         Ok(())
     }
 
-    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
-        if i > self.super_block.ndatablocks - 1 {
+    /// Allocates the data block the allocation policy picks, preferably close to data block `goal`; see `b_alloc` and `b_alloc_near`
+    fn alloc_block(&mut self, goal: Option<DataIdx>) -> Result<DataIdx, BlockLayerError> {
+        if goal.is_some_and(|goal| goal.0 >= self.super_block.ndatablocks) {
             return Err(BlockLayerError::BlockLayerInput(
-                "Trying to access a block with index outside bounds",
+                "Goal block is outside the data region",
             ));
         }
-        let block_len = self.b_get(self.super_block.datastart + i)?.len();
-        let zero_block = Block::new_zero(self.super_block.datastart + i, block_len);
-        self.b_put(&zero_block)
+        if !self.privileged && self.free_count <= self.super_block.nreserved {
+            return Err(BlockLayerError::BlockLayerOp(
+                "No space left outside the reserved blocks!",
+            ));
+        }
+        let group = self.super_block.group_data_blocks();
+        let mut used = self.data_slots();
+        let i = (self.policy.borrow_mut()).choose(&mut used, group, goal.map(|goal| goal.0));
+        used.finish()?;
+        let i = i
+            .map(DataIdx)
+            .ok_or(BlockLayerError::BlockLayerOp("No space left!"))?;
+        self.claim_block(i)?;
+        Ok(i)
     }
 
-    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
-        let bmap_blocks = (self.super_block.ndatablocks as f64 / 8.0).ceil() as u64;
-        let mut bit: u64 = 0;
-        let mut byte_slice: [u8; 1] = Default::default();
-        // iterate over every block to find a free bit
-        for bl in 0..bmap_blocks {
-            let mut block = self.b_get(self.super_block.bmapstart + bl)?;
-            let buf = block.contents_as_ref();
-            //iterate over every byte and count the bits until we find a "0"
-            for by in 0..block.len() {
-                if buf[by as usize] != 0b1111_1111 {
-                    // iterate inside the byte
-                    for i in 0..8 {
-                        //the byte may have padding and go to illegal addresses so we check
-                        if bit + i == self.super_block.ndatablocks {
-                            return Err(BlockLayerError::BlockLayerOp("No space left!"));
-                        }
-                        //if zero bit is found, write the block and persist it
-                        if !buf[by as usize].get_bit(i as usize) {
-                            block.read_data(&mut byte_slice, by)?;
-                            byte_slice.first_mut().unwrap().set_bit(i as usize, true);
-                            block.write_data(&byte_slice, by)?;
-                            self.b_put(&block)?;
-                            return Ok(bit + i as u64);
+    /// Maximum number of blocks the bad-block list can hold, i.e. the number of block numbers that fit in a single block after the length of the list
+    fn bad_capacity(&self) -> u64 {
+        (self.super_block.block_size / 8).saturating_sub(1)
+    }
+
+    /// Writes the cached bad-block list to the disk.
+    /// The list first moves to a newly allocated data block if it has no block yet, or if its block has been marked bad itself.
+    fn store_bad_list(&mut self) -> Result<(), BlockLayerError> {
+        let mut sb = self.super_block;
+        if sb.badlist == 0 || self.bad.contains(&sb.badlist) {
+            let i = self.alloc_block(None)?;
+            sb.badlist = sb.block_no(i).unwrap().0;
+            self.sup_put(&sb)?;
+        }
+        let mut block = Block::new_zero(sb.badlist, sb.block_size);
+        block.serialize_into(&self.bad, 0)?;
+        self.b_put(&block)
+    }
+
+    /// Marks data block `b` as bad, see `b_mark_bad`.
+    /// If `b` is in use, its contents are copied to a newly allocated block, which is returned, but only if `migrate` is set; otherwise, this method errors.
+    /// The caller is responsible for updating the pointers to `b`.
+    pub fn mark_bad(
+        &mut self,
+        b: BlockNo,
+        migrate: bool,
+    ) -> Result<Option<BlockNo>, BlockLayerError> {
+        let i = self
+            .super_block
+            .data_idx(b)
+            .ok_or(BlockLayerError::BlockLayerInput(
+                "Only data blocks can be marked bad",
+            ))?;
+        if self.bad.contains(&b.0) {
+            return Err(BlockLayerError::BlockLayerInput(
+                "Block is already marked bad",
+            ));
+        }
+        if self.bad.len() as u64 >= self.bad_capacity() {
+            return Err(BlockLayerError::BlockLayerOp("The bad-block list is full"));
+        }
+        self.as_privileged(|fs| {
+            let (bmap, bit) = fs.bitmap_pos(i);
+            let moved = match bit_get(fs.b_get(bmap)?.contents_as_ref(), bit) {
+                false => fs.claim_block(i).map(|_| None)?,
+                //the list moves by itself when it is stored
+                true if b.0 == fs.super_block.badlist => None,
+                true if !migrate => {
+                    return Err(BlockLayerError::BlockLayerOp(
+                        "Block is in use, and the block layer cannot update the pointers to it",
+                    ))
+                }
+                true => {
+                    let new = fs.alloc_block(Some(i))?;
+                    let new = fs.super_block.block_no(new).unwrap();
+                    let mut contents = fs.b_get(b)?;
+                    contents.block_no = new.0;
+                    fs.b_put(&contents)?;
+                    Some(new)
+                }
+            };
+            fs.bad.push(b.0);
+            if let Err(e) = fs.store_bad_list() {
+                fs.bad.pop();
+                return Err(e);
+            }
+            Ok(moved)
+        })
+    }
+
+    /// Finishes an operation that ended with `result`: if it was the outermost one, the blocks it freed are discarded if it succeeded, and the cache is reloaded if it failed
+    fn op_ended(&mut self, result: Result<(), BlockLayerError>) -> Result<(), BlockLayerError> {
+        if self.journal.running() {
+            return result;
+        }
+        let discarded = std::mem::take(&mut self.discarded);
+        if result.is_err() {
+            //the operation may have been discarded, so the cache may be ahead of the disk
+            self.load_cache()?;
+            return result;
+        }
+        self.discard_blocks(discarded, self.discard)
+    }
+
+    /// Returns for every data block whether its bit is set in the free bit map.
+    /// Each bitmap block is only loaded once.
+    fn used_blocks(&self) -> Result<Vec<bool>, BlockLayerError> {
+        let mut used = vec![];
+        let mut block: Option<Block> = None;
+        for i in (0..self.super_block.ndatablocks).map(DataIdx) {
+            let (bmap, bit) = self.bitmap_pos(i);
+            if block.as_ref().map(|b| b.block_no) != Some(bmap.0) {
+                block = Some(self.b_get(bmap)?);
+            }
+            used.push(bit_get(block.as_ref().unwrap().contents_as_ref(), bit));
+        }
+        Ok(used)
+    }
+
+    /// Returns the block numbers of all free data blocks, in increasing order
+    fn free_data_blocks(&self) -> Result<Vec<u64>, BlockLayerError> {
+        let sb = self.super_block;
+        Ok((self.used_blocks()?.iter().enumerate())
+            .filter(|(_, &used)| !used)
+            .map(|(i, _)| sb.block_no(DataIdx(i as u64)).unwrap().0)
+            .collect())
+    }
+
+    /// Discards the given blocks on the device according to `policy`, bypassing the log.
+    /// Runs of adjacent blocks are discarded together.
+    fn discard_blocks(
+        &mut self,
+        mut blocks: Vec<u64>,
+        policy: DiscardPolicy,
+    ) -> Result<(), BlockLayerError> {
+        blocks.sort_unstable();
+        blocks.dedup();
+        let mut k = 0;
+        while k < blocks.len() {
+            let start = blocks[k];
+            let mut n = 1;
+            while k + n < blocks.len() && blocks[k + n] == start + n as u64 {
+                n += 1;
+            }
+            match policy {
+                DiscardPolicy::Keep => {}
+                DiscardPolicy::Zero => {
+                    for b in start..start + n as u64 {
+                        self.device
+                            .write_block(&Block::new_zero(b, self.super_block.block_size))?;
+                    }
+                }
+                DiscardPolicy::PunchHole => self.device.discard(start, n as u64)?,
+            }
+            k += n;
+        }
+        Ok(())
+    }
+
+    /// Reads the entire free bit map region into memory
+    fn read_bitmap(&self) -> Result<Vec<u8>, BlockLayerError> {
+        let mut bitmap = vec![];
+        for b in self.super_block.bmapstart..self.super_block.datastart {
+            bitmap.extend_from_slice(self.b_get(BlockNo(b))?.contents_as_ref());
+        }
+        Ok(bitmap)
+    }
+
+    /// Writes the given bitmap to the disk, as a free bit map region starting at block `bmapstart`
+    fn write_bitmap(&mut self, bmapstart: u64, bitmap: &[u8]) -> Result<(), BlockLayerError> {
+        let bs = self.super_block.block_size as usize;
+        for (i, chunk) in bitmap.chunks(bs).enumerate() {
+            self.b_put(&Block::new(bmapstart + i as u64, chunk.into()))?;
+        }
+        Ok(())
+    }
+
+    /// Computes the superblock of this file system after growing it to `nblocks` blocks and `ninodes` inodes.
+    /// Regions never move backwards or shrink; the inode and bitmap regions only grow when they have to, and the data region takes up all remaining blocks.
+    pub fn grown_sb(&self, nblocks: u64, ninodes: u64) -> Result<SuperBlock, BlockLayerError> {
+        let sb = self.super_block;
+        if sb.ngroups > 0 {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Cannot resize a file system with block groups",
+            ));
+        }
+        if sb.badlist != 0 {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Cannot resize a file system with bad blocks",
+            ));
+        }
+        if nblocks < sb.nblocks || ninodes < sb.ninodes {
+            return Err(BlockLayerError::BlockLayerInput(
+                "Cannot grow a file system to a smaller size",
+            ));
+        }
+        if sb.imapstart != 0 && ninodes > (sb.inodestart - sb.imapstart) * sb.block_size * BYTE_SIZE
+        {
+            return Err(BlockLayerError::BlockLayerInput(
+                "The inode bit map cannot keep track of that many inodes",
+            ));
+        }
+        let inode_blocks = ninodes.div_ceil(sb.block_size / sb.inode_slot_size(*DINODE_SIZE));
+        let bmapstart = sb.bmapstart.max(sb.inodestart + inode_blocks);
+        if bmapstart >= nblocks {
+            return Err(BlockLayerError::BlockLayerInput(
+                "No room left for data after growing the inode region",
+            ));
+        }
+        // a chunk of `bits + 1` blocks holds one bitmap block and the data blocks it keeps track of
+        let bits = sb.block_size * BYTE_SIZE;
+        let bmap_blocks =
+            (sb.datastart - sb.bmapstart).max((nblocks - bmapstart).div_ceil(bits + 1));
+        let datastart = bmapstart + bmap_blocks;
+        if datastart >= nblocks {
+            return Err(BlockLayerError::BlockLayerInput(
+                "No room left for data after growing the bitmap region",
+            ));
+        }
+        let new_sb = SuperBlock {
+            nblocks,
+            ninodes,
+            bmapstart,
+            datastart,
+            ndatablocks: nblocks - datastart,
+            ..sb
+        };
+        match Self::sb_valid(&new_sb) {
+            false => Err(BlockLayerError::BlockLayerOp(
+                "Grown superblock is not valid",
+            )),
+            true => Ok(new_sb),
+        }
+    }
+
+    /// First step of growing the file system to `nblocks` blocks and `ninodes` inodes, which plans the grow without writing anything.
+    /// Computes the new geometry and free bit map.
+    /// Data blocks in use at the start of the data region, where the grown metadata will be stored, are planned to move to free blocks of the grown data region, preferring blocks the current geometry does not know about.
+    /// The caller is responsible for updating any pointers to these blocks, as listed in the returned plan, while carrying it out with [`resize_in_tx`](fn.resize_in_tx.html).
+    pub fn grow_prepare(&self, nblocks: u64, ninodes: u64) -> Result<ResizePlan, BlockLayerError> {
+        let old = self.super_block;
+        let sb = self.grown_sb(nblocks, ninodes)?;
+
+        // data block i in the new geometry is data block i + shift in the old one
+        let shift = sb.datastart - old.datastart;
+        let old_bitmap = self.read_bitmap()?;
+        let mut bitmap = vec![0; ((sb.datastart - sb.bmapstart) * sb.block_size) as usize];
+        for i in shift..old.ndatablocks {
+            bit_set(&mut bitmap, i - shift, bit_get(&old_bitmap, i));
+        }
+
+        // the first new data block the old geometry does not keep track of
+        let fresh = (old.datastart + old.ndatablocks).max(sb.datastart) - sb.datastart;
+        let mut candidates = (fresh..sb.ndatablocks).chain(0..fresh);
+        let mut moved = vec![];
+        for i in 0..shift.min(old.ndatablocks) {
+            if !bit_get(&old_bitmap, i) {
+                continue;
+            }
+            let to = loop {
+                match candidates.next() {
+                    None => {
+                        return Err(BlockLayerError::BlockLayerOp(
+                            "No space left to relocate data blocks",
+                        ))
+                    }
+                    Some(j) if !bit_get(&bitmap, j) => break j,
+                    Some(_) => {}
+                }
+            };
+            bit_set(&mut bitmap, to, true);
+            moved.push((BlockNo(old.datastart + i), BlockNo(sb.datastart + to)));
+        }
+        Ok(ResizePlan { sb, moved, bitmap })
+    }
+
+    /// Computes the superblock of this file system after shrinking it to `nblocks` blocks.
+    /// The inode region stays as it is, the bitmap region shrinks to the size it needs and the data region takes up all remaining blocks.
+    pub fn shrunk_sb(&self, nblocks: u64) -> Result<SuperBlock, BlockLayerError> {
+        let sb = self.super_block;
+        if sb.ngroups > 0 {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Cannot resize a file system with block groups",
+            ));
+        }
+        if sb.badlist != 0 {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Cannot resize a file system with bad blocks",
+            ));
+        }
+        if nblocks > sb.nblocks {
+            return Err(BlockLayerError::BlockLayerInput(
+                "Cannot shrink a file system to a larger size",
+            ));
+        }
+        if nblocks < sb.bmapstart + 2 {
+            return Err(BlockLayerError::BlockLayerInput(
+                "No room left for the bitmap and data after shrinking",
+            ));
+        }
+        let bits = sb.block_size * BYTE_SIZE;
+        let bmap_blocks = (nblocks - sb.bmapstart)
+            .div_ceil(bits + 1)
+            .min(sb.datastart - sb.bmapstart);
+        let datastart = sb.bmapstart + bmap_blocks;
+        Ok(SuperBlock {
+            nblocks,
+            datastart,
+            ndatablocks: nblocks - datastart,
+            nreserved: sb.nreserved.min(nblocks - datastart),
+            ..sb
+        })
+    }
+
+    /// Returns the absolute numbers of the data blocks in use past the end of a file system shrunk to `nblocks` blocks
+    fn blocks_past(&self, bitmap: &[u8], nblocks: u64) -> Vec<u64> {
+        let sb = self.super_block;
+        (nblocks.max(sb.datastart) - sb.datastart..sb.ndatablocks)
+            .filter(|&i| bit_get(bitmap, i))
+            .map(|i| sb.datastart + i)
+            .collect()
+    }
+
+    /// First step of shrinking the file system to `nblocks` blocks, which plans the shrink without writing anything.
+    /// Computes the new geometry and free bit map, in which the data blocks in use past the new end move to data blocks before it that are free in the current geometry.
+    /// The caller is responsible for updating the pointers to these blocks, as listed in the returned plan, while carrying it out with [`resize_in_tx`](fn.resize_in_tx.html).
+    ///
+    /// Errors if the data in use does not fit in the shrunk file system.
+    pub fn shrink_prepare(&self, nblocks: u64) -> Result<ResizePlan, BlockLayerError> {
+        let old = self.super_block;
+        let sb = self.shrunk_sb(nblocks)?;
+        let mut old_bitmap = self.read_bitmap()?;
+
+        let past = self.blocks_past(&old_bitmap, nblocks);
+        let free: Vec<u64> = (old.datastart..nblocks)
+            .filter(|&b| {
+                let i = b - old.datastart;
+                i >= old.ndatablocks || !bit_get(&old_bitmap, i)
+            })
+            .take(past.len())
+            .collect();
+        if free.len() < past.len() {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Data in use does not fit in the shrunk file system",
+            ));
+        }
+
+        let mut moved = vec![];
+        for (&from, &to) in past.iter().zip(free.iter()) {
+            if to - old.datastart < old.ndatablocks {
+                bit_set(&mut old_bitmap, to - old.datastart, true);
+            }
+            moved.push((BlockNo(from), BlockNo(to)));
+        }
+
+        // data block i in the old geometry is data block i + shift in the new one
+        let shift = old.datastart - sb.datastart;
+        let mut bitmap = vec![0; ((sb.datastart - sb.bmapstart) * sb.block_size) as usize];
+        for i in 0..old.ndatablocks.min(nblocks.saturating_sub(old.datastart)) {
+            bit_set(&mut bitmap, i + shift, bit_get(&old_bitmap, i));
+        }
+        Ok(ResizePlan { sb, moved, bitmap })
+    }
+
+    /// Starts carrying out the given resize plan: grows the device if necessary, and copies the data blocks that move.
+    /// From now on until `resize_end`, writes to blocks the current geometry does not use go to the device directly instead of to the log.
+    fn resize_begin(&mut self, plan: &ResizePlan) -> Result<(), BlockLayerError> {
+        if self.journal.running() {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Cannot resize while an operation is running",
+            ));
+        }
+        let bitmap = self.read_bitmap()?;
+        let dev_nblocks = self.device.nblocks;
+        if plan.sb.nblocks > dev_nblocks {
+            self.device.resize(plan.sb.nblocks)?;
+        }
+        self.resizing = Some(Resizing {
+            sb: self.super_block,
+            bitmap,
+            dev_nblocks,
+            staged: vec![],
+        });
+        for &(from, to) in &plan.moved {
+            let mut block = self.b_get(from)?;
+            block.block_no = to.0;
+            self.b_put(&block)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `b` to the device directly if a resize is being carried out and the geometry before the resize does not use `b`, and returns whether it did.
+    /// Such writes cannot harm the current geometry in any order, so they take up no room in the log.
+    fn stage(&mut self, b: &Block) -> Result<bool, BlockLayerError> {
+        let r = match self.resizing.as_mut() {
+            Some(r) => r,
+            None => return Ok(false),
+        };
+        let used = match r.sb.data_idx(BlockNo(b.block_no)) {
+            Some(i) => bit_get(&r.bitmap, i.0),
+            None => b.block_no < r.sb.datastart,
+        };
+        if used {
+            return Ok(false);
+        }
+        self.device.write_block(b)?;
+        r.staged.push(b.block_no);
+        Ok(true)
+    }
+
+    /// Last step of carrying out a resize plan, as part of the transaction of `resize_in_tx`; writes the free bit map and the superblock of the given plan.
+    /// Blocks that move from the bitmap or data region to the inode region are not touched, and have to be initialized by the caller.
+    /// Without a log, the superblock is the commit point of the resize, so this errors if the transaction changed any other block the current geometry uses.
+    fn resize_commit(&mut self, plan: &ResizePlan) -> Result<(), BlockLayerError> {
+        self.write_bitmap(plan.sb.bmapstart, &plan.bitmap)?;
+        if self.super_block.nlog == 0 && !self.journal.changed(&self.device)?.is_empty() {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Resizing changes metadata in place, which needs a log to be crash-safe",
+            ));
+        }
+        //the blocks written directly have to be on the disk before the transaction that refers to them is
+        if let Some(r) = &self.resizing {
+            for &b in &r.staged {
+                self.device.sync_block(b)?;
+            }
+        }
+        self.free_count = (0..plan.sb.ndatablocks)
+            .filter(|&i| !bit_get(&plan.bitmap, i))
+            .count() as u64;
+        //the inodes a grown inode region adds are all free
+        self.free_inodes += plan.sb.ninodes - self.super_block.ninodes;
+        self.sup_put(&plan.sb)
+    }
+
+    /// Stops carrying out a resize plan, and gives the device its old size back if the resize was not committed
+    fn resize_end(&mut self, committed: bool) -> Result<(), BlockLayerError> {
+        if let Some(r) = self.resizing.take() {
+            if !committed && self.device.nblocks > r.dev_nblocks {
+                self.device.resize(r.dev_nblocks)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Carries out the resize `plan` on `fs`, whose block layer `block_fs` returns, and which runs `update` to update the pointers to the blocks that move and to initialize the new inodes.
+/// The copies of the blocks that move, and all other writes to blocks the current geometry does not use, go to the device directly.
+/// All other writes, including the new free bit map and finally the superblock, make up a single transaction, so the resize happens as a whole or not at all.
+/// Without a log, only the superblock can be the commit point, so the resize errors if the transaction would change any other block the current geometry uses.
+/// Either way, the device gets its old size back if the resize fails.
+pub(crate) fn resize_in_tx<F, G>(
+    fs: &mut F,
+    block_fs: fn(&mut F) -> &mut BlockLayerFS,
+    plan: ResizePlan,
+    update: G,
+) -> Result<(), F::Error>
+where
+    F: BlockSupport,
+    F::Error: From<BlockLayerError>,
+    G: FnOnce(&mut F, &ResizePlan) -> Result<(), F::Error>,
+{
+    let result = match block_fs(fs).resize_begin(&plan) {
+        Ok(()) => journal::in_tx(fs, |fs| {
+            update(fs, &plan)?;
+            Ok(block_fs(fs).resize_commit(&plan)?)
+        }),
+        Err(e) => Err(e.into()),
+    };
+    let end = block_fs(fs).resize_end(result.is_ok());
+    result?;
+    Ok(end?)
+}
+
+impl FileSysSupport for BlockLayerFS {
+    type Error = BlockLayerError;
+
+    fn sb_valid(sb: &SuperBlock) -> bool {
+        if sb.nreserved > sb.ndatablocks
+            || (sb.badlist != 0 && sb.data_idx(BlockNo(sb.badlist)).is_none())
+            || sb.features & !FEATURES_SUPPORTED != 0
+        {
+            return false;
+        }
+        if sb.ngroups > 0 || sb.groupsize > 0 {
+            return Journal::sb_valid(sb) && sb.imapstart == 0 && Self::sb_groups_valid(sb);
+        }
+        let inode_blocks = (sb.ninodes as f64
+            / (sb.block_size / sb.inode_slot_size(*DINODE_SIZE)) as f64)
+            .ceil() as u64;
+        // ((*DINODE_SIZE * sb.ninodes) as f64 / sb.block_size as f64).ceil() as u64;
+        let bmap_blocks = (sb.ndatablocks as f64 / (sb.block_size * 8) as f64).ceil() as u64;
+        let imap_valid = match sb.imapstart {
+            0 => sb.inodestart == 1 + sb.nlog,
+            imapstart => {
+                imapstart == 1 + sb.nlog
+                    && imapstart + sb.ninodes.div_ceil(sb.block_size * 8) <= sb.inodestart
+            }
+        };
+        Journal::sb_valid(sb)
+            && imap_valid
+            && sb.inodestart + inode_blocks - 1 < sb.bmapstart
+            && sb.bmapstart + bmap_blocks - 1 < sb.datastart
+            && sb.datastart + sb.ndatablocks - 1 < sb.nblocks
+    }
+
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        match Self::sb_valid(sb) && sb.badlist == 0 {
+            false => Err(BlockLayerError::BlockLayerInput("SuperBlock not valid")),
+            true => {
+                let mut device = Device::new(path, sb.block_size, sb.nblocks)?;
+                let mut super_block = Block::new_zero(0, sb.block_size);
+                super_block.serialize_into(sb, 0)?;
+                device.write_block(&super_block)?;
+                let mut fs = BlockLayerFS {
+                    super_block: SuperBlock::from(*sb),
+                    device,
+                    groups: layout::group_descs(sb),
+                    journal: Journal::new(sb),
+                    discard: DiscardPolicy::default(),
+                    discarded: vec![],
+                    free_count: sb.ndatablocks,
+                    privileged: false,
+                    bad: vec![],
+                    tx_discarded: vec![],
+                    policy: RefCell::new(AllocStrategy::default().policy()),
+                    free_inodes: sb.ninodes - 1,
+                    inode_hint: 1,
+                    resizing: None,
+                };
+                for g in 0..sb.ngroups {
+                    fs.put_group(g)?;
+                }
+                if fs.has_inode_bitmap() {
+                    //only inode 0, which is never allocated, is in use
+                    for b in sb.imapstart..sb.inodestart {
+                        let mut imap = Block::new_zero(b, sb.block_size);
+                        if b == sb.imapstart {
+                            imap.write_data(&[1], 0)?;
                         }
+                        fs.device.write_block(&imap)?;
                     }
-                } else {
-                    //no free spot was found, iterate one byte
-                    bit += 8;
                 }
+                Ok(fs)
             }
         }
-        Err(BlockLayerError::BlockLayerOp("No space left!"))
+    }
+
+    fn mountfs_with(mut dev: Device, opts: &MountOptions) -> Result<Self, Self::Error> {
+        let sblock = dev.read_block(0)?;
+        let super_block = sblock.deserialize_from::<SuperBlock>(0)?;
+        if !Self::sb_valid(&super_block) {
+            return Err(BlockLayerError::BlockLayerInput("SuperBlock not valid"));
+        }
+        //finish the last operation, which may have changed the superblock itself as well
+        Journal::recover(&mut dev, &super_block)?;
+        let mut fs = BlockLayerFS {
+            super_block,
+            device: dev,
+            groups: vec![],
+            journal: Journal::new(&super_block),
+            discard: opts.discard,
+            discarded: vec![],
+            free_count: 0,
+            privileged: false,
+            bad: vec![],
+            tx_discarded: vec![],
+            policy: RefCell::new(opts.alloc.policy()),
+            free_inodes: 0,
+            inode_hint: 0,
+            resizing: None,
+        };
+        fs.load_cache()?;
+        if !Self::sb_valid(&fs.super_block) {
+            return Err(BlockLayerError::BlockLayerInput("SuperBlock not valid"));
+        }
+        Ok(fs)
+    }
+
+    fn unmountfs(self) -> Device {
+        self.device
+    }
+}
+
+impl BlockSupport for BlockLayerFS {
+    fn b_get(&self, i: BlockNo) -> Result<Block, Self::Error> {
+        if let Some(b) = self.journal.read(i.0) {
+            return Ok(b);
+        }
+        Ok(self.device.read_block(i.0)?)
+    }
+
+    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        if self.stage(b)? {
+            return Ok(());
+        }
+        if self.journal.active() {
+            return self.journal.write(b);
+        }
+        Ok(self.device.write_block(b)?)
+    }
+
+    fn b_free(&mut self, i: DataIdx) -> Result<(), Self::Error> {
+        journal::in_op(self, |fs| fs.free_block(i))
+    }
+
+    fn b_zero(&mut self, i: DataIdx) -> Result<(), Self::Error> {
+        let block_no = self
+            .super_block
+            .block_no(i)
+            .ok_or(BlockLayerError::BlockLayerInput(
+                "Trying to access a block with index outside bounds",
+            ))?;
+        let block_len = self.b_get(block_no)?.len();
+        let zero_block = Block::new_zero(block_no.0, block_len);
+        self.b_put(&zero_block)
+    }
+
+    fn b_alloc(&mut self) -> Result<DataIdx, Self::Error> {
+        journal::in_op(self, |fs| fs.alloc_block(None))
+    }
+
+    fn b_alloc_near(&mut self, goal: DataIdx) -> Result<DataIdx, Self::Error> {
+        journal::in_op(self, |fs| fs.alloc_block(Some(goal)))
+    }
+
+    fn b_set_privileged(&mut self, privileged: bool) -> bool {
+        std::mem::replace(&mut self.privileged, privileged)
+    }
+
+    fn b_frag_report(&self) -> Result<FragReport, Self::Error> {
+        let group = self.super_block.group_data_blocks().max(1);
+        let mut extents: Vec<FreeExtent> = vec![];
+        for (i, used) in self.used_blocks()?.into_iter().enumerate() {
+            let i = i as u64;
+            match extents.last_mut() {
+                _ if used => continue,
+                Some(e) if e.start.0 + e.len == i && !i.is_multiple_of(group) => e.len += 1,
+                _ => extents.push(FreeExtent {
+                    start: DataIdx(i),
+                    len: 1,
+                }),
+            }
+        }
+        Ok(FragReport::from_extents(extents))
+    }
+
+    fn statfs(&self) -> Result<StatFs, Self::Error> {
+        let sb = &self.super_block;
+        Ok(StatFs {
+            block_size: sb.block_size,
+            blocks: sb.ndatablocks,
+            free_blocks: self.free_count,
+            available_blocks: self.free_count.saturating_sub(sb.nreserved),
+            reserved_blocks: sb.nreserved,
+        })
     }
 
     fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
@@ -200,12 +1258,107 @@ impl BlockSupport for BlockLayerFS {
     }
 
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
-        let mut super_block = self.device.read_block(0)?;
+        let mut super_block = self.b_get(BlockNo(0))?;
         super_block.serialize_into(sup, 0)?;
-        self.device.write_block(&super_block)?;
+        self.b_put(&super_block)?;
         self.super_block = SuperBlock::from(*sup);
         Ok(())
     }
+
+    fn begin_op(&mut self) -> Result<(), Self::Error> {
+        self.journal.begin();
+        Ok(())
+    }
+
+    fn end_op(&mut self) -> Result<(), Self::Error> {
+        let result = self.journal.end(&mut self.device);
+        self.op_ended(result)
+    }
+
+    fn b_tx_begin(&mut self) -> Result<(), Self::Error> {
+        self.journal.begin_tx();
+        self.tx_discarded.push(self.discarded.clone());
+        Ok(())
+    }
+
+    fn b_tx_commit(&mut self) -> Result<(), Self::Error> {
+        self.tx_discarded.pop();
+        let result = self.journal.commit_tx(&mut self.device);
+        self.op_ended(result)
+    }
+
+    fn b_tx_rollback(&mut self) -> Result<(), Self::Error> {
+        if let Some(discarded) = self.tx_discarded.pop() {
+            self.discarded = discarded;
+        }
+        let result = self.journal.rollback_tx(&mut self.device);
+        //the cache may still reflect the writes that were thrown away
+        self.load_cache()?;
+        self.op_ended(result)
+    }
+
+    fn b_grow(&mut self, nblocks: u64, ninodes: u64) -> Result<(), Self::Error> {
+        let old = self.super_block;
+        let shift = self.grown_sb(nblocks, ninodes)?.datastart - old.datastart;
+        let bitmap = self.read_bitmap()?;
+        if (0..shift.min(old.ndatablocks)).any(|i| bit_get(&bitmap, i)) {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Data blocks in the way of the grown metadata are in use",
+            ));
+        }
+        let plan = self.grow_prepare(nblocks, ninodes)?;
+        resize_in_tx(
+            self,
+            |fs| fs,
+            plan,
+            |fs, plan| {
+                // the block layer does not know about inodes, so the grown inode region is simply zeroed
+                for b in old.bmapstart..plan.sb.bmapstart {
+                    fs.b_put(&Block::new_zero(b, old.block_size))?;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    fn b_shrink(dev: Device, nblocks: u64) -> Result<Device, Self::Error> {
+        let mut fs = Self::mountfs(dev)?;
+        fs.shrunk_sb(nblocks)?;
+        let bitmap = fs.read_bitmap()?;
+        if !fs.blocks_past(&bitmap, nblocks).is_empty() {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Data blocks past the new end are in use",
+            ));
+        }
+        let plan = fs.shrink_prepare(nblocks)?;
+        resize_in_tx(&mut fs, |fs| fs, plan, |_, _| Ok(()))?;
+        let mut dev = fs.unmountfs();
+        dev.resize(nblocks)?;
+        Ok(dev)
+    }
+
+    fn b_mark_bad(&mut self, b: BlockNo) -> Result<(), Self::Error> {
+        journal::in_op(self, |fs| fs.mark_bad(b, false).map(|_| ()))
+    }
+
+    fn b_bad_blocks(&self) -> Result<Vec<BlockNo>, Self::Error> {
+        Ok(self.bad.iter().map(|&b| BlockNo(b)).collect())
+    }
+
+    fn b_trim(&mut self, policy: DiscardPolicy) -> Result<u64, Self::Error> {
+        if self.journal.running() {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Cannot trim while an operation is running",
+            ));
+        }
+        if policy == DiscardPolicy::Keep {
+            return Ok(0);
+        }
+        let free = self.free_data_blocks()?;
+        let n = free.len() as u64;
+        self.discard_blocks(free, policy)?;
+        Ok(n)
+    }
 }
 
 // Here we define a submodule, called `tests`, that will contain our unit tests
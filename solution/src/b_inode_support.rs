@@ -21,15 +21,25 @@
 //! ...
 //!
 
+use cplfs_api::alloc::{AllocPolicy, AllocStrategy};
+use cplfs_api::clock::{Clock, SystemClock};
 use cplfs_api::controller::Device;
-use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport};
+use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport, XattrSupport};
 use cplfs_api::types::{
-    Block, Buffer, DInode, FType, Inode, InodeLike, SuperBlock, DINODE_SIZE, DIRECT_POINTERS,
+    AtimePolicy, Block, BlockNo, Buffer, DInode, DataIdx, DiscardPolicy, Extent, FType, FragReport,
+    Inode, InodeExtents, InodeLike, MountOptions, StatFs, SuperBlock, Timestamp, DEFAULT_DIR_MODE,
+    DEFAULT_FILE_MODE, DEFAULT_SYMLINK_MODE, DINODE_SIZE, DIRECT_POINTERS, FEATURE_EXTENTS,
+    FEATURE_INLINE_DATA, FEATURE_XATTR, INLINE_DATA_MAX, INODE_EOF_BLOCKS, INODE_INLINE_DATA,
+    XATTR_NAMESPACES, XATTR_NAME_MAX,
 };
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 
-use super::a_block_support::BlockLayerFS;
+use super::a_block_support::{resize_in_tx, BlockLayerFS};
 use super::error_fs::InodeLayerError;
+use super::journal;
 
 /// You are free to choose the name for your file system. As we will use
 /// automated tests when grading your assignment, indicate here the name of
@@ -41,8 +51,15 @@ pub type FSName = InodeLayerFS;
 #[derive(Debug)]
 pub struct InodeLayerFS {
     block_fs: BlockLayerFS,
-    inodes_per_block: u64,
     inode_max_size: u64,
+    /// picks the inodes to allocate
+    policy: Box<dyn AllocPolicy>,
+    /// source of the timestamps of inodes
+    clock: Box<dyn Clock>,
+    /// when reads update the access time
+    atime: AtimePolicy,
+    /// inodes written since they were last synced, mapped to whether `i_fdatasync` has to persist them
+    dirty: BTreeMap<u64, bool>,
 }
 
 /// Functions specific to InodeLayerFS
@@ -52,15 +69,600 @@ impl InodeLayerFS {
         self.block_fs.sup_as_ref()
     }
 
-    /// Returns the block that contains inode with index i
-    fn get_block_of_inode(&self, i: u64) -> Result<Block, <Self as FileSysSupport>::Error> {
-        if i > self.sup_as_ref().ninodes - 1 {
+    /// Marks the inodes with numbers `from` up to `sb.ninodes` as free, in a file system with superblock `sb`; see `init_free_disk_inodes`.
+    fn init_free_inodes(
+        block_fs: &mut BlockLayerFS,
+        sb: &SuperBlock,
+        from: u64,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        init_free_disk_inodes::<DInode>(block_fs, sb, from, *DINODE_SIZE)
+    }
+
+    /// Allocates the inode the allocation policy picks, preferably close to inode `goal`; see `alloc_disk_inode`
+    fn alloc_inode(
+        &mut self,
+        ft: FType,
+        goal: Option<u64>,
+    ) -> Result<u64, <Self as FileSysSupport>::Error> {
+        let now = self.now();
+        let init = DInode {
+            ft,
+            flags: self.initial_flags(ft),
+            mode: initial_mode(ft),
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            ..Default::default()
+        };
+        let i = alloc_disk_inode(
+            &mut self.block_fs,
+            self.policy.as_mut(),
+            *DINODE_SIZE,
+            goal,
+            init,
+        )?;
+        mark_dirty(self, i, true);
+        Ok(i)
+    }
+
+    /// Returns the number of data blocks holding the contents of `di_node`; inodes with inline contents have none.
+    /// With [`INODE_EOF_BLOCKS`](../../cplfs_api/types/constant.INODE_EOF_BLOCKS.html), the blocks past the end of the inode may be in use as well.
+    fn nblocks(di_node: &DInode, block_size: u64) -> u64 {
+        if di_node.is_inline() {
+            0
+        } else if di_node.flags & INODE_EOF_BLOCKS != 0 {
+            di_node.size.div_ceil(block_size).max(DIRECT_POINTERS)
+        } else {
+            di_node.size.div_ceil(block_size)
+        }
+    }
+
+    /// Returns the absolute numbers of the data blocks of `inode`, in the order in which they hold its contents, skipping holes
+    fn inode_blocks(
+        &self,
+        inode: &<Self as InodeSupport>::Inode,
+    ) -> Result<Vec<u64>, <Self as FileSysSupport>::Error> {
+        let n = Self::nblocks(&inode.disk_node, self.sup_as_ref().block_size);
+        if n > 0 && self.has_extents() {
+            return Ok(self
+                .load_extents(&inode.disk_node)?
+                .iter()
+                .flat_map(|e| e.physical..e.physical + e.len as u64)
+                .collect());
+        }
+        Ok(inode.disk_node.direct_blocks[..n as usize]
+            .iter()
+            .copied()
+            .filter(|&b| b != 0)
+            .collect())
+    }
+
+    /// Returns the flags of a new inode of type `ft`: regular files and directories start out with their contents inline if the file system supports it, see [`FEATURE_INLINE_DATA`](../../cplfs_api/types/constant.FEATURE_INLINE_DATA.html)
+    pub(crate) fn initial_flags(&self, ft: FType) -> u8 {
+        match ft {
+            FType::TFile | FType::TDir if self.sup_as_ref().features & FEATURE_INLINE_DATA != 0 => {
+                INODE_INLINE_DATA
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns whether the inodes of this file system map their contents with extents, see [`FEATURE_EXTENTS`](../../cplfs_api/types/constant.FEATURE_EXTENTS.html)
+    fn has_extents(&self) -> bool {
+        self.sup_as_ref().features & FEATURE_EXTENTS != 0
+    }
+
+    /// Returns the largest size an inode can grow to in a file system with superblock `sb`.
+    /// Extents address the blocks of a file with 32 bits, and have a length of at most `u32::MAX` blocks.
+    fn max_size(sb: &SuperBlock) -> u64 {
+        if sb.features & FEATURE_EXTENTS != 0 {
+            (u32::MAX as u64).saturating_mul(sb.block_size)
+        } else {
+            DIRECT_POINTERS * sb.block_size
+        }
+    }
+
+    /// Returns the extents of the contents of `di_node`, in a file system with extents
+    fn load_extents(
+        &self,
+        di_node: &DInode,
+    ) -> Result<Vec<Extent>, <Self as FileSysSupport>::Error> {
+        match di_node.extent_header() {
+            (_, 0) => Ok(di_node.inline_extents()),
+            (_, block) => Ok(self.b_get(BlockNo(block))?.deserialize_from(0)?),
+        }
+    }
+
+    /// Stores `extents` as the extents of `inode`: in the inode itself if they fit, and in its extent block otherwise.
+    /// The extent block is allocated when the extents no longer fit in the inode, and freed when they fit again.
+    /// Only changes `inode` in memory; storing it is up to the caller.
+    fn store_extents(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        extents: &[Extent],
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        let (_, block) = inode.disk_node.extent_header();
+        if inode.disk_node.set_inline_extents(extents).is_some() {
+            if block != 0 {
+                free_data_block(self, block)?;
+            }
+            return Ok(());
+        }
+        let block = match block {
+            0 => alloc_data_block(self, inode.inum, None)?,
+            block => block,
+        };
+        self.write_extent_block(inode, extents, block)
+    }
+
+    /// Writes `extents` to extent block `block` of `inode`, and has the inode point to it
+    fn write_extent_block(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        extents: &[Extent],
+        block: u64,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        let mut b = Block::new_zero(block, self.sup_as_ref().block_size);
+        b.serialize_into(&extents.to_vec(), 0).map_err(|_| {
+            InodeLayerError::InodeLayerOp("Too many extents to fit in an extent block")
+        })?;
+        self.b_put(&b)?;
+        inode
+            .disk_node
+            .set_extent_block(extents.len() as u64, block);
+        Ok(())
+    }
+
+    /// Returns the current time of the clock of this file system
+    pub fn now(&self) -> Timestamp {
+        self.clock.now()
+    }
+
+    /// Reads from `inode` like `i_read`, but without updating its access time.
+    /// Used for reads the user did not ask for, such as looking up an entry in a directory.
+    pub fn read_contents(
+        &self,
+        inode: &<Self as InodeSupport>::Inode,
+        buf: &mut Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<u64, <Self as FileSysSupport>::Error> {
+        if !inode.disk_node.is_inline() {
+            return read_blocks(self, inode, buf, off, n);
+        }
+        if off > inode.get_size() {
             return Err(InodeLayerError::InodeLayerInput(
-                "Trying to get inode with index out of bounds",
+                "Offset falls outside the inode's data",
             ));
         }
-        let t_block_addr = self.sup_as_ref().inodestart + i / self.inodes_per_block;
-        self.b_get(t_block_addr)
+        let end = inode.get_size().min(off.saturating_add(n));
+        let data = inode.disk_node.inline_data();
+        buf.write_data(&data[off as usize..end as usize], 0)?;
+        Ok(end - off)
+    }
+
+    /// Writes to `inode` like `i_write`, but also to symbolic links, whose contents are only written when creating them; see `write_in_chunks`
+    fn write_contents(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        buf: &Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        //besides the bit map block of every data block, a chunk may write the inode, its extent block, and the block its inline contents move out to, with its bit map block
+        let chunk_blocks = self.block_fs.op_data_blocks(4, 1);
+        write_in_chunks(self, inode, (buf, off, n), chunk_blocks, Self::write_chunk)
+    }
+
+    /// Writes `data` to `inode` at offset `off` in a single transaction; see `write_contents`
+    fn write_chunk(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        data: &[u8],
+        off: u64,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        let end = off + data.len() as u64;
+        change_in_tx(self, inode, |fs, inode| {
+            if !inode.disk_node.is_inline() {
+                write_blocks(fs, inode, data, off)?;
+            } else if end <= INLINE_DATA_MAX {
+                let mut contents = inode.disk_node.inline_data();
+                contents.resize(contents.len().max(end as usize), 0);
+                contents[off as usize..end as usize].copy_from_slice(data);
+                inode.disk_node.set_inline_data(&contents);
+            } else {
+                fs.move_out_inline(inode)?;
+                write_blocks(fs, inode, data, off)?;
+            }
+            inode.disk_node.size = inode.disk_node.size.max(end);
+            Ok(true)
+        })
+    }
+
+    /// Moves the inline contents of `inode` out to data blocks, once they no longer fit in the inode.
+    /// Only changes `inode` in memory; storing it is up to the caller.
+    fn move_out_inline(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        let contents = inode.disk_node.inline_data();
+        inode.disk_node.flags &= !INODE_INLINE_DATA;
+        inode.disk_node.direct_blocks = [0; DIRECT_POINTERS as usize];
+        write_blocks(self, inode, &contents, 0)
+    }
+
+    /// Allocates the `len` bytes of `inode` starting at offset `off`, and zeroes them if `zero` is set; see `i_fallocate` and `i_zero_range`.
+    fn allocate_range(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        off: u64,
+        len: u64,
+        keep_size: bool,
+        zero: bool,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        allocate_range(
+            self,
+            inode,
+            (off, len),
+            keep_size,
+            |fs, inode, end, new_size| {
+                if inode.disk_node.is_inline() && end <= INLINE_DATA_MAX {
+                    //the space of inline contents is always there, so only zeroing and growing are left
+                    let mut contents = inode.disk_node.inline_data();
+                    contents.resize(new_size as usize, 0);
+                    if zero {
+                        let n = contents.len();
+                        contents[(off as usize).min(n)..(end as usize).min(n)].fill(0);
+                    }
+                    inode.disk_node.set_inline_data(&contents);
+                    return Ok(());
+                }
+                if inode.disk_node.is_inline() {
+                    fs.move_out_inline(inode)?;
+                }
+                allocate_blocks(fs, inode, (off, end), new_size, zero)?;
+                if end > new_size {
+                    inode.disk_node.flags |= INODE_EOF_BLOCKS;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Frees the blocks lying entirely in the `len` bytes of `inode` starting at offset `off`, and zeroes the rest of the range; see `i_punch_hole`.
+    fn punch_hole(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        off: u64,
+        len: u64,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        change_in_tx(self, inode, |fs, inode| {
+            if inode.get_ft() != FType::TFile {
+                return Err(InodeLayerError::InodeLayerInput(
+                    "Only regular files can have holes punched in them",
+                ));
+            }
+            if len == 0 {
+                return Ok(false);
+            }
+            //no block lies past the maximum size
+            let end = off.saturating_add(len).min(fs.inode_max_size);
+            if inode.disk_node.is_inline() {
+                let mut contents = inode.disk_node.inline_data();
+                let n = contents.len() as u64;
+                contents[off.min(n) as usize..end.min(n) as usize].fill(0);
+                inode.disk_node.set_inline_data(&contents);
+            } else if off < end {
+                let bs = fs.sup_as_ref().block_size;
+                fs.free_block_range(inode, off.div_ceil(bs), end / bs)?;
+                //the blocks at the edges of the range are only partly in it
+                zero_bytes(fs, inode, off, end.min(off.next_multiple_of(bs)))?;
+                zero_bytes(fs, inode, (end - end % bs).max(off), end)?;
+            }
+            Ok(true)
+        })
+    }
+
+    /// Shrinks or extends `inode` to `new_size` bytes; see `i_truncate`.
+    fn truncate_contents(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        new_size: u64,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        let size = inode.disk_node.size;
+        change_in_tx(self, inode, |fs, inode| {
+            if inode.get_ft() != FType::TFile {
+                return Err(InodeLayerError::InodeLayerInput(
+                    "Only regular files can be truncated to a given size",
+                ));
+            }
+            if new_size > fs.inode_max_size {
+                return Err(InodeLayerError::InodeLayerInput(
+                    "Truncation exceeds inode's max size",
+                ));
+            }
+            if new_size == size {
+                return Ok(false);
+            }
+            if new_size == 0 {
+                fs.free_inode_blocks(inode)?;
+            } else if inode.disk_node.is_inline() && new_size <= INLINE_DATA_MAX {
+                let mut contents = inode.disk_node.inline_data();
+                contents.resize(new_size as usize, 0);
+                inode.disk_node.set_inline_data(&contents);
+            } else {
+                if inode.disk_node.is_inline() {
+                    fs.move_out_inline(inode)?;
+                }
+                let bs = fs.sup_as_ref().block_size;
+                if new_size < size {
+                    fs.free_block_range(inode, new_size.div_ceil(bs), u64::MAX)?;
+                    //no blocks are left past the new end
+                    inode.disk_node.flags &= !INODE_EOF_BLOCKS;
+                }
+                //neither the bytes cut off nor whatever lay past the old end may show up in the file
+                zero_tail(fs, inode, new_size.min(size))?;
+            }
+            inode.disk_node.size = new_size;
+            Ok(true)
+        })
+    }
+
+    /// Stores `target` as the contents of the symbolic link `inode`, which has to be empty: inline if it is short enough, and in data blocks otherwise.
+    pub fn set_link_target(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        target: &str,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        if inode.get_ft() != FType::TSymlink || inode.get_size() != 0 {
+            return Err(InodeLayerError::InodeLayerInput(
+                "The target can only be set on an empty symbolic link",
+            ));
+        }
+        if inode.disk_node.set_inline_data(target.as_bytes()).is_some() {
+            inode.disk_node.ctime = self.now();
+            inode.disk_node.mtime = inode.disk_node.ctime;
+            return self.i_put(inode);
+        }
+        let mut buf = Buffer::new_zero(target.len() as u64);
+        buf.write_data(target.as_bytes(), 0)?;
+        self.write_contents(inode, &buf, 0, target.len() as u64)
+    }
+
+    /// Returns the target of the symbolic link `inode`
+    pub fn link_target(
+        &self,
+        inode: &<Self as InodeSupport>::Inode,
+    ) -> Result<String, <Self as FileSysSupport>::Error> {
+        if inode.get_ft() != FType::TSymlink {
+            return Err(InodeLayerError::InodeLayerInput(
+                "The given inode is not a symbolic link",
+            ));
+        }
+        let mut buf = Buffer::new_zero(inode.get_size());
+        self.read_contents(inode, &mut buf, 0, inode.get_size())?;
+        String::from_utf8(buf.contents_as_ref().to_vec()).map_err(|_| {
+            InodeLayerError::InodeLayerOp("The target of a symbolic link is not valid UTF-8")
+        })
+    }
+
+    /// Rewrites the block pointers of all inodes in use according to the given (old, new) pairs of absolute block numbers
+    fn remap_blocks(
+        &mut self,
+        moved: &[(BlockNo, BlockNo)],
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        if moved.is_empty() {
+            return Ok(());
+        }
+        let moved: HashMap<u64, u64> = moved.iter().map(|(old, new)| (old.0, new.0)).collect();
+        let bs = self.sup_as_ref().block_size;
+        if self.has_xattrs() {
+            self.remap_xattr_blocks(&moved)?;
+        }
+        if self.has_extents() {
+            return self.remap_extents(&moved);
+        }
+        remap_disk_inodes(&mut self.block_fs, *DINODE_SIZE, |di_node: &mut DInode| {
+            let n = Self::nblocks(di_node, bs);
+            di_node
+                .direct_blocks
+                .iter_mut()
+                .take(n as usize)
+                .fold(false, |changed, ptr| remap_ptr(ptr, &moved) | changed)
+        })
+    }
+
+    /// Rewrites the extents of all inodes in use, and the pointers to their extent blocks, according to `moved`.
+    /// Extents are split where only part of their blocks moved; this cannot allocate an extent block, as the free bit map may be in the middle of being resized.
+    fn remap_extents(
+        &mut self,
+        moved: &HashMap<u64, u64>,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        let bs = self.sup_as_ref().block_size;
+        for i in 1..self.sup_as_ref().ninodes {
+            let mut inode = self.i_get(i)?;
+            if inode.get_ft() == FType::TFree || Self::nblocks(&inode.disk_node, bs) == 0 {
+                continue;
+            }
+            let (_, old_block) = inode.disk_node.extent_header();
+            let block = moved.get(&old_block).copied().unwrap_or(old_block);
+            //a moved extent block has already been copied to its new location
+            inode.disk_node.direct_blocks[1] = block;
+            let old = self.load_extents(&inode.disk_node)?;
+            let mut extents: Vec<Extent> = vec![];
+            for (k, b) in old
+                .iter()
+                .flat_map(|e| (e.start as u64..e.end()).zip(e.physical..))
+            {
+                let b = moved.get(&b).copied().unwrap_or(b);
+                match extents.last_mut() {
+                    Some(e) if e.end() == k && e.physical + e.len as u64 == b => e.len += 1,
+                    _ => extents.push(Extent {
+                        start: k as u32,
+                        len: 1,
+                        physical: b,
+                    }),
+                }
+            }
+            if extents == old && block == old_block {
+                continue;
+            }
+            if block != 0 {
+                self.write_extent_block(&mut inode, &extents, block)?;
+            } else if inode.disk_node.set_inline_extents(&extents).is_none() {
+                return Err(InodeLayerError::InodeLayerOp(
+                    "Moving blocks splits the extents of a file beyond what its inode holds",
+                ));
+            }
+            self.i_put(&inode)?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the inodes of this file system can carry extended attributes, see [`FEATURE_XATTR`](../../cplfs_api/types/constant.FEATURE_XATTR.html)
+    fn has_xattrs(&self) -> bool {
+        self.sup_as_ref().features & FEATURE_XATTR != 0
+    }
+
+    /// Errors unless this file system supports extended attributes, and `name` is a valid name for one; see [`XattrSupport`](../../cplfs_api/fs/trait.XattrSupport.html)
+    fn check_xattr_name(&self, name: &str) -> Result<(), <Self as FileSysSupport>::Error> {
+        if !self.has_xattrs() {
+            return Err(InodeLayerError::InodeLayerOp(
+                "The file system does not support extended attributes",
+            ));
+        }
+        if name.len() as u64 > XATTR_NAME_MAX
+            || !XATTR_NAMESPACES
+                .iter()
+                .any(|ns| name.len() > ns.len() && name.starts_with(ns))
+        {
+            return Err(InodeLayerError::InodeLayerInput(
+                "Invalid extended attribute name",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the block holding the number of the attribute block of inode `i`, which follows the inode itself, together with its offset in that block
+    fn xattr_ptr_pos(&self, i: u64) -> Result<(u64, u64), <Self as FileSysSupport>::Error> {
+        let (block_no, offset) = inode_pos(self, i)?;
+        Ok((block_no, offset + *DINODE_SIZE))
+    }
+
+    /// Returns the number of the attribute block of inode `i`, or 0 if it has no extended attributes
+    fn xattr_block(&self, i: u64) -> Result<u64, <Self as FileSysSupport>::Error> {
+        let (block_no, offset) = self.xattr_ptr_pos(i)?;
+        Ok(self.b_get(BlockNo(block_no))?.deserialize_from(offset)?)
+    }
+
+    /// Has inode `i` point to attribute block `block`, or to none if it is 0
+    fn set_xattr_block(
+        &mut self,
+        i: u64,
+        block: u64,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        let (block_no, offset) = self.xattr_ptr_pos(i)?;
+        let mut b = self.b_get(BlockNo(block_no))?;
+        b.serialize_into(&block, offset)?;
+        self.b_put(&b)
+    }
+
+    /// Returns the extended attributes of inode `i` as (name, value) pairs, sorted on name
+    fn load_xattrs(
+        &self,
+        i: u64,
+    ) -> Result<Vec<(String, Vec<u8>)>, <Self as FileSysSupport>::Error> {
+        match self.xattr_block(i)? {
+            0 => Ok(vec![]),
+            block => Ok(self.b_get(BlockNo(block))?.deserialize_from(0)?),
+        }
+    }
+
+    /// Stores `attrs` as the extended attributes of `inode`, as a serialized `Vec` in its attribute block.
+    /// The attribute block is allocated for the first attribute, and freed once there are none left.
+    fn store_xattrs(
+        &mut self,
+        inode: &<Self as InodeSupport>::Inode,
+        attrs: &[(String, Vec<u8>)],
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        let old = self.xattr_block(inode.inum)?;
+        if attrs.is_empty() {
+            if old != 0 {
+                free_data_block(self, old)?;
+                self.set_xattr_block(inode.inum, 0)?;
+            }
+            return Ok(());
+        }
+        let mut block = Block::new_zero(old, self.sup_as_ref().block_size);
+        block.serialize_into(&attrs.to_vec(), 0).map_err(|_| {
+            InodeLayerError::InodeLayerInput("Extended attributes do not fit in a block")
+        })?;
+        if old == 0 {
+            block.block_no = alloc_data_block(self, inode.inum, None)?;
+            self.set_xattr_block(inode.inum, block.block_no)?;
+        }
+        self.b_put(&block)
+    }
+
+    /// Rewrites the numbers of the attribute blocks of all inodes according to `moved`
+    fn remap_xattr_blocks(
+        &mut self,
+        moved: &HashMap<u64, u64>,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        for i in 1..self.sup_as_ref().ninodes {
+            if let Some(&new) = moved.get(&self.xattr_block(i)?) {
+                self.set_xattr_block(i, new)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Frees the data blocks holding blocks `from` up to `to` of `inode`, leaving the blocks outside that range as they are.
+    /// Extents that only partly lie in the range are split.
+    /// Only changes `inode` in memory, apart from its extent block; updating its size and storing it is up to the caller.
+    fn free_block_range(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        from: u64,
+        to: u64,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        if from >= to {
+            return Ok(());
+        }
+        if self.has_extents() {
+            let mut extents = vec![];
+            for e in self.load_extents(&inode.disk_node)? {
+                let (start, end) = (e.start as u64, e.end());
+                let (a, b) = (from.clamp(start, end), to.clamp(start, end));
+                for block_no in e.physical + (a - start)..e.physical + (b - start) {
+                    free_data_block(self, block_no)?;
+                }
+                if a > start {
+                    extents.push(Extent {
+                        len: (a - start) as u32,
+                        ..e
+                    });
+                }
+                if b < end {
+                    extents.push(Extent {
+                        start: b as u32,
+                        len: (end - b) as u32,
+                        physical: e.physical + (b - start),
+                    });
+                }
+            }
+            return self.store_extents(inode, &extents);
+        }
+        let blocks_occupied = Self::nblocks(&inode.disk_node, self.sup_as_ref().block_size);
+        for i in from..to.min(blocks_occupied) {
+            if inode.disk_node.direct_blocks[i as usize] == 0 {
+                continue;
+            }
+            free_data_block(self, inode.disk_node.direct_blocks[i as usize])?;
+            inode.disk_node.direct_blocks[i as usize] = 0;
+        }
+        Ok(())
     }
 
     /// Frees all the blocks of an inode
@@ -68,20 +670,718 @@ impl InodeLayerFS {
         &mut self,
         inode: &mut <Self as InodeSupport>::Inode,
     ) -> Result<(), <Self as FileSysSupport>::Error> {
-        let blocks_occupied =
-            (inode.disk_node.size as f64 / self.sup_as_ref().block_size as f64).ceil() as u64;
-        for i in 0..blocks_occupied {
-            //calculate the relative address to datastart as required by b_free
-            let target_block =
-                inode.disk_node.direct_blocks[i as usize] - self.sup_as_ref().datastart;
-            self.block_fs.b_free(target_block)?;
-            inode.disk_node.direct_blocks[i as usize] = 0;
+        if inode.disk_node.is_inline() {
+            inode.disk_node.set_inline_data(&[]);
+        } else {
+            self.free_block_range(inode, 0, u64::MAX)?;
         }
         inode.disk_node.size = 0;
+        //an empty file starts out inline again
+        inode.disk_node.flags &= !INODE_EOF_BLOCKS;
+        inode.disk_node.flags |= self.initial_flags(inode.get_ft());
         Ok(())
     }
 }
 
+/// Marks the inodes with numbers `from` up to `sb.ninodes` as free, in a file system with superblock `sb` whose disk inodes of type `D` take up `inode_size` bytes.
+/// With `FEATURE_XATTR`, the inodes are left without an attribute block as well.
+/// Each inode block is only loaded and stored once.
+/// The inode blocks are overwritten without being read first, so they may contain anything beforehand.
+pub(crate) fn init_free_disk_inodes<D: Default + Serialize>(
+    block_fs: &mut BlockLayerFS,
+    sb: &SuperBlock,
+    from: u64,
+    inode_size: u64,
+) -> Result<(), InodeLayerError> {
+    let default_dinode = D::default();
+    let mut block: Option<Block> = None;
+    for n in from..sb.ninodes {
+        let (block_no, offset) = block_fs.inode_pos(n, inode_size);
+        if block.as_ref().map(|b| b.block_no) != Some(block_no) {
+            if let Some(b) = block.take() {
+                block_fs.b_put(&b)?;
+            }
+            block = Some(block_fs.b_get(BlockNo(block_no))?);
+        }
+        let b = block.as_mut().unwrap();
+        b.serialize_into(&default_dinode, offset)?;
+        if sb.features & FEATURE_XATTR != 0 {
+            b.serialize_into(&0u64, offset + inode_size)?;
+        }
+    }
+    if let Some(b) = block {
+        block_fs.b_put(&b)?;
+    }
+    Ok(())
+}
+
+/// Visits the disk inodes of type `D`, which take up `inode_size` bytes, with the numbers in `inums` in order, and stores every inode that `visit` changes.
+/// `visit` returns whether it changed the given inode, and whether to stop visiting; the number of the inode the visit stopped at is returned.
+/// Each inode block is loaded once for every run of consecutive inodes it holds, and only stored if one of these inodes changed.
+pub(crate) fn visit_disk_inodes<D, I, F>(
+    block_fs: &mut BlockLayerFS,
+    inode_size: u64,
+    inums: I,
+    mut visit: F,
+) -> Result<Option<u64>, InodeLayerError>
+where
+    D: Serialize + DeserializeOwned,
+    I: IntoIterator<Item = u64>,
+    F: FnMut(u64, &mut D) -> Result<(bool, bool), InodeLayerError>,
+{
+    let mut current: Option<(Block, bool)> = None;
+    for i in inums {
+        let (block_no, offset) = block_fs.inode_pos(i, inode_size);
+        if current.as_ref().map(|(b, _)| b.block_no) != Some(block_no) {
+            if let Some((b, true)) = current.take() {
+                block_fs.b_put(&b)?;
+            }
+            current = Some((block_fs.b_get(BlockNo(block_no))?, false));
+        }
+        let (block, dirty) = current.as_mut().unwrap();
+        let mut di_node = block.deserialize_from::<D>(offset)?;
+        let (changed, stop) = visit(i, &mut di_node)?;
+        if changed {
+            block.serialize_into(&di_node, offset)?;
+            *dirty = true;
+        }
+        if stop {
+            if *dirty {
+                block_fs.b_put(block)?;
+            }
+            return Ok(Some(i));
+        }
+    }
+    if let Some((b, true)) = current {
+        block_fs.b_put(&b)?;
+    }
+    Ok(None)
+}
+
+/// Puts the disk inode `saved` back into `node` if `result` is an error, and passes on `result`.
+/// A failed transaction is rolled back on the disk, so an inode it changed in memory has to be rolled back as well.
+pub(crate) fn restore_on_err<D, T, E>(
+    node: &mut D,
+    saved: D,
+    result: Result<T, E>,
+) -> Result<T, E> {
+    if result.is_err() {
+        *node = saved;
+    }
+    result
+}
+
+/// Disk inode of an [`InodeLayer`], with the fields the helpers shared by the inode layers work with
+pub(crate) trait DiskInode: Serialize + DeserializeOwned + Clone + PartialEq {
+    /// Returns the file type of this inode
+    fn ft(&self) -> FType;
+    /// Sets the size of the file to `size` bytes
+    fn set_size(&mut self, size: u64);
+    /// Records a change to the contents of the file at time `now`
+    fn touch(&mut self, now: Timestamp);
+    /// Returns whether the change from `self` to `new` affects how the contents of the inode are read back, which `i_fdatasync` has to persist
+    fn data_changed(&self, new: &Self) -> bool;
+}
+
+impl DiskInode for DInode {
+    fn ft(&self) -> FType {
+        self.ft
+    }
+    fn set_size(&mut self, size: u64) {
+        self.size = size;
+    }
+    fn touch(&mut self, now: Timestamp) {
+        self.mtime = now;
+        self.ctime = now;
+    }
+    fn data_changed(&self, new: &Self) -> bool {
+        self.ft != new.ft
+            || self.flags != new.flags
+            || self.size != new.size
+            || self.direct_blocks != new.direct_blocks
+    }
+}
+
+/// Inode layer on top of a [`BlockLayerFS`], i.e. this file system or the one with indirect blocks of assignment f.
+/// The layers differ in their disk inodes and in how these map the contents of a file to blocks; the helpers generic over this trait hold the logic they share.
+pub(crate) trait InodeLayer: InodeSupport<Error = InodeLayerError> {
+    /// Type of the inodes of this layer on the disk
+    type DiskInode: DiskInode;
+    /// Returns the size of a disk inode, in bytes
+    fn inode_size() -> u64;
+    /// Returns the disk inode of `inode`
+    fn disk_node(inode: &Self::Inode) -> &Self::DiskInode;
+    /// Returns the disk inode of `inode`, to change it in memory
+    fn disk_node_mut(inode: &mut Self::Inode) -> &mut Self::DiskInode;
+    /// Returns the block layer this layer is built on
+    fn block_fs(&self) -> &BlockLayerFS;
+    /// Returns the block layer this layer is built on, to change it
+    fn block_fs_mut(&mut self) -> &mut BlockLayerFS;
+    /// Returns the source of the timestamps of inodes
+    fn clock(&self) -> &dyn Clock;
+    /// Returns the inodes written since they were last synced, mapped to whether `i_fdatasync` has to persist them
+    fn dirty(&self) -> &BTreeMap<u64, bool>;
+    /// Returns the inodes written since they were last synced, to change them
+    fn dirty_mut(&mut self) -> &mut BTreeMap<u64, bool>;
+    /// Returns the absolute number of the data block that holds block `k` of `inode`, or 0 if that block is a hole
+    fn bmap(&self, inode: &Self::Inode, k: u64) -> Result<u64, InodeLayerError>;
+    /// Returns the absolute number of the data block that holds block `k` of `inode`, allocating it first if it is a hole.
+    /// Only changes `inode` in memory; storing it is up to the caller.
+    fn bmap_alloc(&mut self, inode: &mut Self::Inode, k: u64) -> Result<u64, InodeLayerError>;
+    /// Returns the blocks `inode` refers to: its data blocks, the blocks that map them, and its attribute block if `with_xattrs` is set and it has one
+    fn referenced_blocks(
+        &self,
+        inode: &Self::Inode,
+        with_xattrs: bool,
+    ) -> Result<Vec<u64>, InodeLayerError>;
+}
+
+/// Returns the number of the block that contains inode `i` of `fs`, together with the offset of the inode in this block
+pub(crate) fn inode_pos<F: InodeLayer>(fs: &F, i: u64) -> Result<(u64, u64), InodeLayerError> {
+    if i >= fs.block_fs().sup_as_ref().ninodes {
+        return Err(InodeLayerError::InodeLayerInput(
+            "Trying to get inode with index out of bounds",
+        ));
+    }
+    Ok(fs.block_fs().inode_pos(i, F::inode_size()))
+}
+
+/// Returns the permission bits a new inode of type `ft` starts out with
+pub(crate) fn initial_mode(ft: FType) -> u16 {
+    match ft {
+        FType::TDir => DEFAULT_DIR_MODE,
+        FType::TSymlink => DEFAULT_SYMLINK_MODE,
+        _ => DEFAULT_FILE_MODE,
+    }
+}
+
+/// Allocates the disk inode of type `D`, which takes up `inode_size` bytes, that `policy` picks, preferably close to inode `goal`, and stores `init` in it; see `i_alloc` and `i_alloc_near`.
+/// The free inodes are looked up in the inode bit map if there is one; otherwise the inode blocks are loaded one at a time, as far as the policy searches.
+/// Either way, the block of the picked inode is loaded once more to claim it.
+pub(crate) fn alloc_disk_inode<D: DiskInode>(
+    block_fs: &mut BlockLayerFS,
+    policy: &mut dyn AllocPolicy,
+    inode_size: u64,
+    goal: Option<u64>,
+    init: D,
+) -> Result<u64, InodeLayerError> {
+    let group = block_fs.group_inodes();
+    let goal = goal.or(block_fs.inode_hint());
+    let i = match block_fs.inode_slots() {
+        //no need to search a full inode bit map
+        Some(_) if block_fs.free_inodes() == Some(0) => None,
+        Some(mut used) => {
+            let i = policy.choose(&mut used, group, goal);
+            used.finish()?;
+            i
+        }
+        None => {
+            let mut used = block_fs.inode_table_slots(inode_size, |i, block, offset| {
+                //inode 0 is never allocated
+                Ok(i == 0 || block.deserialize_from::<D>(offset)?.ft() != FType::TFree)
+            });
+            let i = policy.choose(&mut used, group, goal);
+            used.finish()?;
+            i
+        }
+    }
+    .ok_or(InodeLayerError::InodeLayerOp(
+        "Cannot allocate new block, no space left!",
+    ))?;
+    visit_disk_inodes(block_fs, inode_size, [i], |_, di_node: &mut D| {
+        *di_node = init.clone();
+        Ok((true, true))
+    })?;
+    block_fs.set_inode_used(i, true)?;
+    Ok(i)
+}
+
+/// Records that inode `i` of `fs` was written, where `data` says whether the change affects how its contents are read back
+pub(crate) fn mark_dirty<F: InodeLayer>(fs: &mut F, i: u64, data: bool) {
+    *fs.dirty_mut().entry(i).or_insert(false) |= data;
+}
+
+/// Stores `ino` on the disk of `fs`, and records it as dirty if it changed; see `i_put`
+pub(crate) fn put_inode<F: InodeLayer>(fs: &mut F, ino: &F::Inode) -> Result<(), InodeLayerError> {
+    let (block_no, offset) = inode_pos(fs, ino.get_inum())?;
+    journal::in_op(fs, |fs| {
+        let di_node = F::disk_node(ino);
+        let mut block = fs.b_get(BlockNo(block_no))?;
+        let old: F::DiskInode = block.deserialize_from(offset)?;
+        block.serialize_into(di_node, offset)?;
+        fs.b_put(&block)?;
+        if &old != di_node {
+            mark_dirty(fs, ino.get_inum(), old.data_changed(di_node));
+        }
+        let used = di_node.ft() != FType::TFree;
+        Ok(fs.block_fs_mut().set_inode_used(ino.get_inum(), used)?)
+    })
+}
+
+/// Returns whether `inode` or one of the blocks it refers to has been written since it was last synced; see `i_is_dirty`
+pub(crate) fn inode_is_dirty<F: InodeLayer>(
+    fs: &F,
+    inode: &F::Inode,
+) -> Result<bool, InodeLayerError> {
+    if fs.dirty().contains_key(&inode.get_inum()) {
+        return Ok(true);
+    }
+    Ok(fs
+        .referenced_blocks(inode, true)?
+        .into_iter()
+        .any(|b| fs.block_fs().is_dirty(b)))
+}
+
+/// Persists the dirty blocks `inode` refers to, and the allocation state of the file system.
+/// Persists the block holding the inode as well if `with_inode` is set, after which every inode in that block is clean.
+pub(crate) fn sync_inode<F: InodeLayer>(
+    fs: &mut F,
+    inode: &F::Inode,
+    with_inode: bool,
+) -> Result<(), InodeLayerError> {
+    let mut blocks = fs.referenced_blocks(inode, with_inode)?;
+    let (inode_block, _) = inode_pos(fs, inode.get_inum())?;
+    if with_inode {
+        blocks.push(inode_block);
+    }
+    fs.block_fs_mut().sync_blocks(&blocks)?;
+    fs.block_fs_mut().sync_metadata(F::inode_size())?;
+    if with_inode {
+        let clean: Vec<u64> = fs
+            .dirty()
+            .keys()
+            .copied()
+            .filter(|&i| fs.block_fs().inode_pos(i, F::inode_size()).0 == inode_block)
+            .collect();
+        for i in clean {
+            fs.dirty_mut().remove(&i);
+        }
+    }
+    Ok(())
+}
+
+/// Allocates a data block for the contents of inode `inum` of `fs`, preferably data block `goal`, and otherwise in the block group of the inode.
+/// Returns the absolute number of the block.
+pub(crate) fn alloc_data_block<F: InodeLayer>(
+    fs: &mut F,
+    inum: u64,
+    goal: Option<DataIdx>,
+) -> Result<u64, InodeLayerError> {
+    //keep the data of a file in the block group of its inode
+    let block_fs = fs.block_fs();
+    let goal = goal.unwrap_or_else(|| block_fs.group_first_data(block_fs.inode_group(inum)));
+    let data_index = fs.b_alloc_near(goal)?;
+    Ok(fs
+        .block_fs()
+        .sup_as_ref()
+        .block_no(data_index)
+        .ok_or(InodeLayerError::InodeLayerOp(
+            "Allocated block outside the data region",
+        ))?
+        .0)
+}
+
+/// Frees the data block of `fs` with absolute number `b`
+pub(crate) fn free_data_block<F: InodeLayer>(fs: &mut F, b: u64) -> Result<(), InodeLayerError> {
+    //calculate the index in the data region as required by b_free
+    let i =
+        fs.block_fs()
+            .sup_as_ref()
+            .data_idx(BlockNo(b))
+            .ok_or(InodeLayerError::InodeLayerOp(
+                "Inode points to a block outside the data region",
+            ))?;
+    fs.b_free(i)
+}
+
+/// Reads from the data blocks of `inode` like `i_read`, but without updating its access time.
+/// Holes read as zeroes, without going to the device.
+pub(crate) fn read_blocks<F: InodeLayer>(
+    fs: &F,
+    inode: &F::Inode,
+    buf: &mut Buffer,
+    off: u64,
+    n: u64,
+) -> Result<u64, InodeLayerError> {
+    if off > inode.get_size() {
+        return Err(InodeLayerError::InodeLayerInput(
+            "Offset falls outside the inode's data",
+        ));
+    }
+    let bs = fs.block_fs().sup_as_ref().block_size;
+    let end = inode.get_size().min(off.saturating_add(n));
+    let mut pos = off;
+    while pos < end {
+        let block_off = pos % bs;
+        let len = (bs - block_off).min(end - pos);
+        let mut data = vec![0; len as usize];
+        let block_no = fs.bmap(inode, pos / bs)?;
+        if block_no != 0 {
+            fs.b_get(BlockNo(block_no))?
+                .read_data(&mut data, block_off)?;
+        }
+        buf.write_data(&data, pos - off)?;
+        pos += len;
+    }
+    Ok(end - off)
+}
+
+/// Writes the first `n` bytes of `buf` to `inode` at offset `off`, with `write_chunk` writing every chunk of it in a transaction of its own; see `i_write`.
+/// Like in xv6, a write that does not fit in the log is split up into chunks that do, each of at most `chunk_blocks` blocks, if that is given.
+/// If one of these fails, the chunks before it stay written.
+pub(crate) fn write_in_chunks<F, W>(
+    fs: &mut F,
+    inode: &mut F::Inode,
+    (buf, off, n): (&Buffer, u64, u64),
+    chunk_blocks: Option<u64>,
+    mut write_chunk: W,
+) -> Result<(), InodeLayerError>
+where
+    F: InodeLayer,
+    W: FnMut(&mut F, &mut F::Inode, &[u8], u64) -> Result<(), InodeLayerError>,
+{
+    let end = off
+        .checked_add(n)
+        .filter(|&end| end <= fs.i_max_size())
+        .ok_or(InodeLayerError::InodeLayerInput(
+            "Write exceeds inode's max size",
+        ))?;
+    if n > buf.len() {
+        return Err(InodeLayerError::InodeLayerInput(
+            "Write is longer than the buffer it writes from",
+        ));
+    }
+    let bs = fs.block_fs().sup_as_ref().block_size;
+    let chunk = chunk_blocks.map_or(u64::MAX, |m| m * bs);
+    let data = &buf.contents_as_ref()[..n as usize];
+    //an empty write writes no chunk at all, so it does not extend the file, not even past its end
+    let mut pos = off;
+    while pos < end {
+        //chunks end at a block boundary, so that each of them writes the number of data blocks it was sized for
+        let stop = (pos - pos % bs).saturating_add(chunk).min(end);
+        write_chunk(
+            fs,
+            inode,
+            &data[(pos - off) as usize..(stop - off) as usize],
+            pos,
+        )?;
+        pos = stop;
+    }
+    Ok(())
+}
+
+/// Writes `data` to the data blocks of `inode`, starting at offset `off`, and allocates the blocks it has no block for yet.
+/// Only changes `inode` in memory; updating its size and storing it is up to the caller.
+pub(crate) fn write_blocks<F: InodeLayer>(
+    fs: &mut F,
+    inode: &mut F::Inode,
+    data: &[u8],
+    off: u64,
+) -> Result<(), InodeLayerError> {
+    let bs = fs.block_fs().sup_as_ref().block_size;
+    let mut written = 0;
+    while written < data.len() {
+        let pos = off + written as u64;
+        let block_off = pos % bs;
+        let len = ((bs - block_off) as usize).min(data.len() - written);
+        //blocks past the end of the file and holes in it have no block yet
+        let block_n = fs.bmap_alloc(inode, pos / bs)?;
+        let mut block = fs.b_get(BlockNo(block_n))?;
+        block.write_data(&data[written..written + len], block_off)?;
+        fs.b_put(&block)?;
+        written += len;
+    }
+    Ok(())
+}
+
+/// Zeroes bytes `from` up to `to` of `inode` in its data blocks, leaving holes as they are
+pub(crate) fn zero_bytes<F: InodeLayer>(
+    fs: &mut F,
+    inode: &F::Inode,
+    from: u64,
+    to: u64,
+) -> Result<(), InodeLayerError> {
+    let bs = fs.block_fs().sup_as_ref().block_size;
+    let mut pos = from;
+    while pos < to {
+        let end = to.min((pos / bs + 1) * bs);
+        let block_no = fs.bmap(inode, pos / bs)?;
+        if block_no != 0 {
+            let mut block = fs.b_get(BlockNo(block_no))?;
+            block.write_data(&vec![0; (end - pos) as usize], pos % bs)?;
+            fs.b_put(&block)?;
+        }
+        pos = end;
+    }
+    Ok(())
+}
+
+/// Zeroes the block of `inode` that offset `off` falls in, from `off` up to the end of the block.
+/// Does nothing if `off` is at the start of a block, or if the block is a hole.
+pub(crate) fn zero_tail<F: InodeLayer>(
+    fs: &mut F,
+    inode: &F::Inode,
+    off: u64,
+) -> Result<(), InodeLayerError> {
+    let bs = fs.block_fs().sup_as_ref().block_size;
+    zero_bytes(fs, inode, off, off.next_multiple_of(bs))
+}
+
+/// Allocates the data blocks holding the bytes `off` up to `end` of `inode`, zeroing new blocks, and zeroes these bytes as well if `zero` is set.
+/// Also zeroes whatever lay past the end of the inode if it grows to `new_size`, so that it does not show up in the file.
+/// Only changes `inode` in memory, apart from the blocks that map its contents; updating its size and storing it is up to the caller.
+pub(crate) fn allocate_blocks<F: InodeLayer>(
+    fs: &mut F,
+    inode: &mut F::Inode,
+    (off, end): (u64, u64),
+    new_size: u64,
+    zero: bool,
+) -> Result<(), InodeLayerError> {
+    let bs = fs.block_fs().sup_as_ref().block_size;
+    for k in off / bs..end.div_ceil(bs) {
+        if fs.bmap(inode, k)? == 0 {
+            let block_no = fs.bmap_alloc(inode, k)?;
+            fs.b_put(&Block::new_zero(block_no, bs))?;
+        }
+    }
+    if zero {
+        zero_bytes(fs, inode, off, end)?;
+    }
+    let size = inode.get_size();
+    if new_size > size {
+        zero_tail(fs, inode, size)?;
+    }
+    Ok(())
+}
+
+/// Allocates the `len` bytes of `inode` starting at offset `off`, growing it unless `keep_size` is set; see `i_fallocate` and `i_zero_range`.
+/// `allocate` does the actual allocation, given the end of the range and the new size of the inode, which is set afterwards.
+pub(crate) fn allocate_range<F, A>(
+    fs: &mut F,
+    inode: &mut F::Inode,
+    (off, len): (u64, u64),
+    keep_size: bool,
+    allocate: A,
+) -> Result<(), InodeLayerError>
+where
+    F: InodeLayer,
+    A: FnOnce(&mut F, &mut F::Inode, u64, u64) -> Result<(), InodeLayerError>,
+{
+    change_in_tx(fs, inode, |fs, inode| {
+        if inode.get_ft() != FType::TFile {
+            return Err(InodeLayerError::InodeLayerInput(
+                "Only regular files can have space allocated",
+            ));
+        }
+        let end = off
+            .checked_add(len)
+            .filter(|&end| end <= fs.i_max_size())
+            .ok_or(InodeLayerError::InodeLayerInput(
+                "Allocation exceeds inode's max size",
+            ))?;
+        if len == 0 {
+            return Ok(false);
+        }
+        let size = inode.get_size();
+        let new_size = if keep_size { size } else { size.max(end) };
+        allocate(fs, inode, end, new_size)?;
+        F::disk_node_mut(inode).set_size(new_size);
+        Ok(true)
+    })
+}
+
+/// Applies `change` to `inode` in a single transaction, which also updates its modification times and stores it, unless `change` returns that it changed nothing.
+/// A failed transaction is rolled back entirely, so `inode` is restored in memory as well; see `restore_on_err`.
+pub(crate) fn change_in_tx<F, C>(
+    fs: &mut F,
+    inode: &mut F::Inode,
+    change: C,
+) -> Result<(), InodeLayerError>
+where
+    F: InodeLayer,
+    C: FnOnce(&mut F, &mut F::Inode) -> Result<bool, InodeLayerError>,
+{
+    let saved = F::disk_node(inode).clone();
+    let result = journal::in_tx(fs, |fs| {
+        if !change(fs, inode)? {
+            return Ok(());
+        }
+        let now = fs.clock().now();
+        F::disk_node_mut(inode).touch(now);
+        fs.i_put(inode)
+    });
+    restore_on_err(F::disk_node_mut(inode), saved, result)
+}
+
+/// Rewrites the block pointers of the disk inodes of type `D` in use, which take up `inode_size` bytes, with `remap`, which returns whether it changed the given inode
+pub(crate) fn remap_disk_inodes<D, R>(
+    block_fs: &mut BlockLayerFS,
+    inode_size: u64,
+    mut remap: R,
+) -> Result<(), InodeLayerError>
+where
+    D: DiskInode,
+    R: FnMut(&mut D) -> bool,
+{
+    let ninodes = block_fs.sup_as_ref().ninodes;
+    visit_disk_inodes(block_fs, inode_size, 0..ninodes, |_, di_node: &mut D| {
+        if di_node.ft() == FType::TFree {
+            return Ok((false, false));
+        }
+        Ok((remap(di_node), false))
+    })?;
+    Ok(())
+}
+
+/// Rewrites block pointer `ptr` if `moved` maps it to a new block, and returns whether it did
+pub(crate) fn remap_ptr(ptr: &mut u64, moved: &HashMap<u64, u64>) -> bool {
+    match moved.get(ptr) {
+        Some(&new) => {
+            *ptr = new;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Errors if an inode in use of `fs` refers to a block on the bad-block list, as `b_mark_bad` moves all data off a bad block.
+/// `referenced` returns the blocks the given inode refers to.
+/// Only visits the inodes if there are bad blocks at all.
+pub(crate) fn check_bad_refs<F, R>(fs: &F, referenced: R) -> Result<(), F::Error>
+where
+    F: InodeSupport,
+    R: Fn(&F, &F::Inode) -> Result<Vec<u64>, F::Error>,
+    F::Error: From<InodeLayerError>,
+{
+    let bad: Vec<u64> = fs.b_bad_blocks()?.iter().map(|b| b.0).collect();
+    if bad.is_empty() {
+        return Ok(());
+    }
+    for i in 1..fs.sup_get()?.ninodes {
+        let inode = fs.i_get(i)?;
+        if inode.get_ft() != FType::TFree && referenced(fs, &inode)?.iter().any(|b| bad.contains(b))
+        {
+            return Err(InodeLayerError::InodeLayerInput("An inode refers to a bad block").into());
+        }
+    }
+    Ok(())
+}
+
+impl InodeLayer for InodeLayerFS {
+    type DiskInode = DInode;
+
+    fn inode_size() -> u64 {
+        *DINODE_SIZE
+    }
+
+    fn disk_node(inode: &Self::Inode) -> &DInode {
+        &inode.disk_node
+    }
+
+    fn disk_node_mut(inode: &mut Self::Inode) -> &mut DInode {
+        &mut inode.disk_node
+    }
+
+    fn block_fs(&self) -> &BlockLayerFS {
+        &self.block_fs
+    }
+
+    fn block_fs_mut(&mut self) -> &mut BlockLayerFS {
+        &mut self.block_fs
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    fn dirty(&self) -> &BTreeMap<u64, bool> {
+        &self.dirty
+    }
+
+    fn dirty_mut(&mut self) -> &mut BTreeMap<u64, bool> {
+        &mut self.dirty
+    }
+
+    /// Returns the absolute number of the data block that holds block `k` of `inode`, or 0 if that block is a hole
+    fn bmap(&self, inode: &Self::Inode, k: u64) -> Result<u64, InodeLayerError> {
+        if !self.has_extents() {
+            return Ok(inode.get_block(k).0);
+        }
+        Ok(self
+            .load_extents(&inode.disk_node)?
+            .iter()
+            .find_map(|e| e.map(k))
+            .unwrap_or(0))
+    }
+
+    /// Returns the absolute number of the data block that holds block `k` of `inode`, allocating it first if it is a hole.
+    /// With extents, the block right after the one holding block `k - 1` is preferred, so that the extent holding it simply grows.
+    /// Only changes `inode` in memory; storing it is up to the caller.
+    fn bmap_alloc(&mut self, inode: &mut Self::Inode, k: u64) -> Result<u64, InodeLayerError> {
+        if !self.has_extents() {
+            if inode.disk_node.direct_blocks[k as usize] == 0 {
+                inode.disk_node.direct_blocks[k as usize] =
+                    alloc_data_block(self, inode.inum, None)?;
+            }
+            return Ok(inode.disk_node.direct_blocks[k as usize]);
+        }
+        let mut extents = self.load_extents(&inode.disk_node)?;
+        if let Some(b) = extents.iter().find_map(|e| e.map(k)) {
+            return Ok(b);
+        }
+        //the extents before block `k`
+        let i = extents.partition_point(|e| e.end() <= k);
+        let goal = match i {
+            0 => None,
+            _ => self
+                .sup_as_ref()
+                .data_idx(BlockNo(extents[i - 1].physical + extents[i - 1].len as u64)),
+        };
+        let b = alloc_data_block(self, inode.inum, goal)?;
+        match i.checked_sub(1).map(|p| extents[p]) {
+            Some(prev) if prev.end() == k && prev.physical + prev.len as u64 == b => {
+                extents[i - 1].len += 1
+            }
+            _ => extents.insert(
+                i,
+                Extent {
+                    start: k as u32,
+                    len: 1,
+                    physical: b,
+                },
+            ),
+        }
+        //block `k` may close the gap to the next extent
+        let j = extents.partition_point(|e| e.end() <= k);
+        if let Some(&next) = extents.get(j + 1) {
+            let cur = extents[j];
+            if cur.end() == next.start as u64 && cur.physical + cur.len as u64 == next.physical {
+                extents[j].len += next.len;
+                extents.remove(j + 1);
+            }
+        }
+        self.store_extents(inode, &extents)?;
+        Ok(b)
+    }
+
+    /// Returns the blocks `inode` refers to: its data blocks, its extent block if it has one, and its attribute block if `with_xattrs` is set and it has one
+    fn referenced_blocks(
+        &self,
+        inode: &Self::Inode,
+        with_xattrs: bool,
+    ) -> Result<Vec<u64>, InodeLayerError> {
+        let mut blocks = self.inode_blocks(inode)?;
+        if self.has_extents() && !inode.disk_node.is_inline() {
+            blocks.push(inode.disk_node.extent_header().1);
+        }
+        if with_xattrs && self.has_xattrs() {
+            blocks.push(self.xattr_block(inode.inum)?);
+        }
+        blocks.retain(|&b| b != 0);
+        Ok(blocks)
+    }
+}
+
 impl FileSysSupport for InodeLayerFS {
     type Error = InodeLayerError;
 
@@ -92,41 +1392,33 @@ impl FileSysSupport for InodeLayerFS {
     fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
         let mut block_fs = BlockLayerFS::mkfs(path, sb)?;
 
-        let inodes_per_block = sb.block_size / *DINODE_SIZE;
-        let inode_blocks = (sb.ninodes as f64 / inodes_per_block as f64).ceil() as u64;
-        let mut nodes_init = 0;
-        let default_dinode = DInode::default();
         //init every inode as TFree
-        for bl in 0..inode_blocks {
-            let mut block = block_fs.b_get(sb.inodestart + bl)?;
-            for node in 0..inodes_per_block {
-                if nodes_init == sb.ninodes {
-                    break;
-                }
-                //println!("Writing inode with offset {}", node*(*DINODE_SIZE));
-                block.serialize_into(&default_dinode, node * (*DINODE_SIZE))?;
-                nodes_init += 1;
-            }
-            block_fs.b_put(&block)?;
-        }
-        let inode_max_size = DIRECT_POINTERS * sb.block_size;
+        Self::init_free_inodes(&mut block_fs, sb, 0)?;
+        let inode_max_size = Self::max_size(sb);
 
         Ok(InodeLayerFS {
             block_fs,
-            inodes_per_block,
             inode_max_size,
+            policy: AllocStrategy::default().policy(),
+            clock: Box::new(SystemClock),
+            atime: AtimePolicy::default(),
+            dirty: BTreeMap::new(),
         })
     }
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
-        let block_fs = BlockLayerFS::mountfs(dev)?;
-        let inodes_per_block = block_fs.sup_as_ref().block_size / *DINODE_SIZE;
-        let inode_max_size = DIRECT_POINTERS * (*DINODE_SIZE);
-        Ok(InodeLayerFS {
+    fn mountfs_with(dev: Device, opts: &MountOptions) -> Result<Self, Self::Error> {
+        let block_fs = BlockLayerFS::mountfs_with(dev, opts)?;
+        let inode_max_size = Self::max_size(block_fs.sup_as_ref());
+        let fs = InodeLayerFS {
             block_fs,
-            inodes_per_block,
             inode_max_size,
-        })
+            policy: opts.alloc.policy(),
+            clock: Box::new(SystemClock),
+            atime: opts.atime,
+            dirty: BTreeMap::new(),
+        };
+        check_bad_refs(&fs, |fs, inode| fs.referenced_blocks(inode, true))?;
+        Ok(fs)
     }
 
     fn unmountfs(self) -> Device {
@@ -135,7 +1427,7 @@ impl FileSysSupport for InodeLayerFS {
 }
 
 impl BlockSupport for InodeLayerFS {
-    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+    fn b_get(&self, i: BlockNo) -> Result<Block, Self::Error> {
         Ok(self.block_fs.b_get(i)?)
     }
 
@@ -143,18 +1435,34 @@ impl BlockSupport for InodeLayerFS {
         Ok(self.block_fs.b_put(b)?)
     }
 
-    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+    fn b_free(&mut self, i: DataIdx) -> Result<(), Self::Error> {
         Ok(self.block_fs.b_free(i)?)
     }
 
-    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+    fn b_zero(&mut self, i: DataIdx) -> Result<(), Self::Error> {
         Ok(self.block_fs.b_zero(i)?)
     }
 
-    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+    fn b_alloc(&mut self) -> Result<DataIdx, Self::Error> {
         Ok(self.block_fs.b_alloc()?)
     }
 
+    fn b_alloc_near(&mut self, goal: DataIdx) -> Result<DataIdx, Self::Error> {
+        Ok(self.block_fs.b_alloc_near(goal)?)
+    }
+
+    fn b_set_privileged(&mut self, privileged: bool) -> bool {
+        self.block_fs.b_set_privileged(privileged)
+    }
+
+    fn b_frag_report(&self) -> Result<FragReport, Self::Error> {
+        Ok(self.block_fs.b_frag_report()?)
+    }
+
+    fn statfs(&self) -> Result<StatFs, Self::Error> {
+        Ok(self.block_fs.statfs()?)
+    }
+
     fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
         Ok(self.block_fs.sup_get()?)
     }
@@ -162,14 +1470,80 @@ impl BlockSupport for InodeLayerFS {
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
         Ok(self.block_fs.sup_put(sup)?)
     }
+
+    fn begin_op(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.begin_op()?)
+    }
+
+    fn end_op(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.end_op()?)
+    }
+
+    fn b_tx_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_tx_begin()?)
+    }
+
+    fn b_tx_commit(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_tx_commit()?)
+    }
+
+    fn b_tx_rollback(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_tx_rollback()?)
+    }
+
+    fn b_grow(&mut self, nblocks: u64, ninodes: u64) -> Result<(), Self::Error> {
+        let old_ninodes = self.sup_as_ref().ninodes;
+        let plan = self.block_fs.grow_prepare(nblocks, ninodes)?;
+        resize_in_tx(
+            self,
+            |fs| &mut fs.block_fs,
+            plan,
+            |fs, plan| {
+                fs.remap_blocks(&plan.moved)?;
+                Self::init_free_inodes(&mut fs.block_fs, &plan.sb, old_ninodes)?;
+                Ok(())
+            },
+        )
+    }
+
+    fn b_shrink(dev: Device, nblocks: u64) -> Result<Device, Self::Error> {
+        let mut fs = Self::mountfs(dev)?;
+        let plan = fs.block_fs.shrink_prepare(nblocks)?;
+        resize_in_tx(
+            &mut fs,
+            |fs| &mut fs.block_fs,
+            plan,
+            |fs, plan| fs.remap_blocks(&plan.moved),
+        )?;
+        let mut dev = fs.unmountfs();
+        dev.resize(nblocks)?;
+        Ok(dev)
+    }
+
+    fn b_trim(&mut self, policy: DiscardPolicy) -> Result<u64, Self::Error> {
+        Ok(self.block_fs.b_trim(policy)?)
+    }
+
+    fn b_mark_bad(&mut self, b: BlockNo) -> Result<(), Self::Error> {
+        journal::in_op(self, |fs| {
+            if let Some(new) = fs.block_fs.mark_bad(b, true)? {
+                fs.remap_blocks(&[(b, new)])?;
+            }
+            Ok(())
+        })
+    }
+
+    fn b_bad_blocks(&self) -> Result<Vec<BlockNo>, Self::Error> {
+        Ok(self.block_fs.b_bad_blocks()?)
+    }
 }
 
 impl InodeSupport for InodeLayerFS {
     type Inode = Inode;
 
     fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
-        let t_offset = (i % self.inodes_per_block) * (*DINODE_SIZE);
-        let target_block = self.get_block_of_inode(i)?;
+        let (t_block_addr, t_offset) = inode_pos(self, i)?;
+        let target_block = self.b_get(BlockNo(t_block_addr))?;
         let di_node = target_block.deserialize_from::<DInode>(t_offset)?;
         Ok(Inode {
             inum: i,
@@ -178,116 +1552,106 @@ impl InodeSupport for InodeLayerFS {
     }
 
     fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
-        let t_offset = (ino.inum % self.inodes_per_block) * (*DINODE_SIZE);
-        let mut target_block = self.get_block_of_inode(ino.inum)?;
-        target_block.serialize_into(&ino.disk_node, t_offset)?;
-        self.b_put(&target_block)?;
-        Ok(())
+        put_inode(self, ino)
     }
 
     fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
-        let mut inode = self.i_get(i)?;
-        if inode.disk_node.ft == FType::TFree {
-            return Err(InodeLayerError::InodeLayerOp(
-                "Trying to free a TFree inode",
+        journal::in_tx(self, |fs| {
+            let mut inode = fs.i_get(i)?;
+            if inode.disk_node.ft == FType::TFree {
+                return Err(InodeLayerError::InodeLayerOp(
+                    "Trying to free a TFree inode",
+                ));
+            }
+            if inode.disk_node.nlink != 0 {
+                return Ok(());
+            }
+            inode.disk_node.ft = FType::TFree;
+            fs.free_inode_blocks(&mut inode)?;
+            if fs.has_xattrs() {
+                fs.store_xattrs(&inode, &[])?;
+            }
+            fs.i_put(&inode)
+        })
+    }
+
+    fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
+        journal::in_op(self, |fs| fs.alloc_inode(ft, None))
+    }
+
+    fn i_alloc_near(&mut self, ft: FType, parent: u64) -> Result<u64, Self::Error> {
+        if parent >= self.sup_as_ref().ninodes {
+            return Err(InodeLayerError::InodeLayerInput(
+                "Trying to allocate near an inode with index out of bounds",
             ));
         }
-        if inode.disk_node.nlink != 0 {
-            return Ok(());
-        }
-        inode.disk_node.ft = FType::TFree;
-        self.free_inode_blocks(&mut inode)?;
-        self.i_put(&inode)
+        let goal = match self.block_fs.has_inode_bitmap() {
+            true => parent,
+            false => self
+                .block_fs
+                .group_first_inode(self.block_fs.inode_group(parent)),
+        };
+        journal::in_op(self, |fs| fs.alloc_inode(ft, Some(goal)))
     }
 
-    fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
-        let inode_blocks =
-            (self.sup_as_ref().ninodes as f64 / self.inodes_per_block as f64).ceil() as u64;
-        let mut nodes_searched = 1;
-        //iterate over all blocks containing inodes
-        for bl in 0..inode_blocks {
-            let mut block = self.block_fs.b_get(self.sup_as_ref().inodestart + bl)?;
-            //iterate over all inodes in this block
-            for node in 0..self.inodes_per_block {
-                if bl == 0 && node == 0 {
-                    //skip root inode
-                    continue;
-                }
-                if nodes_searched == self.sup_as_ref().ninodes {
-                    break;
-                }
-                let mut di_node = block.deserialize_from::<DInode>(node * (*DINODE_SIZE))?;
-                if di_node.ft == FType::TFree {
-                    di_node.ft = ft;
-                    di_node.size = 0;
-                    di_node.nlink = 0;
-                    block.serialize_into(&di_node, node * (*DINODE_SIZE))?;
-                    self.block_fs.b_put(&block)?;
-                    return Ok(nodes_searched);
-                }
-                nodes_searched += 1;
+    fn i_extent_summary(&self) -> Result<Vec<InodeExtents>, Self::Error> {
+        let mut summary = vec![];
+        for i in 1..self.sup_as_ref().ninodes {
+            let inode = self.i_get(i)?;
+            if inode.get_ft() == FType::TFree {
+                continue;
             }
+            let blocks = self.inode_blocks(&inode)?;
+            let extents = blocks
+                .iter()
+                .enumerate()
+                .filter(|&(k, &b)| k == 0 || blocks[k - 1] + 1 != b)
+                .count();
+            summary.push(InodeExtents {
+                inum: i,
+                blocks: blocks.len() as u64,
+                extents: extents as u64,
+            });
         }
-        Err(InodeLayerError::InodeLayerOp(
-            "Cannot allocate new block, no space left!",
-        ))
+        Ok(summary)
     }
 
     fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
-        self.free_inode_blocks(inode)?;
-        self.i_put(inode)
+        change_in_tx(self, inode, |fs, inode| {
+            fs.free_inode_blocks(inode)?;
+            Ok(true)
+        })
+    }
+
+    fn i_truncate(&mut self, inode: &mut Self::Inode, new_size: u64) -> Result<(), Self::Error> {
+        self.truncate_contents(inode, new_size)
+    }
+
+    fn i_set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn i_max_size(&self) -> u64 {
+        self.inode_max_size
     }
 }
 
 impl InodeRWSupport for InodeLayerFS {
     fn i_read(
-        &self,
-        inode: &Self::Inode,
+        &mut self,
+        inode: &mut Self::Inode,
         buf: &mut Buffer,
         off: u64,
         n: u64,
     ) -> Result<u64, Self::Error> {
-        /*find block to start reading, then change block every blocksize number of bytes*/
-        let s_block_index = off / self.sup_as_ref().block_size;
-        if off > inode.get_size() {
-            return Err(InodeLayerError::InodeLayerInput(
-                "Offset falls outside the inode's data",
-            ));
-        } else if off == inode.get_size() {
-            return Ok(0);
-        }
-        //calculate the real size to be read, subject to how large the inode actually is
-        let real_n: usize = if n + off <= inode.get_size() {
-            n
-        } else {
-            inode.get_size() - off
-        } as usize;
-        let mut bytes_left: usize = real_n;
-        let mut vec: Vec<u8> = vec![];
-        let mut vec_len: usize = 0;
-        let mut buff_off: usize = 0;
-
-        //current_block_offset - can be != 0 only on the first block
-        let mut block_off: usize = (off % self.sup_as_ref().block_size) as usize;
-        //no of blocks that the read spans
-        let no_blocks =
-            ((real_n + off as usize) as f64 / self.sup_as_ref().block_size as f64).ceil() as u64;
-        for bl in 0..no_blocks {
-            let block = self.b_get(inode.get_block(s_block_index + bl))?;
-            //declare an appropriate buffer size for this block
-            vec_len = if block_off + bytes_left < block.len() as usize {
-                bytes_left
-            } else {
-                block.len() as usize - block_off
-            };
-            vec.resize_with(vec_len, Default::default);
-            block.read_data(vec.as_mut_slice(), block_off as u64)?;
-            bytes_left -= vec_len; //bytes_read in this iteration
-            buf.write_data(vec.as_slice(), buff_off as u64)?;
-            buff_off += vec_len;
-            block_off = 0;
+        let read = self.read_contents(inode, buf, off, n)?;
+        let now = self.now();
+        let d = &inode.disk_node;
+        if self.atime.updates(d.atime, d.mtime, d.ctime, now) {
+            inode.disk_node.atime = now;
+            self.i_put(inode)?;
         }
-        Ok(buff_off as u64)
+        Ok(read)
     }
 
     fn i_write(
@@ -297,54 +1661,157 @@ impl InodeRWSupport for InodeLayerFS {
         off: u64,
         n: u64,
     ) -> Result<(), Self::Error> {
-        if off > inode.get_size() {
+        if inode.get_ft() == FType::TSymlink {
             return Err(InodeLayerError::InodeLayerInput(
-                "Offset starts outside current size",
+                "The target of a symbolic link cannot be written to",
             ));
         }
-        if off + n > self.inode_max_size {
-            return Err(InodeLayerError::InodeLayerInput(
-                "Write exceeds inode's max size",
+        self.write_contents(inode, buf, off, n)
+    }
+
+    fn i_seek_data(&self, inode: &Self::Inode, off: u64) -> Result<Option<u64>, Self::Error> {
+        if off >= inode.get_size() {
+            return Ok(None);
+        }
+        if inode.disk_node.is_inline() {
+            return Ok(Some(off));
+        }
+        let bs = self.sup_as_ref().block_size;
+        for k in off / bs..inode.get_size().div_ceil(bs) {
+            if self.bmap(inode, k)? != 0 {
+                return Ok(Some((k * bs).max(off)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn i_seek_hole(&self, inode: &Self::Inode, off: u64) -> Result<Option<u64>, Self::Error> {
+        if off >= inode.get_size() {
+            return Ok(None);
+        }
+        if inode.disk_node.is_inline() {
+            return Ok(Some(inode.get_size()));
+        }
+        let bs = self.sup_as_ref().block_size;
+        for k in off / bs..inode.get_size().div_ceil(bs) {
+            if self.bmap(inode, k)? == 0 {
+                return Ok(Some((k * bs).max(off)));
+            }
+        }
+        Ok(Some(inode.get_size()))
+    }
+
+    fn i_fallocate(
+        &mut self,
+        inode: &mut Self::Inode,
+        off: u64,
+        len: u64,
+        keep_size: bool,
+    ) -> Result<(), Self::Error> {
+        self.allocate_range(inode, off, len, keep_size, false)
+    }
+
+    fn i_punch_hole(
+        &mut self,
+        inode: &mut Self::Inode,
+        off: u64,
+        len: u64,
+    ) -> Result<(), Self::Error> {
+        self.punch_hole(inode, off, len)
+    }
+
+    fn i_zero_range(
+        &mut self,
+        inode: &mut Self::Inode,
+        off: u64,
+        len: u64,
+        keep_size: bool,
+    ) -> Result<(), Self::Error> {
+        self.allocate_range(inode, off, len, keep_size, true)
+    }
+
+    fn i_is_dirty(&self, inode: &Self::Inode) -> Result<bool, Self::Error> {
+        inode_is_dirty(self, inode)
+    }
+
+    fn i_fsync(&mut self, inode: &Self::Inode) -> Result<(), Self::Error> {
+        sync_inode(self, inode, true)
+    }
+
+    fn i_fdatasync(&mut self, inode: &Self::Inode) -> Result<(), Self::Error> {
+        let with_inode = self.dirty.get(&inode.inum) == Some(&true);
+        sync_inode(self, inode, with_inode)
+    }
+}
+
+impl XattrSupport for InodeLayerFS {
+    fn i_getxattr(&self, inode: &Self::Inode, name: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.check_xattr_name(name)?;
+        Ok(self
+            .load_xattrs(inode.inum)?
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value))
+    }
+
+    fn i_setxattr(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), Self::Error> {
+        self.check_xattr_name(name)?;
+        let ctime = inode.disk_node.ctime;
+        let result = journal::in_tx(self, |fs| {
+            let mut attrs = fs.load_xattrs(inode.inum)?;
+            match attrs.binary_search_by(|(n, _)| n.as_str().cmp(name)) {
+                Ok(i) => attrs[i].1 = value.to_vec(),
+                Err(i) => attrs.insert(i, (name.to_string(), value.to_vec())),
+            }
+            fs.store_xattrs(inode, &attrs)?;
+            inode.disk_node.ctime = fs.now();
+            fs.i_put(inode)
+        });
+        if result.is_err() {
+            inode.disk_node.ctime = ctime;
+        }
+        result
+    }
+
+    fn i_listxattr(&self, inode: &Self::Inode) -> Result<Vec<String>, Self::Error> {
+        if !self.has_xattrs() {
+            return Err(InodeLayerError::InodeLayerOp(
+                "The file system does not support extended attributes",
             ));
         }
-        let init_blocks =
-            (inode.get_size() as f64 / self.sup_as_ref().block_size as f64).ceil() as usize;
-        let s_block_index = (off / self.sup_as_ref().block_size) as usize;
-        let mut block_off = (off % self.sup_as_ref().block_size) as usize;
-        let mut bytes_left = n as usize;
-        //no of blocks that the write spans
-        let no_blocks =
-            ((n as usize + block_off) as f64 / self.sup_as_ref().block_size as f64).ceil() as usize;
-        let mut dirty_i = false;
-
-        for bl in 0..no_blocks {
-            let t_block_idx = s_block_index + bl;
-            if t_block_idx + 1 > init_blocks {
-                let block_n = self.b_alloc()? + self.sup_as_ref().datastart;
-                inode.disk_node.direct_blocks[t_block_idx as usize] = block_n;
-                dirty_i = true;
-            }
-            let mut block = self.b_get(inode.get_block(t_block_idx as u64))?;
-            let write_size = if block_off + bytes_left < block.len() as usize {
-                bytes_left
-            } else {
-                block.len() as usize - block_off
-            };
-            let start_idx = n as usize - bytes_left;
-            let end_idx = start_idx + write_size as usize;
-            block.write_data(&buf.contents_as_ref()[start_idx..end_idx], block_off as u64)?;
-            self.b_put(&block)?;
-            bytes_left -= write_size;
-            block_off = 0;
-        }
-        if off + n > inode.get_size() {
-            inode.disk_node.size = off + n;
-            dirty_i = true;
-        }
-        if dirty_i {
-            self.i_put(inode)?;
+        Ok(self
+            .load_xattrs(inode.inum)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    fn i_removexattr(&mut self, inode: &mut Self::Inode, name: &str) -> Result<(), Self::Error> {
+        self.check_xattr_name(name)?;
+        let ctime = inode.disk_node.ctime;
+        let result = journal::in_tx(self, |fs| {
+            let mut attrs = fs.load_xattrs(inode.inum)?;
+            let i = attrs
+                .binary_search_by(|(n, _)| n.as_str().cmp(name))
+                .map_err(|_| {
+                    InodeLayerError::InodeLayerInput(
+                        "Inode has no extended attribute with that name",
+                    )
+                })?;
+            attrs.remove(i);
+            fs.store_xattrs(inode, &attrs)?;
+            inode.disk_node.ctime = fs.now();
+            fs.i_put(inode)
+        });
+        if result.is_err() {
+            inode.disk_node.ctime = ctime;
         }
-        Ok(())
+        result
     }
 }
 
@@ -23,15 +23,20 @@
 //! ...
 //!
 
+use cplfs_api::clock::Clock;
 use cplfs_api::controller::Device;
-use cplfs_api::fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeRWSupport, InodeSupport};
+use cplfs_api::fs::{
+    BlockSupport, DirectorySupport, FileSysSupport, InodeRWSupport, InodeSupport, XattrSupport,
+};
 use cplfs_api::types::{
-    Block, Buffer, DInode, DirEntry, FType, Inode, InodeLike, SuperBlock, DIRENTRY_SIZE,
-    DIRNAME_SIZE,
+    Block, BlockNo, Buffer, DataIdx, DirEntry, DiscardPolicy, FType, FragReport, Inode,
+    InodeExtents, InodeLike, MountOptions, StatFs, SuperBlock, Timestamp, DEFAULT_DIR_MODE,
+    DIRENTRY_SIZE, DIRNAME_SIZE,
 };
 use std::path::Path;
 
 use super::error_fs::DirLayerError;
+use super::journal;
 use crate::b_inode_support::InodeLayerFS;
 
 /// You are free to choose the name for your file system. As we will use
@@ -68,10 +73,99 @@ impl DirLayerFS {
     ) -> Result<DirEntry, <Self as FileSysSupport>::Error> {
         let mut buf = Buffer::new_zero(*DIRENTRY_SIZE);
         self.inode_fs
-            .i_read(inode, &mut buf, idx * (*DIRENTRY_SIZE), *DIRENTRY_SIZE)?;
+            .read_contents(inode, &mut buf, idx * (*DIRENTRY_SIZE), *DIRENTRY_SIZE)?;
         Ok(buf.deserialize_from::<DirEntry>(0)?)
     }
 
+    /// Returns the current time of the clock of this file system
+    pub fn now(&self) -> Timestamp {
+        self.inode_fs.now()
+    }
+
+    /// Stores `target` in the empty symbolic link `inode`, see [`InodeLayerFS::set_link_target`]
+    pub fn set_link_target(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        target: &str,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        Ok(self.inode_fs.set_link_target(inode, target)?)
+    }
+
+    /// Returns the target of the symbolic link `inode`
+    pub fn link_target(
+        &self,
+        inode: &<Self as InodeSupport>::Inode,
+    ) -> Result<String, <Self as FileSysSupport>::Error> {
+        Ok(self.inode_fs.link_target(inode)?)
+    }
+
+    /// Returns the entries of directory `inode` that are in use, i.e. that have a nonzero `inum`, in the order in which they are stored
+    pub fn dir_entries(
+        &self,
+        inode: &<Self as InodeSupport>::Inode,
+    ) -> Result<Vec<DirEntry>, <Self as FileSysSupport>::Error> {
+        if inode.get_ft() != FType::TDir {
+            return Err(DirLayerError::DirLayerInput(
+                "The given inode does not represent a Directory",
+            ));
+        }
+        let mut entries = vec![];
+        for i in 0..inode.get_size() / (*DIRENTRY_SIZE) {
+            let entry = self.get_dir_entry(inode, i)?;
+            if entry.inum != 0 {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Inverse of `dirlink`: empties the entry at byte offset `offset` of directory `inode`, i.e. sets its `inum` and all characters of its name to 0.
+    /// Decrements the `nlink` field of the inode the entry pointed to, unless that is `inode` itself, and returns the number of that inode.
+    /// Freeing the inode once it has no links left is up to the caller.
+    ///
+    /// Errors if `inode` is not a directory, or if `offset` does not point to an entry in use.
+    pub fn dirunlink(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        offset: u64,
+    ) -> Result<u64, <Self as FileSysSupport>::Error> {
+        let (mtime, ctime) = (inode.disk_node.mtime, inode.disk_node.ctime);
+        let result = journal::in_tx(self, |fs| {
+            if inode.get_ft() != FType::TDir {
+                return Err(DirLayerError::DirLayerInput(
+                    "The given inode does not correspond to a directory",
+                ));
+            }
+            if !offset.is_multiple_of(*DIRENTRY_SIZE) || offset >= inode.get_size() {
+                return Err(DirLayerError::DirLayerInput(
+                    "The given offset does not point to a directory entry",
+                ));
+            }
+            let entry = fs.get_dir_entry(inode, offset / (*DIRENTRY_SIZE))?;
+            if entry.inum == 0 {
+                return Err(DirLayerError::DirLayerInput(
+                    "The given offset points to an empty directory entry",
+                ));
+            }
+
+            let mut buf = Buffer::new_zero(*DIRENTRY_SIZE);
+            buf.serialize_into(&DirEntry::default(), 0)?;
+            fs.inode_fs.i_write(inode, &buf, offset, *DIRENTRY_SIZE)?;
+            if entry.inum != inode.get_inum() {
+                let mut queried_inode = fs.i_get(entry.inum)?;
+                queried_inode.disk_node.nlink = queried_inode.disk_node.nlink.saturating_sub(1);
+                queried_inode.disk_node.ctime = fs.inode_fs.now();
+                fs.i_put(&queried_inode)?;
+            }
+            Ok(entry.inum)
+        });
+        if result.is_err() {
+            inode.disk_node.mtime = mtime;
+            inode.disk_node.ctime = ctime;
+        }
+        result
+    }
+
     /// checks if a string represents a valid directory name
     pub fn is_valid_dir_name(name: &str) -> bool {
         //println!("Checking {}", name);
@@ -92,17 +186,19 @@ impl FileSysSupport for DirLayerFS {
 
     fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
         let mut inode_fs = InodeLayerFS::mkfs(path, sb)?;
-        let root = <<Self as InodeSupport>::Inode as InodeLike>::new(1, &FType::TDir, 1, 0, &[])
-            .ok_or(DirLayerError::DirLayerOp(
-                "Couldn't initialize the filesystem",
-            ))?;
+        let mut root =
+            <<Self as InodeSupport>::Inode as InodeLike>::new(1, &FType::TDir, 1, 0, &[]).ok_or(
+                DirLayerError::DirLayerOp("Couldn't initialize the filesystem"),
+            )?;
+        root.disk_node.mode = DEFAULT_DIR_MODE;
+        root.disk_node.flags = inode_fs.initial_flags(FType::TDir);
         inode_fs.i_put(&root)?;
         Ok(DirLayerFS { inode_fs })
     }
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
+    fn mountfs_with(dev: Device, opts: &MountOptions) -> Result<Self, Self::Error> {
         Ok(DirLayerFS {
-            inode_fs: InodeLayerFS::mountfs(dev)?,
+            inode_fs: InodeLayerFS::mountfs_with(dev, opts)?,
         })
     }
 
@@ -112,7 +208,7 @@ impl FileSysSupport for DirLayerFS {
 }
 
 impl BlockSupport for DirLayerFS {
-    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+    fn b_get(&self, i: BlockNo) -> Result<Block, Self::Error> {
         Ok(self.inode_fs.b_get(i)?)
     }
 
@@ -120,18 +216,34 @@ impl BlockSupport for DirLayerFS {
         Ok(self.inode_fs.b_put(b)?)
     }
 
-    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+    fn b_free(&mut self, i: DataIdx) -> Result<(), Self::Error> {
         Ok(self.inode_fs.b_free(i)?)
     }
 
-    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+    fn b_zero(&mut self, i: DataIdx) -> Result<(), Self::Error> {
         Ok(self.inode_fs.b_zero(i)?)
     }
 
-    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+    fn b_alloc(&mut self) -> Result<DataIdx, Self::Error> {
         Ok(self.inode_fs.b_alloc()?)
     }
 
+    fn b_alloc_near(&mut self, goal: DataIdx) -> Result<DataIdx, Self::Error> {
+        Ok(self.inode_fs.b_alloc_near(goal)?)
+    }
+
+    fn b_set_privileged(&mut self, privileged: bool) -> bool {
+        self.inode_fs.b_set_privileged(privileged)
+    }
+
+    fn b_frag_report(&self) -> Result<FragReport, Self::Error> {
+        Ok(self.inode_fs.b_frag_report()?)
+    }
+
+    fn statfs(&self) -> Result<StatFs, Self::Error> {
+        Ok(self.inode_fs.statfs()?)
+    }
+
     fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
         Ok(self.inode_fs.sup_get()?)
     }
@@ -139,6 +251,46 @@ impl BlockSupport for DirLayerFS {
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
         Ok(self.inode_fs.sup_put(sup)?)
     }
+
+    fn begin_op(&mut self) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.begin_op()?)
+    }
+
+    fn end_op(&mut self) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.end_op()?)
+    }
+
+    fn b_tx_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.b_tx_begin()?)
+    }
+
+    fn b_tx_commit(&mut self) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.b_tx_commit()?)
+    }
+
+    fn b_tx_rollback(&mut self) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.b_tx_rollback()?)
+    }
+
+    fn b_grow(&mut self, nblocks: u64, ninodes: u64) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.b_grow(nblocks, ninodes)?)
+    }
+
+    fn b_shrink(dev: Device, nblocks: u64) -> Result<Device, Self::Error> {
+        Ok(InodeLayerFS::b_shrink(dev, nblocks)?)
+    }
+
+    fn b_trim(&mut self, policy: DiscardPolicy) -> Result<u64, Self::Error> {
+        Ok(self.inode_fs.b_trim(policy)?)
+    }
+
+    fn b_mark_bad(&mut self, b: BlockNo) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.b_mark_bad(b)?)
+    }
+
+    fn b_bad_blocks(&self) -> Result<Vec<BlockNo>, Self::Error> {
+        Ok(self.inode_fs.b_bad_blocks()?)
+    }
 }
 
 impl InodeSupport for DirLayerFS {
@@ -160,9 +312,52 @@ impl InodeSupport for DirLayerFS {
         Ok(self.inode_fs.i_alloc(ft)?)
     }
 
+    fn i_alloc_near(&mut self, ft: FType, parent: u64) -> Result<u64, Self::Error> {
+        Ok(self.inode_fs.i_alloc_near(ft, parent)?)
+    }
+
+    fn i_extent_summary(&self) -> Result<Vec<InodeExtents>, Self::Error> {
+        Ok(self.inode_fs.i_extent_summary()?)
+    }
+
+    fn i_set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.inode_fs.i_set_clock(clock)
+    }
+
     fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
         Ok(self.inode_fs.i_trunc(inode)?)
     }
+
+    fn i_truncate(&mut self, inode: &mut Self::Inode, new_size: u64) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.i_truncate(inode, new_size)?)
+    }
+
+    fn i_max_size(&self) -> u64 {
+        self.inode_fs.i_max_size()
+    }
+}
+
+impl XattrSupport for DirLayerFS {
+    fn i_getxattr(&self, inode: &Self::Inode, name: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.inode_fs.i_getxattr(inode, name)?)
+    }
+
+    fn i_setxattr(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.i_setxattr(inode, name, value)?)
+    }
+
+    fn i_listxattr(&self, inode: &Self::Inode) -> Result<Vec<String>, Self::Error> {
+        Ok(self.inode_fs.i_listxattr(inode)?)
+    }
+
+    fn i_removexattr(&mut self, inode: &mut Self::Inode, name: &str) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.i_removexattr(inode, name)?)
+    }
 }
 
 impl DirectorySupport for DirLayerFS {
@@ -231,52 +426,65 @@ impl DirectorySupport for DirLayerFS {
         name: &str,
         inum: u64,
     ) -> Result<u64, Self::Error> {
-        // First check if inode is a dir, doesn't contain an entry with 'name'
-        // and the inode with 'inum' is already allocated
-        if inode.get_ft() != FType::TDir {
-            return Err(DirLayerError::DirLayerInput(
-                "The given inode does not correspond to a directory",
-            ));
-        }
-        match self.dirlookup(inode, name) {
-            Err(DirLayerError::DirLookupNotFound()) => {}
-            Ok(_) => {
+        let (size, blocks) = (inode.disk_node.size, inode.disk_node.direct_blocks);
+        let flags = inode.disk_node.flags;
+        let (mtime, ctime) = (inode.disk_node.mtime, inode.disk_node.ctime);
+        let result = journal::in_tx(self, |fs| {
+            // First check if inode is a dir, doesn't contain an entry with 'name'
+            // and the inode with 'inum' is already allocated
+            if inode.get_ft() != FType::TDir {
                 return Err(DirLayerError::DirLayerInput(
-                    "The given node contains a dir entry with the same name",
-                ))
+                    "The given inode does not correspond to a directory",
+                ));
+            }
+            match fs.dirlookup(inode, name) {
+                Err(DirLayerError::DirLookupNotFound()) => {}
+                Ok(_) => {
+                    return Err(DirLayerError::DirLayerInput(
+                        "The given node contains a dir entry with the same name",
+                    ))
+                }
+                Err(e) => return Err(e),
+            }
+            let mut queried_inode = fs.i_get(inum)?;
+            if queried_inode.get_ft() == FType::TFree {
+                return Err(DirLayerError::DirLayerInput(
+                    "The given inum points to a free inode",
+                ));
             }
-            Err(e) => return Err(e),
-        }
-        let mut queried_inode = self.i_get(inum)?;
-        if queried_inode.get_ft() == FType::TFree {
-            return Err(DirLayerError::DirLayerInput(
-                "The given inum points to a free inode",
-            ));
-        }
-
-        let entry = Self::new_de(inum, name).ok_or(DirLayerError::DirLayerOp(
-            "Could not initialize new dirEntry",
-        ))?;
-        let mut t_offest = inode.get_size();
 
-        // try to see if there is some free DirEntry
-        let no_entries = inode.get_size() / (*DIRENTRY_SIZE);
-        for i in 0..no_entries {
-            if self.get_dir_entry(inode, i)?.inum == 0 {
-                t_offest = i * (*DIRENTRY_SIZE);
-                break;
+            let entry = Self::new_de(inum, name).ok_or(DirLayerError::DirLayerOp(
+                "Could not initialize new dirEntry",
+            ))?;
+            let mut t_offest = inode.get_size();
+
+            // try to see if there is some free DirEntry
+            let no_entries = inode.get_size() / (*DIRENTRY_SIZE);
+            for i in 0..no_entries {
+                if fs.get_dir_entry(inode, i)?.inum == 0 {
+                    t_offest = i * (*DIRENTRY_SIZE);
+                    break;
+                }
             }
-        }
 
-        let mut buf = Buffer::new_zero(*DIRENTRY_SIZE);
-        buf.serialize_into(&entry, 0)?;
-        self.inode_fs
-            .i_write(inode, &buf, t_offest, *DIRENTRY_SIZE)?;
-        if inum != inode.get_inum() {
-            queried_inode.disk_node.nlink += 1;
-            self.i_put(&queried_inode)?;
+            let mut buf = Buffer::new_zero(*DIRENTRY_SIZE);
+            buf.serialize_into(&entry, 0)?;
+            fs.inode_fs.i_write(inode, &buf, t_offest, *DIRENTRY_SIZE)?;
+            if inum != inode.get_inum() {
+                queried_inode.disk_node.nlink += 1;
+                queried_inode.disk_node.ctime = fs.inode_fs.now();
+                fs.i_put(&queried_inode)?;
+            }
+            Ok(t_offest)
+        });
+        if result.is_err() {
+            inode.disk_node.size = size;
+            inode.disk_node.direct_blocks = blocks;
+            inode.disk_node.flags = flags;
+            inode.disk_node.mtime = mtime;
+            inode.disk_node.ctime = ctime;
         }
-        Ok(t_offest)
+        result
     }
 }
 
@@ -26,10 +26,19 @@
 
 use crate::c_dirs_support::DirLayerFS;
 use crate::error_fs::PathError;
+use crate::journal;
+use cplfs_api::clock::Clock;
 use cplfs_api::controller::Device;
-use cplfs_api::fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport, PathSupport};
-use cplfs_api::types::{Block, DirEntry, FType, Inode, InodeLike, SuperBlock, ROOT_INUM};
+use cplfs_api::fs::{
+    BlockSupport, DirectorySupport, FileSysSupport, InodeSupport, PathSupport, XattrSupport,
+};
+use cplfs_api::types::{
+    Access, Block, BlockNo, Credentials, DataIdx, DirEntry, DiscardPolicy, FType, FragReport,
+    Inode, InodeExtents, InodeLike, MountOptions, StatFs, SuperBlock, MAX_SYMLINK_HOPS, MODE_MASK,
+    MODE_SETGID, MODE_SETUID, MODE_STICKY, ROOT_INUM, XATTR_NAMESPACES,
+};
 use relative_path::RelativePath;
+use std::collections::VecDeque;
 use std::path::Path;
 
 /// You are free to choose the name for your file system. As we will use
@@ -43,6 +52,7 @@ pub type FSName = PathFS;
 pub struct PathFS {
     dir_fs: DirLayerFS,
     cur_dir: String,
+    cred: Credentials,
 }
 
 impl PathFS {
@@ -69,6 +79,140 @@ impl PathFS {
         }
         full_path
     }
+
+    /// Errors with `PermissionDenied` for `path` unless the credentials permit all of `accesses` to `inode`
+    fn check_access(
+        &self,
+        inode: &Inode,
+        accesses: &[Access],
+        path: &str,
+    ) -> Result<(), PathError> {
+        if accesses.iter().all(|&a| self.cred.permits(inode, a)) {
+            Ok(())
+        } else {
+            Err(PathError::PermissionDenied(path.to_string()))
+        }
+    }
+
+    /// Errors with `PermissionDenied` for `path` unless the credentials permit `access` to the extended attribute `name` of `inode`.
+    /// Root may access any attribute; anyone else only those outside the `trusted.` namespace, of files they have `access` to.
+    fn check_xattr_access(
+        &self,
+        inode: &Inode,
+        name: &str,
+        access: Access,
+        path: &str,
+    ) -> Result<(), PathError> {
+        if self.cred.is_root() {
+            return Ok(());
+        }
+        if name.starts_with(XATTR_NAMESPACES[1]) {
+            return Err(PathError::PermissionDenied(path.to_string()));
+        }
+        self.check_access(inode, &[access], path)
+    }
+
+    /// Returns whether `target` can be stored in a symbolic link: like a valid path, but it may be relative without starting with "." or ".."
+    fn valid_link_target(target: &str) -> bool {
+        target == "/"
+            || (!target.is_empty()
+                && !target.ends_with("/")
+                && target
+                    .strip_prefix("/")
+                    .unwrap_or(target)
+                    .split("/")
+                    .all(DirLayerFS::is_valid_dir_name))
+    }
+
+    /// Looks up `path` like `resolve_path`, following a symbolic link in the final name only if `follow_last` is set
+    fn walk(&self, path: &str, follow_last: bool) -> Result<Inode, PathError> {
+        if !Self::valid_path(path) {
+            return Err(PathError::InvalidPathName(path.to_string()));
+        }
+        //formulate the correct full path to look for
+        let full_path: String;
+        if Path::new(path).has_root() {
+            full_path = path.to_string();
+        } else if self.get_cwd() == "/" {
+            full_path = "/".to_string() + path;
+        } else {
+            full_path = self.get_cwd() + "/" + path;
+        }
+
+        //names still to look up, into which the targets of links are spliced
+        let mut names: VecDeque<String> = full_path
+            .split("/")
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+        let mut hops = 0;
+        let mut cur_inode = self.i_get(ROOT_INUM)?;
+        while let Some(name) = names.pop_front() {
+            if cur_inode.get_ft() != FType::TDir {
+                return Err(PathError::InodeNotDir(name));
+            }
+            self.check_access(&cur_inode, &[Access::Exec], path)?;
+            let next = self.dirlookup(&cur_inode, &name)?.0;
+            let follow = next.get_ft() == FType::TSymlink && (follow_last || !names.is_empty());
+            if !follow {
+                cur_inode = next;
+                continue;
+            }
+            hops += 1;
+            if hops > MAX_SYMLINK_HOPS {
+                return Err(PathError::TooManyLinks(path.to_string()));
+            }
+            //relative targets continue from the directory containing the link, i.e. `cur_inode`
+            let target = self.dir_fs.link_target(&next)?;
+            if target.starts_with("/") {
+                cur_inode = self.i_get(ROOT_INUM)?;
+            }
+            for name in target.rsplit("/").filter(|name| !name.is_empty()) {
+                names.push_front(name.to_string());
+            }
+        }
+        Ok(cur_inode)
+    }
+
+    /// Allocates an inode of type `ft` to be linked into directory `parent`, owned by the caller.
+    /// The inode gets the group of `parent` if it has the setgid bit set, and directories then inherit that bit as well.
+    fn alloc_owned(&mut self, ft: FType, parent: &Inode) -> Result<Inode, PathError> {
+        let inum = self.i_alloc_near(ft, parent.get_inum())?;
+        let mut inode = self.i_get(inum)?;
+        inode.disk_node.uid = self.cred.uid;
+        if parent.get_mode() & MODE_SETGID != 0 {
+            inode.disk_node.gid = parent.get_gid();
+            if ft == FType::TDir {
+                inode.disk_node.mode |= MODE_SETGID;
+            }
+        } else {
+            inode.disk_node.gid = self.cred.gid;
+        }
+        self.i_put(&inode)?;
+        Ok(inode)
+    }
+
+    /// Splits `path` into the directory it is in and its last name, and resolves that directory.
+    /// Errors if the path is invalid, if its last name is "." or "..", or if the directory cannot be resolved or is not a directory.
+    fn resolve_parent(&self, path: &str) -> Result<(Inode, String), PathError> {
+        if !Self::valid_path(path) {
+            return Err(PathError::InvalidPathName(path.to_string()));
+        }
+        let (dir, name) = path.rsplit_once("/").unwrap_or(("", path));
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(PathError::InvalidPathName(path.to_string()));
+        }
+        let parent = match dir {
+            "" => self.resolve_path("/")?,
+            "." => self.resolve_path(&self.get_cwd())?,
+            ".." => self.resolve_path("./..")?,
+            _ => self.resolve_path(dir)?,
+        };
+        if parent.get_ft() != FType::TDir {
+            return Err(PathError::InodeNotDir(dir.to_string()));
+        }
+        Ok((parent, name.to_string()))
+    }
 }
 
 impl FileSysSupport for PathFS {
@@ -87,13 +231,15 @@ impl FileSysSupport for PathFS {
         Ok(PathFS {
             dir_fs,
             cur_dir: String::from("/"),
+            cred: Credentials::root(),
         })
     }
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
+    fn mountfs_with(dev: Device, opts: &MountOptions) -> Result<Self, Self::Error> {
         Ok(PathFS {
-            dir_fs: DirLayerFS::mountfs(dev)?,
+            dir_fs: DirLayerFS::mountfs_with(dev, opts)?,
             cur_dir: String::from("/"),
+            cred: Credentials::root(),
         })
     }
 
@@ -103,7 +249,7 @@ impl FileSysSupport for PathFS {
 }
 
 impl BlockSupport for PathFS {
-    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+    fn b_get(&self, i: BlockNo) -> Result<Block, Self::Error> {
         Ok(self.dir_fs.b_get(i)?)
     }
 
@@ -111,18 +257,34 @@ impl BlockSupport for PathFS {
         Ok(self.dir_fs.b_put(b)?)
     }
 
-    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+    fn b_free(&mut self, i: DataIdx) -> Result<(), Self::Error> {
         Ok(self.dir_fs.b_free(i)?)
     }
 
-    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+    fn b_zero(&mut self, i: DataIdx) -> Result<(), Self::Error> {
         Ok(self.dir_fs.b_zero(i)?)
     }
 
-    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+    fn b_alloc(&mut self) -> Result<DataIdx, Self::Error> {
         Ok(self.dir_fs.b_alloc()?)
     }
 
+    fn b_alloc_near(&mut self, goal: DataIdx) -> Result<DataIdx, Self::Error> {
+        Ok(self.dir_fs.b_alloc_near(goal)?)
+    }
+
+    fn b_set_privileged(&mut self, privileged: bool) -> bool {
+        self.dir_fs.b_set_privileged(privileged)
+    }
+
+    fn b_frag_report(&self) -> Result<FragReport, Self::Error> {
+        Ok(self.dir_fs.b_frag_report()?)
+    }
+
+    fn statfs(&self) -> Result<StatFs, Self::Error> {
+        Ok(self.dir_fs.statfs()?)
+    }
+
     fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
         Ok(self.dir_fs.sup_get()?)
     }
@@ -130,6 +292,46 @@ impl BlockSupport for PathFS {
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
         Ok(self.dir_fs.sup_put(sup)?)
     }
+
+    fn begin_op(&mut self) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.begin_op()?)
+    }
+
+    fn end_op(&mut self) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.end_op()?)
+    }
+
+    fn b_tx_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.b_tx_begin()?)
+    }
+
+    fn b_tx_commit(&mut self) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.b_tx_commit()?)
+    }
+
+    fn b_tx_rollback(&mut self) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.b_tx_rollback()?)
+    }
+
+    fn b_grow(&mut self, nblocks: u64, ninodes: u64) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.b_grow(nblocks, ninodes)?)
+    }
+
+    fn b_shrink(dev: Device, nblocks: u64) -> Result<Device, Self::Error> {
+        Ok(DirLayerFS::b_shrink(dev, nblocks)?)
+    }
+
+    fn b_trim(&mut self, policy: DiscardPolicy) -> Result<u64, Self::Error> {
+        Ok(self.dir_fs.b_trim(policy)?)
+    }
+
+    fn b_mark_bad(&mut self, b: BlockNo) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.b_mark_bad(b)?)
+    }
+
+    fn b_bad_blocks(&self) -> Result<Vec<BlockNo>, Self::Error> {
+        Ok(self.dir_fs.b_bad_blocks()?)
+    }
 }
 
 impl InodeSupport for PathFS {
@@ -151,9 +353,52 @@ impl InodeSupport for PathFS {
         Ok(self.dir_fs.i_alloc(ft)?)
     }
 
+    fn i_alloc_near(&mut self, ft: FType, parent: u64) -> Result<u64, Self::Error> {
+        Ok(self.dir_fs.i_alloc_near(ft, parent)?)
+    }
+
+    fn i_extent_summary(&self) -> Result<Vec<InodeExtents>, Self::Error> {
+        Ok(self.dir_fs.i_extent_summary()?)
+    }
+
+    fn i_set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.dir_fs.i_set_clock(clock)
+    }
+
     fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
         Ok(self.dir_fs.i_trunc(inode)?)
     }
+
+    fn i_truncate(&mut self, inode: &mut Self::Inode, new_size: u64) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.i_truncate(inode, new_size)?)
+    }
+
+    fn i_max_size(&self) -> u64 {
+        self.dir_fs.i_max_size()
+    }
+}
+
+impl XattrSupport for PathFS {
+    fn i_getxattr(&self, inode: &Self::Inode, name: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.dir_fs.i_getxattr(inode, name)?)
+    }
+
+    fn i_setxattr(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.i_setxattr(inode, name, value)?)
+    }
+
+    fn i_listxattr(&self, inode: &Self::Inode) -> Result<Vec<String>, Self::Error> {
+        Ok(self.dir_fs.i_listxattr(inode)?)
+    }
+
+    fn i_removexattr(&mut self, inode: &mut Self::Inode, name: &str) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.i_removexattr(inode, name)?)
+    }
 }
 
 impl DirectorySupport for PathFS {
@@ -227,36 +472,155 @@ impl PathSupport for PathFS {
         Some(())
     }
 
+    fn get_credentials(&self) -> Credentials {
+        self.cred.clone()
+    }
+
+    fn set_credentials(&mut self, cred: Credentials) {
+        self.cred = cred;
+    }
+
     fn resolve_path(&self, path: &str) -> Result<Self::Inode, Self::Error> {
-        if !Self::valid_path(path) {
-            return Err(PathError::InvalidPathName(path.to_string()));
+        self.walk(path, true)
+    }
+
+    fn lresolve_path(&self, path: &str) -> Result<Self::Inode, Self::Error> {
+        self.walk(path, false)
+    }
+
+    fn symlink(&mut self, target: &str, path: &str) -> Result<Self::Inode, Self::Error> {
+        if !Self::valid_link_target(target) {
+            return Err(PathError::InvalidPathName(target.to_string()));
         }
-        //formulate the correct full path to look for
-        let full_path: String;
-        if Path::new(path).has_root() {
-            full_path = path.to_string();
-        } else if self.get_cwd() == "/" {
-            full_path = "/".to_string() + path;
-        } else {
-            full_path = self.get_cwd() + "/" + path;
+        let (parent, name) = self.resolve_parent(path)?;
+        self.check_access(&parent, &[Access::Write, Access::Exec], path)?;
+        let inum = journal::in_tx(self, |fs| {
+            let mut link = fs.alloc_owned(FType::TSymlink, &parent)?;
+            fs.dir_fs.set_link_target(&mut link, target)?;
+            let mut parent = fs.i_get(parent.get_inum())?;
+            fs.dirlink(&mut parent, &name, link.get_inum())?;
+            Ok(link.get_inum())
+        })?;
+        self.i_get(inum)
+    }
+
+    fn readlink(&self, path: &str) -> Result<String, Self::Error> {
+        let inode = self.lresolve_path(path)?;
+        if inode.get_ft() != FType::TSymlink {
+            return Err(PathError::InodeNotSymlink(path.to_string()));
         }
+        Ok(self.dir_fs.link_target(&inode)?)
+    }
 
-        let mut cur_inode = self.i_get(ROOT_INUM)?;
-        for dir in full_path.split("/").skip(1) {
-            if cur_inode.get_ft() != FType::TDir {
-                return Err(PathError::InodeNotDir(dir.to_string()));
-            }
-            cur_inode = self.dirlookup(&cur_inode, dir)?.0;
+    fn open(&self, path: &str, access: Access) -> Result<Self::Inode, Self::Error> {
+        let inode = self.resolve_path(path)?;
+        self.check_access(&inode, &[access], path)?;
+        Ok(inode)
+    }
+
+    fn chmod(&mut self, path: &str, mode: u16) -> Result<(), Self::Error> {
+        let mut inode = self.resolve_path(path)?;
+        if !self.cred.is_root() && self.cred.uid != inode.get_uid() {
+            return Err(PathError::PermissionDenied(path.to_string()));
         }
-        Ok(cur_inode)
+        let mut mode = mode & MODE_MASK;
+        if !self.cred.is_root() && !self.cred.in_group(inode.get_gid()) {
+            mode &= !MODE_SETGID;
+        }
+        inode.disk_node.mode = mode;
+        inode.disk_node.ctime = self.dir_fs.now();
+        self.i_put(&inode)
+    }
+
+    fn chown(&mut self, path: &str, uid: u32, gid: u32) -> Result<(), Self::Error> {
+        let mut inode = self.resolve_path(path)?;
+        if !self.cred.is_root() {
+            return Err(PathError::PermissionDenied(path.to_string()));
+        }
+        inode.disk_node.uid = uid;
+        inode.disk_node.gid = gid;
+        inode.disk_node.mode &= !(MODE_SETUID | MODE_SETGID);
+        inode.disk_node.ctime = self.dir_fs.now();
+        self.i_put(&inode)
+    }
+
+    fn getxattr(&self, path: &str, name: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        let inode = self.resolve_path(path)?;
+        self.check_xattr_access(&inode, name, Access::Read, path)?;
+        self.i_getxattr(&inode, name)
+    }
+
+    fn setxattr(&mut self, path: &str, name: &str, value: &[u8]) -> Result<(), Self::Error> {
+        let mut inode = self.resolve_path(path)?;
+        self.check_xattr_access(&inode, name, Access::Write, path)?;
+        self.i_setxattr(&mut inode, name, value)
+    }
+
+    fn listxattr(&self, path: &str) -> Result<Vec<String>, Self::Error> {
+        let inode = self.resolve_path(path)?;
+        let mut names = self.i_listxattr(&inode)?;
+        if !self.cred.is_root() {
+            names.retain(|name| !name.starts_with(XATTR_NAMESPACES[1]));
+        }
+        Ok(names)
     }
 
-    fn mkdir(&mut self, _path: &str) -> Result<Self::Inode, Self::Error> {
-        unimplemented!()
+    fn removexattr(&mut self, path: &str, name: &str) -> Result<(), Self::Error> {
+        let mut inode = self.resolve_path(path)?;
+        self.check_xattr_access(&inode, name, Access::Write, path)?;
+        self.i_removexattr(&mut inode, name)
     }
 
-    fn unlink(&mut self, _path: &str) -> Result<(), Self::Error> {
-        unimplemented!()
+    fn mkdir(&mut self, path: &str) -> Result<Self::Inode, Self::Error> {
+        let (parent, name) = self.resolve_parent(path)?;
+        self.check_access(&parent, &[Access::Write, Access::Exec], path)?;
+        let inum = journal::in_tx(self, |fs| {
+            let mut dir = fs.alloc_owned(FType::TDir, &parent)?;
+            let inum = dir.get_inum();
+            fs.dirlink(&mut dir, ".", inum)?;
+            fs.dirlink(&mut dir, "..", parent.get_inum())?;
+            //linking ".." changed the parent on disk
+            let mut parent = fs.i_get(parent.get_inum())?;
+            fs.dirlink(&mut parent, &name, inum)?;
+            Ok(inum)
+        })?;
+        self.i_get(inum)
+    }
+
+    fn unlink(&mut self, path: &str) -> Result<(), Self::Error> {
+        let (mut parent, name) = self.resolve_parent(path)?;
+        self.check_access(&parent, &[Access::Write, Access::Exec], path)?;
+        let (target, offset) = self.dirlookup(&parent, &name)?;
+        if parent.get_mode() & MODE_STICKY != 0
+            && !self.cred.is_root()
+            && self.cred.uid != target.get_uid()
+            && self.cred.uid != parent.get_uid()
+        {
+            return Err(PathError::PermissionDenied(path.to_string()));
+        }
+        if target.get_ft() == FType::TDir
+            && self
+                .dir_fs
+                .dir_entries(&target)?
+                .iter()
+                .any(|de| !matches!(Self::get_name_str(de).as_str(), "." | ".."))
+        {
+            return Err(PathError::DirNotEmpty(path.to_string()));
+        }
+        journal::in_tx(self, |fs| {
+            let inum = fs.dir_fs.dirunlink(&mut parent, offset)?;
+            let mut target = fs.i_get(inum)?;
+            if inum == parent.get_inum() || target.get_nlink() != 0 {
+                return Ok(());
+            }
+            //the ".." entry of a directory disappears together with it
+            if target.get_ft() == FType::TDir {
+                if let Ok((_, up)) = fs.dirlookup(&target, "..") {
+                    fs.dir_fs.dirunlink(&mut target, up)?;
+                }
+            }
+            fs.i_free(inum)
+        })
     }
 }
 
@@ -81,11 +81,29 @@ pub enum PathError {
     #[error("Error in the DirectoryInode layer")]
     DirectoryLayerError(#[from] DirLayerError),
 
+    ///the given path is not a valid path
     #[error("Invalid Path Name: {0}")]
     InvalidPathName(String),
 
+    ///an intermediate name in a path does not refer to a directory
     #[error("Inode with name {0} is not a directory")]
     InodeNotDir(String),
+
+    ///the given path does not refer to a symbolic link
+    #[error("Inode at {0} is not a symbolic link")]
+    InodeNotSymlink(String),
+
+    ///resolving the given path takes more than `MAX_SYMLINK_HOPS` symbolic links, which probably form a loop
+    #[error("Too many levels of symbolic links: {0}")]
+    TooManyLinks(String),
+
+    ///the directory at the given path still contains entries other than "." and ".."
+    #[error("Directory {0} is not empty")]
+    DirNotEmpty(String),
+
+    ///the credentials do not permit the operation on the given path
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
 }
 
 /*/// Define a generic alias for a `Result` with the error type `APIError`.
@@ -31,27 +31,986 @@
 //!
 //! # Status
 //!
-//! **TODO**: Replace the question mark below with YES, NO, or PARTIAL to
 //! indicate the status of this assignment. If you want to tell something
 //! about this assignment to the grader, e.g., you have a bug you can't fix,
 //! or you want to explain your approach, write it down after the comments
 //! section. If you had no major issues and everything works, there is no need to write any comments.
 //!
-//! COMPLETED: ?
+//! COMPLETED: YES
 //!
-//! COMMENTS:
+//! COMMENTS: Besides the single indirect block, inodes also have a double- and a triple-indirect block, see [`IndDInode`].
+//! `new` and `get_block` therefore accept and return up to `DIRECT_POINTERS + 3` pointers, the last three being the single-, double- and triple-indirect block.
+//! The block map is walked by `bmap` and `bmap_alloc`, which work for any level of indirection, and `free_block_range` releases blocks bottom-up.
+//! Everything that does not depend on the block map, such as allocating inodes, syncing them and writing in chunks, is shared with the base file system through the helpers generic over its `InodeLayer` trait.
 //!
-//! ...
+//! [`IndDInode`]: struct.IndDInode.html
 //!
 
+use cplfs_api::alloc::{AllocPolicy, AllocStrategy};
+use cplfs_api::clock::{Clock, SystemClock};
+use cplfs_api::controller::Device;
+use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport};
+use cplfs_api::types::{
+    AtimePolicy, Block, BlockNo, Buffer, DataIdx, DiscardPolicy, FType, FragReport, InodeExtents,
+    InodeLike, MountOptions, StatFs, SuperBlock, Timestamp, DIRECT_POINTERS,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use super::a_block_support::{resize_in_tx, BlockLayerFS};
+use super::b_inode_support::{
+    alloc_data_block, alloc_disk_inode, allocate_blocks, allocate_range, change_in_tx,
+    check_bad_refs, free_data_block, init_free_disk_inodes, initial_mode, inode_is_dirty,
+    inode_pos, mark_dirty, put_inode, read_blocks, remap_disk_inodes, remap_ptr, sync_inode,
+    write_blocks, write_in_chunks, zero_bytes, zero_tail, DiskInode, InodeLayer,
+};
+use super::error_fs::InodeLayerError;
+use super::journal;
+
 /// You are free to choose the name for your file system. As we will use
 /// automated tests when grading your assignment, indicate here the name of
 /// your file system data type so we can just use `FSName` instead of
 /// having to manually figure out the name.
-/// **TODO**: replace the below type by the type of your file system
-pub type FSName = ();
+pub type FSName = IndirectInodeFS;
+
+/// Number of pointers to indirect blocks in an inode: a single-, a double- and a triple-indirect one
+pub const INDIRECT_POINTERS: u64 = 3;
+
+/// Total number of block pointers in an inode, direct and indirect
+const NPOINTERS: usize = (DIRECT_POINTERS + INDIRECT_POINTERS) as usize;
+
+/// Size of a block number stored in an indirect block, in bytes
+const PTR_SIZE: u64 = 8;
+
+/// Disk inode that, like [`DInode`], holds `DIRECT_POINTERS` direct block pointers, followed by pointers to a single-, a double- and a triple-indirect block.
+/// An indirect block holds `block_size / 8` block numbers; those in a single-indirect block point to data blocks, those in a double-indirect block to single-indirect blocks, and so on.
+///
+/// [`DInode`]: ../../cplfs_api/types/struct.DInode.html
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct IndDInode {
+    /// Registers the file type
+    pub ft: FType,
+    /// Counts the number of links to this inode in the file system
+    pub nlink: u16,
+    /// Permission bits of this file, together with the setuid, setgid and sticky bits
+    pub mode: u16,
+    /// User id of the owner of this file
+    pub uid: u32,
+    /// Group id of this file
+    pub gid: u32,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// The `DIRECT_POINTERS` direct block pointers, followed by the single-, double- and triple-indirect one; 0 if there is no such block (yet)
+    pub blocks: [u64; NPOINTERS],
+    /// Time of the last access to the contents of this file
+    pub atime: Timestamp,
+    /// Time of the last change to the contents of this file
+    pub mtime: Timestamp,
+    /// Time of the last change to this inode
+    pub ctime: Timestamp,
+    /// Time at which this inode was allocated
+    pub crtime: Timestamp,
+}
+
+lazy_static! {
+    /// Size of an [`IndDInode`](struct.IndDInode.html) on the disk, in bytes
+    pub static ref IND_DINODE_SIZE: u64 = bincode::serialize(&IndDInode::default()).unwrap().len() as u64;
+}
+
+/// Inode with indirect blocks, wrapping an [`IndDInode`](struct.IndDInode.html) together with its number
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct IndInode {
+    /// Number of the inode
+    pub inum: u64,
+    /// Contents of the inode on the disk
+    pub disk_node: IndDInode,
+}
+
+impl InodeLike for IndInode {
+    fn new(inum: u64, ft: &FType, nlink: u64, size: u64, blocks: &[u64]) -> Option<Self> {
+        if nlink > u16::MAX as u64 || blocks.len() > NPOINTERS {
+            return None;
+        }
+        let mut disk_node = IndDInode {
+            ft: *ft,
+            nlink: nlink as u16,
+            size,
+            ..Default::default()
+        };
+        disk_node.blocks[..blocks.len()].copy_from_slice(blocks);
+        Some(IndInode { inum, disk_node })
+    }
+
+    fn get_ft(&self) -> FType {
+        self.disk_node.ft
+    }
+    fn get_nlink(&self) -> u64 {
+        self.disk_node.nlink as u64
+    }
+    fn get_size(&self) -> u64 {
+        self.disk_node.size
+    }
+    /// Indices `DIRECT_POINTERS` up to `DIRECT_POINTERS + 2` return the single-, double- and triple-indirect block
+    fn get_block(&self, i: u64) -> BlockNo {
+        BlockNo(self.disk_node.blocks.get(i as usize).copied().unwrap_or(0))
+    }
+    fn get_inum(&self) -> u64 {
+        self.inum
+    }
+    fn get_atime(&self) -> Timestamp {
+        self.disk_node.atime
+    }
+    fn get_mtime(&self) -> Timestamp {
+        self.disk_node.mtime
+    }
+    fn get_ctime(&self) -> Timestamp {
+        self.disk_node.ctime
+    }
+    fn get_crtime(&self) -> Timestamp {
+        self.disk_node.crtime
+    }
+    fn get_mode(&self) -> u16 {
+        self.disk_node.mode
+    }
+    fn get_uid(&self) -> u32 {
+        self.disk_node.uid
+    }
+    fn get_gid(&self) -> u32 {
+        self.disk_node.gid
+    }
+}
+
+/// Returns the level of indirection of block pointer `r` of an inode: 0 for the direct pointers, 1 up to 3 for the indirect ones
+impl DiskInode for IndDInode {
+    fn ft(&self) -> FType {
+        self.ft
+    }
+    fn set_size(&mut self, size: u64) {
+        self.size = size;
+    }
+    fn touch(&mut self, now: Timestamp) {
+        self.mtime = now;
+        self.ctime = now;
+    }
+    fn data_changed(&self, new: &Self) -> bool {
+        self.ft != new.ft || self.size != new.size || self.blocks != new.blocks
+    }
+}
+
+fn depth(r: usize) -> u32 {
+    (r + 1).saturating_sub(DIRECT_POINTERS as usize) as u32
+}
+
+/// Returns the number of data blocks that can be reached through a block pointer at `depth` levels of indirection, in a file system with `block_size` byte blocks
+fn span(depth: u32, block_size: u64) -> u64 {
+    (block_size / PTR_SIZE).saturating_pow(depth)
+}
+
+/// Returns the largest size an inode with indirect blocks can grow to, in a file system with superblock `sb`
+pub fn max_file_size(sb: &SuperBlock) -> u64 {
+    (0..NPOINTERS)
+        .map(|r| span(depth(r), sb.block_size))
+        .fold(0u64, |n, s| n.saturating_add(s))
+        .saturating_mul(sb.block_size)
+}
+
+///Struct representing a file system with inodes that have indirect blocks
+#[derive(Debug)]
+pub struct IndirectInodeFS {
+    block_fs: BlockLayerFS,
+    inode_max_size: u64,
+    /// picks the inodes to allocate
+    policy: Box<dyn AllocPolicy>,
+    /// source of the timestamps of inodes
+    clock: Box<dyn Clock>,
+    /// when reads update the access time
+    atime: AtimePolicy,
+    /// inodes written since they were last synced, mapped to whether `i_fdatasync` has to persist them
+    dirty: BTreeMap<u64, bool>,
+}
+
+/// Functions specific to IndirectInodeFS
+impl IndirectInodeFS {
+    /// Returns a reference to the Filesystem's cached superblock
+    pub fn sup_as_ref(&self) -> &SuperBlock {
+        self.block_fs.sup_as_ref()
+    }
+
+    /// Returns the current time of the clock of this file system
+    pub fn now(&self) -> Timestamp {
+        self.clock.now()
+    }
+
+    /// Allocates the inode the allocation policy picks, preferably close to inode `goal`; see `alloc_disk_inode`
+    fn alloc_inode(
+        &mut self,
+        ft: FType,
+        goal: Option<u64>,
+    ) -> Result<u64, <Self as FileSysSupport>::Error> {
+        let now = self.now();
+        let init = IndDInode {
+            ft,
+            mode: initial_mode(ft),
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            ..Default::default()
+        };
+        let i = alloc_disk_inode(
+            &mut self.block_fs,
+            self.policy.as_mut(),
+            *IND_DINODE_SIZE,
+            goal,
+            init,
+        )?;
+        mark_dirty(self, i, true);
+        Ok(i)
+    }
+
+    /// Returns the number of block numbers that fit in an indirect block
+    fn per_block(&self) -> u64 {
+        self.sup_as_ref().block_size / PTR_SIZE
+    }
+
+    /// Returns the path to block `k` of a file in its block map: the block pointer of the inode the path starts from, followed by the slots in the indirect blocks along the way.
+    /// Returns `None` if the block lies beyond the reach of the inode.
+    fn block_path(&self, k: u64) -> Option<(usize, Vec<u64>)> {
+        let (bs, p) = (self.sup_as_ref().block_size, self.per_block());
+        let mut k = k;
+        for r in 0..NPOINTERS {
+            let span = span(depth(r), bs);
+            if k < span {
+                let mut slots = vec![0; depth(r) as usize];
+                for slot in slots.iter_mut().rev() {
+                    *slot = k % p;
+                    k /= p;
+                }
+                return Some((r, slots));
+            }
+            k -= span;
+        }
+        None
+    }
+
+    /// Frees the blocks of the tree below block `ptr`, at `depth` levels of indirection, that hold file blocks `from` up to `to`; the tree starts at file block `start`.
+    /// Works bottom-up: an indirect block is only freed after the blocks it points to, and only if it no longer points to any block.
+    /// Returns whether `ptr` itself was freed.
+    fn free_tree(
+        &mut self,
+        ptr: u64,
+        depth: u32,
+        start: u64,
+        (from, to): (u64, u64),
+    ) -> Result<bool, <Self as FileSysSupport>::Error> {
+        if depth == 0 {
+            if start < from || start >= to {
+                return Ok(false);
+            }
+            free_data_block(self, ptr)?;
+            return Ok(true);
+        }
+        let child_span = span(depth - 1, self.sup_as_ref().block_size);
+        let mut block = self.b_get(BlockNo(ptr))?;
+        let (mut changed, mut empty) = (false, true);
+        for slot in 0..self.per_block() {
+            let child: u64 = block.deserialize_from(slot * PTR_SIZE)?;
+            if child == 0 {
+                continue;
+            }
+            let child_start = start.saturating_add(slot.saturating_mul(child_span));
+            if child_start.saturating_add(child_span) <= from
+                || child_start >= to
+                || !self.free_tree(child, depth - 1, child_start, (from, to))?
+            {
+                empty = false;
+                continue;
+            }
+            block.serialize_into(&0u64, slot * PTR_SIZE)?;
+            changed = true;
+        }
+        if empty {
+            free_data_block(self, ptr)?;
+        } else if changed {
+            self.b_put(&block)?;
+        }
+        Ok(empty)
+    }
+
+    /// Frees all blocks of `inode` that hold file blocks `from` up to `to`, together with the indirect blocks that no longer point to any block.
+    /// Only changes `inode` in memory; storing it is up to the caller.
+    fn free_block_range(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+        from: u64,
+        to: u64,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        let bs = self.sup_as_ref().block_size;
+        let mut start = 0u64;
+        for r in 0..NPOINTERS {
+            let ptr = inode.disk_node.blocks[r];
+            let span = span(depth(r), bs);
+            if ptr != 0
+                && start.saturating_add(span) > from
+                && start < to
+                && self.free_tree(ptr, depth(r), start, (from, to))?
+            {
+                inode.disk_node.blocks[r] = 0;
+            }
+            start = start.saturating_add(span);
+        }
+        Ok(())
+    }
+
+    /// Appends the data blocks of the tree below block `ptr`, at `depth` levels of indirection, to `blocks`, in the order in which they hold the contents of the file.
+    /// Each indirect block is appended as well, before the blocks below it, if `indirect` is set.
+    fn tree_blocks(
+        &self,
+        ptr: u64,
+        depth: u32,
+        indirect: bool,
+        blocks: &mut Vec<u64>,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        if ptr == 0 {
+            return Ok(());
+        }
+        if depth == 0 || indirect {
+            blocks.push(ptr);
+        }
+        if depth == 0 {
+            return Ok(());
+        }
+        let block = self.b_get(BlockNo(ptr))?;
+        for slot in 0..self.per_block() {
+            let child = block.deserialize_from(slot * PTR_SIZE)?;
+            self.tree_blocks(child, depth - 1, indirect, blocks)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the absolute numbers of the data blocks of `inode`, in the order in which they hold its contents, skipping holes and indirect blocks
+    fn inode_blocks(
+        &self,
+        inode: &<Self as InodeSupport>::Inode,
+    ) -> Result<Vec<u64>, <Self as FileSysSupport>::Error> {
+        let mut blocks = vec![];
+        for (r, &ptr) in inode.disk_node.blocks.iter().enumerate() {
+            self.tree_blocks(ptr, depth(r), false, &mut blocks)?;
+        }
+        Ok(blocks)
+    }
+
+    /// Rewrites the pointers in the tree below block `ptr`, at `depth` levels of indirection, according to `moved`; `ptr` itself has already been rewritten
+    fn remap_tree(
+        &mut self,
+        ptr: u64,
+        depth: u32,
+        moved: &HashMap<u64, u64>,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        if depth == 0 {
+            return Ok(());
+        }
+        let mut block = self.b_get(BlockNo(ptr))?;
+        let mut changed = false;
+        for slot in 0..self.per_block() {
+            let mut child: u64 = block.deserialize_from(slot * PTR_SIZE)?;
+            if child == 0 {
+                continue;
+            }
+            if remap_ptr(&mut child, moved) {
+                block.serialize_into(&child, slot * PTR_SIZE)?;
+                changed = true;
+            }
+            self.remap_tree(child, depth - 1, moved)?;
+        }
+        if changed {
+            self.b_put(&block)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the block pointers of all inodes in use, and those in their indirect blocks, according to the given (old, new) pairs of absolute block numbers
+    fn remap_blocks(
+        &mut self,
+        moved: &[(BlockNo, BlockNo)],
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        if moved.is_empty() {
+            return Ok(());
+        }
+        let moved: HashMap<u64, u64> = moved.iter().map(|(old, new)| (old.0, new.0)).collect();
+        let mut trees = vec![];
+        remap_disk_inodes(
+            &mut self.block_fs,
+            *IND_DINODE_SIZE,
+            |di_node: &mut IndDInode| {
+                let mut changed = false;
+                for (r, ptr) in di_node.blocks.iter_mut().enumerate() {
+                    changed |= remap_ptr(ptr, &moved);
+                    if *ptr != 0 && depth(r) > 0 {
+                        trees.push((*ptr, depth(r)));
+                    }
+                }
+                changed
+            },
+        )?;
+        for (ptr, depth) in trees {
+            self.remap_tree(ptr, depth, &moved)?;
+        }
+        Ok(())
+    }
+
+    /// Frees all the blocks of an inode, and sets its size to 0
+    fn free_inode_blocks(
+        &mut self,
+        inode: &mut <Self as InodeSupport>::Inode,
+    ) -> Result<(), <Self as FileSysSupport>::Error> {
+        self.free_block_range(inode, 0, u64::MAX)?;
+        inode.disk_node.size = 0;
+        Ok(())
+    }
+}
+
+impl InodeLayer for IndirectInodeFS {
+    type DiskInode = IndDInode;
+
+    fn inode_size() -> u64 {
+        *IND_DINODE_SIZE
+    }
+
+    fn disk_node(inode: &Self::Inode) -> &IndDInode {
+        &inode.disk_node
+    }
+
+    fn disk_node_mut(inode: &mut Self::Inode) -> &mut IndDInode {
+        &mut inode.disk_node
+    }
+
+    fn block_fs(&self) -> &BlockLayerFS {
+        &self.block_fs
+    }
+
+    fn block_fs_mut(&mut self) -> &mut BlockLayerFS {
+        &mut self.block_fs
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    fn dirty(&self) -> &BTreeMap<u64, bool> {
+        &self.dirty
+    }
+
+    fn dirty_mut(&mut self) -> &mut BTreeMap<u64, bool> {
+        &mut self.dirty
+    }
+
+    /// Returns the number of the data block that holds block `k` of `inode`, or 0 if that block is a hole
+    fn bmap(&self, inode: &Self::Inode, k: u64) -> Result<u64, InodeLayerError> {
+        let (r, slots) = match self.block_path(k) {
+            Some(path) => path,
+            None => return Ok(0),
+        };
+        let mut ptr = inode.disk_node.blocks[r];
+        for slot in slots {
+            if ptr == 0 {
+                break;
+            }
+            ptr = self
+                .b_get(BlockNo(ptr))?
+                .deserialize_from(slot * PTR_SIZE)?;
+        }
+        Ok(ptr)
+    }
+
+    /// Returns the number of the data block that holds block `k` of `inode`, allocating it first if it is a hole, together with the indirect blocks on the way to it.
+    /// Only changes `inode` in memory; storing it is up to the caller.
+    fn bmap_alloc(&mut self, inode: &mut Self::Inode, k: u64) -> Result<u64, InodeLayerError> {
+        let (r, slots) = self.block_path(k).ok_or(InodeLayerError::InodeLayerInput(
+            "Block lies beyond the reach of the inode",
+        ))?;
+        //a new indirect block may hold the pointers of whatever used the block before, so it is zeroed first
+        let bs = self.sup_as_ref().block_size;
+        let depth = slots.len();
+        if inode.disk_node.blocks[r] == 0 {
+            inode.disk_node.blocks[r] = alloc_data_block(self, inode.inum, None)?;
+            if depth > 0 {
+                self.b_put(&Block::new_zero(inode.disk_node.blocks[r], bs))?;
+            }
+        }
+        let mut ptr = inode.disk_node.blocks[r];
+        for (level, slot) in slots.into_iter().enumerate() {
+            let mut block = self.b_get(BlockNo(ptr))?;
+            let mut next: u64 = block.deserialize_from(slot * PTR_SIZE)?;
+            if next == 0 {
+                next = alloc_data_block(self, inode.inum, None)?;
+                if level + 1 < depth {
+                    self.b_put(&Block::new_zero(next, bs))?;
+                }
+                block.serialize_into(&next, slot * PTR_SIZE)?;
+                self.b_put(&block)?;
+            }
+            ptr = next;
+        }
+        Ok(ptr)
+    }
+
+    /// Returns the blocks `inode` refers to: its data blocks and its indirect blocks; inodes of this file system have no extended attributes
+    fn referenced_blocks(
+        &self,
+        inode: &Self::Inode,
+        _with_xattrs: bool,
+    ) -> Result<Vec<u64>, InodeLayerError> {
+        let mut blocks = vec![];
+        for (r, &ptr) in inode.disk_node.blocks.iter().enumerate() {
+            self.tree_blocks(ptr, depth(r), true, &mut blocks)?;
+        }
+        Ok(blocks)
+    }
+}
+
+impl FileSysSupport for IndirectInodeFS {
+    type Error = InodeLayerError;
+
+    fn sb_valid(sb: &SuperBlock) -> bool {
+        //the optional features are only implemented for the base inodes, so none of them apply here
+        if !BlockLayerFS::sb_valid(sb) || sb.features != 0 {
+            return false;
+        }
+        //the inode region has to be large enough for the larger inodes of this file system
+        match sb.block_size / *IND_DINODE_SIZE {
+            0 => false,
+            per_block if sb.ngroups > 0 => {
+                sb.inodestart + (sb.ninodes / sb.ngroups).div_ceil(per_block) <= sb.datastart
+            }
+            per_block => sb.inodestart + sb.ninodes.div_ceil(per_block) <= sb.bmapstart,
+        }
+    }
+
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        if !Self::sb_valid(sb) {
+            return Err(InodeLayerError::InodeLayerInput("SuperBlock not valid"));
+        }
+        let mut block_fs = BlockLayerFS::mkfs(path, sb)?;
+        //init every inode as TFree
+        init_free_disk_inodes::<IndDInode>(&mut block_fs, sb, 0, *IND_DINODE_SIZE)?;
+        Ok(IndirectInodeFS {
+            block_fs,
+            inode_max_size: max_file_size(sb),
+            policy: AllocStrategy::default().policy(),
+            clock: Box::new(SystemClock),
+            atime: AtimePolicy::default(),
+            dirty: BTreeMap::new(),
+        })
+    }
+
+    fn mountfs_with(dev: Device, opts: &MountOptions) -> Result<Self, Self::Error> {
+        let block_fs = BlockLayerFS::mountfs_with(dev, opts)?;
+        if !Self::sb_valid(block_fs.sup_as_ref()) {
+            return Err(InodeLayerError::InodeLayerInput("SuperBlock not valid"));
+        }
+        let fs = IndirectInodeFS {
+            inode_max_size: max_file_size(block_fs.sup_as_ref()),
+            block_fs,
+            policy: opts.alloc.policy(),
+            clock: Box::new(SystemClock),
+            atime: opts.atime,
+            dirty: BTreeMap::new(),
+        };
+        check_bad_refs(&fs, |fs, inode| fs.referenced_blocks(inode, true))?;
+        Ok(fs)
+    }
+
+    fn unmountfs(self) -> Device {
+        self.block_fs.unmountfs()
+    }
+}
+
+impl BlockSupport for IndirectInodeFS {
+    fn b_get(&self, i: BlockNo) -> Result<Block, Self::Error> {
+        Ok(self.block_fs.b_get(i)?)
+    }
+
+    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_put(b)?)
+    }
+
+    fn b_free(&mut self, i: DataIdx) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_free(i)?)
+    }
+
+    fn b_zero(&mut self, i: DataIdx) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_zero(i)?)
+    }
+
+    fn b_alloc(&mut self) -> Result<DataIdx, Self::Error> {
+        Ok(self.block_fs.b_alloc()?)
+    }
+
+    fn b_alloc_near(&mut self, goal: DataIdx) -> Result<DataIdx, Self::Error> {
+        Ok(self.block_fs.b_alloc_near(goal)?)
+    }
+
+    fn b_set_privileged(&mut self, privileged: bool) -> bool {
+        self.block_fs.b_set_privileged(privileged)
+    }
+
+    fn b_frag_report(&self) -> Result<FragReport, Self::Error> {
+        Ok(self.block_fs.b_frag_report()?)
+    }
+
+    fn statfs(&self) -> Result<StatFs, Self::Error> {
+        Ok(self.block_fs.statfs()?)
+    }
+
+    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
+        Ok(self.block_fs.sup_get()?)
+    }
+
+    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
+        Ok(self.block_fs.sup_put(sup)?)
+    }
+
+    fn begin_op(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.begin_op()?)
+    }
+
+    fn end_op(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.end_op()?)
+    }
+
+    fn b_tx_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_tx_begin()?)
+    }
+
+    fn b_tx_commit(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_tx_commit()?)
+    }
+
+    fn b_tx_rollback(&mut self) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_tx_rollback()?)
+    }
+
+    fn b_grow(&mut self, nblocks: u64, ninodes: u64) -> Result<(), Self::Error> {
+        //the block layer sizes the inode region for the smaller inodes of the base file system
+        if !Self::sb_valid(&self.block_fs.grown_sb(nblocks, ninodes)?) {
+            return Err(InodeLayerError::InodeLayerOp(
+                "Grown inode region is too small for the inodes of this file system",
+            ));
+        }
+        let old_ninodes = self.sup_as_ref().ninodes;
+        let plan = self.block_fs.grow_prepare(nblocks, ninodes)?;
+        resize_in_tx(
+            self,
+            |fs| &mut fs.block_fs,
+            plan,
+            |fs, plan| {
+                fs.remap_blocks(&plan.moved)?;
+                init_free_disk_inodes::<IndDInode>(
+                    &mut fs.block_fs,
+                    &plan.sb,
+                    old_ninodes,
+                    *IND_DINODE_SIZE,
+                )?;
+                Ok(())
+            },
+        )
+    }
+
+    fn b_shrink(dev: Device, nblocks: u64) -> Result<Device, Self::Error> {
+        let mut fs = Self::mountfs(dev)?;
+        let plan = fs.block_fs.shrink_prepare(nblocks)?;
+        resize_in_tx(
+            &mut fs,
+            |fs| &mut fs.block_fs,
+            plan,
+            |fs, plan| fs.remap_blocks(&plan.moved),
+        )?;
+        let mut dev = fs.unmountfs();
+        dev.resize(nblocks)?;
+        Ok(dev)
+    }
+
+    fn b_trim(&mut self, policy: DiscardPolicy) -> Result<u64, Self::Error> {
+        Ok(self.block_fs.b_trim(policy)?)
+    }
+
+    fn b_mark_bad(&mut self, b: BlockNo) -> Result<(), Self::Error> {
+        journal::in_op(self, |fs| {
+            if let Some(new) = fs.block_fs.mark_bad(b, true)? {
+                fs.remap_blocks(&[(b, new)])?;
+            }
+            Ok(())
+        })
+    }
+
+    fn b_bad_blocks(&self) -> Result<Vec<BlockNo>, Self::Error> {
+        Ok(self.block_fs.b_bad_blocks()?)
+    }
+}
+
+impl InodeSupport for IndirectInodeFS {
+    type Inode = IndInode;
+
+    fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
+        let (t_block_addr, t_offset) = inode_pos(self, i)?;
+        let target_block = self.b_get(BlockNo(t_block_addr))?;
+        Ok(IndInode {
+            inum: i,
+            disk_node: target_block.deserialize_from(t_offset)?,
+        })
+    }
+
+    fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        put_inode(self, ino)
+    }
+
+    fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        journal::in_tx(self, |fs| {
+            let mut inode = fs.i_get(i)?;
+            if inode.disk_node.ft == FType::TFree {
+                return Err(InodeLayerError::InodeLayerOp(
+                    "Trying to free a TFree inode",
+                ));
+            }
+            if inode.disk_node.nlink != 0 {
+                return Ok(());
+            }
+            inode.disk_node.ft = FType::TFree;
+            fs.free_inode_blocks(&mut inode)?;
+            fs.i_put(&inode)
+        })
+    }
+
+    fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
+        journal::in_op(self, |fs| fs.alloc_inode(ft, None))
+    }
+
+    fn i_alloc_near(&mut self, ft: FType, parent: u64) -> Result<u64, Self::Error> {
+        if parent >= self.sup_as_ref().ninodes {
+            return Err(InodeLayerError::InodeLayerInput(
+                "Trying to allocate near an inode with index out of bounds",
+            ));
+        }
+        let goal = match self.block_fs.has_inode_bitmap() {
+            true => parent,
+            false => self
+                .block_fs
+                .group_first_inode(self.block_fs.inode_group(parent)),
+        };
+        journal::in_op(self, |fs| fs.alloc_inode(ft, Some(goal)))
+    }
+
+    fn i_extent_summary(&self) -> Result<Vec<InodeExtents>, Self::Error> {
+        let mut summary = vec![];
+        for i in 1..self.sup_as_ref().ninodes {
+            let inode = self.i_get(i)?;
+            if inode.get_ft() == FType::TFree {
+                continue;
+            }
+            let blocks = self.inode_blocks(&inode)?;
+            let extents = blocks
+                .iter()
+                .enumerate()
+                .filter(|&(k, &b)| k == 0 || blocks[k - 1] + 1 != b)
+                .count();
+            summary.push(InodeExtents {
+                inum: i,
+                blocks: blocks.len() as u64,
+                extents: extents as u64,
+            });
+        }
+        Ok(summary)
+    }
+
+    fn i_set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
+        change_in_tx(self, inode, |fs, inode| {
+            fs.free_inode_blocks(inode)?;
+            Ok(true)
+        })
+    }
+
+    fn i_truncate(&mut self, inode: &mut Self::Inode, new_size: u64) -> Result<(), Self::Error> {
+        let size = inode.get_size();
+        change_in_tx(self, inode, |fs, inode| {
+            if inode.get_ft() != FType::TFile {
+                return Err(InodeLayerError::InodeLayerInput(
+                    "Only regular files can be truncated to a given size",
+                ));
+            }
+            if new_size > fs.inode_max_size {
+                return Err(InodeLayerError::InodeLayerInput(
+                    "Truncation exceeds inode's max size",
+                ));
+            }
+            if new_size == size {
+                return Ok(false);
+            }
+            if new_size < size {
+                let bs = fs.sup_as_ref().block_size;
+                fs.free_block_range(inode, new_size.div_ceil(bs), u64::MAX)?;
+            }
+            //neither the bytes cut off nor whatever lay past the old end may show up in the file
+            zero_tail(fs, inode, new_size.min(size))?;
+            inode.disk_node.size = new_size;
+            Ok(true)
+        })
+    }
+
+    fn i_max_size(&self) -> u64 {
+        self.inode_max_size
+    }
+}
+
+impl InodeRWSupport for IndirectInodeFS {
+    fn i_read(
+        &mut self,
+        inode: &mut Self::Inode,
+        buf: &mut Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<u64, Self::Error> {
+        let read = read_blocks(self, inode, buf, off, n)?;
+        let now = self.now();
+        let d = &inode.disk_node;
+        if self.atime.updates(d.atime, d.mtime, d.ctime, now) {
+            inode.disk_node.atime = now;
+            self.i_put(inode)?;
+        }
+        Ok(read)
+    }
+
+    /// Writes in chunks that fit in the log; see `write_in_chunks`
+    fn i_write(
+        &mut self,
+        inode: &mut Self::Inode,
+        buf: &Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<(), Self::Error> {
+        //besides the bit map block of every data block and a share of the indirect blocks, a chunk may write the inode and a new indirect block on every level, with its bit map block
+        let chunk_blocks = self.block_fs.op_data_blocks(7, 2);
+        write_in_chunks(
+            self,
+            inode,
+            (buf, off, n),
+            chunk_blocks,
+            |fs, inode, data, pos| {
+                change_in_tx(fs, inode, |fs, inode| {
+                    write_blocks(fs, inode, data, pos)?;
+                    let end = pos + data.len() as u64;
+                    inode.disk_node.size = inode.disk_node.size.max(end);
+                    Ok(true)
+                })
+            },
+        )
+    }
+
+    fn i_seek_data(&self, inode: &Self::Inode, off: u64) -> Result<Option<u64>, Self::Error> {
+        if off >= inode.get_size() {
+            return Ok(None);
+        }
+        let bs = self.sup_as_ref().block_size;
+        for k in off / bs..inode.get_size().div_ceil(bs) {
+            if self.bmap(inode, k)? != 0 {
+                return Ok(Some((k * bs).max(off)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn i_seek_hole(&self, inode: &Self::Inode, off: u64) -> Result<Option<u64>, Self::Error> {
+        if off >= inode.get_size() {
+            return Ok(None);
+        }
+        let bs = self.sup_as_ref().block_size;
+        for k in off / bs..inode.get_size().div_ceil(bs) {
+            if self.bmap(inode, k)? == 0 {
+                return Ok(Some((k * bs).max(off)));
+            }
+        }
+        Ok(Some(inode.get_size()))
+    }
+
+    fn i_fallocate(
+        &mut self,
+        inode: &mut Self::Inode,
+        off: u64,
+        len: u64,
+        keep_size: bool,
+    ) -> Result<(), Self::Error> {
+        allocate_range(
+            self,
+            inode,
+            (off, len),
+            keep_size,
+            |fs, inode, end, new_size| allocate_blocks(fs, inode, (off, end), new_size, false),
+        )
+    }
+
+    fn i_punch_hole(
+        &mut self,
+        inode: &mut Self::Inode,
+        off: u64,
+        len: u64,
+    ) -> Result<(), Self::Error> {
+        change_in_tx(self, inode, |fs, inode| {
+            if inode.get_ft() != FType::TFile {
+                return Err(InodeLayerError::InodeLayerInput(
+                    "Only regular files can have holes punched in them",
+                ));
+            }
+            if len == 0 {
+                return Ok(false);
+            }
+            //no block lies past the maximum size
+            let end = off.saturating_add(len).min(fs.inode_max_size);
+            if off < end {
+                let bs = fs.sup_as_ref().block_size;
+                fs.free_block_range(inode, off.div_ceil(bs), end / bs)?;
+                //the blocks at the edges of the range are only partly in it
+                zero_bytes(fs, inode, off, end.min(off.next_multiple_of(bs)))?;
+                zero_bytes(fs, inode, (end - end % bs).max(off), end)?;
+            }
+            Ok(true)
+        })
+    }
+
+    fn i_zero_range(
+        &mut self,
+        inode: &mut Self::Inode,
+        off: u64,
+        len: u64,
+        keep_size: bool,
+    ) -> Result<(), Self::Error> {
+        allocate_range(
+            self,
+            inode,
+            (off, len),
+            keep_size,
+            |fs, inode, end, new_size| allocate_blocks(fs, inode, (off, end), new_size, true),
+        )
+    }
+
+    fn i_is_dirty(&self, inode: &Self::Inode) -> Result<bool, Self::Error> {
+        inode_is_dirty(self, inode)
+    }
+
+    fn i_fsync(&mut self, inode: &Self::Inode) -> Result<(), Self::Error> {
+        sync_inode(self, inode, true)
+    }
 
-// **TODO** define your own tests here.
+    fn i_fdatasync(&mut self, inode: &Self::Inode) -> Result<(), Self::Error> {
+        let with_inode = self.dirty.get(&inode.inum) == Some(&true);
+        sync_inode(self, inode, with_inode)
+    }
+}
 
 // WARNING: DO NOT TOUCH THE BELOW CODE -- IT IS REQUIRED FOR TESTING -- YOU WILL LOSE POINTS IF I MANUALLY HAVE TO FIX YOUR TESTS
 #[cfg(all(test, any(feature = "f", feature = "all")))]
@@ -0,0 +1,291 @@
+//! Write-ahead log of file system operations, in the style of xv6
+//!
+//! The log region of the file system (see the `logstart` and `nlog` fields of the [`SuperBlock`]) consists of a header block followed by `nlog - 1` log blocks.
+//! The header lists the block numbers of the blocks that are currently stored in the log, in order; an empty header means the log holds nothing.
+//!
+//! While an operation is running, writes are absorbed in memory by the [`Journal`].
+//! Once the outermost operation ends, the absorbed blocks are committed: they are written to the log blocks, after which the header is written to mark the operation as committed.
+//! Only then are the blocks installed at their actual location, after which the header is cleared again.
+//! Each of these steps is persisted before the next one starts, so the disk never sees a header before the log blocks it lists, nor an installed block before its header.
+//! A crash before the header is written loses the operation as a whole, while a crash after it is repaired by [`Journal::recover`] at mount time.
+//!
+//! Transactions are operations that can be rolled back as well.
+//! While a transaction is running, writes are absorbed even if the file system has no log; the writes absorbed when the transaction started are saved, so rolling it back simply restores them.
+//! Without a log, the superblock is the only block whose write can serve as a commit point, so it is always written last, once all other blocks of the operation are persisted.
+//!
+//! [`SuperBlock`]: ../../cplfs_api/types/struct.SuperBlock.html
+//! [`Journal`]: struct.Journal.html
+//! [`Journal::recover`]: struct.Journal.html#method.recover
+
+use cplfs_api::controller::Device;
+use cplfs_api::fs::BlockSupport;
+use cplfs_api::layout;
+use cplfs_api::types::{Block, SuperBlock};
+
+use super::error_fs::BlockLayerError;
+
+/// In-memory state of the log of a mounted file system
+#[derive(Debug, Default)]
+pub struct Journal {
+    /// block index of the log header, followed by the log blocks
+    logstart: u64,
+    /// number of blocks in the log region, including the header
+    nlog: u64,
+    /// number of operations that have started, but not ended yet
+    depth: u64,
+    /// blocks written by the current operation, in the order they were first written
+    pending: Vec<Block>,
+    /// whether the current operation wrote more blocks than the log can hold
+    overflowed: bool,
+    /// state of the journal at the start of every running transaction, innermost last
+    savepoints: Vec<Savepoint>,
+}
+
+/// State of the journal at the start of a transaction
+#[derive(Debug)]
+struct Savepoint {
+    pending: Vec<Block>,
+    overflowed: bool,
+}
+
+/// Returns a copy of the given block
+fn copy_block(b: &Block) -> Block {
+    Block::new(b.block_no, b.contents_as_ref().into())
+}
+
+/// Persist the writes to the given blocks of `dev`
+fn sync_blocks<I: Iterator<Item = u64>>(
+    dev: &mut Device,
+    blocks: I,
+) -> Result<(), BlockLayerError> {
+    for b in blocks {
+        dev.sync_block(b)?;
+    }
+    Ok(())
+}
+
+impl Journal {
+    /// Create the journal of a file system with superblock `sb`
+    pub fn new(sb: &SuperBlock) -> Journal {
+        Journal {
+            logstart: sb.logstart,
+            nlog: sb.nlog,
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether the superblock describes a valid log region, i.e. either no log at all, or a log right after the super block whose header can list all its blocks
+    pub fn sb_valid(sb: &SuperBlock) -> bool {
+        match sb.nlog {
+            0 => sb.logstart == 0,
+            n => sb.logstart == 1 && n >= 2 && n - 1 <= layout::log_capacity(sb.block_size),
+        }
+    }
+
+    /// Number of distinct blocks a single operation can write
+    pub fn capacity(&self) -> u64 {
+        self.nlog.saturating_sub(1)
+    }
+
+    /// Returns whether writes are currently absorbed by the journal, rather than written to the disk directly
+    pub fn active(&self) -> bool {
+        self.depth > 0 && (self.nlog > 0 || !self.savepoints.is_empty())
+    }
+
+    /// Returns whether an operation has started, but not ended yet
+    pub fn running(&self) -> bool {
+        self.depth > 0
+    }
+
+    /// Start a (possibly nested) operation
+    pub fn begin(&mut self) {
+        self.depth += 1;
+    }
+
+    /// Returns the numbers of the blocks the current operation wrote, whose contents differ from those on `dev`
+    pub fn changed(&self, dev: &Device) -> Result<Vec<u64>, BlockLayerError> {
+        let mut changed = vec![];
+        for b in &self.pending {
+            if dev.read_block(b.block_no)? != *b {
+                changed.push(b.block_no);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Returns the contents of block `i` as written by the current operation, if it wrote this block
+    pub fn read(&self, i: u64) -> Option<Block> {
+        self.pending
+            .iter()
+            .find(|b| b.block_no == i)
+            .map(copy_block)
+    }
+
+    /// Absorb a write of block `b` in the current operation.
+    /// Errors if this write does not fit in the log anymore, in which case the entire operation will be discarded when it ends.
+    pub fn write(&mut self, b: &Block) -> Result<(), BlockLayerError> {
+        let full = self.nlog > 0 && self.pending.len() as u64 >= self.capacity();
+        match self.pending.iter_mut().find(|p| p.block_no == b.block_no) {
+            Some(p) => *p = copy_block(b),
+            None if !full => self.pending.push(copy_block(b)),
+            None => {
+                self.overflowed = true;
+                return Err(BlockLayerError::BlockLayerOp(
+                    "Operation writes more blocks than the log can hold",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Start a (possibly nested) transaction
+    pub fn begin_tx(&mut self) {
+        self.begin();
+        self.savepoints.push(Savepoint {
+            pending: self.pending.iter().map(copy_block).collect(),
+            overflowed: self.overflowed,
+        });
+    }
+
+    /// Commit the current transaction and end the operation it started, see `end`.
+    /// Without a log, the absorbed writes are written to `dev` as soon as no transaction is running anymore, since later writes go to the disk directly.
+    pub fn commit_tx(&mut self, dev: &mut Device) -> Result<(), BlockLayerError> {
+        if self.savepoints.pop().is_none() {
+            return Err(BlockLayerError::BlockLayerOp("No transaction to commit"));
+        }
+        if self.nlog == 0 && self.savepoints.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.commit(dev, &pending)?;
+        }
+        self.end(dev)
+    }
+
+    /// Throw away all writes absorbed since the start of the current transaction, and end the operation it started, see `end`
+    pub fn rollback_tx(&mut self, dev: &mut Device) -> Result<(), BlockLayerError> {
+        let savepoint = self
+            .savepoints
+            .pop()
+            .ok_or(BlockLayerError::BlockLayerOp("No transaction to roll back"))?;
+        self.pending = savepoint.pending;
+        self.overflowed = savepoint.overflowed;
+        self.end(dev)
+    }
+
+    /// End the current operation, and commit its writes to `dev` if it is the outermost one.
+    /// Errors without writing anything if the operation overflowed the log.
+    pub fn end(&mut self, dev: &mut Device) -> Result<(), BlockLayerError> {
+        if self.depth == 0 {
+            return Err(BlockLayerError::BlockLayerOp("No operation to end"));
+        }
+        self.depth -= 1;
+        if self.depth > 0 {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        if std::mem::take(&mut self.overflowed) {
+            return Err(BlockLayerError::BlockLayerOp(
+                "Operation did not fit in the log and was discarded",
+            ));
+        }
+        self.commit(dev, &pending)
+    }
+
+    /// Write the given blocks to the log, mark them as committed and install them.
+    /// Without a log, the blocks are simply installed, the superblock last.
+    fn commit(&self, dev: &mut Device, blocks: &[Block]) -> Result<(), BlockLayerError> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        if self.nlog == 0 {
+            let (sb, rest): (Vec<&Block>, Vec<&Block>) =
+                blocks.iter().partition(|b| b.block_no == 0);
+            for b in &rest {
+                dev.write_block(b)?;
+            }
+            if let Some(sb) = sb.first() {
+                sync_blocks(dev, rest.iter().map(|b| b.block_no))?;
+                dev.write_block(sb)?;
+                dev.sync_block(0)?;
+            }
+            return Ok(());
+        }
+        for (k, b) in blocks.iter().enumerate() {
+            let mut logged = copy_block(b);
+            logged.block_no = self.logstart + 1 + k as u64;
+            dev.write_block(&logged)?;
+        }
+        sync_blocks(dev, (0..blocks.len() as u64).map(|k| self.logstart + 1 + k))?;
+        let homes: Vec<u64> = blocks.iter().map(|b| b.block_no).collect();
+        self.write_head(dev, &homes)?;
+        for b in blocks {
+            dev.write_block(b)?;
+        }
+        sync_blocks(dev, homes.into_iter())?;
+        self.write_head(dev, &[])
+    }
+
+    /// Write a log header listing the given block numbers, and persist it
+    fn write_head(&self, dev: &mut Device, homes: &[u64]) -> Result<(), BlockLayerError> {
+        let mut head = Block::new_zero(self.logstart, dev.block_size);
+        head.serialize_into(&homes.to_vec(), 0)?;
+        dev.write_block(&head)?;
+        Ok(dev.sync_block(self.logstart)?)
+    }
+
+    /// Install the blocks of an operation that was committed to the log of the file system with superblock `sb` on `dev`, but not installed yet, e.g. because of a crash.
+    /// Does nothing if the log is empty, or if the file system has no log.
+    pub fn recover(dev: &mut Device, sb: &SuperBlock) -> Result<(), BlockLayerError> {
+        if sb.nlog == 0 {
+            return Ok(());
+        }
+        let journal = Journal::new(sb);
+        let homes = dev
+            .read_block(sb.logstart)?
+            .deserialize_from::<Vec<u64>>(0)?;
+        if homes.len() as u64 > journal.capacity() {
+            return Err(BlockLayerError::BlockLayerInput("Log header not valid"));
+        }
+        for (k, &home) in homes.iter().enumerate() {
+            let mut b = dev.read_block(sb.logstart + 1 + k as u64)?;
+            b.block_no = home;
+            dev.write_block(&b)?;
+        }
+        sync_blocks(dev, homes.iter().copied())?;
+        if !homes.is_empty() {
+            journal.write_head(dev, &[])?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `op` on `fs` as a single transaction, which is committed if `op` succeeds, and rolled back if it fails.
+/// The error of `op` takes precedence over errors while rolling back.
+pub fn in_tx<F, T, G>(fs: &mut F, op: G) -> Result<T, F::Error>
+where
+    F: BlockSupport,
+    G: FnOnce(&mut F) -> Result<T, F::Error>,
+{
+    let mut tx = fs.transaction()?;
+    match op(&mut tx) {
+        Ok(value) => tx.commit().map(|_| value),
+        Err(e) => {
+            let _ = tx.rollback();
+            Err(e)
+        }
+    }
+}
+
+/// Run `op` on `fs` as a single file system operation, i.e. between `begin_op` and `end_op`.
+/// The operation is ended even if `op` fails; the first error that occurs is returned.
+pub fn in_op<F, T, G>(fs: &mut F, op: G) -> Result<T, F::Error>
+where
+    F: BlockSupport,
+    G: FnOnce(&mut F) -> Result<T, F::Error>,
+{
+    fs.begin_op()?;
+    let result = op(fs);
+    let end = fs.end_op();
+    let value = result?;
+    end?;
+    Ok(value)
+}
@@ -41,7 +41,7 @@
 //! 1.47, you don't have to do anything. Otherwise, replace the version
 //! below with the output of `rustc --version`.
 //!
-//! VERSION: rustc 1.47 (2020-10-08)
+//! VERSION: rustc 1.95.0 (59807616e 2026-04-14)
 
 // This line forces you to write documentation for all important things.
 #![deny(missing_docs)]
@@ -65,3 +65,4 @@ pub mod g_caching_inodes;
 // Declare additional modules below or declare them in other modules.
 /// the generic error type
 pub mod error_fs;
+pub mod journal;